@@ -4,14 +4,23 @@ use semver::{Version};
 fn main() {
     static_vcruntime::metabuild();
 
-    let git_rev_parse = Command::new("git")
-        .args(&["rev-parse", "HEAD"])
-        .output()
-        .unwrap();
-
-    let git_hash = String::from_utf8(git_rev_parse.stdout).unwrap();
+    let git_hash = git(&["rev-parse", "HEAD"]);
     println!("cargo:rustc-env=GIT_HASH={}", git_hash);
 
+    // build info, exposed by the profiler::build_info module
+    println!(
+        "cargo:rustc-env=BUILD_INFO_GIT_COMMIT={}",
+        git(&["rev-parse", "--short", "HEAD"])
+    );
+    println!(
+        "cargo:rustc-env=BUILD_INFO_BUILD_TIMESTAMP={}",
+        chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+    );
+    println!(
+        "cargo:rustc-env=BUILD_INFO_TARGET_TRIPLE={}",
+        std::env::var("TARGET").unwrap_or_else(|_| "unknown".into())
+    );
+
     let _restore  = Command::new("dotnet")
         .args(&["tool", "restore"])
         .output()
@@ -45,3 +54,17 @@ fn main() {
     }
 
 }
+
+/// Runs a git command, returning its trimmed output, or "unknown" if git is unavailable
+/// or the command fails
+fn git(args: &[&str]) -> String {
+    Command::new("git")
+        .args(args)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| "unknown".into())
+}
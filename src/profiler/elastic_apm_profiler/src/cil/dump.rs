@@ -0,0 +1,123 @@
+// Licensed to Elasticsearch B.V under
+// one or more agreements.
+// Elasticsearch B.V licenses this file to you under the Apache 2.0 License.
+// See the LICENSE file in the project root for more information
+
+use crate::cil::{Instruction, Operand};
+
+/// Formats bytes as hexadecimal, 16 bytes per line prefixed with the offset of the first
+pub fn il_hex_dump(bytes: &[u8]) -> String {
+    bytes
+        .chunks(16)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+            format!("{:04x}: {}\n", i * 16, hex.join(" "))
+        })
+        .collect()
+}
+
+/// Disassembles the instructions in `bytes` into ILASM style mnemonics, one per line
+/// prefixed with the label of its offset. Branch targets are written as labels and tokens as
+/// hexadecimal. Disassembly stops at bytes that are not a valid instruction, which are
+/// written as a hex dump
+pub fn il_disassemble(bytes: &[u8]) -> String {
+    let mut buf = String::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let instruction = match Instruction::from_bytes(&bytes[offset..]) {
+            Ok(instruction) => instruction,
+            Err(_) => {
+                buf.push_str(&format!("IL_{:04x}: invalid instruction\n", offset));
+                buf.push_str(&il_hex_dump(&bytes[offset..]));
+                break;
+            }
+        };
+
+        let next = offset + instruction.len();
+        buf.push_str(&format!("IL_{:04x}: {}", offset, instruction.opcode.name));
+        if let Some(operand) = format_operand(&instruction.operand, next) {
+            buf.push(' ');
+            buf.push_str(&operand);
+        }
+        buf.push('\n');
+        offset = next;
+    }
+    buf
+}
+
+/// Formats an operand of the instruction preceding the offset `next`, relative to which
+/// branch targets are
+fn format_operand(operand: &Operand, next: usize) -> Option<String> {
+    let label = |target: i32| format!("IL_{:04x}", next as i64 + target as i64);
+    let operand = match operand {
+        Operand::InlineNone => return None,
+        Operand::ShortInlineVar(val) => val.to_string(),
+        Operand::InlineVar(val) => val.to_string(),
+        Operand::ShortInlineI(val) => val.to_string(),
+        Operand::InlineI(val) => val.to_string(),
+        Operand::InlineI8(val) => val.to_string(),
+        Operand::ShortInlineR(val) => val.to_string(),
+        Operand::InlineR(val) => val.to_string(),
+        Operand::ShortInlineBrTarget(target) => label(*target as i32),
+        Operand::InlineBrTarget(target) => label(*target),
+        Operand::InlineSwitch(_, targets) => format!(
+            "({})",
+            targets
+                .iter()
+                .map(|target| label(*target))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        Operand::InlineMethod(token)
+        | Operand::InlineSig(token)
+        | Operand::InlineType(token)
+        | Operand::InlineString(token)
+        | Operand::InlineField(token)
+        | Operand::InlineTok(token) => format!("0x{:08x}", token),
+    };
+    Some(operand)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cil::{il_disassemble, il_hex_dump};
+
+    #[test]
+    fn il_hex_dump_writes_offsets() {
+        let bytes: Vec<u8> = (0..18).collect();
+        assert_eq!(
+            il_hex_dump(&bytes),
+            "0000: 00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f\n0010: 10 11\n"
+        );
+    }
+
+    #[test]
+    fn il_disassemble_writes_mnemonics_with_operands() {
+        let bytes = [
+            0x02, // ldarg.0
+            0x1f, 0xfe, // ldc.i4.s -2
+            0x28, 0x01, 0x00, 0x00, 0x0a, // call 0x0a000001
+            0x2c, 0xfe, // brfalse.s IL_0008
+            0x45, 0x01, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, // switch (IL_0017)
+            0x2a, // ret
+        ];
+        assert_eq!(
+            il_disassemble(&bytes),
+            "IL_0000: ldarg.0\n\
+            IL_0001: ldc.i4.s -2\n\
+            IL_0003: call 0x0a000001\n\
+            IL_0008: brfalse.s IL_0008\n\
+            IL_000a: switch (IL_0017)\n\
+            IL_0013: ret\n"
+        );
+    }
+
+    #[test]
+    fn il_disassemble_dumps_invalid_instructions_as_hex() {
+        assert_eq!(
+            il_disassemble(&[0x2a, 0x28, 0x01]),
+            "IL_0000: ret\nIL_0001: invalid instruction\n0000: 28 01\n"
+        );
+    }
+}
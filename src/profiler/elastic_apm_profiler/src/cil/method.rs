@@ -27,7 +27,7 @@
 use crate::{
     cil::{
         check_flag, il_u32, nearest_multiple, CorExceptionFlag, FatSectionClause, FatSectionHeader,
-        Instruction, Opcode,
+        Instruction, Instructions, Opcode,
         Operand::{InlineBrTarget, InlineSwitch, ShortInlineBrTarget},
         Section,
     },
@@ -675,15 +675,7 @@ impl Method {
     }
 
     fn instructions_from_bytes(il: &[u8]) -> Result<Vec<Instruction>, Error> {
-        let mut index = 0;
-        let mut instructions = Vec::new();
-        while index < il.len() {
-            let il = &il[index..];
-            let instruction = Instruction::from_bytes(il)?;
-            index += instruction.len();
-            instructions.push(instruction);
-        }
-        Ok(instructions)
+        Instructions::new(il).collect()
     }
 
     fn sections_from_bytes(il: &[u8]) -> Result<Vec<Section>, Error> {
@@ -698,7 +690,8 @@ impl Method {
         Ok(sections)
     }
 
-    fn instructions_to_bytes(&self) -> Vec<u8> {
+    /// Gets the bytes of the instructions, without the header and sections
+    pub fn instructions_to_bytes(&self) -> Vec<u8> {
         self.instructions
             .iter()
             .flat_map(|i| i.into_bytes())
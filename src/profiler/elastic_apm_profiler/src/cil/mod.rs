@@ -4,12 +4,16 @@
 // See the LICENSE file in the project root for more information
 
 mod cor;
+mod dump;
 mod helpers;
 mod instruction;
 mod method;
 mod opcode;
 mod section;
+mod stream;
 
-pub use self::{cor::*, helpers::*, instruction::*, method::*, opcode::*, section::*};
+pub use self::{
+    cor::*, dump::*, helpers::*, instruction::*, method::*, opcode::*, section::*, stream::*,
+};
 
 pub const MAX_LENGTH: u32 = 1024;
@@ -0,0 +1,264 @@
+// Licensed to Elasticsearch B.V under
+// one or more agreements.
+// Elasticsearch B.V licenses this file to you under the Apache 2.0 License.
+// See the LICENSE file in the project root for more information
+
+use crate::{
+    cil::{
+        Instruction,
+        Operand::{InlineBrTarget, InlineSwitch, ShortInlineBrTarget},
+        CALL, CALLVIRT, NOP,
+    },
+    error::Error,
+};
+use std::collections::HashSet;
+
+/// Raw IL instruction bytes, which are read and modified at instruction boundaries.
+///
+/// Modifications don't adjust the branch targets of other instructions; use
+/// [crate::cil::Method] to insert instructions into a method body.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct InstructionStream {
+    bytes: Vec<u8>,
+}
+
+impl InstructionStream {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Self { bytes }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    /// Iterates over the instructions in the stream. When bytes can't be decoded as an
+    /// instruction, an error is returned and iteration ends
+    pub fn iter(&self) -> Instructions<'_> {
+        Instructions::new(&self.bytes)
+    }
+
+    /// Overwrites the bytes starting at `offset` with `new_bytes`, which must be whole
+    /// instructions that replace whole instructions
+    pub fn patch(&mut self, offset: usize, new_bytes: &[u8]) -> Result<(), Error> {
+        let end = offset
+            .checked_add(new_bytes.len())
+            .ok_or(Error::InvalidCil)?;
+        if !self.is_boundary(offset)? || !self.is_boundary(end)? {
+            return Err(Error::InvalidCil);
+        }
+        validate_instructions(new_bytes)?;
+        self.bytes[offset..end].copy_from_slice(new_bytes);
+        Ok(())
+    }
+
+    /// Inserts `new_bytes`, which must be whole instructions, before the instruction starting
+    /// at `offset`. An offset of the stream length appends the instructions
+    pub fn insert_before(&mut self, offset: usize, new_bytes: &[u8]) -> Result<(), Error> {
+        if !self.is_boundary(offset)? {
+            return Err(Error::InvalidCil);
+        }
+        validate_instructions(new_bytes)?;
+        self.bytes.splice(offset..offset, new_bytes.iter().copied());
+        Ok(())
+    }
+
+    /// Whether `offset` is the start of an instruction, or the end of the stream
+    fn is_boundary(&self, offset: usize) -> Result<bool, Error> {
+        let mut current = 0;
+        for instruction in self.iter() {
+            if current >= offset {
+                break;
+            }
+            current += instruction?.len();
+        }
+        Ok(current == offset)
+    }
+}
+
+impl From<&[u8]> for InstructionStream {
+    fn from(bytes: &[u8]) -> Self {
+        Self::new(bytes.to_vec())
+    }
+}
+
+/// Checks that the bytes decode as whole instructions
+fn validate_instructions(bytes: &[u8]) -> Result<(), Error> {
+    let mut len = 0;
+    for instruction in Instructions::new(bytes) {
+        len += instruction?.len();
+    }
+    if len == bytes.len() {
+        Ok(())
+    } else {
+        Err(Error::InvalidCil)
+    }
+}
+
+/// Verifies that `modified` IL is a consistent rewrite of `original` IL in which calls
+/// are replaced with NOP padding and new instructions are inserted. The rewrite is consistent
+/// when
+/// - both decode as whole instructions
+/// - the original instructions appear in order in the modified IL, each unchanged, or
+///   replaced with a NOP when it is a call. Branch instructions may branch to a different
+///   offset. Any other instruction in the modified IL, including a NOP, is inserted
+/// - every branch target in the modified IL is the start of an instruction
+pub fn verify_nop_padding(original: &[u8], modified: &[u8]) -> bool {
+    let (original, modified) = match (
+        Instructions::new(original).collect::<Result<Vec<_>, _>>(),
+        Instructions::new(modified).collect::<Result<Vec<_>, _>>(),
+    ) {
+        (Ok(original), Ok(modified)) => (original, modified),
+        _ => return false,
+    };
+
+    // instructions in the modified IL that don't match are inserted instructions
+    let mut remaining = modified.iter();
+    let padding_consistent = original.iter().all(|instruction| {
+        remaining.any(|m| {
+            if m.opcode == NOP && instruction.opcode != NOP {
+                return instruction.opcode == CALL || instruction.opcode == CALLVIRT;
+            }
+            m.opcode == instruction.opcode
+                && (branch_offsets(m).is_some() || m.into_bytes() == instruction.into_bytes())
+        })
+    });
+    if !padding_consistent {
+        return false;
+    }
+
+    let mut boundaries = HashSet::with_capacity(modified.len());
+    let mut offset = 0;
+    for instruction in &modified {
+        boundaries.insert(offset as i64);
+        offset += instruction.len();
+    }
+
+    let mut offset = 0;
+    modified.iter().all(|instruction| {
+        offset += instruction.len();
+        branch_offsets(instruction)
+            .unwrap_or_default()
+            .iter()
+            .all(|o| boundaries.contains(&(offset as i64 + *o as i64)))
+    })
+}
+
+/// The offsets that a branch instruction branches to, relative to the end of the instruction
+fn branch_offsets(instruction: &Instruction) -> Option<Vec<i32>> {
+    match &instruction.operand {
+        ShortInlineBrTarget(offset) => Some(vec![*offset as i32]),
+        InlineBrTarget(offset) => Some(vec![*offset]),
+        InlineSwitch(_, offsets) => Some(offsets.clone()),
+        _ => None,
+    }
+}
+
+/// An iterator over the instructions of an [InstructionStream]
+#[derive(Debug)]
+pub struct Instructions<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Instructions<'a> {
+    /// Iterates over the instructions in raw IL bytes, without copying them into an
+    /// [InstructionStream]
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, offset: 0 }
+    }
+}
+
+impl Iterator for Instructions<'_> {
+    type Item = Result<Instruction, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let bytes = self.bytes.get(self.offset..).filter(|b| !b.is_empty())?;
+        match Instruction::from_bytes(bytes) {
+            Ok(instruction) if instruction.len() <= bytes.len() => {
+                self.offset += instruction.len();
+                Some(Ok(instruction))
+            }
+            Ok(_) => {
+                self.offset = self.bytes.len();
+                Some(Err(Error::InvalidCil))
+            }
+            Err(e) => {
+                self.offset = self.bytes.len();
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cil::{verify_nop_padding, InstructionStream, RET};
+
+    #[test]
+    fn instruction_stream_iterates_instructions() {
+        let stream = InstructionStream::from(&[0x02, 0x1f, 0x2a, 0x2a][..]);
+        let lens: Vec<usize> = stream.iter().map(|i| i.unwrap().len()).collect();
+        assert_eq!(lens, vec![1, 2, 1]);
+
+        let truncated = InstructionStream::from(&[0x02, 0x28, 0x01][..]);
+        let results: Vec<bool> = truncated.iter().map(|i| i.is_ok()).collect();
+        assert_eq!(results, vec![true, false]);
+    }
+
+    #[test]
+    fn instruction_stream_modifies_at_instruction_boundaries() {
+        // ldarg.0, ldc.i4.s 42, ret
+        let mut stream = InstructionStream::from(&[0x02, 0x1f, 0x2a, 0x2a][..]);
+
+        // ldc.i4.s 7 replaces ldc.i4.s 42
+        stream.patch(1, &[0x1f, 0x07]).unwrap();
+        assert_eq!(stream.as_bytes(), &[0x02, 0x1f, 0x07, 0x2a]);
+        // not at an instruction boundary
+        assert!(stream.patch(2, &[0x00]).is_err());
+        // ends within ldc.i4.s
+        assert!(stream.patch(1, &[0x00]).is_err());
+        // not whole instructions
+        assert!(stream.patch(1, &[0x1f]).is_err());
+
+        // nop before ret, and ret at the end
+        stream.insert_before(3, &[0x00]).unwrap();
+        stream.insert_before(5, &[RET.byte_2]).unwrap();
+        assert_eq!(stream.as_bytes(), &[0x02, 0x1f, 0x07, 0x00, 0x2a, 0x2a]);
+        assert!(stream.insert_before(2, &[0x00]).is_err());
+        assert!(stream.insert_before(7, &[0x00]).is_err());
+    }
+
+    #[test]
+    fn verify_nop_padding_checks_rewritten_il() {
+        // ldarg.0, call 0x0a000001, br.s -8 (ldarg.0), ret
+        let original = [0x02, 0x28, 0x01, 0x00, 0x00, 0x0a, 0x2b, 0xf8, 0x2a];
+        // ldarg.0, nop, call 0x0a000002, br.s -9 (ldarg.0), ret
+        let modified = [0x02, 0x00, 0x28, 0x02, 0x00, 0x00, 0x0a, 0x2b, 0xf7, 0x2a];
+        assert!(verify_nop_padding(&original, &modified));
+
+        // br.s -6 branches into the call
+        let broken_branch = [0x02, 0x00, 0x28, 0x02, 0x00, 0x00, 0x0a, 0x2b, 0xfa, 0x2a];
+        assert!(!verify_nop_padding(&original, &broken_branch));
+
+        // ldarg.0 removed
+        let removed = [0x28, 0x01, 0x00, 0x00, 0x0a, 0x2b, 0xf9, 0x2a];
+        assert!(!verify_nop_padding(&original, &removed));
+
+        // ldarg.0 replaced with a nop, which only replaces calls
+        let replaced = [0x00, 0x00, 0x28, 0x02, 0x00, 0x00, 0x0a, 0x2b, 0xf7, 0x2a];
+        assert!(!verify_nop_padding(&original, &replaced));
+
+        // call replaced with a nop, with nop padding inserted before ret
+        let padded = [
+            0x02, 0x00, 0x28, 0x02, 0x00, 0x00, 0x0a, 0x2b, 0xf7, 0x00, 0x2a,
+        ];
+        assert!(verify_nop_padding(&original, &padded));
+
+        // truncated
+        assert!(!verify_nop_padding(&original, &modified[..4]));
+    }
+}
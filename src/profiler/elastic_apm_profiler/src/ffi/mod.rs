@@ -808,6 +808,12 @@ bitflags! {
         const COR_PRF_REJIT_INLINING_CALLBACKS = 0x2;
     }
 }
+bitflags! {
+    pub struct COR_PRF_CODEGEN_FLAGS: DWORD {
+        const COR_PRF_CODEGEN_DISABLE_INLINING = 0x1;
+        const COR_PRF_CODEGEN_DISABLE_ALL_OPTIMIZATIONS = 0x2;
+    }
+}
 bitflags! {
     pub struct COR_PRF_FINALIZER_FLAGS: DWORD {
         const COR_PRF_FINALIZER_CRITICAL = 0x1;
@@ -27,6 +27,15 @@ interfaces! {
 }
 
 impl ICorProfilerFunctionControl {
+    pub fn set_codegen_flags(&self, flags: COR_PRF_CODEGEN_FLAGS) -> Result<(), HRESULT> {
+        let hr = unsafe { self.SetCodegenFlags(flags.bits()) };
+        if FAILED(hr) {
+            Err(hr)
+        } else {
+            Ok(())
+        }
+    }
+
     pub fn set_il_function_body(&self, new_method: &[u8]) -> Result<(), HRESULT> {
         let len = new_method.len() as ULONG;
         let ptr = new_method.as_ptr();
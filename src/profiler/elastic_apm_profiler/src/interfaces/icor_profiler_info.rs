@@ -584,7 +584,7 @@ interfaces! {
 
 /// Rust abstractions over COM functions
 impl ICorProfilerInfo {
-    fn get_class_from_object(&self, object_id: ObjectID) -> Result<ClassID, HRESULT> {
+    pub fn get_class_from_object(&self, object_id: ObjectID) -> Result<ClassID, HRESULT> {
         let mut class_id = MaybeUninit::uninit();
         let hr = unsafe { self.GetClassFromObject(object_id, class_id.as_mut_ptr()) };
         match hr {
@@ -1050,6 +1050,37 @@ impl ICorProfilerInfo {
     }
 }
 
+impl ICorProfilerInfo2 {
+    /// Gets the memory ranges of the GC heap generations
+    pub fn get_generation_bounds(&self) -> Result<Vec<COR_PRF_GC_GENERATION_RANGE>, HRESULT> {
+        let mut ranges_length = MaybeUninit::uninit();
+        let hr =
+            unsafe { self.GetGenerationBounds(0, ranges_length.as_mut_ptr(), ptr::null_mut()) };
+        if FAILED(hr) {
+            return Err(hr);
+        }
+
+        let ranges_length = unsafe { ranges_length.assume_init() };
+        let mut ranges = Vec::<COR_PRF_GC_GENERATION_RANGE>::with_capacity(ranges_length as usize);
+        let mut ranges_read = MaybeUninit::uninit();
+        let hr = unsafe {
+            self.GetGenerationBounds(
+                ranges_length,
+                ranges_read.as_mut_ptr(),
+                ranges.as_mut_ptr(),
+            )
+        };
+        match hr {
+            S_OK => {
+                let ranges_read = unsafe { ranges_read.assume_init() };
+                unsafe { ranges.set_len(ranges_read.min(ranges_length) as usize) };
+                Ok(ranges)
+            }
+            _ => Err(hr),
+        }
+    }
+}
+
 impl ICorProfilerInfo3 {
     pub fn get_module_info_2(&self, module_id: ModuleID) -> Result<ModuleInfo2, HRESULT> {
         let mut file_name_buffer_length = MaybeUninit::uninit();
@@ -1172,6 +1203,18 @@ impl ICorProfilerInfo3 {
 }
 
 impl ICorProfilerInfo4 {
+    pub fn get_object_size_2(&self, object_id: ObjectID) -> Result<usize, HRESULT> {
+        let mut size = MaybeUninit::uninit();
+        let hr = unsafe { self.GetObjectSize2(object_id, size.as_mut_ptr()) };
+        match hr {
+            S_OK => {
+                let size = unsafe { size.assume_init() };
+                Ok(size)
+            }
+            _ => Err(hr),
+        }
+    }
+
     pub fn initialize_current_thread(&self) -> Result<(), HRESULT> {
         let hr = unsafe { self.InitializeCurrentThread() };
         match hr {
@@ -0,0 +1,15 @@
+// Licensed to Elasticsearch B.V under
+// one or more agreements.
+// Elasticsearch B.V licenses this file to you under the Apache 2.0 License.
+// See the LICENSE file in the project root for more information
+
+//! Information about the build of the profiler, captured at compile time by build.rs
+
+/// The short git commit hash the profiler was built from, or "unknown"
+pub const GIT_COMMIT: &str = env!("BUILD_INFO_GIT_COMMIT");
+
+/// The UTC timestamp at which the profiler was built, in RFC 3339 format
+pub const BUILD_TIMESTAMP: &str = env!("BUILD_INFO_BUILD_TIMESTAMP");
+
+/// The target triple the profiler was built for
+pub const TARGET_TRIPLE: &str = env!("BUILD_INFO_TARGET_TRIPLE");
@@ -0,0 +1,233 @@
+// Licensed to Elasticsearch B.V under
+// one or more agreements.
+// Elasticsearch B.V licenses this file to you under the Apache 2.0 License.
+// See the LICENSE file in the project root for more information
+
+//! Batching of CallTarget ReJIT requests.
+//!
+//! Requesting ReJIT for many methods at once can stall the JIT compilation in which the
+//! requests are made. A [CallTargetBatcher] holds pending CallTarget methods so that at most
+//! `ELASTIC_APM_PROFILER_CALLTARGET_BATCH_SIZE` of them are requested per
+//! `JITCompilationStarted` callback, deferring the remainder to subsequent callbacks.
+//! A [CallTargetBatchDrainer] enqueues the remainder when there are no subsequent callbacks.
+
+use crate::{
+    ffi::{mdMethodDef, ModuleID},
+    profiler::metrics::PeriodicTask,
+};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// The default maximum number of CallTarget methods requested for ReJIT per batch
+pub const DEFAULT_CALLTARGET_BATCH_SIZE: usize = 100;
+
+/// The interval at which a [CallTargetBatchDrainer] enqueues the next batch of deferred
+/// CallTarget methods
+pub const CALLTARGET_BATCH_DRAIN_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A method to instrument with CallTarget
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct CallTarget {
+    pub module_id: ModuleID,
+    pub method_def: mdMethodDef,
+}
+
+impl CallTarget {
+    pub fn new(module_id: ModuleID, method_def: mdMethodDef) -> Self {
+        Self {
+            module_id,
+            method_def,
+        }
+    }
+}
+
+/// Queues CallTarget methods and hands them out in batches of at most `batch_size`,
+/// in the order in which they were pushed
+#[derive(Debug)]
+pub struct CallTargetBatcher {
+    batch_size: usize,
+    pending: VecDeque<CallTarget>,
+}
+
+impl CallTargetBatcher {
+    /// Creates a new batcher. A `batch_size` of 0 is treated as 1
+    pub fn new(batch_size: usize) -> Self {
+        Self {
+            batch_size: batch_size.max(1),
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Queues a target to be returned by a subsequent [CallTargetBatcher::drain_batch]
+    pub fn push(&mut self, target: CallTarget) {
+        self.pending.push_back(target);
+    }
+
+    /// Removes and returns up to `batch_size` of the oldest queued targets
+    pub fn drain_batch(&mut self) -> Vec<CallTarget> {
+        let len = self.batch_size.min(self.pending.len());
+        self.pending.drain(..len).collect()
+    }
+
+    /// Removes the queued targets of a module, such as one being unloaded
+    pub fn remove_module(&mut self, module_id: ModuleID) {
+        self.pending.retain(|t| t.module_id != module_id);
+    }
+
+    /// The number of queued targets
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+impl Default for CallTargetBatcher {
+    fn default() -> Self {
+        Self::new(DEFAULT_CALLTARGET_BATCH_SIZE)
+    }
+}
+
+/// Removes the next batch of queued targets from the batcher and passes their module and
+/// method IDs to `enqueue`, if there are any
+pub fn enqueue_next_batch(
+    batcher: &Mutex<CallTargetBatcher>,
+    enqueue: impl FnOnce(Vec<ModuleID>, Vec<mdMethodDef>),
+) {
+    let (batch, remaining) = {
+        let mut batcher = batcher.lock().unwrap();
+        let batch = batcher.drain_batch();
+        (batch, batcher.len())
+    };
+
+    if batch.is_empty() {
+        return;
+    }
+
+    if remaining > 0 {
+        log::debug!(
+            "enqueue for ReJIT: {} CallTarget methods, deferring {}",
+            batch.len(),
+            remaining
+        );
+    }
+
+    let (module_ids, method_ids) = batch
+        .into_iter()
+        .map(|target| (target.module_id, target.method_def))
+        .unzip();
+    enqueue(module_ids, method_ids);
+}
+
+/// Periodically enqueues the next batch of queued targets on a background thread, so that
+/// targets deferred by batching are enqueued even when no more methods are JIT compiled
+pub struct CallTargetBatchDrainer {
+    task: PeriodicTask,
+}
+
+impl CallTargetBatchDrainer {
+    pub fn new(
+        batcher: Arc<Mutex<CallTargetBatcher>>,
+        interval: Duration,
+        enqueue: impl Fn(Vec<ModuleID>, Vec<mdMethodDef>) + Send + 'static,
+    ) -> Self {
+        Self {
+            task: PeriodicTask::spawn(interval, move || enqueue_next_batch(&batcher, &enqueue)),
+        }
+    }
+
+    pub fn shutdown(self) {
+        if self.task.stop().is_err() {
+            log::error!("Error in joining CallTarget batch drainer thread");
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::profiler::calltarget::{CallTarget, CallTargetBatchDrainer, CallTargetBatcher};
+    use std::{
+        sync::{Arc, Mutex},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    fn drain_batch_returns_at_most_batch_size_targets_in_order() {
+        let mut batcher = CallTargetBatcher::new(2);
+        for method_def in 1..=5 {
+            batcher.push(CallTarget::new(7, method_def));
+        }
+
+        assert_eq!(
+            batcher.drain_batch(),
+            vec![CallTarget::new(7, 1), CallTarget::new(7, 2)]
+        );
+        assert_eq!(batcher.len(), 3);
+        assert_eq!(
+            batcher.drain_batch(),
+            vec![CallTarget::new(7, 3), CallTarget::new(7, 4)]
+        );
+        assert_eq!(batcher.drain_batch(), vec![CallTarget::new(7, 5)]);
+        assert!(batcher.is_empty());
+        assert!(batcher.drain_batch().is_empty());
+    }
+
+    #[test]
+    fn remove_module_removes_only_targets_of_module() {
+        let mut batcher = CallTargetBatcher::new(10);
+        batcher.push(CallTarget::new(1, 1));
+        batcher.push(CallTarget::new(2, 1));
+        batcher.push(CallTarget::new(1, 2));
+
+        batcher.remove_module(1);
+
+        assert_eq!(batcher.drain_batch(), vec![CallTarget::new(2, 1)]);
+    }
+
+    #[test]
+    fn new_treats_zero_batch_size_as_one() {
+        let mut batcher = CallTargetBatcher::new(0);
+        batcher.push(CallTarget::new(1, 1));
+        batcher.push(CallTarget::new(1, 2));
+
+        assert_eq!(batcher.drain_batch().len(), 1);
+    }
+
+    #[test]
+    fn drainer_enqueues_deferred_targets_in_batches() {
+        let batcher = Arc::new(Mutex::new(CallTargetBatcher::new(2)));
+        for method_def in 1..=5 {
+            batcher.lock().unwrap().push(CallTarget::new(7, method_def));
+        }
+
+        let enqueued = Arc::new(Mutex::new(Vec::new()));
+        let drainer = {
+            let enqueued = enqueued.clone();
+            CallTargetBatchDrainer::new(
+                batcher.clone(),
+                Duration::from_millis(10),
+                move |module_ids, method_ids| {
+                    assert_eq!(module_ids, vec![7; method_ids.len()]);
+                    enqueued.lock().unwrap().push(method_ids);
+                },
+            )
+        };
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !batcher.lock().unwrap().is_empty() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        drainer.shutdown();
+
+        assert_eq!(
+            *enqueued.lock().unwrap(),
+            vec![vec![1, 2], vec![3, 4], vec![5]]
+        );
+    }
+}
@@ -43,6 +43,8 @@ pub struct CallTargetTokens {
     call_target_state_type_get_default: mdMemberRef,
     call_target_return_void_type_get_default: mdMemberRef,
     get_default_member_ref: mdMemberRef,
+    interlocked_type_ref: mdTypeRef,
+    interlocked_increment_member_ref: mdMemberRef,
 }
 
 impl CallTargetTokens {
@@ -68,6 +70,8 @@ impl CallTargetTokens {
             call_target_state_type_get_default: mdMemberRefNil,
             call_target_return_void_type_get_default: mdMemberRefNil,
             get_default_member_ref: mdMemberRefNil,
+            interlocked_type_ref: mdTypeRefNil,
+            interlocked_increment_member_ref: mdMemberRefNil,
         }
     }
 
@@ -304,6 +308,42 @@ impl CallTargetTokens {
         Ok(self.call_target_state_type_ref)
     }
 
+    /// Gets the member ref of `long System.Threading.Interlocked.Increment(ref long)`
+    pub fn get_interlocked_increment_member_ref(
+        &mut self,
+        module_metadata: &ModuleMetadata,
+    ) -> Result<mdMemberRef, HRESULT> {
+        self.ensure_cor_lib_tokens(module_metadata)?;
+        if self.interlocked_type_ref == mdTypeRefNil {
+            self.interlocked_type_ref = module_metadata
+                .emit
+                .define_type_ref_by_name(self.cor_lib_assembly_ref, "System.Threading.Interlocked")
+                .map_err(|e| {
+                    log::warn!("Could not define type_ref for System.Threading.Interlocked");
+                    e
+                })?;
+        }
+
+        if self.interlocked_increment_member_ref == mdMemberRefNil {
+            let signature = [
+                CorCallingConvention::IMAGE_CEE_CS_CALLCONV_DEFAULT.bits(),
+                1,
+                CorElementType::ELEMENT_TYPE_I8 as COR_SIGNATURE,
+                CorElementType::ELEMENT_TYPE_BYREF as COR_SIGNATURE,
+                CorElementType::ELEMENT_TYPE_I8 as COR_SIGNATURE,
+            ];
+            self.interlocked_increment_member_ref = module_metadata
+                .emit
+                .define_member_ref(self.interlocked_type_ref, "Increment", &signature)
+                .map_err(|e| {
+                    log::warn!("Could not define member_ref for Interlocked.Increment");
+                    e
+                })?;
+        }
+
+        Ok(self.interlocked_increment_member_ref)
+    }
+
     pub fn get_target_void_return_type_ref(
         &mut self,
         module_metadata: &ModuleMetadata,
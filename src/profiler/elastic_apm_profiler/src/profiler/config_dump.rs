@@ -0,0 +1,89 @@
+// Licensed to Elasticsearch B.V under
+// one or more agreements.
+// Elasticsearch B.V licenses this file to you under the Apache 2.0 License.
+// See the LICENSE file in the project root for more information
+
+//! Dumps the profiler configuration as JSON to a file in the log directory on demand, when
+//! enabled with `ELASTIC_APM_PROFILER_CONFIG_DUMP_ENABLED`.
+//!
+//! On Linux and macOS, a dump is triggered by sending the process a `SIGUSR1` signal.
+//! On Windows, a dump is triggered by setting the `ELASTIC_APM_PROFILER_DUMP_CONFIG`
+//! environment variable to `true` in the process after it has started, for example from
+//! managed code. The environment variable is removed once the dump is written.
+
+use crate::profiler::env;
+use std::{
+    io,
+    path::{Path, PathBuf},
+    thread,
+};
+
+/// Starts a background thread that dumps the configuration to `dir` each time a dump is
+/// triggered
+pub fn start_config_dump_trigger(dir: PathBuf) {
+    if let Err(e) = spawn_trigger_thread(dir) {
+        log::warn!("could not start configuration dump trigger: {}", e);
+    }
+}
+
+#[cfg(unix)]
+fn spawn_trigger_thread(dir: PathBuf) -> io::Result<()> {
+    use signal_hook::{consts::SIGUSR1, iterator::Signals};
+
+    let mut signals = Signals::new([SIGUSR1])?;
+    thread::Builder::new()
+        .name("elastic_apm_profiler_config_dump".into())
+        .spawn(move || {
+            for _ in signals.forever() {
+                dump_config(&dir);
+            }
+        })?;
+    log::debug!("send SIGUSR1 to dump the profiler configuration");
+    Ok(())
+}
+
+#[cfg(windows)]
+const ELASTIC_APM_PROFILER_DUMP_CONFIG_ENV_VAR: &str = "ELASTIC_APM_PROFILER_DUMP_CONFIG";
+
+#[cfg(windows)]
+fn spawn_trigger_thread(dir: PathBuf) -> io::Result<()> {
+    use std::time::Duration;
+
+    thread::Builder::new()
+        .name("elastic_apm_profiler_config_dump".into())
+        .spawn(move || loop {
+            thread::sleep(Duration::from_secs(5));
+            let triggered = std::env::var(ELASTIC_APM_PROFILER_DUMP_CONFIG_ENV_VAR)
+                .map(|v| v.trim().eq_ignore_ascii_case("true"))
+                .unwrap_or(false);
+            if triggered {
+                std::env::remove_var(ELASTIC_APM_PROFILER_DUMP_CONFIG_ENV_VAR);
+                dump_config(&dir);
+            }
+        })?;
+    log::debug!(
+        "set {} to true to dump the profiler configuration",
+        ELASTIC_APM_PROFILER_DUMP_CONFIG_ENV_VAR
+    );
+    Ok(())
+}
+
+fn dump_config(dir: &Path) {
+    let path = config_dump_path(dir);
+    match std::fs::write(&path, env::profiler_config_as_json()) {
+        Ok(()) => log::info!("wrote profiler configuration to {}", path.display()),
+        Err(e) => log::warn!(
+            "could not write profiler configuration to {}: {}",
+            path.display(),
+            e
+        ),
+    }
+}
+
+fn config_dump_path(dir: &Path) -> PathBuf {
+    dir.join(format!(
+        "elastic_apm_profiler_config_{}_{}.json",
+        std::process::id(),
+        chrono::Utc::now().format("%Y%m%dT%H%M%S%.3f")
+    ))
+}
@@ -0,0 +1,376 @@
+// Licensed to Elasticsearch B.V under
+// one or more agreements.
+// Elasticsearch B.V licenses this file to you under the Apache 2.0 License.
+// See the LICENSE file in the project root for more information
+
+//! Sampling of the CPU time used by each thread.
+//!
+//! When `ELASTIC_APM_PROFILER_THREAD_CPU_SAMPLING_INTERVAL_MS` is set, a [ThreadCpuSampler]
+//! reads the user and system CPU time of each thread of the process on a background thread,
+//! from `/proc/self/task/*/stat` on Linux and `GetThreadTimes` on Windows. The most recent
+//! [CpuSnapshot]s are kept in a ring buffer, and a summary of the CPU time used by each thread
+//! is logged at INFO level when the sampler shuts down.
+
+use crate::profiler::metrics::PeriodicTask;
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// The number of snapshots kept by a [ThreadCpuSampler]
+const MAX_SNAPSHOTS: usize = 60;
+
+/// The number of threads with the most CPU time that are logged in a summary
+const SUMMARY_TOP_THREADS: usize = 10;
+
+/// The CPU time used by a thread since it started
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ThreadCpuTime {
+    /// The OS thread ID
+    pub thread_id: u64,
+    /// The name of the thread, where the platform provides one
+    pub name: Option<String>,
+    pub user: Duration,
+    pub system: Duration,
+}
+
+impl ThreadCpuTime {
+    pub fn total(&self) -> Duration {
+        self.user + self.system
+    }
+}
+
+/// The CPU time used by each thread of the process at a point in time
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CpuSnapshot {
+    pub timestamp: Instant,
+    pub threads: Vec<ThreadCpuTime>,
+}
+
+/// A ring buffer of the most recent [CpuSnapshot]s
+#[derive(Debug)]
+pub struct CpuSnapshots {
+    capacity: usize,
+    snapshots: VecDeque<CpuSnapshot>,
+}
+
+impl CpuSnapshots {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            capacity,
+            snapshots: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Adds a snapshot, removing the oldest snapshot when the buffer is full
+    pub fn push(&mut self, snapshot: CpuSnapshot) {
+        if self.snapshots.len() == self.capacity {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(snapshot);
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    pub fn latest(&self) -> Option<&CpuSnapshot> {
+        self.snapshots.back()
+    }
+
+    /// Summarizes the CPU time used by the threads in the latest snapshot, with the threads
+    /// that used the most CPU time first. The usage of each thread is its share of a CPU over
+    /// the window between the oldest and latest snapshots
+    pub fn summary(&self) -> String {
+        let (oldest, latest) = match (self.snapshots.front(), self.snapshots.back()) {
+            (Some(oldest), Some(latest)) => (oldest, latest),
+            _ => return "snapshots=0".into(),
+        };
+
+        let window = latest.timestamp.saturating_duration_since(oldest.timestamp);
+        let oldest_totals: HashMap<u64, Duration> = oldest
+            .threads
+            .iter()
+            .map(|t| (t.thread_id, t.total()))
+            .collect();
+
+        let mut threads: Vec<(&ThreadCpuTime, Duration)> = latest
+            .threads
+            .iter()
+            .map(|t| {
+                // a thread that started within the window used all of its CPU time within it
+                let start = oldest_totals.get(&t.thread_id).copied().unwrap_or_default();
+                (t, t.total().saturating_sub(start))
+            })
+            .collect();
+        threads.sort_by(|(a, a_window), (b, b_window)| {
+            b_window
+                .cmp(a_window)
+                .then_with(|| b.total().cmp(&a.total()))
+                .then_with(|| a.thread_id.cmp(&b.thread_id))
+        });
+
+        let mut summary = format!(
+            "snapshots={} window_ms={} threads={} cpu_ms={}",
+            self.snapshots.len(),
+            window.as_millis(),
+            latest.threads.len(),
+            latest
+                .threads
+                .iter()
+                .map(|t| t.total())
+                .sum::<Duration>()
+                .as_millis()
+        );
+        for (thread, window_cpu) in threads.iter().take(SUMMARY_TOP_THREADS) {
+            let usage = if window.is_zero() {
+                0.0
+            } else {
+                window_cpu.as_secs_f64() / window.as_secs_f64() * 100.0
+            };
+            summary.push_str(&format!(
+                "\n  thread={} name={} user_ms={} system_ms={} usage={:.1}%",
+                thread.thread_id,
+                thread.name.as_deref().unwrap_or("-"),
+                thread.user.as_millis(),
+                thread.system.as_millis(),
+                usage
+            ));
+        }
+        summary
+    }
+}
+
+/// Periodically samples the CPU time used by each thread on a background thread
+pub struct ThreadCpuSampler {
+    snapshots: Arc<Mutex<CpuSnapshots>>,
+    task: PeriodicTask,
+}
+
+impl ThreadCpuSampler {
+    pub fn new(interval: Duration) -> Self {
+        let snapshots = Arc::new(Mutex::new(CpuSnapshots::new(MAX_SNAPSHOTS)));
+        // take a snapshot at the start, against which the first window is measured
+        sample(&snapshots);
+        let sampled = snapshots.clone();
+        Self {
+            snapshots,
+            task: PeriodicTask::spawn(interval, move || sample(&sampled)),
+        }
+    }
+
+    /// Gets the most recent snapshot
+    pub fn snapshot(&self) -> Option<CpuSnapshot> {
+        self.snapshots.lock().unwrap().latest().cloned()
+    }
+
+    /// Stops sampling and logs a summary of the CPU time used by each thread
+    pub fn shutdown(self) {
+        match self.task.stop() {
+            Ok(()) => {
+                sample(&self.snapshots);
+                log::info!(
+                    "ThreadCpuSampler: {}",
+                    self.snapshots.lock().unwrap().summary()
+                )
+            }
+            Err(_) => log::error!("Error in joining thread CPU sampler thread"),
+        }
+    }
+}
+
+fn sample(snapshots: &Mutex<CpuSnapshots>) {
+    match read_thread_cpu_times() {
+        Ok(threads) => snapshots.lock().unwrap().push(CpuSnapshot {
+            timestamp: Instant::now(),
+            threads,
+        }),
+        Err(e) => log::debug!("ThreadCpuSampler: error reading thread CPU times: {}", e),
+    }
+}
+
+/// Reads the CPU time of each thread of the process from `/proc/self/task/*/stat`
+#[cfg(target_os = "linux")]
+fn read_thread_cpu_times() -> io::Result<Vec<ThreadCpuTime>> {
+    // SAFETY: sysconf has no preconditions
+    let ticks_per_sec = match unsafe { libc::sysconf(libc::_SC_CLK_TCK) } {
+        ticks if ticks > 0 => ticks as u64,
+        _ => 100,
+    };
+
+    let mut threads = Vec::new();
+    for entry in std::fs::read_dir("/proc/self/task")? {
+        let entry = entry?;
+        let thread_id = match entry.file_name().to_str().and_then(|s| s.parse().ok()) {
+            Some(thread_id) => thread_id,
+            None => continue,
+        };
+        // a thread may exit between listing the tasks and reading its stat
+        if let Ok(stat) = std::fs::read_to_string(entry.path().join("stat")) {
+            if let Some(thread) = parse_task_stat(thread_id, &stat, ticks_per_sec) {
+                threads.push(thread);
+            }
+        }
+    }
+    Ok(threads)
+}
+
+/// Parses the name, and the user and system CPU time in clock ticks, from the contents of
+/// a `/proc/<pid>/task/<tid>/stat` file
+#[cfg(any(target_os = "linux", test))]
+fn parse_task_stat(thread_id: u64, stat: &str, ticks_per_sec: u64) -> Option<ThreadCpuTime> {
+    // the name is in parentheses, and may itself contain spaces and parentheses
+    let name_start = stat.find('(')?;
+    let name_end = stat.rfind(')')?;
+    let name = stat.get(name_start + 1..name_end)?;
+
+    // the fields after the name start from field 3, state. utime and stime are fields 14 and 15
+    let mut fields = stat.get(name_end + 1..)?.split_whitespace().skip(11);
+    let mut ticks = || -> Option<Duration> {
+        let ticks: u64 = fields.next()?.parse().ok()?;
+        Some(Duration::from_millis(ticks * 1000 / ticks_per_sec.max(1)))
+    };
+
+    Some(ThreadCpuTime {
+        thread_id,
+        name: Some(name.to_string()),
+        user: ticks()?,
+        system: ticks()?,
+    })
+}
+
+/// Reads the CPU time of each thread of the process with `GetThreadTimes`
+#[cfg(target_os = "windows")]
+fn read_thread_cpu_times() -> io::Result<Vec<ThreadCpuTime>> {
+    use winapi::{
+        shared::minwindef::FILETIME,
+        um::{
+            handleapi::{CloseHandle, INVALID_HANDLE_VALUE},
+            processthreadsapi::{GetCurrentProcessId, GetThreadTimes, OpenThread},
+            tlhelp32::{
+                CreateToolhelp32Snapshot, Thread32First, Thread32Next, TH32CS_SNAPTHREAD,
+                THREADENTRY32,
+            },
+            winnt::THREAD_QUERY_LIMITED_INFORMATION,
+        },
+    };
+
+    // FILETIME is in 100 nanosecond intervals
+    let duration = |time: FILETIME| {
+        Duration::from_nanos(
+            (((time.dwHighDateTime as u64) << 32) | time.dwLowDateTime as u64).saturating_mul(100),
+        )
+    };
+
+    // SAFETY: the snapshot handle is closed before returning
+    let snapshot = unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPTHREAD, 0) };
+    if snapshot == INVALID_HANDLE_VALUE {
+        return Err(io::Error::last_os_error());
+    }
+
+    let process_id = unsafe { GetCurrentProcessId() };
+    let mut threads = Vec::new();
+    // SAFETY: THREADENTRY32 is plain data, and its size is set as Thread32First requires
+    let mut entry: THREADENTRY32 = unsafe { std::mem::zeroed() };
+    entry.dwSize = std::mem::size_of::<THREADENTRY32>() as u32;
+    let mut more = unsafe { Thread32First(snapshot, &mut entry) } != 0;
+    while more {
+        if entry.th32OwnerProcessID == process_id {
+            // a thread may exit between taking the snapshot and opening it
+            let thread =
+                unsafe { OpenThread(THREAD_QUERY_LIMITED_INFORMATION, 0, entry.th32ThreadID) };
+            if !thread.is_null() {
+                // SAFETY: FILETIME is plain data
+                let (mut creation, mut exit, mut kernel, mut user): (
+                    FILETIME,
+                    FILETIME,
+                    FILETIME,
+                    FILETIME,
+                ) = unsafe { std::mem::zeroed() };
+                if unsafe {
+                    GetThreadTimes(thread, &mut creation, &mut exit, &mut kernel, &mut user)
+                } != 0
+                {
+                    threads.push(ThreadCpuTime {
+                        thread_id: entry.th32ThreadID as u64,
+                        name: None,
+                        user: duration(user),
+                        system: duration(kernel),
+                    });
+                }
+                unsafe { CloseHandle(thread) };
+            }
+        }
+        more = unsafe { Thread32Next(snapshot, &mut entry) } != 0;
+    }
+
+    unsafe { CloseHandle(snapshot) };
+    Ok(threads)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+fn read_thread_cpu_times() -> io::Result<Vec<ThreadCpuTime>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "thread CPU sampling is not supported on this platform",
+    ))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::profiler::cpu::{parse_task_stat, CpuSnapshot, CpuSnapshots, ThreadCpuTime};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn cpu_snapshots_summarize_thread_cpu_time() {
+        let stat = "1234 (.NET (Finalizer)) S 1 1234 1234 0 -1 4194368 100 0 0 0 250 75 0 0 20 \
+            0 12 0 500 0 0";
+        let thread = parse_task_stat(1234, stat, 100).unwrap();
+        assert_eq!(
+            thread,
+            ThreadCpuTime {
+                thread_id: 1234,
+                name: Some(".NET (Finalizer)".into()),
+                user: Duration::from_millis(2500),
+                system: Duration::from_millis(750),
+            }
+        );
+        assert!(parse_task_stat(1234, "1234 (truncated) S 1 2", 100).is_none());
+
+        let cpu = |thread_id, user_ms, system_ms| ThreadCpuTime {
+            thread_id,
+            name: None,
+            user: Duration::from_millis(user_ms),
+            system: Duration::from_millis(system_ms),
+        };
+        let start = Instant::now();
+        let mut snapshots = CpuSnapshots::new(2);
+        assert_eq!(snapshots.summary(), "snapshots=0");
+        for (secs, threads) in [
+            (0, vec![cpu(1, 0, 0)]),
+            (1, vec![cpu(1, 100, 0)]),
+            (2, vec![cpu(1, 300, 100), cpu(2, 200, 0)]),
+        ] {
+            snapshots.push(CpuSnapshot {
+                timestamp: start + Duration::from_secs(secs),
+                threads,
+            });
+        }
+
+        // the first snapshot is removed
+        assert_eq!(snapshots.len(), 2);
+        assert_eq!(snapshots.latest().unwrap().threads.len(), 2);
+        assert_eq!(
+            snapshots.summary(),
+            "snapshots=2 window_ms=1000 threads=2 cpu_ms=600\n  thread=1 name=- user_ms=300 \
+            system_ms=100 usage=30.0%\n  thread=2 name=- user_ms=200 system_ms=0 usage=20.0%"
+        );
+    }
+}
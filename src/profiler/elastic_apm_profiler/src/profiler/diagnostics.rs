@@ -0,0 +1,153 @@
+// Licensed to Elasticsearch B.V under
+// one or more agreements.
+// Elasticsearch B.V licenses this file to you under the Apache 2.0 License.
+// See the LICENSE file in the project root for more information
+
+//! Writes a diagnostic report to help troubleshoot the profiler.
+//!
+//! The report contains the environment variables of interest, the effective configuration,
+//! the loaded integrations and information about the build of the profiler. A report is
+//! written at startup when `ELASTIC_APM_PROFILER_DIAGNOSTIC_REPORT_PATH` is set.
+
+use crate::profiler::{
+    build_info,
+    env::{self, ProfilerConfig},
+    error::ProfilerError,
+    types::Integration,
+};
+use serde::Serialize;
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+/// Writes a diagnostic report of the integrations loaded at startup to `path`, replacing
+/// any existing file
+pub fn write_diagnostic_report(
+    path: &Path,
+    integrations: &Result<Vec<Integration>, ProfilerError>,
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_report(
+        &mut writer,
+        &chrono::Utc::now().to_rfc3339(),
+        &env::get_env_vars(),
+        &env::PROFILER_CONFIG,
+        integrations,
+    )?;
+    writer.flush()
+}
+
+fn write_report(
+    w: &mut impl Write,
+    timestamp: &str,
+    env_vars: &str,
+    config: &ProfilerConfig,
+    integrations: &Result<Vec<Integration>, ProfilerError>,
+) -> io::Result<()> {
+    writeln!(w, "Elastic APM .NET profiler diagnostic report")?;
+    writeln!(w, "timestamp: {}", timestamp)?;
+    writeln!(w, "pid: {}", std::process::id())?;
+    writeln!(w, "process: {}", env::get_process_name())?;
+    writeln!(w, "os: {}", env::os_info())?;
+    writeln!(w)?;
+
+    writeln!(w, "== Build ==")?;
+    writeln!(w, "version: {}", env::profiler_version())?;
+    writeln!(w, "commit: {}", build_info::GIT_COMMIT)?;
+    writeln!(w, "built: {}", build_info::BUILD_TIMESTAMP)?;
+    writeln!(w, "target: {}", build_info::TARGET_TRIPLE)?;
+    writeln!(w)?;
+
+    writeln!(w, "== Environment variables ==")?;
+    writeln!(w, "{}", env_vars)?;
+    writeln!(w)?;
+
+    writeln!(w, "== Configuration ==")?;
+    write_json(w, config)?;
+    writeln!(w)?;
+
+    writeln!(w, "== Integrations ==")?;
+    match integrations {
+        Ok(integrations) => {
+            writeln!(w, "{} integration(s) loaded", integrations.len())?;
+            for integration in integrations {
+                writeln!(w, "  {}", integration)?;
+            }
+            write_json(w, integrations)?;
+        }
+        Err(e) => writeln!(w, "could not load integrations: {}", e)?,
+    }
+
+    Ok(())
+}
+
+fn write_json(w: &mut impl Write, value: &impl Serialize) -> io::Result<()> {
+    serde_json::to_writer_pretty(&mut *w, value)?;
+    writeln!(w)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::profiler::{
+        diagnostics::write_report,
+        env::ProfilerConfig,
+        error::{ProfilerError, ProfilerErrorKind},
+        types::Integration,
+    };
+    use std::error::Error;
+
+    #[test]
+    fn write_report_includes_all_sections() -> Result<(), Box<dyn Error>> {
+        let integrations: Vec<Integration> = serde_yaml::from_str(
+            r#"
+- name: AdoNet
+  method_replacements: []
+"#,
+        )?;
+        let mut report = Vec::new();
+        write_report(
+            &mut report,
+            "2024-01-02T03:04:05+00:00",
+            "  ELASTIC_APM_SERVICE_NAME=\"my-service\"",
+            &ProfilerConfig::with_defaults(),
+            &Ok(integrations),
+        )?;
+        let report = String::from_utf8(report)?;
+
+        assert!(report.contains("timestamp: 2024-01-02T03:04:05+00:00"));
+        assert!(report.contains(&format!("os: {}", std::env::consts::OS)));
+        assert!(report.contains("== Build ==\nversion: "));
+        assert!(report
+            .contains("== Environment variables ==\n  ELASTIC_APM_SERVICE_NAME=\"my-service\"\n"));
+        let config = report
+            .split("== Configuration ==\n")
+            .nth(1)
+            .and_then(|s| s.split("\n\n== Integrations ==").next())
+            .unwrap();
+        let config: serde_json::Value = serde_json::from_str(config)?;
+        assert_eq!(config["calltarget_enabled"], true);
+        assert!(report.contains("== Integrations ==\n1 integration(s) loaded\n  AdoNet\n"));
+        assert!(report.contains("\"name\": \"AdoNet\""));
+        Ok(())
+    }
+
+    #[test]
+    fn write_report_includes_integrations_error() -> Result<(), Box<dyn Error>> {
+        let mut report = Vec::new();
+        write_report(
+            &mut report,
+            "2024-01-02T03:04:05+00:00",
+            "",
+            &ProfilerConfig::with_defaults(),
+            &Err(ProfilerError::new(
+                ProfilerErrorKind::IntegrationsNotConfigured,
+            )),
+        )?;
+        let report = String::from_utf8(report)?;
+
+        assert!(report.contains("== Integrations ==\ncould not load integrations: "));
+        Ok(())
+    }
+}
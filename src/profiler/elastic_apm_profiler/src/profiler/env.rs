@@ -3,56 +3,194 @@
 // Elasticsearch B.V licenses this file to you under the Apache 2.0 License.
 // See the LICENSE file in the project root for more information
 
-use crate::{ffi::E_FAIL, profiler::types::Integration};
+//! Profiler configuration read from environment variables.
+//!
+//! # Process selection
+//!
+//! Whether the profiler profiles a process is determined by [process_is_allowed], which
+//! evaluates two comma separated lists of process names, compared case-insensitively:
+//!
+//! 1. `ELASTIC_APM_PROFILER_PROCESS_EXCLUDE` is checked first. A process named in this list
+//!    is never profiled, even if it's also named in `ELASTIC_APM_PROFILER_PROCESS_FILTER`.
+//! 2. `ELASTIC_APM_PROFILER_PROCESS_FILTER` is checked next. When set, only processes named
+//!    in this list are profiled. When not set, all processes not excluded are profiled.
+
+use crate::{
+    ffi::E_FAIL,
+    profiler::{
+        calltarget::DEFAULT_CALLTARGET_BATCH_SIZE,
+        error::{ProfilerError, ProfilerErrorKind},
+        logging::LOG_TARGET_ROOT,
+        managed::MANAGED_PROFILER_ASSEMBLY_LOADER,
+        timeout::with_timeout,
+        types::{Integration, INTEGRATION_SCHEMA_VERSION},
+    },
+};
 use com::sys::HRESULT;
+use crypto::{digest::Digest, sha2::Sha256};
 use log::LevelFilter;
-use log4rs::{
-    append::{
-        console::ConsoleAppender,
-        rolling_file::{
-            policy::compound::{
-                roll::fixed_window::FixedWindowRoller, trigger::size::SizeTrigger, CompoundPolicy,
-            },
-            RollingFileAppender,
-        },
-    },
-    config::{Appender, Root},
-    encode::pattern::PatternEncoder,
-    Config, Handle,
+use once_cell::sync::{Lazy, OnceCell};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::time::Duration;
+use std::{
+    collections::{BTreeSet, HashSet},
+    convert::TryFrom,
+    fmt::{self, Display, Formatter},
+    fs::{File, OpenOptions},
+    io::{BufReader, BufWriter, Read, Write},
+    iter::FromIterator,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, RwLock},
 };
-use once_cell::sync::Lazy;
-use std::time::SystemTime;
-use std::{collections::HashSet, fs::File, io::BufReader, path::PathBuf, str::FromStr};
+
+pub mod catalog;
+pub mod snapshot;
+
+pub use catalog::print_env_var_help;
 
 const APP_POOL_ID_ENV_VAR: &str = "APP_POOL_ID";
 const DOTNET_CLI_TELEMETRY_PROFILE_ENV_VAR: &str = "DOTNET_CLI_TELEMETRY_PROFILE";
+const DOTNET_STARTUP_HOOKS_ENV_VAR: &str = "DOTNET_STARTUP_HOOKS";
+const KUBERNETES_SERVICE_HOST_ENV_VAR: &str = "KUBERNETES_SERVICE_HOST";
 const COMPLUS_LOADEROPTIMIZATION: &str = "COMPLUS_LOADEROPTIMIZATION";
+const ELASTIC_APM_AGENT_CONFIG_FILE_ENV_VAR: &str = "ELASTIC_APM_AGENT_CONFIG_FILE";
+const ELASTIC_APM_ENVIRONMENT_ENV_VAR: &str = "ELASTIC_APM_ENVIRONMENT";
 
+const ELASTIC_APM_PROFILER_AGENT_CONFIG_PATH_ENV_VAR: &str =
+    "ELASTIC_APM_PROFILER_AGENT_CONFIG_PATH";
+const ELASTIC_APM_PROFILER_AGENT_ENVIRONMENT_ENV_VAR: &str =
+    "ELASTIC_APM_PROFILER_AGENT_ENVIRONMENT";
+const ELASTIC_APM_PROFILER_AGENT_SERVICE_NAME_ENV_VAR: &str =
+    "ELASTIC_APM_PROFILER_AGENT_SERVICE_NAME";
+const ELASTIC_APM_PROFILER_CALLTARGET_ALLOW_LIST_ENV_VAR: &str =
+    "ELASTIC_APM_PROFILER_CALLTARGET_ALLOW_LIST";
+const ELASTIC_APM_PROFILER_CALLTARGET_BATCH_SIZE_ENV_VAR: &str =
+    "ELASTIC_APM_PROFILER_CALLTARGET_BATCH_SIZE";
 const ELASTIC_APM_PROFILER_CALLTARGET_ENABLED_ENV_VAR: &str =
     "ELASTIC_APM_PROFILER_CALLTARGET_ENABLED";
+const ELASTIC_APM_PROFILER_CALLTARGET_EXCLUDE_ASSEMBLIES_ENV_VAR: &str =
+    "ELASTIC_APM_PROFILER_CALLTARGET_EXCLUDE_ASSEMBLIES";
+const ELASTIC_APM_PROFILER_CONFIG_DUMP_ENABLED_ENV_VAR: &str =
+    "ELASTIC_APM_PROFILER_CONFIG_DUMP_ENABLED";
+const ELASTIC_APM_PROFILER_DIAGNOSTIC_REPORT_PATH_ENV_VAR: &str =
+    "ELASTIC_APM_PROFILER_DIAGNOSTIC_REPORT_PATH";
+const ELASTIC_APM_PROFILER_DISABLE_ALL_ENV_VAR: &str = "ELASTIC_APM_PROFILER_DISABLE_ALL";
 const ELASTIC_APM_PROFILER_DISABLE_OPTIMIZATIONS_ENV_VAR: &str =
     "ELASTIC_APM_PROFILER_DISABLE_OPTIMIZATIONS";
+const ELASTIC_APM_PROFILER_EVENTLOG_SOURCE_ENV_VAR: &str = "ELASTIC_APM_PROFILER_EVENTLOG_SOURCE";
 const ELASTIC_APM_PROFILER_ENABLE_INLINING_ENV_VAR: &str = "ELASTIC_APM_PROFILER_ENABLE_INLINING";
+const ELASTIC_APM_PROFILER_EXCEPTION_FILTER_TYPES_ENV_VAR: &str =
+    "ELASTIC_APM_PROFILER_EXCEPTION_FILTER_TYPES";
+const ELASTIC_APM_PROFILER_EXCEPTION_SAMPLING_RATE_ENV_VAR: &str =
+    "ELASTIC_APM_PROFILER_EXCEPTION_SAMPLING_RATE";
 const ELASTIC_APM_PROFILER_EXCLUDE_INTEGRATIONS_ENV_VAR: &str =
     "ELASTIC_APM_PROFILER_EXCLUDE_INTEGRATIONS";
 const ELASTIC_APM_PROFILER_EXCLUDE_PROCESSES_ENV_VAR: &str =
     "ELASTIC_APM_PROFILER_EXCLUDE_PROCESSES";
 const ELASTIC_APM_PROFILER_EXCLUDE_SERVICE_NAMES_ENV_VAR: &str =
     "ELASTIC_APM_PROFILER_EXCLUDE_SERVICE_NAMES";
+const ELASTIC_APM_PROFILER_GC_PRESSURE_SAMPLING_ENV_VAR: &str =
+    "ELASTIC_APM_PROFILER_GC_PRESSURE_SAMPLING";
+const ELASTIC_APM_PROFILER_HEAP_PROFILING_ENABLED_ENV_VAR: &str =
+    "ELASTIC_APM_PROFILER_HEAP_PROFILING_ENABLED";
+const ELASTIC_APM_PROFILER_HEAP_PROFILING_INTERVAL_SECS_ENV_VAR: &str =
+    "ELASTIC_APM_PROFILER_HEAP_PROFILING_INTERVAL_SECS";
+const ELASTIC_APM_PROFILER_HEAP_PROFILING_RATE_ENV_VAR: &str =
+    "ELASTIC_APM_PROFILER_HEAP_PROFILING_RATE";
 const ELASTIC_APM_PROFILER_HOME_ENV_VAR: &str = "ELASTIC_APM_PROFILER_HOME";
+const ELASTIC_APM_PROFILER_IL_DUMP_FORMAT_ENV_VAR: &str = "ELASTIC_APM_PROFILER_IL_DUMP_FORMAT";
+const ELASTIC_APM_PROFILER_INTEGRATION_TIMEOUT_MS_ENV_VAR: &str =
+    "ELASTIC_APM_PROFILER_INTEGRATION_TIMEOUT_MS";
 const ELASTIC_APM_PROFILER_INTEGRATIONS_ENV_VAR: &str = "ELASTIC_APM_PROFILER_INTEGRATIONS";
+const ELASTIC_APM_PROFILER_INTEGRATIONS_CACHE_PATH_ENV_VAR: &str =
+    "ELASTIC_APM_PROFILER_INTEGRATIONS_CACHE_PATH";
+const ELASTIC_APM_PROFILER_INTEGRATIONS_DOWNLOAD_ETAG_CACHE_ENV_VAR: &str =
+    "ELASTIC_APM_PROFILER_INTEGRATIONS_DOWNLOAD_ETAG_CACHE";
+const ELASTIC_APM_PROFILER_INTEGRATIONS_DOWNLOAD_TIMEOUT_SECS_ENV_VAR: &str =
+    "ELASTIC_APM_PROFILER_INTEGRATIONS_DOWNLOAD_TIMEOUT_SECS";
+const ELASTIC_APM_PROFILER_INTEGRATIONS_DOWNLOAD_URL_ENV_VAR: &str =
+    "ELASTIC_APM_PROFILER_INTEGRATIONS_DOWNLOAD_URL";
+const ELASTIC_APM_PROFILER_INTEGRATIONS_EXPECTED_SHA256_ENV_VAR: &str =
+    "ELASTIC_APM_PROFILER_INTEGRATIONS_EXPECTED_SHA256";
+const ELASTIC_APM_PROFILER_INTEGRATIONS_GLOB_ENV_VAR: &str =
+    "ELASTIC_APM_PROFILER_INTEGRATIONS_GLOB";
+const ELASTIC_APM_PROFILER_INTEGRATIONS_INLINE_ENV_VAR: &str =
+    "ELASTIC_APM_PROFILER_INTEGRATIONS_INLINE";
+const ELASTIC_APM_PROFILER_INTEGRATIONS_SCHEMA_VALIDATE_ENV_VAR: &str =
+    "ELASTIC_APM_PROFILER_INTEGRATIONS_SCHEMA_VALIDATE";
+const ELASTIC_APM_PROFILER_INTEGRATIONS_RELOAD_INTERVAL_SECS_ENV_VAR: &str =
+    "ELASTIC_APM_PROFILER_INTEGRATIONS_RELOAD_INTERVAL_SECS";
+const ELASTIC_APM_PROFILER_MANAGED_LOADER_PATH_ENV_VAR: &str =
+    "ELASTIC_APM_PROFILER_MANAGED_LOADER_PATH";
+const ELASTIC_APM_PROFILER_MAX_CALL_TARGETS_ENV_VAR: &str = "ELASTIC_APM_PROFILER_MAX_CALL_TARGETS";
+const ELASTIC_APM_PROFILER_METRICS_ENABLED_ENV_VAR: &str = "ELASTIC_APM_PROFILER_METRICS_ENABLED";
+const ELASTIC_APM_PROFILER_METRICS_INTERVAL_SECS_ENV_VAR: &str =
+    "ELASTIC_APM_PROFILER_METRICS_INTERVAL_SECS";
+const ELASTIC_APM_PROFILER_INTEGRATIONS_WATCH_ENV_VAR: &str =
+    "ELASTIC_APM_PROFILER_INTEGRATIONS_WATCH";
+const ELASTIC_APM_PROFILER_SPAN_STACK_TRACE_MIN_DURATION_MS_ENV_VAR: &str =
+    "ELASTIC_APM_PROFILER_SPAN_STACK_TRACE_MIN_DURATION_MS";
+const ELASTIC_APM_PROFILER_STARTUP_HOOKS_ENV_VAR: &str = "ELASTIC_APM_PROFILER_STARTUP_HOOKS";
+const ELASTIC_APM_PROFILER_TEMP_DIR_ENV_VAR: &str = "ELASTIC_APM_PROFILER_TEMP_DIR";
+const ELASTIC_APM_PROFILER_THREAD_CPU_SAMPLING_INTERVAL_MS_ENV_VAR: &str =
+    "ELASTIC_APM_PROFILER_THREAD_CPU_SAMPLING_INTERVAL_MS";
+const ELASTIC_APM_PROFILER_PROCESS_EXCLUDE_ENV_VAR: &str = "ELASTIC_APM_PROFILER_PROCESS_EXCLUDE";
+const ELASTIC_APM_PROFILER_PROCESS_FILTER_ENV_VAR: &str = "ELASTIC_APM_PROFILER_PROCESS_FILTER";
+const ELASTIC_APM_PROFILER_PRINT_ENV_ENV_VAR: &str = "ELASTIC_APM_PROFILER_PRINT_ENV";
+const ELASTIC_APM_PROFILER_PRINT_HELP_ENV_VAR: &str = "ELASTIC_APM_PROFILER_PRINT_HELP";
+const ELASTIC_APM_PROFILER_LOG_ASYNC_BUFFER_SIZE_ENV_VAR: &str =
+    "ELASTIC_APM_PROFILER_LOG_ASYNC_BUFFER_SIZE";
+const ELASTIC_APM_PROFILER_LOG_CONTEXT_FIELDS_ENV_VAR: &str =
+    "ELASTIC_APM_PROFILER_LOG_CONTEXT_FIELDS";
 const ELASTIC_APM_PROFILER_LOG_DIR_ENV_VAR: &str = "ELASTIC_APM_PROFILER_LOG_DIR";
 const ELASTIC_APM_PROFILER_LOG_ENV_VAR: &str = "ELASTIC_APM_PROFILER_LOG";
+const ELASTIC_APM_PROFILER_LOG_FLUSH_INTERVAL_MS_ENV_VAR: &str =
+    "ELASTIC_APM_PROFILER_LOG_FLUSH_INTERVAL_MS";
+const ELASTIC_APM_PROFILER_LOG_LEVEL_OVERRIDES_ENV_VAR: &str =
+    "ELASTIC_APM_PROFILER_LOG_LEVEL_OVERRIDES";
+const ELASTIC_APM_PROFILER_LOG_FORMAT_ENV_VAR: &str = "ELASTIC_APM_PROFILER_LOG_FORMAT";
+const ELASTIC_APM_PROFILER_LOG_MAX_SIZE_MB_ENV_VAR: &str = "ELASTIC_APM_PROFILER_LOG_MAX_SIZE_MB";
+const ELASTIC_APM_PROFILER_LOG_MAX_FILES_ENV_VAR: &str = "ELASTIC_APM_PROFILER_LOG_MAX_FILES";
+const ELASTIC_APM_PROFILER_LOG_PREFIX_ENV_VAR: &str = "ELASTIC_APM_PROFILER_LOG_PREFIX";
+const ELASTIC_APM_PROFILER_LOG_REDACT_ENV_VAR: &str = "ELASTIC_APM_PROFILER_LOG_REDACT";
+const ELASTIC_APM_PROFILER_LOG_ROTATION_ENV_VAR: &str = "ELASTIC_APM_PROFILER_LOG_ROTATION";
+const ELASTIC_APM_PROFILER_LOG_SHUTDOWN_TIMEOUT_MS_ENV_VAR: &str =
+    "ELASTIC_APM_PROFILER_LOG_SHUTDOWN_TIMEOUT_MS";
 const ELASTIC_APM_PROFILER_LOG_TARGETS_ENV_VAR: &str = "ELASTIC_APM_PROFILER_LOG_TARGETS";
 const ELASTIC_APM_PROFILER_LOG_IL_ENV_VAR: &str = "ELASTIC_APM_PROFILER_LOG_IL";
+const ELASTIC_APM_PROFILER_LOG_IL_DIR_ENV_VAR: &str = "ELASTIC_APM_PROFILER_LOG_IL_DIR";
 
 const ELASTIC_APM_SERVICE_NAME_ENV_VAR: &str = "ELASTIC_APM_SERVICE_NAME";
+const ELASTIC_APM_SPAN_STACK_TRACE_MIN_DURATION_ENV_VAR: &str =
+    "ELASTIC_APM_SPAN_STACK_TRACE_MIN_DURATION";
 
-pub static ELASTIC_APM_PROFILER_LOG_IL: Lazy<bool> =
-    Lazy::new(|| read_bool_env_var(ELASTIC_APM_PROFILER_LOG_IL_ENV_VAR, false));
+const DEFAULT_EVENTLOG_SOURCE: &str = "ElasticAPMProfiler";
+const DEFAULT_EXCEPTION_SAMPLING_RATE: f64 = 1.0;
+const DEFAULT_HEAP_PROFILING_INTERVAL_SECS: u64 = 60;
+const DEFAULT_HEAP_PROFILING_RATE: u64 = 1000;
+const DEFAULT_INTEGRATION_TIMEOUT_MS: u64 = 5000;
+const DEFAULT_LOG_SHUTDOWN_TIMEOUT_MS: u64 = 2000;
+const DEFAULT_INTEGRATIONS_DOWNLOAD_TIMEOUT_SECS: u64 = 10;
+const DEFAULT_LOG_ASYNC_BUFFER_SIZE: usize = 1024;
+const DEFAULT_LOG_FLUSH_INTERVAL_MS: u64 = 500;
+/// The minimum interval at which the async log buffer is flushed, to prevent thrashing
+const MIN_LOG_FLUSH_INTERVAL_MS: u64 = 10;
+const DEFAULT_METRICS_INTERVAL_SECS: u64 = 60;
+/// The maximum number of characters in a log prefix
+const MAX_LOG_PREFIX_LEN: usize = 64;
+
+/// The profiler configuration, read from environment variables on first access
+pub static PROFILER_CONFIG: Lazy<ProfilerConfig> = Lazy::new(ProfilerConfig::from_env);
+
+pub static ELASTIC_APM_PROFILER_LOG_IL: Lazy<bool> = Lazy::new(|| PROFILER_CONFIG.log_il);
 
 pub static ELASTIC_APM_PROFILER_CALLTARGET_ENABLED: Lazy<bool> =
-    Lazy::new(|| read_bool_env_var(ELASTIC_APM_PROFILER_CALLTARGET_ENABLED_ENV_VAR, true));
+    Lazy::new(|| PROFILER_CONFIG.calltarget_enabled);
+
+/// Whether the profiler is disabled by the [ELASTIC_APM_PROFILER_DISABLE_ALL_ENV_VAR]
+/// kill switch
+pub static ELASTIC_APM_PROFILER_DISABLE_ALL: Lazy<bool> = Lazy::new(|| PROFILER_CONFIG.disable_all);
 
 pub static IS_AZURE_APP_SERVICE: Lazy<bool> = Lazy::new(|| {
     std::env::var("WEBSITE_SITE_NAME").is_ok()
@@ -94,6 +232,56 @@ pub fn check_if_running_in_azure_app_service() -> Result<(), HRESULT> {
     Ok(())
 }
 
+/// Markers in `/proc/1/cgroup` that indicate the process is running in a container
+const CONTAINER_CGROUP_MARKERS: [&str; 3] = ["docker", "kubepods", "containerd"];
+
+static IS_RUNNING_IN_CONTAINER: Lazy<bool> = Lazy::new(|| {
+    #[cfg(target_os = "linux")]
+    let cgroup = std::fs::read_to_string("/proc/1/cgroup").ok();
+    #[cfg(not(target_os = "linux"))]
+    let cgroup: Option<String> = None;
+    detect_container(
+        cgroup.as_deref(),
+        Path::new("/.dockerenv").exists(),
+        |key| std::env::var(key).ok(),
+    )
+});
+
+/// Whether the profiler is running in a container, detected from the cgroup of the
+/// init process on Linux, the presence of `/.dockerenv` on other platforms, or the
+/// `KUBERNETES_SERVICE_HOST` environment variable
+pub fn is_running_in_container() -> bool {
+    *IS_RUNNING_IN_CONTAINER
+}
+
+fn detect_container(
+    cgroup: Option<&str>,
+    dockerenv_exists: bool,
+    get_var: impl Fn(&str) -> Option<String>,
+) -> bool {
+    let in_container = match cgroup {
+        Some(cgroup) => CONTAINER_CGROUP_MARKERS
+            .iter()
+            .any(|marker| cgroup.contains(marker)),
+        None => dockerenv_exists,
+    };
+    in_container || get_var(KUBERNETES_SERVICE_HOST_ENV_VAR).is_some()
+}
+
+/// Prints the environment variables of interest to stderr when
+/// [ELASTIC_APM_PROFILER_PRINT_ENV_ENV_VAR] is `true`. Called before logging is initialized,
+/// for environments in which log files are inaccessible
+pub fn print_env_vars() {
+    if PROFILER_CONFIG.print_env {
+        // nowhere to report a failure to write to stderr
+        let _ = write_env_vars(&mut std::io::stderr());
+    }
+}
+
+fn write_env_vars(w: &mut impl Write) -> std::io::Result<()> {
+    writeln!(w, "Environment variables\n{}", get_env_vars())
+}
+
 /// Gets the environment variables of interest
 pub fn get_env_vars() -> String {
     std::env::vars()
@@ -102,11 +290,12 @@ pub fn get_env_vars() -> String {
             if key.starts_with("ELASTIC_")
                 || key.starts_with("CORECLR_")
                 || key.starts_with("COR_")
+                || key.starts_with("DOTNET_")
+                || key.starts_with("ASPNETCORE_")
                 || key == APP_POOL_ID_ENV_VAR
-                || key == DOTNET_CLI_TELEMETRY_PROFILE_ENV_VAR
                 || key == COMPLUS_LOADEROPTIMIZATION
             {
-                let value = if key.contains("SECRET") || key.contains("API_KEY") {
+                let value = if is_secret_env_var(&key) {
                     "[REDACTED]"
                 } else {
                     &v
@@ -120,6 +309,12 @@ pub fn get_env_vars() -> String {
         .join("\n")
 }
 
+/// Whether the value of the environment variable is a secret that should not be logged
+pub(crate) fn is_secret_env_var(key: &str) -> bool {
+    let key = key.to_uppercase();
+    key.contains("SECRET") || key.contains("API_KEY")
+}
+
 fn read_semicolon_separated_env_var(key: &str) -> Option<Vec<String>> {
     match std::env::var(key) {
         Ok(val) => Some(val.split(';').map(|s| s.to_string()).collect()),
@@ -127,16 +322,571 @@ fn read_semicolon_separated_env_var(key: &str) -> Option<Vec<String>> {
     }
 }
 
+/// Reads a comma separated list, trimming whitespace from and ignoring empty values.
+/// Returns [None] if the environment variable is not set, or contains no values
+fn read_comma_separated_env_var(key: &str) -> Option<Vec<String>> {
+    let values: Vec<String> = std::env::var(key)
+        .ok()?
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect();
+
+    if values.is_empty() {
+        None
+    } else {
+        Some(values)
+    }
+}
+
+/// The profiler configuration, derived from environment variables
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProfilerConfig {
+    /// The path of the Elastic APM agent config file, passed to the managed loader
+    pub agent_config_path: Option<PathBuf>,
+    /// The deployment environment passed to the managed loader and the agent, unless the
+    /// agent environment is already set
+    pub agent_environment: Option<String>,
+    /// The service name passed to the managed loader and the agent, unless the agent
+    /// service name is already set
+    pub agent_service_name: Option<String>,
+    /// The lowercased simple assembly names, type names and method names of the only methods
+    /// instrumented with CallTarget. When empty, all methods are instrumented
+    pub calltarget_allow_list: HashSet<(String, String, String)>,
+    /// The maximum number of CallTarget methods requested for ReJIT per JIT compilation
+    pub calltarget_batch_size: usize,
+    /// Whether CallTarget instrumentation is enabled
+    pub calltarget_enabled: bool,
+    /// Lowercased simple names of assemblies that are not instrumented with CallTarget
+    pub calltarget_exclude_assemblies: HashSet<String>,
+    /// Whether the configuration can be dumped to the log directory on demand, after startup
+    pub config_dump_enabled: bool,
+    /// The path of a diagnostic report to write at startup
+    pub diagnostic_report_path: Option<PathBuf>,
+    /// Whether the profiler is disabled for all processes, without being uninstalled
+    pub disable_all: bool,
+    /// Whether JIT optimizations are disabled
+    pub disable_optimizations: bool,
+    /// Whether JIT inlining is enabled. Defaults to [ProfilerConfig::calltarget_enabled]
+    pub enable_inlining: bool,
+    /// The source name to use when logging to the Windows Event Log
+    pub eventlog_source: String,
+    /// Prefixes of the fully qualified names of the only exception types captured. When
+    /// [None], exceptions of all types are captured
+    pub exception_filter_types: Option<Vec<String>>,
+    /// The fraction of exceptions captured, between 0.0 and 1.0
+    pub exception_sampling_rate: f64,
+    /// Names of integrations to exclude
+    pub exclude_integrations: Option<Vec<String>>,
+    /// Names of processes to exclude
+    pub exclude_processes: Option<Vec<String>>,
+    /// Names of services to exclude
+    pub exclude_service_names: Option<Vec<String>>,
+    /// Whether to log the sizes of the GC heap generations after each GC
+    pub gc_pressure_sampling: bool,
+    /// Whether to sample object allocations
+    pub heap_profiling_enabled: bool,
+    /// The interval in seconds at which to log the sampled object allocations
+    pub heap_profiling_interval_secs: u64,
+    /// 1 in every `heap_profiling_rate` object allocations is sampled
+    pub heap_profiling_rate: u64,
+    /// The home directory of the profiler
+    pub home: Option<PathBuf>,
+    /// The format in which IL is dumped when [ProfilerConfig::log_il] is `true`
+    pub il_dump_format: IlDumpFormat,
+    /// The timeout in milliseconds for loading integrations. 0 disables the timeout
+    pub integration_timeout_ms: u64,
+    /// The path to the file in which parsed integrations are cached
+    pub integrations_cache_path: Option<PathBuf>,
+    /// Whether to download the integrations file only when it has changed, using the ETag
+    /// of the previous download
+    pub integrations_download_etag_cache: bool,
+    /// The timeout in seconds for downloading the integrations file
+    pub integrations_download_timeout_secs: u64,
+    /// The HTTP URL from which to download the integrations file
+    pub integrations_download_url: Option<String>,
+    /// The lowercase hex encoded SHA-256 hash that integrations files must have
+    pub integrations_expected_sha256: Option<String>,
+    /// A glob pattern matching the integrations files
+    pub integrations_glob: Option<String>,
+    /// The path to the integrations file
+    pub integrations_path: Option<String>,
+    /// The integrations yml, supplied inline
+    pub integrations_inline: Option<String>,
+    /// The interval in seconds at which to reload integrations. 0 disables reloading
+    pub integrations_reload_interval_secs: u64,
+    /// Whether integrations with unknown fields fail to load
+    pub integrations_schema_validate: bool,
+    /// Whether to reload integrations when the integrations files change
+    pub integrations_watch: bool,
+    /// The maximum number of log records buffered for writing to log files.
+    /// 0 writes log records to log files synchronously
+    pub log_async_buffer_size: usize,
+    /// The static key-value pairs attached to every log record
+    pub log_context_fields: Vec<(String, String)>,
+    /// The directory in which to write log files
+    pub log_dir: PathBuf,
+    /// The interval in milliseconds at which log records buffered for writing to log files
+    /// are flushed
+    pub log_flush_interval_ms: u64,
+    /// The format in which to write log records
+    pub log_format: LogFormat,
+    /// Whether to log IL
+    pub log_il: bool,
+    /// The directory in which to write IL log files. When [None], IL is written to the log
+    pub log_il_dir: Option<PathBuf>,
+    /// The log level
+    pub log_level: LevelFilter,
+    /// Log levels for log targets, overriding [ProfilerConfig::log_level] for each target
+    pub log_level_overrides: Vec<(String, LevelFilter)>,
+    /// The maximum number of rolled log files
+    pub log_max_files: u32,
+    /// The strategy with which log files are rotated
+    pub log_rotation: LogRotation,
+    /// The maximum size in bytes of a log file before it's rolled
+    pub log_max_size: u64,
+    /// A string prepended to every text log record
+    pub log_prefix: Option<String>,
+    /// Whether to redact the home directory and path segments containing the username
+    /// from log records
+    pub log_redact: bool,
+    /// The time in milliseconds to wait for buffered log records to be written when the
+    /// process exits
+    pub log_shutdown_timeout_ms: u64,
+    /// The targets to log to
+    pub log_targets: LogTargetSet,
+    /// The path to the managed loader assembly, used instead of the embedded assembly
+    pub managed_loader_path: Option<PathBuf>,
+    /// The maximum number of methods instrumented by enabled integrations. When [None], the
+    /// number is unlimited
+    pub max_call_targets: Option<usize>,
+    /// Whether to periodically log a summary of the profiler metrics
+    pub metrics_enabled: bool,
+    /// The interval in seconds at which to log a summary of the profiler metrics
+    pub metrics_interval_secs: u64,
+    /// Whether to print the environment variables of interest to stderr at startup,
+    /// regardless of the log level
+    pub print_env: bool,
+    /// Whether to print the table of recognised environment variables to stdout at startup
+    pub print_help: bool,
+    /// Names of processes for which the profiler is disabled
+    pub process_exclude: Option<Vec<String>>,
+    /// Names of processes for which the profiler is enabled. When [None], the profiler is
+    /// enabled for all processes
+    pub process_filter: Option<Vec<String>>,
+    /// The APM service name
+    pub service_name: Option<String>,
+    /// The minimum duration in milliseconds of spans for which the agent captures a stack
+    /// trace. When [None], the agent captures no stack traces for spans
+    pub span_stack_trace_min_duration_ms: Option<u64>,
+    /// The directory in which temporary files are created. When [None], the system temp
+    /// directory is used
+    pub temp_dir: Option<PathBuf>,
+    /// The interval in milliseconds at which to sample the CPU time of each thread.
+    /// 0 disables sampling
+    pub thread_cpu_sampling_interval_ms: u64,
+}
+
+impl ProfilerConfig {
+    /// Creates a new instance of [ProfilerConfig] by reading environment variables
+    pub fn from_env() -> Self {
+        let calltarget_enabled =
+            read_bool_env_var(ELASTIC_APM_PROFILER_CALLTARGET_ENABLED_ENV_VAR, true);
+        Self {
+            agent_config_path: std::env::var(ELASTIC_APM_PROFILER_AGENT_CONFIG_PATH_ENV_VAR)
+                .ok()
+                .filter(|s| !s.trim().is_empty())
+                .map(PathBuf::from),
+            agent_environment: std::env::var(ELASTIC_APM_PROFILER_AGENT_ENVIRONMENT_ENV_VAR)
+                .ok()
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty()),
+            agent_service_name: std::env::var(ELASTIC_APM_PROFILER_AGENT_SERVICE_NAME_ENV_VAR)
+                .ok()
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty()),
+            calltarget_allow_list: std::env::var(
+                ELASTIC_APM_PROFILER_CALLTARGET_ALLOW_LIST_ENV_VAR,
+            )
+            .map(|value| parse_calltarget_allow_list(&value))
+            .unwrap_or_default(),
+            calltarget_batch_size: clamp_to_min(
+                ELASTIC_APM_PROFILER_CALLTARGET_BATCH_SIZE_ENV_VAR,
+                read_u64_env_var(
+                    ELASTIC_APM_PROFILER_CALLTARGET_BATCH_SIZE_ENV_VAR,
+                    DEFAULT_CALLTARGET_BATCH_SIZE as u64,
+                ),
+                1,
+            ) as usize,
+            calltarget_enabled,
+            calltarget_exclude_assemblies: std::env::var(
+                ELASTIC_APM_PROFILER_CALLTARGET_EXCLUDE_ASSEMBLIES_ENV_VAR,
+            )
+            .map(|value| parse_assembly_names(&value))
+            .unwrap_or_default(),
+            config_dump_enabled: read_bool_env_var(
+                ELASTIC_APM_PROFILER_CONFIG_DUMP_ENABLED_ENV_VAR,
+                false,
+            ),
+            diagnostic_report_path: std::env::var(
+                ELASTIC_APM_PROFILER_DIAGNOSTIC_REPORT_PATH_ENV_VAR,
+            )
+            .ok()
+            .filter(|s| !s.trim().is_empty())
+            .map(PathBuf::from),
+            disable_all: read_bool_env_var(ELASTIC_APM_PROFILER_DISABLE_ALL_ENV_VAR, false),
+            disable_optimizations: read_bool_env_var(
+                ELASTIC_APM_PROFILER_DISABLE_OPTIMIZATIONS_ENV_VAR,
+                false,
+            ),
+            enable_inlining: read_bool_env_var(
+                ELASTIC_APM_PROFILER_ENABLE_INLINING_ENV_VAR,
+                calltarget_enabled,
+            ),
+            eventlog_source: std::env::var(ELASTIC_APM_PROFILER_EVENTLOG_SOURCE_ENV_VAR)
+                .ok()
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .unwrap_or_else(|| DEFAULT_EVENTLOG_SOURCE.into()),
+            exception_filter_types: read_semicolon_separated_env_var(
+                ELASTIC_APM_PROFILER_EXCEPTION_FILTER_TYPES_ENV_VAR,
+            ),
+            exception_sampling_rate: read_f64_env_var(
+                ELASTIC_APM_PROFILER_EXCEPTION_SAMPLING_RATE_ENV_VAR,
+                DEFAULT_EXCEPTION_SAMPLING_RATE,
+            ),
+            exclude_integrations: read_semicolon_separated_env_var(
+                ELASTIC_APM_PROFILER_EXCLUDE_INTEGRATIONS_ENV_VAR,
+            ),
+            exclude_processes: read_semicolon_separated_env_var(
+                ELASTIC_APM_PROFILER_EXCLUDE_PROCESSES_ENV_VAR,
+            ),
+            exclude_service_names: read_semicolon_separated_env_var(
+                ELASTIC_APM_PROFILER_EXCLUDE_SERVICE_NAMES_ENV_VAR,
+            ),
+            gc_pressure_sampling: read_bool_env_var(
+                ELASTIC_APM_PROFILER_GC_PRESSURE_SAMPLING_ENV_VAR,
+                false,
+            ),
+            heap_profiling_enabled: read_bool_env_var(
+                ELASTIC_APM_PROFILER_HEAP_PROFILING_ENABLED_ENV_VAR,
+                false,
+            ),
+            heap_profiling_interval_secs: clamp_to_min(
+                ELASTIC_APM_PROFILER_HEAP_PROFILING_INTERVAL_SECS_ENV_VAR,
+                read_u64_env_var(
+                    ELASTIC_APM_PROFILER_HEAP_PROFILING_INTERVAL_SECS_ENV_VAR,
+                    DEFAULT_HEAP_PROFILING_INTERVAL_SECS,
+                ),
+                1,
+            ),
+            heap_profiling_rate: clamp_to_min(
+                ELASTIC_APM_PROFILER_HEAP_PROFILING_RATE_ENV_VAR,
+                read_u64_env_var(
+                    ELASTIC_APM_PROFILER_HEAP_PROFILING_RATE_ENV_VAR,
+                    DEFAULT_HEAP_PROFILING_RATE,
+                ),
+                1,
+            ),
+            home: read_path_env_var(ELASTIC_APM_PROFILER_HOME_ENV_VAR),
+            il_dump_format: std::env::var(ELASTIC_APM_PROFILER_IL_DUMP_FORMAT_ENV_VAR)
+                .ok()
+                .and_then(|value| IlDumpFormat::parse(&value))
+                .unwrap_or(IlDumpFormat::Hex),
+            integration_timeout_ms: read_u64_env_var(
+                ELASTIC_APM_PROFILER_INTEGRATION_TIMEOUT_MS_ENV_VAR,
+                DEFAULT_INTEGRATION_TIMEOUT_MS,
+            ),
+            integrations_cache_path: std::env::var(
+                ELASTIC_APM_PROFILER_INTEGRATIONS_CACHE_PATH_ENV_VAR,
+            )
+            .ok()
+            .filter(|s| !s.trim().is_empty())
+            .map(PathBuf::from),
+            integrations_download_etag_cache: read_bool_env_var(
+                ELASTIC_APM_PROFILER_INTEGRATIONS_DOWNLOAD_ETAG_CACHE_ENV_VAR,
+                false,
+            ),
+            integrations_download_timeout_secs: clamp_to_min(
+                ELASTIC_APM_PROFILER_INTEGRATIONS_DOWNLOAD_TIMEOUT_SECS_ENV_VAR,
+                read_u64_env_var(
+                    ELASTIC_APM_PROFILER_INTEGRATIONS_DOWNLOAD_TIMEOUT_SECS_ENV_VAR,
+                    DEFAULT_INTEGRATIONS_DOWNLOAD_TIMEOUT_SECS,
+                ),
+                1,
+            ),
+            integrations_download_url: std::env::var(
+                ELASTIC_APM_PROFILER_INTEGRATIONS_DOWNLOAD_URL_ENV_VAR,
+            )
+            .ok()
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty()),
+            integrations_expected_sha256: std::env::var(
+                ELASTIC_APM_PROFILER_INTEGRATIONS_EXPECTED_SHA256_ENV_VAR,
+            )
+            .ok()
+            .map(|s| s.trim().to_lowercase())
+            .filter(|s| !s.is_empty()),
+            integrations_glob: std::env::var(ELASTIC_APM_PROFILER_INTEGRATIONS_GLOB_ENV_VAR)
+                .ok()
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty()),
+            integrations_path: std::env::var(ELASTIC_APM_PROFILER_INTEGRATIONS_ENV_VAR).ok(),
+            integrations_inline: std::env::var(ELASTIC_APM_PROFILER_INTEGRATIONS_INLINE_ENV_VAR)
+                .ok(),
+            integrations_reload_interval_secs: read_u64_env_var(
+                ELASTIC_APM_PROFILER_INTEGRATIONS_RELOAD_INTERVAL_SECS_ENV_VAR,
+                0,
+            ),
+            integrations_schema_validate: read_bool_env_var(
+                ELASTIC_APM_PROFILER_INTEGRATIONS_SCHEMA_VALIDATE_ENV_VAR,
+                false,
+            ),
+            integrations_watch: read_bool_env_var(
+                ELASTIC_APM_PROFILER_INTEGRATIONS_WATCH_ENV_VAR,
+                false,
+            ),
+            log_async_buffer_size: read_usize_env_var(
+                ELASTIC_APM_PROFILER_LOG_ASYNC_BUFFER_SIZE_ENV_VAR,
+                DEFAULT_LOG_ASYNC_BUFFER_SIZE,
+            ),
+            log_context_fields: std::env::var(ELASTIC_APM_PROFILER_LOG_CONTEXT_FIELDS_ENV_VAR)
+                .map(|value| parse_log_context_fields(&value))
+                .unwrap_or_default(),
+            log_dir: get_log_dir(),
+            log_flush_interval_ms: clamp_to_min(
+                ELASTIC_APM_PROFILER_LOG_FLUSH_INTERVAL_MS_ENV_VAR,
+                read_u64_env_var(
+                    ELASTIC_APM_PROFILER_LOG_FLUSH_INTERVAL_MS_ENV_VAR,
+                    DEFAULT_LOG_FLUSH_INTERVAL_MS,
+                ),
+                MIN_LOG_FLUSH_INTERVAL_MS,
+            ),
+            log_format: read_log_format_from_env_var(),
+            log_il: read_bool_env_var(ELASTIC_APM_PROFILER_LOG_IL_ENV_VAR, false),
+            log_il_dir: read_path_env_var(ELASTIC_APM_PROFILER_LOG_IL_DIR_ENV_VAR),
+            log_level: read_log_level_from_env_var(LevelFilter::Warn),
+            log_level_overrides: std::env::var(ELASTIC_APM_PROFILER_LOG_LEVEL_OVERRIDES_ENV_VAR)
+                .map(|value| parse_log_level_overrides(&value))
+                .unwrap_or_default(),
+            log_max_files: read_log_max_files_from_env_var(),
+            log_max_size: read_log_max_size_from_env_var(),
+            log_prefix: std::env::var(ELASTIC_APM_PROFILER_LOG_PREFIX_ENV_VAR)
+                .ok()
+                .and_then(|value| sanitize_log_prefix(&value)),
+            log_redact: read_bool_env_var(ELASTIC_APM_PROFILER_LOG_REDACT_ENV_VAR, false),
+            log_rotation: read_log_rotation_from_env_var(),
+            log_shutdown_timeout_ms: read_u64_env_var(
+                ELASTIC_APM_PROFILER_LOG_SHUTDOWN_TIMEOUT_MS_ENV_VAR,
+                DEFAULT_LOG_SHUTDOWN_TIMEOUT_MS,
+            ),
+            log_targets: read_log_targets_from_env_var(),
+            managed_loader_path: read_path_env_var(
+                ELASTIC_APM_PROFILER_MANAGED_LOADER_PATH_ENV_VAR,
+            ),
+            max_call_targets: read_optional_parsed_env_var(
+                ELASTIC_APM_PROFILER_MAX_CALL_TARGETS_ENV_VAR,
+            ),
+            metrics_enabled: read_bool_env_var(ELASTIC_APM_PROFILER_METRICS_ENABLED_ENV_VAR, false),
+            metrics_interval_secs: clamp_to_min(
+                ELASTIC_APM_PROFILER_METRICS_INTERVAL_SECS_ENV_VAR,
+                read_u64_env_var(
+                    ELASTIC_APM_PROFILER_METRICS_INTERVAL_SECS_ENV_VAR,
+                    DEFAULT_METRICS_INTERVAL_SECS,
+                ),
+                1,
+            ),
+            print_env: read_bool_env_var(ELASTIC_APM_PROFILER_PRINT_ENV_ENV_VAR, false),
+            print_help: read_bool_env_var(ELASTIC_APM_PROFILER_PRINT_HELP_ENV_VAR, false),
+            process_exclude: read_comma_separated_env_var(
+                ELASTIC_APM_PROFILER_PROCESS_EXCLUDE_ENV_VAR,
+            ),
+            process_filter: read_comma_separated_env_var(
+                ELASTIC_APM_PROFILER_PROCESS_FILTER_ENV_VAR,
+            ),
+            service_name: std::env::var(ELASTIC_APM_SERVICE_NAME_ENV_VAR).ok(),
+            span_stack_trace_min_duration_ms: read_optional_parsed_env_var(
+                ELASTIC_APM_PROFILER_SPAN_STACK_TRACE_MIN_DURATION_MS_ENV_VAR,
+            ),
+            temp_dir: read_path_env_var(ELASTIC_APM_PROFILER_TEMP_DIR_ENV_VAR),
+            thread_cpu_sampling_interval_ms: read_u64_env_var(
+                ELASTIC_APM_PROFILER_THREAD_CPU_SAMPLING_INTERVAL_MS_ENV_VAR,
+                0,
+            ),
+        }
+    }
+
+    /// Creates a new instance of [ProfilerConfig] with default values,
+    /// without reading environment variables
+    pub fn with_defaults() -> Self {
+        Self {
+            agent_config_path: None,
+            agent_environment: None,
+            agent_service_name: None,
+            calltarget_allow_list: HashSet::new(),
+            calltarget_batch_size: DEFAULT_CALLTARGET_BATCH_SIZE,
+            calltarget_enabled: true,
+            calltarget_exclude_assemblies: HashSet::new(),
+            config_dump_enabled: false,
+            diagnostic_report_path: None,
+            disable_all: false,
+            disable_optimizations: false,
+            enable_inlining: true,
+            eventlog_source: DEFAULT_EVENTLOG_SOURCE.into(),
+            exception_filter_types: None,
+            exception_sampling_rate: DEFAULT_EXCEPTION_SAMPLING_RATE,
+            exclude_integrations: None,
+            exclude_processes: None,
+            exclude_service_names: None,
+            gc_pressure_sampling: false,
+            heap_profiling_enabled: false,
+            heap_profiling_interval_secs: DEFAULT_HEAP_PROFILING_INTERVAL_SECS,
+            heap_profiling_rate: DEFAULT_HEAP_PROFILING_RATE,
+            home: None,
+            il_dump_format: IlDumpFormat::Hex,
+            integration_timeout_ms: DEFAULT_INTEGRATION_TIMEOUT_MS,
+            integrations_cache_path: None,
+            integrations_download_etag_cache: false,
+            integrations_download_timeout_secs: DEFAULT_INTEGRATIONS_DOWNLOAD_TIMEOUT_SECS,
+            integrations_download_url: None,
+            integrations_expected_sha256: None,
+            integrations_glob: None,
+            integrations_path: None,
+            integrations_inline: None,
+            integrations_reload_interval_secs: 0,
+            integrations_schema_validate: false,
+            integrations_watch: false,
+            log_async_buffer_size: DEFAULT_LOG_ASYNC_BUFFER_SIZE,
+            log_context_fields: Vec::new(),
+            log_dir: get_default_log_dir(),
+            log_flush_interval_ms: DEFAULT_LOG_FLUSH_INTERVAL_MS,
+            log_format: LogFormat::Text,
+            log_il: false,
+            log_il_dir: None,
+            log_level: LevelFilter::Warn,
+            log_level_overrides: Vec::new(),
+            log_max_files: 10,
+            log_max_size: 5 * 1024 * 1024,
+            log_prefix: None,
+            log_redact: false,
+            log_rotation: LogRotation::Size,
+            log_shutdown_timeout_ms: DEFAULT_LOG_SHUTDOWN_TIMEOUT_MS,
+            log_targets: LogTargetSet::default(),
+            managed_loader_path: None,
+            max_call_targets: None,
+            metrics_enabled: false,
+            metrics_interval_secs: DEFAULT_METRICS_INTERVAL_SECS,
+            print_env: false,
+            print_help: false,
+            process_exclude: None,
+            process_filter: None,
+            service_name: None,
+            span_stack_trace_min_duration_ms: None,
+            temp_dir: None,
+            thread_cpu_sampling_interval_ms: 0,
+        }
+    }
+}
+
+/// Gets the lowercased simple names of assemblies excluded from CallTarget instrumentation
+pub fn get_calltarget_exclude_assemblies() -> HashSet<String> {
+    PROFILER_CONFIG.calltarget_exclude_assemblies.clone()
+}
+
+/// Whether the method is instrumented with CallTarget according to the
+/// [ELASTIC_APM_PROFILER_CALLTARGET_ALLOW_LIST_ENV_VAR] environment variable. An empty or
+/// absent allow list allows all methods
+pub fn calltarget_is_allowed(assembly: &str, type_name: &str, method: &str) -> bool {
+    calltarget_is_allowed_with_config(&PROFILER_CONFIG, assembly, type_name, method)
+}
+
+fn calltarget_is_allowed_with_config(
+    config: &ProfilerConfig,
+    assembly: &str,
+    type_name: &str,
+    method: &str,
+) -> bool {
+    config.calltarget_allow_list.is_empty()
+        || config.calltarget_allow_list.contains(&(
+            assembly.to_lowercase(),
+            type_name.to_string(),
+            method.to_string(),
+        ))
+}
+
+/// Parses semicolon separated `Assembly::Type::Method` entries into lowercased simple
+/// assembly names, type names and method names, ignoring invalid entries
+fn parse_calltarget_allow_list(value: &str) -> HashSet<(String, String, String)> {
+    value
+        .split(';')
+        .filter_map(parse_calltarget_allow_list_entry)
+        .collect()
+}
+
+/// Parses an `Assembly::Type::Method` entry. Type and method names are case sensitive
+fn parse_calltarget_allow_list_entry(entry: &str) -> Option<(String, String, String)> {
+    let parts: Vec<&str> = entry.split("::").map(str::trim).collect();
+    match parts.as_slice() {
+        [assembly, type_name, method]
+            if !assembly.is_empty() && !type_name.is_empty() && !method.is_empty() =>
+        {
+            Some((
+                assembly.to_lowercase(),
+                type_name.to_string(),
+                method.to_string(),
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// Parses semicolon separated assembly names into a set of lowercased simple names,
+/// ignoring any version, culture or public key token
+fn parse_assembly_names(value: &str) -> HashSet<String> {
+    value
+        .split(';')
+        .filter_map(|name| name.split(',').next())
+        .map(|name| name.trim().to_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
 pub fn get_exclude_processes() -> Option<Vec<String>> {
-    read_semicolon_separated_env_var(ELASTIC_APM_PROFILER_EXCLUDE_PROCESSES_ENV_VAR)
+    PROFILER_CONFIG.exclude_processes.clone()
+}
+
+/// Whether the profiler is allowed to profile the process with the given name,
+/// according to the [ELASTIC_APM_PROFILER_PROCESS_EXCLUDE_ENV_VAR] and
+/// [ELASTIC_APM_PROFILER_PROCESS_FILTER_ENV_VAR] environment variables
+pub fn process_is_allowed(process_name: &str) -> bool {
+    process_is_allowed_with_config(&PROFILER_CONFIG, process_name)
+}
+
+fn process_is_allowed_with_config(config: &ProfilerConfig, process_name: &str) -> bool {
+    if let Some(process_exclude) = &config.process_exclude {
+        if process_exclude
+            .iter()
+            .any(|p| p.eq_ignore_ascii_case(process_name))
+        {
+            return false;
+        }
+    }
+
+    match &config.process_filter {
+        Some(process_filter) => process_filter
+            .iter()
+            .any(|p| p.eq_ignore_ascii_case(process_name)),
+        None => true,
+    }
 }
 
 pub fn get_exclude_service_names() -> Option<Vec<String>> {
-    read_semicolon_separated_env_var(ELASTIC_APM_PROFILER_EXCLUDE_SERVICE_NAMES_ENV_VAR)
+    PROFILER_CONFIG.exclude_service_names.clone()
 }
 
 pub fn get_service_name() -> Option<String> {
-    std::env::var(ELASTIC_APM_SERVICE_NAME_ENV_VAR).ok()
+    PROFILER_CONFIG.service_name.clone()
 }
 
 /// Gets the path to the profiler file on windows
@@ -165,302 +915,5325 @@ pub fn get_native_profiler_file() -> Result<String, HRESULT> {
     }
 }
 
+/// Gets the path to the managed loader assembly for the target framework `tfm`, from
+/// [ELASTIC_APM_PROFILER_MANAGED_LOADER_PATH_ENV_VAR], falling back to the assembly in the
+/// `tfm` subdirectory of the directory of the native profiler. Returns an error when the
+/// assembly does not exist or is not readable
+pub fn get_managed_loader_path(tfm: &str) -> Result<PathBuf, ProfilerError> {
+    get_managed_loader_path_with(
+        PROFILER_CONFIG.managed_loader_path.as_deref(),
+        |key| std::env::var(key).ok(),
+        tfm,
+    )
+}
+
+fn get_managed_loader_path_with(
+    configured_path: Option<&Path>,
+    get_var: impl Fn(&str) -> Option<String>,
+    tfm: &str,
+) -> Result<PathBuf, ProfilerError> {
+    let path = match configured_path {
+        Some(path) => path.to_path_buf(),
+        None => get_default_managed_loader_path(get_var, tfm)
+            .ok_or_else(|| ProfilerError::new(ProfilerErrorKind::ManagedLoaderNotConfigured))?,
+    };
+
+    File::open(&path)
+        .and_then(|file| file.metadata())
+        .and_then(|metadata| {
+            if metadata.is_file() {
+                Ok(())
+            } else {
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "not a file",
+                ))
+            }
+        })
+        .map_err(|e| {
+            ProfilerError::with_source(ProfilerErrorKind::ManagedLoaderFile(path.clone()), e)
+        })?;
+
+    log::info!("using managed loader assembly {}", path.display());
+    Ok(path)
+}
+
+fn get_default_managed_loader_path(
+    get_var: impl Fn(&str) -> Option<String>,
+    tfm: &str,
+) -> Option<PathBuf> {
+    let profiler_path = get_profiler_dir(get_var)?;
+    let mut path = profiler_path.parent()?.to_path_buf();
+    path.push(tfm);
+    path.push(format!("{}.dll", MANAGED_PROFILER_ASSEMBLY_LOADER));
+    Some(path)
+}
+
+/// Gets the resolved path of the Elastic APM agent config file, if configured.
+/// A relative path is resolved against the current directory. [None] is returned when
+/// the path does not refer to an existing file.
+pub fn get_agent_config_path() -> Option<PathBuf> {
+    get_agent_config_path_with(PROFILER_CONFIG.agent_config_path.as_deref())
+}
+
+/// Gets the deployment environment to pass to the managed loader and the agent, if configured
+pub fn get_agent_environment() -> Option<String> {
+    PROFILER_CONFIG.agent_environment.clone()
+}
+
+/// Gets the service name to pass to the managed loader and the agent, if configured
+pub fn get_agent_service_name() -> Option<String> {
+    PROFILER_CONFIG.agent_service_name.clone()
+}
+
+fn get_agent_config_path_with(path: Option<&Path>) -> Option<PathBuf> {
+    let resolved =
+        canonicalize_env_var_path(ELASTIC_APM_PROFILER_AGENT_CONFIG_PATH_ENV_VAR, path?)?;
+    if resolved.is_file() {
+        Some(resolved)
+    } else {
+        log::warn!(
+            "{} is set to {} which is not a file. Agent config file will not be used",
+            ELASTIC_APM_PROFILER_AGENT_CONFIG_PATH_ENV_VAR,
+            resolved.display()
+        );
+        None
+    }
+}
+
+/// Gets the maximum number of CallTarget methods requested for ReJIT per JIT compilation
+pub fn get_calltarget_batch_size() -> usize {
+    PROFILER_CONFIG.calltarget_batch_size
+}
+
+/// Gets the directory in which configuration dumps are written, if dumping the
+/// configuration on demand is enabled. Dumps are written to the log directory, which
+/// unlike the temp directory is not shared with other users
+pub fn get_config_dump_dir() -> Option<PathBuf> {
+    if PROFILER_CONFIG.config_dump_enabled {
+        Some(PROFILER_CONFIG.log_dir.clone())
+    } else {
+        None
+    }
+}
+
+/// Gets the path of the diagnostic report to write at startup, if configured
+pub fn get_diagnostic_report_path() -> Option<PathBuf> {
+    PROFILER_CONFIG.diagnostic_report_path.clone()
+}
+
 pub fn disable_optimizations() -> bool {
-    read_bool_env_var(ELASTIC_APM_PROFILER_DISABLE_OPTIMIZATIONS_ENV_VAR, false)
+    PROFILER_CONFIG.disable_optimizations
+}
+
+/// Whether to log the sizes of the GC heap generations after each GC
+pub fn gc_pressure_sampling() -> bool {
+    PROFILER_CONFIG.gc_pressure_sampling
+}
+
+/// Gets the rate at which to sample object allocations, and the interval at which to log
+/// the sampled allocations, if heap profiling is enabled
+pub fn get_heap_profiling() -> Option<(u64, Duration)> {
+    if PROFILER_CONFIG.heap_profiling_enabled {
+        Some((
+            PROFILER_CONFIG.heap_profiling_rate,
+            Duration::from_secs(PROFILER_CONFIG.heap_profiling_interval_secs),
+        ))
+    } else {
+        None
+    }
+}
+
+pub fn il_dump_format() -> IlDumpFormat {
+    PROFILER_CONFIG.il_dump_format
+}
+
+/// Gets the directory in which raw IL dumps are written, which is
+/// [ProfilerConfig::log_il_dir] when set, otherwise the log directory
+pub fn get_il_dump_dir() -> PathBuf {
+    PROFILER_CONFIG
+        .log_il_dir
+        .clone()
+        .unwrap_or_else(|| PROFILER_CONFIG.log_dir.clone())
+}
+
+pub fn get_log_shutdown_timeout_ms() -> u64 {
+    PROFILER_CONFIG.log_shutdown_timeout_ms
+}
+
+pub fn enable_inlining() -> bool {
+    PROFILER_CONFIG.enable_inlining
 }
 
-pub fn enable_inlining(default: bool) -> bool {
-    read_bool_env_var(ELASTIC_APM_PROFILER_ENABLE_INLINING_ENV_VAR, default)
+/// Gets the paths of the integrations files to watch for changes, if watching is enabled
+pub fn get_watched_integrations_files() -> Option<Vec<PathBuf>> {
+    if PROFILER_CONFIG.integrations_watch {
+        Some(get_integrations_file_paths(&PROFILER_CONFIG))
+    } else {
+        None
+    }
 }
 
-fn read_log_targets_from_env_var() -> HashSet<String> {
-    let mut set = match std::env::var(ELASTIC_APM_PROFILER_LOG_TARGETS_ENV_VAR) {
-        Ok(value) => value
+/// Gets the paths of the integrations files from which integrations are loaded.
+/// Empty when integrations are supplied inline
+fn get_integrations_file_paths(config: &ProfilerConfig) -> Vec<PathBuf> {
+    if config.integrations_download_url.is_some() {
+        Vec::new()
+    } else if let Some(pattern) = &config.integrations_glob {
+        glob_integrations_file_paths(pattern).unwrap_or_default()
+    } else if let Some(paths) = &config.integrations_path {
+        paths
             .split(';')
-            .into_iter()
-            .filter_map(|s| match s.to_lowercase().as_str() {
-                out if out == "file" || out == "stdout" => Some(out.into()),
-                _ => None,
-            })
-            .collect(),
-        _ => HashSet::with_capacity(1),
-    };
+            .map(str::trim)
+            .filter(|p| !p.is_empty())
+            .map(PathBuf::from)
+            .collect()
+    } else if config.integrations_inline.is_some() {
+        Vec::new()
+    } else {
+        config
+            .home
+            .iter()
+            .map(|home| home.join("integrations.yml"))
+            .collect()
+    }
+}
 
-    if set.is_empty() {
-        set.insert("file".into());
+/// Gets the interval at which to log a summary of the profiler metrics, if enabled
+pub fn get_metrics_interval() -> Option<Duration> {
+    if PROFILER_CONFIG.metrics_enabled {
+        Some(Duration::from_secs(PROFILER_CONFIG.metrics_interval_secs))
+    } else {
+        None
     }
-    set
 }
 
-pub fn read_log_level_from_env_var(default: LevelFilter) -> LevelFilter {
-    match std::env::var(ELASTIC_APM_PROFILER_LOG_ENV_VAR) {
-        Ok(value) => LevelFilter::from_str(value.as_str()).unwrap_or(default),
-        _ => default,
+/// Gets the interval at which to reload integrations, if reloading is enabled
+pub fn get_integrations_reload_interval() -> Option<Duration> {
+    match PROFILER_CONFIG.integrations_reload_interval_secs {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
     }
 }
 
-fn read_bool_env_var(key: &str, default: bool) -> bool {
-    match std::env::var(key) {
-        Ok(enabled) => match enabled.to_lowercase().as_str() {
-            "true" | "1" => true,
-            "false" | "0" => false,
-            _ => {
-                log::warn!(
-                    "Unknown value for {}: {}. Setting to {}",
-                    key,
-                    enabled,
-                    default
-                );
-                default
-            }
-        },
-        Err(e) => {
-            log::debug!(
-                "Problem reading {}: {}. Setting to {}",
-                key,
-                e.to_string(),
-                default
-            );
-            default
+/// Gets the interval at which to sample the CPU time of each thread, if sampling is enabled
+pub fn get_thread_cpu_sampling_interval() -> Option<Duration> {
+    match PROFILER_CONFIG.thread_cpu_sampling_interval_ms {
+        0 => None,
+        ms => Some(Duration::from_millis(ms)),
+    }
+}
+
+/// A target to which log records are written
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum LogTarget {
+    File,
+    Stdout,
+    Stderr,
+    #[cfg(target_os = "windows")]
+    EventLog,
+}
+
+impl FromStr for LogTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "file" => Ok(LogTarget::File),
+            "stdout" => Ok(LogTarget::Stdout),
+            "stderr" => Ok(LogTarget::Stderr),
+            #[cfg(target_os = "windows")]
+            "eventlog" => Ok(LogTarget::EventLog),
+            _ => Err(format!("unknown log target {}", s)),
         }
     }
 }
 
-/// get the profiler directory
-fn get_profiler_dir() -> String {
-    let env_var = if cfg!(target_pointer_width = "64") {
-        "CORECLR_PROFILER_PATH_64"
-    } else {
-        "CORECLR_PROFILER_PATH_32"
-    };
+impl Display for LogTarget {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            LogTarget::File => "file",
+            LogTarget::Stdout => "stdout",
+            LogTarget::Stderr => "stderr",
+            #[cfg(target_os = "windows")]
+            LogTarget::EventLog => "eventlog",
+        };
+        f.write_str(name)
+    }
+}
 
-    match std::env::var(env_var) {
-        Ok(v) => v,
-        Err(_) => match std::env::var("CORECLR_PROFILER_PATH") {
-            Ok(v) => v,
-            Err(_) => {
-                // try .NET Framework env vars
-                let env_var = if cfg!(target_pointer_width = "64") {
-                    "COR_PROFILER_PATH_64"
-                } else {
-                    "COR_PROFILER_PATH_32"
-                };
+/// A set of targets to which log records are written
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct LogTargetSet(HashSet<LogTarget>);
 
-                match std::env::var(env_var) {
-                    Ok(v) => v,
-                    Err(_) => std::env::var("COR_PROFILER_PATH").unwrap_or_else(|_| String::new()),
-                }
-            }
-        },
+impl LogTargetSet {
+    pub fn contains(&self, target: LogTarget) -> bool {
+        self.0.contains(&target)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &LogTarget> {
+        self.0.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
     }
 }
 
-/// Gets the default log directory on Windows
-#[cfg(target_os = "windows")]
-fn get_default_log_dir() -> PathBuf {
-    // ideally we would use the windows function SHGetKnownFolderPath to get
-    // the CommonApplicationData special folder. However, this requires a few package dependencies
-    // like winapi that would increase the size of the profiler binary. Instead,
-    // use the %PROGRAMDATA% environment variable if it exists
-    match std::env::var("PROGRAMDATA") {
-        Ok(path) => {
-            let mut path_buf = PathBuf::from(path);
-            path_buf.push("elastic");
-            path_buf.push("apm-agent-dotnet");
-            path_buf.push("logs");
-            path_buf
-        }
-        Err(_) => get_home_log_dir(),
+/// The default set of log targets, containing only [LogTarget::File]
+impl Default for LogTargetSet {
+    fn default() -> Self {
+        std::iter::once(LogTarget::File).collect()
     }
 }
 
-/// Gets the path to the profiler file on non windows
-#[cfg(not(target_os = "windows"))]
-fn get_default_log_dir() -> PathBuf {
-    PathBuf::from_str("/var/log/elastic/apm-agent-dotnet").unwrap()
+/// Serializes the log targets as a sorted list of names
+impl Serialize for LogTargetSet {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut names: Vec<String> = self.iter().map(LogTarget::to_string).collect();
+        names.sort();
+        serializer.collect_seq(names)
+    }
 }
 
-fn get_home_log_dir() -> PathBuf {
-    let mut path_buf = match std::env::var(ELASTIC_APM_PROFILER_HOME_ENV_VAR) {
-        Ok(val) => PathBuf::from(val),
-        Err(_) => PathBuf::from(get_profiler_dir()),
-    };
+impl FromIterator<LogTarget> for LogTargetSet {
+    fn from_iter<T: IntoIterator<Item = LogTarget>>(iter: T) -> Self {
+        LogTargetSet(iter.into_iter().collect())
+    }
+}
 
-    path_buf.push("logs");
-    path_buf
+fn read_log_targets_from_env_var() -> LogTargetSet {
+    read_log_targets_with(|key| std::env::var(key).ok(), is_running_in_container())
 }
 
-fn get_log_dir() -> PathBuf {
-    match std::env::var(ELASTIC_APM_PROFILER_LOG_DIR_ENV_VAR) {
-        Ok(path) => PathBuf::from(path),
-        Err(_) => get_default_log_dir(),
-    }
-}
-
-fn get_sys_time_in_seconds() -> u64 {
-    match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
-        Ok(n) => n.as_secs(),
-        Err(_) => 0,
-    }
-}
-
-pub fn initialize_logging(process_name: &str) -> Option<Handle> {
-    let targets = read_log_targets_from_env_var();
-    let level = read_log_level_from_env_var(LevelFilter::Warn);
-    let mut root_builder = Root::builder();
-    let mut config_builder = Config::builder();
-    let log_pattern = "[{d(%Y-%m-%dT%H:%M:%S.%f%:z)}] [{l:<5}] {m}{n}";
-
-    if targets.contains("stdout") {
-        let pattern = PatternEncoder::new(log_pattern);
-        let stdout = ConsoleAppender::builder()
-            .encoder(Box::new(pattern))
-            .build();
-        config_builder =
-            config_builder.appender(Appender::builder().build("stdout", Box::new(stdout)));
-        root_builder = root_builder.appender("stdout");
-    }
-
-    if targets.contains("file") {
-        let pid = std::process::id();
-        let timestamp = get_sys_time_in_seconds();
-        let mut log_dir = get_log_dir();
-        let mut valid_log_dir = true;
-
-        // try to create the log directory ahead of time so that we can determine if it's a valid
-        // directory. if the directory can't be created, try the default log directory or home directory
-        // before bailing and not setting up the file logger.
-        if std::fs::create_dir_all(&log_dir).is_err() {
-            let default_log_dir = get_default_log_dir();
-            if log_dir != default_log_dir {
-                log_dir = default_log_dir;
-                if std::fs::create_dir_all(&log_dir).is_err() {
-                    let home_log_dir = get_home_log_dir();
-                    if log_dir != home_log_dir {
-                        log_dir = home_log_dir;
-                        if std::fs::create_dir_all(&log_dir).is_err() {
-                            valid_log_dir = false;
-                        }
-                    } else {
-                        valid_log_dir = false;
-                    }
-                }
-            } else {
-                log_dir = get_home_log_dir();
-                if std::fs::create_dir_all(&log_dir).is_err() {
-                    valid_log_dir = false;
-                }
-            }
+/// Reads the log targets, defaulting to [LogTarget::Stdout] in a container, where logs
+/// are typically collected from standard output, and to [LogTarget::File] otherwise
+fn read_log_targets_with(
+    get_var: impl Fn(&str) -> Option<String>,
+    in_container: bool,
+) -> LogTargetSet {
+    if let Some(value) = get_var(ELASTIC_APM_PROFILER_LOG_TARGETS_ENV_VAR) {
+        let set: LogTargetSet = value
+            .split(';')
+            .filter_map(|s| LogTarget::from_str(s).ok())
+            .collect();
+        if !set.is_empty() {
+            return set;
         }
+    }
 
-        if valid_log_dir {
-            let log_file_name = log_dir
-                .join(format!(
-                    "elastic_apm_profiler_{}_{}_{}.log",
-                    process_name, pid, timestamp
-                ))
-                .to_string_lossy()
-                .to_string();
-            let rolling_log_file_name = log_dir
-                .join(format!(
-                    "elastic_apm_profiler_{}_{}_{}_{{}}.log",
-                    process_name, pid, timestamp
-                ))
-                .to_string_lossy()
-                .to_string();
-
-            let trigger = SizeTrigger::new(5 * 1024 * 1024);
-            let roller_result = FixedWindowRoller::builder().build(&rolling_log_file_name, 10);
-            if let Ok(roller) = roller_result {
-                let policy = CompoundPolicy::new(Box::new(trigger), Box::new(roller));
-                let pattern = PatternEncoder::new(log_pattern);
-                let file_result = RollingFileAppender::builder()
-                    .append(true)
-                    .encoder(Box::new(pattern))
-                    .build(&log_file_name, Box::new(policy));
-                if let Ok(file) = file_result {
-                    config_builder =
-                        config_builder.appender(Appender::builder().build("file", Box::new(file)));
-                    root_builder = root_builder.appender("file");
-                }
-            }
-        }
+    if in_container {
+        std::iter::once(LogTarget::Stdout).collect()
+    } else {
+        LogTargetSet::default()
     }
+}
 
-    let root = root_builder.build(level);
-    let config = config_builder.build(root);
-    return match config {
-        Ok(c) => log4rs::init_config(c).ok(),
-        Err(_) => None,
-    };
+/// The format in which log records are written
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json,
 }
 
-/// Loads the integrations by reading the yml file pointed to
-/// by [ELASTIC_APM_PROFILER_INTEGRATIONS] environment variable, filtering
-/// integrations by [ELASTIC_APM_PROFILER_EXCLUDE_INTEGRATIONS_ENV_VAR] environment variable,
-/// if present
-pub fn load_integrations() -> Result<Vec<Integration>, HRESULT> {
-    let path = match std::env::var(ELASTIC_APM_PROFILER_INTEGRATIONS_ENV_VAR) {
-        Ok(val) => val,
-        Err(e) => {
-            log::debug!(
-                "problem reading {} environment variable: {}. trying integrations.yml in directory of {} environment variable value",
-                ELASTIC_APM_PROFILER_INTEGRATIONS_ENV_VAR,
-                e.to_string(),
-                ELASTIC_APM_PROFILER_HOME_ENV_VAR
-            );
+fn read_log_format_from_env_var() -> LogFormat {
+    match std::env::var(ELASTIC_APM_PROFILER_LOG_FORMAT_ENV_VAR) {
+        Ok(value) => match value.to_lowercase().as_str() {
+            "json" => LogFormat::Json,
+            _ => LogFormat::Text,
+        },
+        _ => LogFormat::Text,
+    }
+}
 
-            match std::env::var(ELASTIC_APM_PROFILER_HOME_ENV_VAR) {
-                Ok(val) => {
-                    let mut path_buf = PathBuf::from(val);
-                    path_buf.push("integrations.yml");
-                    path_buf.to_string_lossy().to_string()
-                }
-                Err(e) => {
-                    log::warn!(
-                        "problem reading {} environment variable: {}. profiler disabled",
-                        ELASTIC_APM_PROFILER_HOME_ENV_VAR,
-                        e.to_string(),
-                    );
-                    return Err(E_FAIL);
-                }
-            }
+/// The format in which IL is dumped
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IlDumpFormat {
+    /// The bytes of the method body as hexadecimal, with offsets
+    Hex,
+    /// Mnemonics of the instructions of the method body, within its exception handling
+    /// blocks and with resolved method, type and string tokens
+    Disasm,
+    /// The bytes of the method body, written to a file
+    Raw,
+}
+
+impl IlDumpFormat {
+    /// Parses a format from its case insensitive name
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "hex" => Some(IlDumpFormat::Hex),
+            "disasm" => Some(IlDumpFormat::Disasm),
+            "raw" => Some(IlDumpFormat::Raw),
+            _ => None,
         }
-    };
+    }
+}
 
-    let file = File::open(&path).map_err(|e| {
-        log::warn!(
-            "problem reading integrations file {}: {}. profiler is disabled.",
-            &path,
-            e.to_string()
-        );
-        E_FAIL
-    })?;
+/// The strategy with which log files are rotated
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    /// Rotate when the log file exceeds the maximum size
+    Size,
+    /// Rotate at midnight UTC
+    Daily,
+}
 
-    let reader = BufReader::new(file);
-    let mut integrations: Vec<Integration> = serde_yaml::from_reader(reader).map_err(|e| {
-        log::warn!(
-            "problem reading integrations file {}: {}. profiler is disabled.",
-            &path,
-            e.to_string()
-        );
-        E_FAIL
-    })?;
+fn read_log_rotation_from_env_var() -> LogRotation {
+    match std::env::var(ELASTIC_APM_PROFILER_LOG_ROTATION_ENV_VAR) {
+        Ok(value) => match value.to_lowercase().as_str() {
+            "daily" => LogRotation::Daily,
+            _ => LogRotation::Size,
+        },
+        _ => LogRotation::Size,
+    }
+}
 
-    log::trace!(
-        "loaded {} integration(s) from {}",
-        integrations.len(),
-        &path
+pub fn read_log_level_from_env_var(default: LevelFilter) -> LevelFilter {
+    match std::env::var(ELASTIC_APM_PROFILER_LOG_ENV_VAR) {
+        Ok(value) => parse_log_level(&value).unwrap_or_else(|| {
+            unknown_env_var_value(ELASTIC_APM_PROFILER_LOG_ENV_VAR, &value, default)
+        }),
+        _ => default,
+    }
+}
+
+/// Handles an unrecognised value of an environment variable by logging a warning and
+/// returning `default`. With the `strict-env` feature, records the value instead, so that
+/// [check_strict_env] fails initialization of a misconfigured environment
+#[cfg(not(feature = "strict-env"))]
+fn unknown_env_var_value<T: Display>(key: &str, value: &str, default: T) -> T {
+    log::warn!(
+        "Unknown value for {}: {}. Setting to {}",
+        key,
+        value,
+        default
     );
+    default
+}
 
-    // Now filter integrations
-    if let Ok(val) = std::env::var(ELASTIC_APM_PROFILER_EXCLUDE_INTEGRATIONS_ENV_VAR) {
-        let exclude_integrations = val.split(';');
-        for exclude_integration in exclude_integrations {
-            log::trace!("exclude integrations that match {}", exclude_integration);
-            integrations.retain(|i| i.name.to_lowercase() != exclude_integration.to_lowercase());
+/// The unrecognised environment variable values read, as `key=value`
+#[cfg(feature = "strict-env")]
+static UNKNOWN_ENV_VAR_VALUES: std::sync::Mutex<Vec<String>> = std::sync::Mutex::new(Vec::new());
+
+#[cfg(feature = "strict-env")]
+fn unknown_env_var_value<T: Display>(key: &str, value: &str, default: T) -> T {
+    UNKNOWN_ENV_VAR_VALUES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push(format!("{}={}", key, value));
+    default
+}
+
+/// With the `strict-env` feature, returns an error listing the unrecognised environment
+/// variable values read. Without it, unrecognised values are logged as they are read and
+/// this always succeeds
+pub fn check_strict_env() -> Result<(), ProfilerError> {
+    #[cfg(feature = "strict-env")]
+    {
+        let values = UNKNOWN_ENV_VAR_VALUES
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if !values.is_empty() {
+            return Err(ProfilerError::new(ProfilerErrorKind::UnknownEnvVarValues(
+                values.clone(),
+            )));
         }
-    };
+    }
+    Ok(())
+}
+
+/// Parses a log level, one of off, error, warn, info, debug or trace, or a numeric
+/// syslog-style severity from 0 (off) to 5 (trace)
+fn parse_log_level(value: &str) -> Option<LevelFilter> {
+    if let Ok(level) = LevelFilter::from_str(value) {
+        return Some(level);
+    }
+
+    let severity = value.trim().parse::<u8>().ok()?;
+    let level = match severity {
+        0 => LevelFilter::Off,
+        1 => LevelFilter::Error,
+        2 => LevelFilter::Warn,
+        3 => LevelFilter::Info,
+        4 => LevelFilter::Debug,
+        5 => LevelFilter::Trace,
+        _ => return None,
+    };
+    Some(level)
+}
+
+/// Parses semicolon separated `module=level` entries into log target names and log levels,
+/// ignoring invalid entries. When a module is specified more than once, the last entry wins.
+fn parse_log_level_overrides(value: &str) -> Vec<(String, LevelFilter)> {
+    let mut overrides: Vec<(String, LevelFilter)> = Vec::new();
+    for (target, level) in value.split(';').filter_map(parse_log_level_override) {
+        match overrides.iter_mut().find(|(t, _)| *t == target) {
+            Some(existing) => existing.1 = level,
+            None => overrides.push((target, level)),
+        }
+    }
+    overrides
+}
+
+/// Parses a `module=level` entry. The module is a path relative to the profiler crate,
+/// such as `profiler::rejit`, or a log target starting with the profiler crate name
+fn parse_log_level_override(entry: &str) -> Option<(String, LevelFilter)> {
+    let (module, level) = entry.split_once('=')?;
+    let module = module.trim().trim_matches(':');
+    if module.is_empty() {
+        return None;
+    }
+
+    let level = parse_log_level(level.trim())?;
+    let target =
+        if module == LOG_TARGET_ROOT || module.starts_with(&format!("{}::", LOG_TARGET_ROOT)) {
+            module.to_string()
+        } else {
+            format!("{}::{}", LOG_TARGET_ROOT, module)
+        };
+    Some((target, level))
+}
+
+/// The fields of JSON log records written by the profiler, which context fields can't replace
+const RESERVED_LOG_FIELDS: [&str; 5] = ["timestamp", "level", "message", "process_id", "thread_id"];
+
+/// Parses semicolon separated `key=value` entries into the context fields attached to every
+/// log record, ignoring invalid entries. When a key is specified more than once, the last
+/// entry wins.
+fn parse_log_context_fields(value: &str) -> Vec<(String, String)> {
+    let mut fields: Vec<(String, String)> = Vec::new();
+    for (key, value) in value.split(';').filter_map(parse_log_context_field) {
+        match fields.iter_mut().find(|(k, _)| *k == key) {
+            Some(existing) => existing.1 = value,
+            None => fields.push((key, value)),
+        }
+    }
+    fields
+}
+
+/// Parses a `key=value` entry. Newlines are removed, since log records are written one per line
+fn parse_log_context_field(entry: &str) -> Option<(String, String)> {
+    let (key, value) = entry.split_once('=')?;
+    let sanitize = |s: &str| s.trim().replace(&['\r', '\n'][..], "");
+    let key = sanitize(key);
+    if key.is_empty()
+        || key.contains(char::is_whitespace)
+        || RESERVED_LOG_FIELDS.contains(&key.as_str())
+    {
+        return None;
+    }
+    Some((key, sanitize(value)))
+}
+
+/// Where a configuration value was read from
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum EnvSource {
+    /// Read from an environment variable
+    EnvVar,
+    /// The default value, used when the environment variable is not set or its value is
+    /// not recognised
+    Default,
+    /// Read from a configuration file
+    ConfigFile,
+}
+
+impl Display for EnvSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            EnvSource::EnvVar => f.write_str("env var"),
+            EnvSource::Default => f.write_str("default"),
+            EnvSource::ConfigFile => f.write_str("config file"),
+        }
+    }
+}
+
+/// Gets the source of the value of an environment variable, which is [EnvSource::EnvVar]
+/// when the variable is set to a non empty value that is not one of the `ignored` keys,
+/// whose values are not recognised
+fn env_source_with(
+    key: &str,
+    get_var: impl Fn(&str) -> Option<String>,
+    ignored: &HashSet<String>,
+) -> EnvSource {
+    match get_var(key) {
+        Some(value) if !value.trim().is_empty() && !ignored.contains(key) => EnvSource::EnvVar,
+        _ => EnvSource::Default,
+    }
+}
+
+fn read_bool_env_var(key: &str, default: bool) -> bool {
+    read_bool_env_var_with_source(key, default).0
+}
+
+/// Reads a boolean environment variable, returning the value and where it was read from
+fn read_bool_env_var_with_source(key: &str, default: bool) -> (bool, EnvSource) {
+    match std::env::var(key) {
+        Ok(enabled) => match enabled.to_lowercase().as_str() {
+            "true" | "1" => (true, EnvSource::EnvVar),
+            "false" | "0" => (false, EnvSource::EnvVar),
+            _ => (
+                unknown_env_var_value(key, &enabled, default),
+                EnvSource::Default,
+            ),
+        },
+        Err(e) => {
+            log::debug!(
+                "Problem reading {}: {}. Setting to {}",
+                key,
+                e.to_string(),
+                default
+            );
+            (default, EnvSource::Default)
+        }
+    }
+}
+
+/// Reads an environment variable as a value of type `T`, returning the default
+/// if the variable is not set or can't be parsed
+fn read_parsed_env_var<T: FromStr + Display>(key: &str, default: T) -> T {
+    match std::env::var(key) {
+        Ok(value) => match value.trim().parse::<T>() {
+            Ok(v) => v,
+            Err(_) => {
+                log::warn!(
+                    "Unknown value for {}: {}. Setting to {}",
+                    key,
+                    value,
+                    default
+                );
+                default
+            }
+        },
+        Err(e) => {
+            log::debug!("Problem reading {}: {}. Setting to {}", key, e, default);
+            default
+        }
+    }
+}
+
+/// Reads an environment variable as a value of type `T`, returning [None] if the variable
+/// is not set, is empty or can't be parsed
+fn read_optional_parsed_env_var<T: FromStr>(key: &str) -> Option<T> {
+    let value = std::env::var(key).ok()?;
+    if value.trim().is_empty() {
+        return None;
+    }
+    match value.trim().parse::<T>() {
+        Ok(v) => Some(v),
+        Err(_) => {
+            log::warn!("Unknown value for {}: {}. Leaving unset", key, value);
+            None
+        }
+    }
+}
+
+fn read_u32_env_var(key: &str, default: u32) -> u32 {
+    read_parsed_env_var(key, default)
+}
+
+fn read_u64_env_var(key: &str, default: u64) -> u64 {
+    read_parsed_env_var(key, default)
+}
+
+fn read_usize_env_var(key: &str, default: usize) -> usize {
+    read_parsed_env_var(key, default)
+}
+
+/// Reads an environment variable as a fraction, clamped to between 0.0 and 1.0. Returns
+/// the default if the variable is not set or is not a number
+fn read_f64_env_var(key: &str, default: f64) -> f64 {
+    clamp_fraction(key, read_parsed_env_var(key, default), default)
+}
+
+fn clamp_fraction(key: &str, value: f64, default: f64) -> f64 {
+    if value.is_nan() {
+        log::warn!("Unknown value for {}: NaN. Setting to {}", key, default);
+        default
+    } else if !(0.0..=1.0).contains(&value) {
+        let clamped = value.clamp(0.0, 1.0);
+        log::warn!(
+            "Value for {}: {} is not between 0.0 and 1.0. Setting to {}",
+            key,
+            value,
+            clamped
+        );
+        clamped
+    } else {
+        value
+    }
+}
+
+/// Gets the fraction of exceptions captured, between 0.0 and 1.0
+pub fn get_exception_sampling_rate() -> f64 {
+    PROFILER_CONFIG.exception_sampling_rate
+}
+
+/// Whether thrown exceptions are monitored, which is when
+/// [ELASTIC_APM_PROFILER_EXCEPTION_FILTER_TYPES_ENV_VAR] or
+/// [ELASTIC_APM_PROFILER_EXCEPTION_SAMPLING_RATE_ENV_VAR] limits the exceptions captured
+pub fn exception_capture_enabled() -> bool {
+    exception_capture_enabled_with_config(&PROFILER_CONFIG)
+}
+
+fn exception_capture_enabled_with_config(config: &ProfilerConfig) -> bool {
+    matches!(&config.exception_filter_types, Some(filter_types) if !filter_types.is_empty())
+        || config.exception_sampling_rate < 1.0
+}
+
+/// Whether exceptions of the type are captured according to the
+/// [ELASTIC_APM_PROFILER_EXCEPTION_FILTER_TYPES_ENV_VAR] environment variable. The type name
+/// is captured when it starts with one of the filter types. An empty or absent filter
+/// captures all types
+pub fn exception_type_is_capturable(type_name: &str) -> bool {
+    exception_type_is_capturable_with_config(&PROFILER_CONFIG, type_name)
+}
+
+fn exception_type_is_capturable_with_config(config: &ProfilerConfig, type_name: &str) -> bool {
+    match &config.exception_filter_types {
+        Some(filter_types) if !filter_types.is_empty() => filter_types
+            .iter()
+            .any(|filter_type| type_name.starts_with(filter_type.as_str())),
+        _ => true,
+    }
+}
+
+/// Reads an environment variable as a path, resolved against the current directory and
+/// canonicalised. Returns [None] if the variable is not set or empty, or if the path can't
+/// be canonicalised, logging a warning when the path does not exist
+pub fn read_path_env_var(key: &str) -> Option<PathBuf> {
+    let value = std::env::var(key).ok().filter(|v| !v.trim().is_empty())?;
+    canonicalize_env_var_path(key, Path::new(value.trim()))
+}
+
+/// Canonicalises a path read from the environment variable `key`, logging a warning and
+/// returning [None] if it can't be canonicalised
+fn canonicalize_env_var_path(key: &str, path: &Path) -> Option<PathBuf> {
+    match path.canonicalize() {
+        Ok(path) => Some(path),
+        Err(e) => {
+            match e.kind() {
+                std::io::ErrorKind::NotFound => {
+                    log::warn!("{} is set to {} which does not exist", key, path.display())
+                }
+                _ => log::warn!(
+                    "{} is set to {} which cannot be resolved: {}",
+                    key,
+                    path.display(),
+                    e
+                ),
+            }
+            None
+        }
+    }
+}
+
+/// Reads an environment variable as a duration, formatted as an integer followed by one of
+/// the units `ms`, `s`, `m` or `h`. An integer without a unit is in milliseconds. Returns
+/// the default if the variable is not set or can't be parsed
+pub fn read_duration_env_var(key: &str, default: Duration) -> Duration {
+    match std::env::var(key) {
+        Ok(value) => match parse_duration(&value) {
+            Some(duration) => duration,
+            None => {
+                unknown_env_var_value(key, &value, format_duration(default));
+                default
+            }
+        },
+        Err(e) => {
+            log::debug!(
+                "Problem reading {}: {}. Setting to {}",
+                key,
+                e,
+                format_duration(default)
+            );
+            default
+        }
+    }
+}
+
+/// Parses a duration such as `500ms`, `2s`, `1m` or `1h`. An integer without a unit is
+/// in milliseconds
+fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim().to_lowercase();
+    let unit_start = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(unit_start);
+    let amount: u64 = amount.parse().ok()?;
+    let millis = match unit {
+        "" | "ms" => Some(amount),
+        "s" => amount.checked_mul(1000),
+        "m" => amount.checked_mul(60 * 1000),
+        "h" => amount.checked_mul(60 * 60 * 1000),
+        _ => None,
+    }?;
+    Some(Duration::from_millis(millis))
+}
+
+fn format_duration(duration: Duration) -> String {
+    format!("{}ms", duration.as_millis())
+}
+
+/// Clamps a numeric env var value to a minimum, logging a warning if the value is below it
+fn clamp_to_min(key: &str, value: u64, min: u64) -> u64 {
+    if value < min {
+        log::warn!(
+            "Value for {}: {} is less than the minimum {}. Setting to {}",
+            key,
+            value,
+            min,
+            min
+        );
+        min
+    } else {
+        value
+    }
+}
+
+/// Gets the maximum size in bytes of a log file before it is rolled
+fn read_log_max_size_from_env_var() -> u64 {
+    let size_mb = clamp_to_min(
+        ELASTIC_APM_PROFILER_LOG_MAX_SIZE_MB_ENV_VAR,
+        read_u64_env_var(ELASTIC_APM_PROFILER_LOG_MAX_SIZE_MB_ENV_VAR, 5),
+        1,
+    );
+    size_mb.saturating_mul(1024 * 1024)
+}
+
+/// Removes newlines from the log prefix and truncates it to [MAX_LOG_PREFIX_LEN] characters.
+/// Returns [None] if the prefix is empty
+fn sanitize_log_prefix(value: &str) -> Option<String> {
+    let prefix: String = value
+        .chars()
+        .filter(|c| *c != '\n' && *c != '\r')
+        .take(MAX_LOG_PREFIX_LEN)
+        .collect();
+    let prefix = prefix.trim();
+    if prefix.is_empty() {
+        None
+    } else {
+        Some(prefix.into())
+    }
+}
+
+/// Gets the maximum number of rolled log files to keep
+fn read_log_max_files_from_env_var() -> u32 {
+    clamp_to_min(
+        ELASTIC_APM_PROFILER_LOG_MAX_FILES_ENV_VAR,
+        read_u32_env_var(ELASTIC_APM_PROFILER_LOG_MAX_FILES_ENV_VAR, 10) as u64,
+        1,
+    ) as u32
+}
+
+/// A problem with the value of an environment variable
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct EnvVarWarning {
+    /// The environment variable key
+    pub key: String,
+    /// The value of the environment variable
+    pub raw_value: String,
+    /// The reason the value is invalid
+    pub reason: String,
+    /// Whether the value is not recognised, so that the default is used in its place
+    pub ignored: bool,
+}
+
+impl EnvVarWarning {
+    fn new(key: &str, raw_value: &str, reason: impl Into<String>) -> Self {
+        Self {
+            key: key.into(),
+            raw_value: raw_value.into(),
+            reason: reason.into(),
+            ignored: false,
+        }
+    }
+
+    fn ignored(key: &str, raw_value: &str, reason: impl Into<String>) -> Self {
+        Self {
+            ignored: true,
+            ..Self::new(key, raw_value, reason)
+        }
+    }
+}
+
+/// Validates the values of all known profiler environment variables,
+/// returning all problems found
+pub fn validate_env_vars() -> Vec<EnvVarWarning> {
+    validate_env_vars_with(|key| std::env::var(key).ok())
+}
+
+fn validate_env_vars_with(get_var: impl Fn(&str) -> Option<String>) -> Vec<EnvVarWarning> {
+    let mut warnings = Vec::new();
+
+    for key in [
+        ELASTIC_APM_PROFILER_CALLTARGET_ENABLED_ENV_VAR,
+        ELASTIC_APM_PROFILER_CONFIG_DUMP_ENABLED_ENV_VAR,
+        ELASTIC_APM_PROFILER_DISABLE_ALL_ENV_VAR,
+        ELASTIC_APM_PROFILER_DISABLE_OPTIMIZATIONS_ENV_VAR,
+        ELASTIC_APM_PROFILER_ENABLE_INLINING_ENV_VAR,
+        ELASTIC_APM_PROFILER_GC_PRESSURE_SAMPLING_ENV_VAR,
+        ELASTIC_APM_PROFILER_HEAP_PROFILING_ENABLED_ENV_VAR,
+        ELASTIC_APM_PROFILER_INTEGRATIONS_DOWNLOAD_ETAG_CACHE_ENV_VAR,
+        ELASTIC_APM_PROFILER_INTEGRATIONS_SCHEMA_VALIDATE_ENV_VAR,
+        ELASTIC_APM_PROFILER_LOG_IL_ENV_VAR,
+        ELASTIC_APM_PROFILER_LOG_REDACT_ENV_VAR,
+        ELASTIC_APM_PROFILER_METRICS_ENABLED_ENV_VAR,
+        ELASTIC_APM_PROFILER_PRINT_ENV_ENV_VAR,
+        ELASTIC_APM_PROFILER_PRINT_HELP_ENV_VAR,
+    ] {
+        if let Some(value) = get_var(key) {
+            if !matches!(value.to_lowercase().as_str(), "true" | "false" | "1" | "0") {
+                warnings.push(EnvVarWarning::ignored(
+                    key,
+                    &value,
+                    "expected one of true, false, 1 or 0",
+                ));
+            }
+        }
+    }
+
+    for (key, min) in [
+        (
+            ELASTIC_APM_PROFILER_INTEGRATIONS_DOWNLOAD_TIMEOUT_SECS_ENV_VAR,
+            1,
+        ),
+        (ELASTIC_APM_PROFILER_CALLTARGET_BATCH_SIZE_ENV_VAR, 1),
+        (ELASTIC_APM_PROFILER_HEAP_PROFILING_INTERVAL_SECS_ENV_VAR, 1),
+        (ELASTIC_APM_PROFILER_HEAP_PROFILING_RATE_ENV_VAR, 1),
+        (ELASTIC_APM_PROFILER_INTEGRATION_TIMEOUT_MS_ENV_VAR, 0),
+        (
+            ELASTIC_APM_PROFILER_INTEGRATIONS_RELOAD_INTERVAL_SECS_ENV_VAR,
+            0,
+        ),
+        (ELASTIC_APM_PROFILER_LOG_ASYNC_BUFFER_SIZE_ENV_VAR, 0),
+        (
+            ELASTIC_APM_PROFILER_LOG_FLUSH_INTERVAL_MS_ENV_VAR,
+            MIN_LOG_FLUSH_INTERVAL_MS,
+        ),
+        (ELASTIC_APM_PROFILER_LOG_MAX_FILES_ENV_VAR, 1),
+        (ELASTIC_APM_PROFILER_LOG_MAX_SIZE_MB_ENV_VAR, 1),
+        (ELASTIC_APM_PROFILER_LOG_SHUTDOWN_TIMEOUT_MS_ENV_VAR, 0),
+        (ELASTIC_APM_PROFILER_MAX_CALL_TARGETS_ENV_VAR, 0),
+        (ELASTIC_APM_PROFILER_METRICS_INTERVAL_SECS_ENV_VAR, 1),
+        (
+            ELASTIC_APM_PROFILER_SPAN_STACK_TRACE_MIN_DURATION_MS_ENV_VAR,
+            0,
+        ),
+        (
+            ELASTIC_APM_PROFILER_THREAD_CPU_SAMPLING_INTERVAL_MS_ENV_VAR,
+            0,
+        ),
+    ] {
+        if let Some(value) = get_var(key) {
+            match value.trim().parse::<u64>() {
+                Ok(v) if v < min => warnings.push(EnvVarWarning::new(
+                    key,
+                    &value,
+                    format!("expected a value of at least {}", min),
+                )),
+                Ok(_) => {}
+                Err(_) => warnings.push(EnvVarWarning::ignored(
+                    key,
+                    &value,
+                    "expected a non-negative integer",
+                )),
+            }
+        }
+    }
+
+    if let Some(value) = get_var(ELASTIC_APM_PROFILER_LOG_ENV_VAR) {
+        if parse_log_level(&value).is_none() {
+            warnings.push(EnvVarWarning::ignored(
+                ELASTIC_APM_PROFILER_LOG_ENV_VAR,
+                &value,
+                "expected one of off, error, warn, info, debug or trace, or 0 to 5",
+            ));
+        }
+    }
+
+    if let Some(value) = get_var(ELASTIC_APM_PROFILER_IL_DUMP_FORMAT_ENV_VAR) {
+        if IlDumpFormat::parse(&value).is_none() {
+            warnings.push(EnvVarWarning::ignored(
+                ELASTIC_APM_PROFILER_IL_DUMP_FORMAT_ENV_VAR,
+                &value,
+                "expected one of hex, disasm or raw",
+            ));
+        }
+    }
+
+    if let Some(value) = get_var(ELASTIC_APM_PROFILER_INTEGRATIONS_EXPECTED_SHA256_ENV_VAR) {
+        let hash = value.trim();
+        if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            warnings.push(EnvVarWarning::new(
+                ELASTIC_APM_PROFILER_INTEGRATIONS_EXPECTED_SHA256_ENV_VAR,
+                &value,
+                "expected a hex encoded SHA-256 hash of 64 characters",
+            ));
+        }
+    }
+
+    if let Some(value) = get_var(ELASTIC_APM_PROFILER_INTEGRATIONS_GLOB_ENV_VAR) {
+        if let Err(e) = glob::Pattern::new(value.trim()) {
+            warnings.push(EnvVarWarning::new(
+                ELASTIC_APM_PROFILER_INTEGRATIONS_GLOB_ENV_VAR,
+                &value,
+                format!("invalid glob pattern: {}", e),
+            ));
+        }
+    }
+
+    if let Some(value) = get_var(ELASTIC_APM_PROFILER_EXCEPTION_SAMPLING_RATE_ENV_VAR) {
+        let reason = "expected a number between 0.0 and 1.0";
+        match value.trim().parse::<f64>() {
+            Ok(rate) if (0.0..=1.0).contains(&rate) => {}
+            // clamped to the nearest of 0.0 and 1.0
+            Ok(rate) if !rate.is_nan() => warnings.push(EnvVarWarning::new(
+                ELASTIC_APM_PROFILER_EXCEPTION_SAMPLING_RATE_ENV_VAR,
+                &value,
+                reason,
+            )),
+            _ => warnings.push(EnvVarWarning::ignored(
+                ELASTIC_APM_PROFILER_EXCEPTION_SAMPLING_RATE_ENV_VAR,
+                &value,
+                reason,
+            )),
+        }
+    }
+
+    if let Some(value) = get_var(ELASTIC_APM_PROFILER_CALLTARGET_ALLOW_LIST_ENV_VAR) {
+        for entry in value.split(';').filter(|e| !e.trim().is_empty()) {
+            if parse_calltarget_allow_list_entry(entry).is_none() {
+                warnings.push(EnvVarWarning::new(
+                    ELASTIC_APM_PROFILER_CALLTARGET_ALLOW_LIST_ENV_VAR,
+                    &value,
+                    format!(
+                        "ignoring '{}'. expected Assembly::Type::Method",
+                        entry.trim()
+                    ),
+                ));
+            }
+        }
+    }
+
+    if let Some(value) = get_var(ELASTIC_APM_PROFILER_LOG_LEVEL_OVERRIDES_ENV_VAR) {
+        for entry in value.split(';').filter(|e| !e.trim().is_empty()) {
+            if parse_log_level_override(entry).is_none() {
+                warnings.push(EnvVarWarning::new(
+                    ELASTIC_APM_PROFILER_LOG_LEVEL_OVERRIDES_ENV_VAR,
+                    &value,
+                    format!(
+                        "ignoring '{}'. expected module=level, with level one of off, error, warn, info, debug or trace",
+                        entry.trim()
+                    ),
+                ));
+            }
+        }
+    }
+
+    if let Some(value) = get_var(ELASTIC_APM_PROFILER_LOG_CONTEXT_FIELDS_ENV_VAR) {
+        for entry in value.split(';').filter(|e| !e.trim().is_empty()) {
+            if parse_log_context_field(entry).is_none() {
+                warnings.push(EnvVarWarning::new(
+                    ELASTIC_APM_PROFILER_LOG_CONTEXT_FIELDS_ENV_VAR,
+                    &value,
+                    format!(
+                        "ignoring '{}'. expected key=value, with a key other than {}",
+                        entry.trim(),
+                        RESERVED_LOG_FIELDS.join(", ")
+                    ),
+                ));
+            }
+        }
+    }
+
+    if let Some(value) = get_var(ELASTIC_APM_PROFILER_LOG_FORMAT_ENV_VAR) {
+        if !matches!(value.to_lowercase().as_str(), "text" | "json") {
+            warnings.push(EnvVarWarning::ignored(
+                ELASTIC_APM_PROFILER_LOG_FORMAT_ENV_VAR,
+                &value,
+                "expected one of text or json",
+            ));
+        }
+    }
+
+    if let Some(value) = get_var(ELASTIC_APM_PROFILER_LOG_PREFIX_ENV_VAR) {
+        if value.contains(['\n', '\r']) {
+            warnings.push(EnvVarWarning::new(
+                ELASTIC_APM_PROFILER_LOG_PREFIX_ENV_VAR,
+                &value,
+                "newlines are removed",
+            ));
+        }
+        let len = value.chars().filter(|c| *c != '\n' && *c != '\r').count();
+        if len > MAX_LOG_PREFIX_LEN {
+            warnings.push(EnvVarWarning::new(
+                ELASTIC_APM_PROFILER_LOG_PREFIX_ENV_VAR,
+                &value,
+                format!("truncated to {} characters", MAX_LOG_PREFIX_LEN),
+            ));
+        }
+    }
+
+    if let Some(value) = get_var(ELASTIC_APM_PROFILER_LOG_ROTATION_ENV_VAR) {
+        if !matches!(value.to_lowercase().as_str(), "size" | "daily") {
+            warnings.push(EnvVarWarning::ignored(
+                ELASTIC_APM_PROFILER_LOG_ROTATION_ENV_VAR,
+                &value,
+                "expected one of size or daily",
+            ));
+        }
+    }
+
+    if let Some(value) = get_var(ELASTIC_APM_PROFILER_LOG_TARGETS_ENV_VAR) {
+        let invalid_targets: Vec<&str> = value
+            .split(';')
+            .filter(|s| !s.is_empty() && LogTarget::from_str(s).is_err())
+            .collect();
+        if !invalid_targets.is_empty() {
+            warnings.push(EnvVarWarning::new(
+                ELASTIC_APM_PROFILER_LOG_TARGETS_ENV_VAR,
+                &value,
+                format!("unknown log target(s) {}", invalid_targets.join(", ")),
+            ));
+        }
+    }
+
+    if let Some(value) = get_var(ELASTIC_APM_PROFILER_INTEGRATIONS_ENV_VAR) {
+        let missing_files: Vec<&str> = value
+            .split(';')
+            .map(str::trim)
+            .filter(|p| !p.is_empty() && !Path::new(p).is_file())
+            .collect();
+        if !missing_files.is_empty() {
+            warnings.push(EnvVarWarning::new(
+                ELASTIC_APM_PROFILER_INTEGRATIONS_ENV_VAR,
+                &value,
+                format!("file(s) do not exist {}", missing_files.join(", ")),
+            ));
+        }
+    }
+
+    warnings
+}
+
+/// Logs all problems with the values of profiler environment variables
+pub fn log_env_var_warnings() {
+    let warnings = validate_env_vars();
+    if warnings.is_empty() {
+        return;
+    }
+
+    let report = warnings
+        .iter()
+        .map(|w| format!("  {}=\"{}\": {}", w.key, w.raw_value, w.reason))
+        .collect::<Vec<_>>()
+        .join("\n");
+    log::warn!(
+        "Found {} problem(s) with environment variables\n{}",
+        warnings.len(),
+        report
+    );
+}
+
+/// Logs the resolved value of each profiler setting at DEBUG level, annotated with the
+/// [EnvSource] of the value
+pub fn dump_configuration_to_log(config: &ProfilerConfig) {
+    // read regardless of the log level so that a malformed CLSID is always logged
+    let profiler_clsid = get_coreclr_profiler_clsid();
+    if log::log_enabled!(log::Level::Debug) {
+        log::debug!(
+            "Profiler configuration\n{}\n  profiler_clsid={:?}",
+            format_configuration(config),
+            profiler_clsid
+        );
+
+        // logged here rather than when parsed, since the log level is read before logging
+        // is initialized
+        if let Ok(value) = std::env::var(ELASTIC_APM_PROFILER_LOG_ENV_VAR) {
+            if let (Ok(severity), Some(level)) =
+                (value.trim().parse::<u8>(), parse_log_level(&value))
+            {
+                log::debug!("mapped numeric log level {} to {}", severity, level);
+            }
+        }
+    }
+}
+
+/// Gets the profiler CLSID from the `CORECLR_PROFILER` environment variable, falling back
+/// to the `COR_PROFILER` environment variable. Returns [None] with a warning when the CLSID
+/// is not formatted as `{XXXXXXXX-XXXX-XXXX-XXXX-XXXXXXXXXXXX}`
+pub fn get_coreclr_profiler_clsid() -> Option<String> {
+    get_coreclr_profiler_clsid_with(|key| std::env::var(key).ok())
+}
+
+fn get_coreclr_profiler_clsid_with(get_var: impl Fn(&str) -> Option<String>) -> Option<String> {
+    let (key, value) = ["CORECLR_PROFILER", "COR_PROFILER"]
+        .iter()
+        .find_map(|key| get_var(key).map(|value| (*key, value)))?;
+
+    let clsid = value.trim();
+    if is_valid_clsid(clsid) {
+        Some(clsid.to_string())
+    } else {
+        log::warn!(
+            "{} environment variable value '{}' is not a valid CLSID. \
+            expected {{XXXXXXXX-XXXX-XXXX-XXXX-XXXXXXXXXXXX}}",
+            key,
+            &value
+        );
+        None
+    }
+}
+
+/// Whether the value is a CLSID formatted as `{XXXXXXXX-XXXX-XXXX-XXXX-XXXXXXXXXXXX}`,
+/// where `X` is a hexadecimal digit
+fn is_valid_clsid(value: &str) -> bool {
+    let guid = match value
+        .strip_prefix('{')
+        .and_then(|value| value.strip_suffix('}'))
+    {
+        Some(guid) => guid,
+        None => return false,
+    };
+
+    let groups: Vec<&str> = guid.split('-').collect();
+    groups.len() == 5
+        && groups
+            .iter()
+            .zip([8, 4, 4, 4, 12])
+            .all(|(group, len)| group.len() == len && group.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// Formats each field of the configuration as `key=value (source)` on its own line
+fn format_configuration(config: &ProfilerConfig) -> String {
+    format_configuration_with(config, |key| std::env::var(key).ok())
+}
+
+fn format_configuration_with(
+    config: &ProfilerConfig,
+    get_var: impl Fn(&str) -> Option<String>,
+) -> String {
+    // destructured so that adding a field to the config fails to compile until it is added here
+    let ProfilerConfig {
+        agent_config_path,
+        agent_environment,
+        agent_service_name,
+        calltarget_allow_list,
+        calltarget_batch_size,
+        calltarget_enabled,
+        calltarget_exclude_assemblies,
+        config_dump_enabled,
+        diagnostic_report_path,
+        disable_all,
+        disable_optimizations,
+        enable_inlining,
+        eventlog_source,
+        exception_filter_types,
+        exception_sampling_rate,
+        exclude_integrations,
+        exclude_processes,
+        exclude_service_names,
+        gc_pressure_sampling,
+        heap_profiling_enabled,
+        heap_profiling_interval_secs,
+        heap_profiling_rate,
+        home,
+        il_dump_format,
+        integration_timeout_ms,
+        integrations_cache_path,
+        integrations_download_etag_cache,
+        integrations_download_timeout_secs,
+        integrations_download_url,
+        integrations_expected_sha256,
+        integrations_glob,
+        integrations_path,
+        integrations_inline,
+        integrations_reload_interval_secs,
+        integrations_schema_validate,
+        integrations_watch,
+        log_async_buffer_size,
+        log_context_fields,
+        log_dir,
+        log_flush_interval_ms,
+        log_format,
+        log_il,
+        log_il_dir,
+        log_level,
+        log_level_overrides,
+        log_max_files,
+        log_rotation,
+        log_max_size,
+        log_prefix,
+        log_redact,
+        log_shutdown_timeout_ms,
+        log_targets,
+        managed_loader_path,
+        max_call_targets,
+        metrics_enabled,
+        metrics_interval_secs,
+        print_env,
+        print_help,
+        process_exclude,
+        process_filter,
+        service_name,
+        span_stack_trace_min_duration_ms,
+        temp_dir,
+        thread_cpu_sampling_interval_ms,
+    } = config;
+
+    let settings: [(&str, &str, &dyn fmt::Debug); 64] = [
+        (
+            "agent_config_path",
+            ELASTIC_APM_PROFILER_AGENT_CONFIG_PATH_ENV_VAR,
+            agent_config_path,
+        ),
+        (
+            "agent_environment",
+            ELASTIC_APM_PROFILER_AGENT_ENVIRONMENT_ENV_VAR,
+            agent_environment,
+        ),
+        (
+            "agent_service_name",
+            ELASTIC_APM_PROFILER_AGENT_SERVICE_NAME_ENV_VAR,
+            agent_service_name,
+        ),
+        (
+            "calltarget_allow_list",
+            ELASTIC_APM_PROFILER_CALLTARGET_ALLOW_LIST_ENV_VAR,
+            calltarget_allow_list,
+        ),
+        (
+            "calltarget_batch_size",
+            ELASTIC_APM_PROFILER_CALLTARGET_BATCH_SIZE_ENV_VAR,
+            calltarget_batch_size,
+        ),
+        (
+            "calltarget_enabled",
+            ELASTIC_APM_PROFILER_CALLTARGET_ENABLED_ENV_VAR,
+            calltarget_enabled,
+        ),
+        (
+            "calltarget_exclude_assemblies",
+            ELASTIC_APM_PROFILER_CALLTARGET_EXCLUDE_ASSEMBLIES_ENV_VAR,
+            calltarget_exclude_assemblies,
+        ),
+        (
+            "config_dump_enabled",
+            ELASTIC_APM_PROFILER_CONFIG_DUMP_ENABLED_ENV_VAR,
+            config_dump_enabled,
+        ),
+        (
+            "diagnostic_report_path",
+            ELASTIC_APM_PROFILER_DIAGNOSTIC_REPORT_PATH_ENV_VAR,
+            diagnostic_report_path,
+        ),
+        (
+            "disable_all",
+            ELASTIC_APM_PROFILER_DISABLE_ALL_ENV_VAR,
+            disable_all,
+        ),
+        (
+            "disable_optimizations",
+            ELASTIC_APM_PROFILER_DISABLE_OPTIMIZATIONS_ENV_VAR,
+            disable_optimizations,
+        ),
+        (
+            "enable_inlining",
+            ELASTIC_APM_PROFILER_ENABLE_INLINING_ENV_VAR,
+            enable_inlining,
+        ),
+        (
+            "eventlog_source",
+            ELASTIC_APM_PROFILER_EVENTLOG_SOURCE_ENV_VAR,
+            eventlog_source,
+        ),
+        (
+            "exception_filter_types",
+            ELASTIC_APM_PROFILER_EXCEPTION_FILTER_TYPES_ENV_VAR,
+            exception_filter_types,
+        ),
+        (
+            "exception_sampling_rate",
+            ELASTIC_APM_PROFILER_EXCEPTION_SAMPLING_RATE_ENV_VAR,
+            exception_sampling_rate,
+        ),
+        (
+            "exclude_integrations",
+            ELASTIC_APM_PROFILER_EXCLUDE_INTEGRATIONS_ENV_VAR,
+            exclude_integrations,
+        ),
+        (
+            "exclude_processes",
+            ELASTIC_APM_PROFILER_EXCLUDE_PROCESSES_ENV_VAR,
+            exclude_processes,
+        ),
+        (
+            "exclude_service_names",
+            ELASTIC_APM_PROFILER_EXCLUDE_SERVICE_NAMES_ENV_VAR,
+            exclude_service_names,
+        ),
+        (
+            "gc_pressure_sampling",
+            ELASTIC_APM_PROFILER_GC_PRESSURE_SAMPLING_ENV_VAR,
+            gc_pressure_sampling,
+        ),
+        (
+            "heap_profiling_enabled",
+            ELASTIC_APM_PROFILER_HEAP_PROFILING_ENABLED_ENV_VAR,
+            heap_profiling_enabled,
+        ),
+        (
+            "heap_profiling_interval_secs",
+            ELASTIC_APM_PROFILER_HEAP_PROFILING_INTERVAL_SECS_ENV_VAR,
+            heap_profiling_interval_secs,
+        ),
+        (
+            "heap_profiling_rate",
+            ELASTIC_APM_PROFILER_HEAP_PROFILING_RATE_ENV_VAR,
+            heap_profiling_rate,
+        ),
+        ("home", ELASTIC_APM_PROFILER_HOME_ENV_VAR, home),
+        (
+            "il_dump_format",
+            ELASTIC_APM_PROFILER_IL_DUMP_FORMAT_ENV_VAR,
+            il_dump_format,
+        ),
+        (
+            "integration_timeout_ms",
+            ELASTIC_APM_PROFILER_INTEGRATION_TIMEOUT_MS_ENV_VAR,
+            integration_timeout_ms,
+        ),
+        (
+            "integrations_cache_path",
+            ELASTIC_APM_PROFILER_INTEGRATIONS_CACHE_PATH_ENV_VAR,
+            integrations_cache_path,
+        ),
+        (
+            "integrations_download_etag_cache",
+            ELASTIC_APM_PROFILER_INTEGRATIONS_DOWNLOAD_ETAG_CACHE_ENV_VAR,
+            integrations_download_etag_cache,
+        ),
+        (
+            "integrations_download_timeout_secs",
+            ELASTIC_APM_PROFILER_INTEGRATIONS_DOWNLOAD_TIMEOUT_SECS_ENV_VAR,
+            integrations_download_timeout_secs,
+        ),
+        (
+            "integrations_download_url",
+            ELASTIC_APM_PROFILER_INTEGRATIONS_DOWNLOAD_URL_ENV_VAR,
+            integrations_download_url,
+        ),
+        (
+            "integrations_expected_sha256",
+            ELASTIC_APM_PROFILER_INTEGRATIONS_EXPECTED_SHA256_ENV_VAR,
+            integrations_expected_sha256,
+        ),
+        (
+            "integrations_glob",
+            ELASTIC_APM_PROFILER_INTEGRATIONS_GLOB_ENV_VAR,
+            integrations_glob,
+        ),
+        (
+            "integrations_path",
+            ELASTIC_APM_PROFILER_INTEGRATIONS_ENV_VAR,
+            integrations_path,
+        ),
+        (
+            "integrations_inline",
+            ELASTIC_APM_PROFILER_INTEGRATIONS_INLINE_ENV_VAR,
+            integrations_inline,
+        ),
+        (
+            "integrations_reload_interval_secs",
+            ELASTIC_APM_PROFILER_INTEGRATIONS_RELOAD_INTERVAL_SECS_ENV_VAR,
+            integrations_reload_interval_secs,
+        ),
+        (
+            "integrations_schema_validate",
+            ELASTIC_APM_PROFILER_INTEGRATIONS_SCHEMA_VALIDATE_ENV_VAR,
+            integrations_schema_validate,
+        ),
+        (
+            "integrations_watch",
+            ELASTIC_APM_PROFILER_INTEGRATIONS_WATCH_ENV_VAR,
+            integrations_watch,
+        ),
+        (
+            "log_async_buffer_size",
+            ELASTIC_APM_PROFILER_LOG_ASYNC_BUFFER_SIZE_ENV_VAR,
+            log_async_buffer_size,
+        ),
+        (
+            "log_context_fields",
+            ELASTIC_APM_PROFILER_LOG_CONTEXT_FIELDS_ENV_VAR,
+            log_context_fields,
+        ),
+        ("log_dir", ELASTIC_APM_PROFILER_LOG_DIR_ENV_VAR, log_dir),
+        (
+            "log_flush_interval_ms",
+            ELASTIC_APM_PROFILER_LOG_FLUSH_INTERVAL_MS_ENV_VAR,
+            log_flush_interval_ms,
+        ),
+        (
+            "log_format",
+            ELASTIC_APM_PROFILER_LOG_FORMAT_ENV_VAR,
+            log_format,
+        ),
+        ("log_il", ELASTIC_APM_PROFILER_LOG_IL_ENV_VAR, log_il),
+        (
+            "log_il_dir",
+            ELASTIC_APM_PROFILER_LOG_IL_DIR_ENV_VAR,
+            log_il_dir,
+        ),
+        ("log_level", ELASTIC_APM_PROFILER_LOG_ENV_VAR, log_level),
+        (
+            "log_level_overrides",
+            ELASTIC_APM_PROFILER_LOG_LEVEL_OVERRIDES_ENV_VAR,
+            log_level_overrides,
+        ),
+        (
+            "log_max_files",
+            ELASTIC_APM_PROFILER_LOG_MAX_FILES_ENV_VAR,
+            log_max_files,
+        ),
+        (
+            "log_rotation",
+            ELASTIC_APM_PROFILER_LOG_ROTATION_ENV_VAR,
+            log_rotation,
+        ),
+        (
+            "log_max_size",
+            ELASTIC_APM_PROFILER_LOG_MAX_SIZE_MB_ENV_VAR,
+            log_max_size,
+        ),
+        (
+            "log_prefix",
+            ELASTIC_APM_PROFILER_LOG_PREFIX_ENV_VAR,
+            log_prefix,
+        ),
+        (
+            "log_redact",
+            ELASTIC_APM_PROFILER_LOG_REDACT_ENV_VAR,
+            log_redact,
+        ),
+        (
+            "log_shutdown_timeout_ms",
+            ELASTIC_APM_PROFILER_LOG_SHUTDOWN_TIMEOUT_MS_ENV_VAR,
+            log_shutdown_timeout_ms,
+        ),
+        (
+            "log_targets",
+            ELASTIC_APM_PROFILER_LOG_TARGETS_ENV_VAR,
+            log_targets,
+        ),
+        (
+            "managed_loader_path",
+            ELASTIC_APM_PROFILER_MANAGED_LOADER_PATH_ENV_VAR,
+            managed_loader_path,
+        ),
+        (
+            "max_call_targets",
+            ELASTIC_APM_PROFILER_MAX_CALL_TARGETS_ENV_VAR,
+            max_call_targets,
+        ),
+        (
+            "metrics_enabled",
+            ELASTIC_APM_PROFILER_METRICS_ENABLED_ENV_VAR,
+            metrics_enabled,
+        ),
+        (
+            "metrics_interval_secs",
+            ELASTIC_APM_PROFILER_METRICS_INTERVAL_SECS_ENV_VAR,
+            metrics_interval_secs,
+        ),
+        (
+            "print_env",
+            ELASTIC_APM_PROFILER_PRINT_ENV_ENV_VAR,
+            print_env,
+        ),
+        (
+            "print_help",
+            ELASTIC_APM_PROFILER_PRINT_HELP_ENV_VAR,
+            print_help,
+        ),
+        (
+            "process_exclude",
+            ELASTIC_APM_PROFILER_PROCESS_EXCLUDE_ENV_VAR,
+            process_exclude,
+        ),
+        (
+            "process_filter",
+            ELASTIC_APM_PROFILER_PROCESS_FILTER_ENV_VAR,
+            process_filter,
+        ),
+        (
+            "service_name",
+            ELASTIC_APM_SERVICE_NAME_ENV_VAR,
+            service_name,
+        ),
+        (
+            "span_stack_trace_min_duration_ms",
+            ELASTIC_APM_PROFILER_SPAN_STACK_TRACE_MIN_DURATION_MS_ENV_VAR,
+            span_stack_trace_min_duration_ms,
+        ),
+        ("temp_dir", ELASTIC_APM_PROFILER_TEMP_DIR_ENV_VAR, temp_dir),
+        (
+            "thread_cpu_sampling_interval_ms",
+            ELASTIC_APM_PROFILER_THREAD_CPU_SAMPLING_INTERVAL_MS_ENV_VAR,
+            thread_cpu_sampling_interval_ms,
+        ),
+    ];
+
+    let ignored: HashSet<String> = validate_env_vars_with(&get_var)
+        .into_iter()
+        .filter(|w| w.ignored)
+        .map(|w| w.key)
+        .collect();
+
+    settings
+        .iter()
+        .map(|(name, key, value)| {
+            format!(
+                "  {}={:?} ({})",
+                name,
+                value,
+                env_source_with(key, &get_var, &ignored)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Serializes the profiler configuration as pretty printed JSON
+pub fn profiler_config_as_json() -> String {
+    config_as_json(&PROFILER_CONFIG)
+}
+
+fn config_as_json(config: &ProfilerConfig) -> String {
+    serde_json::to_string_pretty(config).unwrap_or_else(|e| {
+        log::warn!("could not serialize profiler configuration: {}", e);
+        "{}".into()
+    })
+}
+
+/// Gets the profiler path from the profiler path environment variables, if any is set
+fn get_profiler_dir(get_var: impl Fn(&str) -> Option<String>) -> Option<PathBuf> {
+    let (coreclr_env_var, cor_env_var) = if cfg!(target_pointer_width = "64") {
+        ("CORECLR_PROFILER_PATH_64", "COR_PROFILER_PATH_64")
+    } else {
+        ("CORECLR_PROFILER_PATH_32", "COR_PROFILER_PATH_32")
+    };
+
+    // try .NET Core env vars, then .NET Framework env vars
+    [
+        coreclr_env_var,
+        "CORECLR_PROFILER_PATH",
+        cor_env_var,
+        "COR_PROFILER_PATH",
+    ]
+    .iter()
+    .filter_map(|key| get_var(key))
+    .find(|value| !value.trim().is_empty())
+    .map(PathBuf::from)
+}
+
+/// Gets the default log directory on Windows
+#[cfg(target_os = "windows")]
+fn get_default_log_dir() -> PathBuf {
+    get_windows_default_log_dir_with(|key| std::env::var(key).ok())
+}
+
+fn get_windows_default_log_dir_with(get_var: impl Fn(&str) -> Option<String>) -> PathBuf {
+    // ideally we would use the windows function SHGetKnownFolderPath to get
+    // the CommonApplicationData special folder. However, this requires a few package dependencies
+    // like winapi that would increase the size of the profiler binary. Instead,
+    // use the %PROGRAMDATA% environment variable if it exists
+    match get_var("PROGRAMDATA") {
+        Some(path) => {
+            let mut path_buf = PathBuf::from(path);
+            path_buf.push("elastic");
+            path_buf.push("apm-agent-dotnet");
+            path_buf.push("logs");
+            path_buf
+        }
+        None => get_home_log_dir_with(&get_var).unwrap_or_else(|| {
+            std::env::temp_dir()
+                .join("elastic")
+                .join("apm-agent-dotnet")
+                .join("logs")
+        }),
+    }
+}
+
+/// Gets the path to the profiler file on non windows
+#[cfg(not(target_os = "windows"))]
+fn get_default_log_dir() -> PathBuf {
+    PathBuf::from_str("/var/log/elastic/apm-agent-dotnet").unwrap()
+}
+
+/// Gets the logs directory within the profiler home directory, or the profiler path
+/// when the home directory is not set
+fn get_home_log_dir() -> Option<PathBuf> {
+    get_home_log_dir_with(|key| std::env::var(key).ok())
+}
+
+fn get_home_log_dir_with(get_var: impl Fn(&str) -> Option<String>) -> Option<PathBuf> {
+    let mut path_buf = match get_var(ELASTIC_APM_PROFILER_HOME_ENV_VAR) {
+        Some(val) => PathBuf::from(val),
+        None => get_profiler_dir(&get_var)?,
+    };
+
+    path_buf.push("logs");
+    Some(path_buf)
+}
+
+/// Gets the preferred log directory, which is the first of the directories in
+/// [ELASTIC_APM_PROFILER_LOG_DIR_ENV_VAR], or the default log directory
+fn get_log_dir() -> PathBuf {
+    get_configured_log_dirs_with(|key| std::env::var(key).ok())
+        .into_iter()
+        .next()
+        .unwrap_or_else(get_default_log_dir)
+}
+
+/// Gets the semicolon separated list of directories in [ELASTIC_APM_PROFILER_LOG_DIR_ENV_VAR]
+fn get_configured_log_dirs_with(get_var: impl Fn(&str) -> Option<String>) -> Vec<PathBuf> {
+    get_var(ELASTIC_APM_PROFILER_LOG_DIR_ENV_VAR)
+        .unwrap_or_default()
+        .split(';')
+        .map(str::trim)
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .collect()
+}
+
+/// Gets the directories to try in order for log files: `log_dir`, then the directories
+/// in [ELASTIC_APM_PROFILER_LOG_DIR_ENV_VAR], then the default log directory, then the
+/// home log directory, without duplicates
+pub fn get_log_dir_candidates(log_dir: &Path) -> Vec<PathBuf> {
+    log_dir_candidates(
+        log_dir,
+        get_configured_log_dirs_with(|key| std::env::var(key).ok()),
+        get_default_log_dir(),
+        get_home_log_dir(),
+    )
+}
+
+fn log_dir_candidates(
+    log_dir: &Path,
+    configured: Vec<PathBuf>,
+    default_log_dir: PathBuf,
+    home_log_dir: Option<PathBuf>,
+) -> Vec<PathBuf> {
+    let mut candidates = vec![log_dir.to_path_buf()];
+    for dir in configured
+        .into_iter()
+        .chain(std::iter::once(default_log_dir))
+        .chain(home_log_dir)
+    {
+        if !candidates.contains(&dir) {
+            candidates.push(dir);
+        }
+    }
+    candidates
+}
+
+/// Gets the directory in which the profiler creates temporary files, such as downloaded
+/// integrations and configuration dumps. This is [ProfilerConfig::temp_dir] when set,
+/// otherwise the system temp directory when it exists, otherwise the log directory
+pub fn get_temp_dir() -> PathBuf {
+    get_temp_dir_with(
+        PROFILER_CONFIG.temp_dir.as_deref(),
+        &std::env::temp_dir(),
+        get_log_dir,
+    )
+}
+
+fn get_temp_dir_with(
+    configured: Option<&Path>,
+    system_temp_dir: &Path,
+    log_dir: impl FnOnce() -> PathBuf,
+) -> PathBuf {
+    match configured {
+        Some(dir) => dir.to_path_buf(),
+        None if system_temp_dir.is_dir() => system_temp_dir.to_path_buf(),
+        None => log_dir(),
+    }
+}
+
+/// The separator of startup hook paths in [DOTNET_STARTUP_HOOKS_ENV_VAR], which is
+/// the platform path separator used by the .NET runtime
+#[cfg(target_os = "windows")]
+const STARTUP_HOOKS_SEPARATOR: char = ';';
+#[cfg(not(target_os = "windows"))]
+const STARTUP_HOOKS_SEPARATOR: char = ':';
+
+/// Appends the startup hooks in [ELASTIC_APM_PROFILER_STARTUP_HOOKS_ENV_VAR] to
+/// [DOTNET_STARTUP_HOOKS_ENV_VAR], without overwriting or duplicating existing entries.
+/// The .NET runtime reads startup hooks before the profiler is initialized, so the
+/// updated value applies to child processes started by the current process.
+pub fn apply_startup_hooks_env_var() {
+    let additional_hooks = match std::env::var(ELASTIC_APM_PROFILER_STARTUP_HOOKS_ENV_VAR) {
+        Ok(value) => value,
+        Err(_) => return,
+    };
+
+    let existing_hooks = std::env::var(DOTNET_STARTUP_HOOKS_ENV_VAR).ok();
+    let startup_hooks = merge_startup_hooks(existing_hooks.as_deref(), &additional_hooks);
+    if existing_hooks.as_deref() != Some(startup_hooks.as_str()) {
+        log::debug!(
+            "setting {} environment variable to {}",
+            DOTNET_STARTUP_HOOKS_ENV_VAR,
+            &startup_hooks
+        );
+        std::env::set_var(DOTNET_STARTUP_HOOKS_ENV_VAR, startup_hooks);
+    }
+}
+
+/// Whether a profiler instance has already attached to the current process, recording the
+/// attach if not.
+///
+/// The attach is recorded by acquiring a guard named after the process ID, which every
+/// profiler instance in the process sees, including instances loaded from another copy of the
+/// profiler library. The guard is held until the process exits and is not inherited by child
+/// processes
+pub fn is_profiler_already_attached() -> bool {
+    is_profiler_already_attached_with(&format!("elastic_apm_profiler_{}", std::process::id()))
+}
+
+fn is_profiler_already_attached_with(name: &str) -> bool {
+    match attach_guard::acquire(name) {
+        Ok(acquired) => !acquired,
+        Err(e) => {
+            // not being able to detect a second attach shouldn't disable the profiler
+            log::warn!(
+                "is_profiler_already_attached: could not acquire attach guard {}. {}",
+                name,
+                e
+            );
+            false
+        }
+    }
+}
+
+/// A guard on Linux is an abstract unix socket bound to the name. The socket is closed on
+/// exec and when the process exits, and the abstract namespace doesn't leave files behind
+#[cfg(target_os = "linux")]
+mod attach_guard {
+    use std::{io, mem, os::unix::ffi::OsStrExt};
+
+    pub fn acquire(name: &str) -> io::Result<bool> {
+        // SAFETY: socket has no preconditions
+        let fd = unsafe { libc::socket(libc::AF_UNIX, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // SAFETY: sockaddr_un is plain data, for which all zeroes is valid
+        let mut addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+        addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+        // the leading NUL of sun_path places the name in the abstract namespace
+        let name = std::ffi::OsStr::new(name).as_bytes();
+        let len = name.len().min(addr.sun_path.len() - 1);
+        for (dst, src) in addr.sun_path[1..=len].iter_mut().zip(name) {
+            *dst = *src as libc::c_char;
+        }
+        let addr_len = (mem::size_of::<libc::sa_family_t>() + 1 + len) as libc::socklen_t;
+
+        // SAFETY: addr is a valid sockaddr_un of at least addr_len bytes
+        let result = unsafe {
+            libc::bind(
+                fd,
+                &addr as *const libc::sockaddr_un as *const libc::sockaddr,
+                addr_len,
+            )
+        };
+        if result == 0 {
+            // the socket stays open, holding the name, for the lifetime of the process
+            return Ok(true);
+        }
+
+        let e = io::Error::last_os_error();
+        // SAFETY: fd is an open socket that is not used after closing
+        unsafe { libc::close(fd) };
+        if e.raw_os_error() == Some(libc::EADDRINUSE) {
+            Ok(false)
+        } else {
+            Err(e)
+        }
+    }
+}
+
+/// A guard on Windows is a named mutex in the session namespace. The handle is not
+/// inheritable, and the mutex is destroyed when the process exits
+#[cfg(windows)]
+mod attach_guard {
+    use std::{io, iter::once, ptr};
+    use winapi::{
+        shared::winerror::ERROR_ALREADY_EXISTS,
+        um::{errhandlingapi::GetLastError, handleapi::CloseHandle, synchapi::CreateMutexW},
+    };
+
+    pub fn acquire(name: &str) -> io::Result<bool> {
+        let name: Vec<u16> = format!("Local\\{}", name)
+            .encode_utf16()
+            .chain(once(0))
+            .collect();
+        // SAFETY: name is a NUL terminated wide string
+        let handle = unsafe { CreateMutexW(ptr::null_mut(), 0, name.as_ptr()) };
+        if handle.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+
+        // SAFETY: GetLastError has no preconditions
+        if unsafe { GetLastError() } == ERROR_ALREADY_EXISTS {
+            // SAFETY: handle is an open handle that is not used after closing
+            unsafe { CloseHandle(handle) };
+            Ok(false)
+        } else {
+            // the handle stays open, holding the mutex, for the lifetime of the process
+            Ok(true)
+        }
+    }
+}
+
+/// Other platforms don't have a named primitive that is released on exit, so the guard only
+/// detects a second attach by the same copy of the profiler library
+#[cfg(not(any(target_os = "linux", windows)))]
+mod attach_guard {
+    use once_cell::sync::Lazy;
+    use std::{collections::HashSet, io, sync::Mutex};
+
+    static ACQUIRED: Lazy<Mutex<HashSet<String>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+    pub fn acquire(name: &str) -> io::Result<bool> {
+        Ok(ACQUIRED
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(name.to_string()))
+    }
+}
+
+/// Passes the path from [get_agent_config_path] to the managed loader and the agent
+/// through [ELASTIC_APM_AGENT_CONFIG_FILE_ENV_VAR]. A value already set for
+/// [ELASTIC_APM_AGENT_CONFIG_FILE_ENV_VAR] takes precedence.
+pub fn apply_agent_config_env_var() {
+    let path = match get_agent_config_path() {
+        Some(path) => path,
+        None => return,
+    };
+
+    if let Ok(existing) = std::env::var(ELASTIC_APM_AGENT_CONFIG_FILE_ENV_VAR) {
+        log::info!(
+            "{} is already set to {}. Ignoring {}",
+            ELASTIC_APM_AGENT_CONFIG_FILE_ENV_VAR,
+            existing,
+            ELASTIC_APM_PROFILER_AGENT_CONFIG_PATH_ENV_VAR
+        );
+        return;
+    }
+
+    log::info!("using agent config file {}", path.display());
+    std::env::set_var(ELASTIC_APM_AGENT_CONFIG_FILE_ENV_VAR, path);
+}
+
+/// Passes the service name from [get_agent_service_name] to the managed loader and the
+/// agent through [ELASTIC_APM_SERVICE_NAME_ENV_VAR].
+///
+/// A value already set for [ELASTIC_APM_SERVICE_NAME_ENV_VAR] is the agent's own setting
+/// and takes precedence, so that the profiler never overrides a service name configured
+/// for the agent directly.
+pub fn apply_agent_service_name_env_var() {
+    apply_agent_env_var(
+        ELASTIC_APM_SERVICE_NAME_ENV_VAR,
+        ELASTIC_APM_PROFILER_AGENT_SERVICE_NAME_ENV_VAR,
+        get_agent_service_name().as_deref(),
+    )
+}
+
+/// Passes the deployment environment from [get_agent_environment] to the managed loader and
+/// the agent through [ELASTIC_APM_ENVIRONMENT_ENV_VAR]. As with
+/// [apply_agent_service_name_env_var], a value already set for the agent takes precedence.
+pub fn apply_agent_environment_env_var() {
+    apply_agent_env_var(
+        ELASTIC_APM_ENVIRONMENT_ENV_VAR,
+        ELASTIC_APM_PROFILER_AGENT_ENVIRONMENT_ENV_VAR,
+        get_agent_environment().as_deref(),
+    )
+}
+
+/// Passes [ProfilerConfig::span_stack_trace_min_duration_ms] to the agent through
+/// [ELASTIC_APM_SPAN_STACK_TRACE_MIN_DURATION_ENV_VAR], when it is set. Otherwise, the agent's
+/// own configuration and default apply.
+///
+/// The agent captures the managed stack trace of a span when it ends, in the CallTarget
+/// `EndMethod` exit hook for CallTarget integrations, and attaches the stack trace when the
+/// span's duration is at least the minimum. As with [apply_agent_service_name_env_var], a
+/// value already set for the agent takes precedence.
+pub fn apply_agent_span_stack_trace_env_var() {
+    apply_agent_span_stack_trace_env_var_with(&PROFILER_CONFIG)
+}
+
+fn apply_agent_span_stack_trace_env_var_with(config: &ProfilerConfig) {
+    apply_agent_env_var(
+        ELASTIC_APM_SPAN_STACK_TRACE_MIN_DURATION_ENV_VAR,
+        ELASTIC_APM_PROFILER_SPAN_STACK_TRACE_MIN_DURATION_MS_ENV_VAR,
+        config
+            .span_stack_trace_min_duration_ms
+            .map(|ms| format!("{}ms", ms))
+            .as_deref(),
+    );
+}
+
+/// Sets the agent environment variable `agent_key` to the `value` of the profiler
+/// environment variable `profiler_key`, unless `agent_key` is already set
+fn apply_agent_env_var(agent_key: &str, profiler_key: &str, value: Option<&str>) {
+    let value = match value {
+        Some(value) => value,
+        None => return,
+    };
+
+    if let Ok(existing) = std::env::var(agent_key) {
+        log::info!(
+            "{} is already set to {}. Ignoring {}",
+            agent_key,
+            existing,
+            profiler_key
+        );
+        return;
+    }
+
+    log::info!("setting {} to {} from {}", agent_key, value, profiler_key);
+    std::env::set_var(agent_key, value);
+}
+
+fn merge_startup_hooks(existing_hooks: Option<&str>, additional_hooks: &str) -> String {
+    let mut hooks: Vec<&str> = existing_hooks
+        .unwrap_or_default()
+        .split(STARTUP_HOOKS_SEPARATOR)
+        .filter(|h| !h.is_empty())
+        .collect();
+
+    for hook in additional_hooks
+        .split(STARTUP_HOOKS_SEPARATOR)
+        .map(str::trim)
+        .filter(|h| !h.is_empty())
+    {
+        if !hooks.contains(&hook) {
+            hooks.push(hook);
+        }
+    }
+
+    hooks.join(&STARTUP_HOOKS_SEPARATOR.to_string())
+}
+
+/// Gets the version of the profiler
+pub fn profiler_version() -> &'static str {
+    env!("CARGO_PKG_VERSION")
+}
+
+/// Information about the operating system, logged and included in diagnostic reports
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct OsInfo {
+    /// The operating system, such as `linux`, `macos` or `windows`
+    pub os_name: String,
+    /// The version of the operating system, or `unknown` if it can't be determined
+    pub os_version: String,
+    /// The CPU architecture, such as `x86_64` or `aarch64`
+    pub arch: String,
+}
+
+impl Display for OsInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} ({})", &self.os_name, &self.os_version, &self.arch)
+    }
+}
+
+/// Gets information about the operating system
+pub fn os_info() -> OsInfo {
+    OsInfo {
+        os_name: std::env::consts::OS.into(),
+        os_version: os_version().unwrap_or_else(|| "unknown".into()),
+        arch: std::env::consts::ARCH.into(),
+    }
+}
+
+/// Gets the pretty name of the Linux distribution from `/etc/os-release`
+#[cfg(target_os = "linux")]
+fn os_version() -> Option<String> {
+    std::fs::read_to_string("/etc/os-release")
+        .or_else(|_| std::fs::read_to_string("/usr/lib/os-release"))
+        .ok()
+        .and_then(|contents| parse_os_release(&contents))
+}
+
+/// Gets the Windows version as `major.minor.build`
+#[cfg(target_os = "windows")]
+fn os_version() -> Option<String> {
+    use winapi::um::{sysinfoapi::GetVersionExW, winnt::OSVERSIONINFOW};
+
+    // SAFETY: OSVERSIONINFOW is plain data, and its size is set as GetVersionExW requires
+    let mut info: OSVERSIONINFOW = unsafe { std::mem::zeroed() };
+    info.dwOSVersionInfoSize = std::mem::size_of::<OSVERSIONINFOW>() as u32;
+    if unsafe { GetVersionExW(&mut info) } == 0 {
+        return None;
+    }
+    Some(format!(
+        "{}.{}.{}",
+        info.dwMajorVersion, info.dwMinorVersion, info.dwBuildNumber
+    ))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+fn os_version() -> Option<String> {
+    None
+}
+
+/// Parses the version from the contents of an os-release file, preferring `PRETTY_NAME`
+/// over `NAME` and `VERSION_ID`
+fn parse_os_release(contents: &str) -> Option<String> {
+    let value = |key: &str| {
+        contents
+            .lines()
+            .filter_map(|line| line.split_once('='))
+            .find(|(k, _)| k.trim() == key)
+            .map(|(_, v)| v.trim().trim_matches(|c| c == '"' || c == '\'').to_string())
+            .filter(|v| !v.is_empty())
+    };
+
+    value("PRETTY_NAME").or_else(|| match (value("NAME"), value("VERSION_ID")) {
+        (Some(name), Some(version)) => Some(format!("{} {}", name, version)),
+        (name, version) => name.or(version),
+    })
+}
+
+/// Gets the name of the current process, stripping the .exe extension on Windows.
+/// Returns "unknown" if the name can't be determined
+pub fn get_process_name() -> String {
+    current_exe()
+        .ok()
+        .and_then(|path| get_process_name_from_path(&path))
+        .unwrap_or_else(|| "unknown".into())
+}
+
+fn get_process_name_from_path(path: &Path) -> Option<String> {
+    let file_name = path.file_name()?.to_string_lossy();
+    if cfg!(target_os = "windows") {
+        let len = file_name.len();
+        if len > 4
+            && file_name.is_char_boundary(len - 4)
+            && file_name[len - 4..].eq_ignore_ascii_case(".exe")
+        {
+            return Some(file_name[..len - 4].to_string());
+        }
+    }
+    Some(file_name.to_string())
+}
+
+#[cfg(not(test))]
+fn current_exe() -> std::io::Result<PathBuf> {
+    std::env::current_exe()
+}
+
+#[cfg(test)]
+thread_local! {
+    /// Overrides the path returned by [current_exe] in tests
+    static CURRENT_EXE: std::cell::RefCell<Option<PathBuf>> =
+        const { std::cell::RefCell::new(None) };
+}
+
+#[cfg(test)]
+fn current_exe() -> std::io::Result<PathBuf> {
+    match CURRENT_EXE.with(|c| c.borrow().clone()) {
+        Some(path) => Ok(path),
+        None => std::env::current_exe(),
+    }
+}
+
+/// Loads the integrations by reading the yml file pointed to
+/// by [ELASTIC_APM_PROFILER_INTEGRATIONS] environment variable, or the yml
+/// supplied by [ELASTIC_APM_PROFILER_INTEGRATIONS_INLINE] environment variable, filtering
+/// integrations by [ELASTIC_APM_PROFILER_EXCLUDE_INTEGRATIONS_ENV_VAR] environment variable,
+/// if present. The integrations are cached after they are first loaded successfully,
+/// until the cache is invalidated with [invalidate_integrations_cache].
+/// No integrations are loaded when [ELASTIC_APM_PROFILER_DISABLE_ALL] is true. Integrations
+/// that do not support the runtime version set with [set_runtime_version], or the
+/// architecture of the process, are skipped
+pub fn load_integrations() -> Result<Vec<Integration>, ProfilerError> {
+    let integrations = filter_architecture_integrations(
+        filter_empty_integrations(filter_enabled_integrations(load_all_integrations()?)),
+        current_architecture(),
+    );
+    Ok(match RUNTIME_VERSION.get() {
+        Some(version) => filter_supported_integrations(integrations, version),
+        None => integrations,
+    })
+}
+
+/// Calls `load` on another thread when `timeout_ms` is greater than 0, so that loading from
+/// a slow file system can be abandoned
+fn load_integrations_with_timeout(
+    timeout_ms: u64,
+    load: impl FnOnce() -> Result<Vec<Integration>, ProfilerError> + Send + 'static,
+) -> Result<Vec<Integration>, ProfilerError> {
+    if timeout_ms == 0 {
+        return load();
+    }
+
+    with_timeout("loading integrations", timeout_ms, load)?
+}
+
+/// The version of the runtime into which the profiler is loaded
+static RUNTIME_VERSION: OnceCell<String> = OnceCell::new();
+
+/// Sets the version of the runtime into which the profiler is loaded, against which
+/// integrations are checked when loaded. The version can only be set once
+pub fn set_runtime_version(version: String) {
+    if RUNTIME_VERSION.set(version).is_err() {
+        log::debug!("runtime version already set");
+    }
+}
+
+/// Loads all integrations, including those that are disabled. Loading fails with
+/// [ProfilerErrorKind::Timeout] when it takes longer than [integrations_load_timeout_ms]
+pub fn load_all_integrations() -> Result<Vec<Integration>, ProfilerError> {
+    load_cached_integrations(&INTEGRATIONS_CACHE, || {
+        load_integrations_with_timeout(integrations_load_timeout_ms(&PROFILER_CONFIG), || {
+            load_integrations_with_config(&PROFILER_CONFIG)
+        })
+    })
+}
+
+/// The timeout for loading integrations. Downloaded integrations are not loaded with a
+/// timeout, because [download_integrations] has its own, and falls back to the previous
+/// download when it elapses
+fn integrations_load_timeout_ms(config: &ProfilerConfig) -> u64 {
+    if config.integrations_download_url.is_some() {
+        0
+    } else {
+        config.integration_timeout_ms
+    }
+}
+
+fn filter_enabled_integrations(integrations: Vec<Integration>) -> Vec<Integration> {
+    let count = integrations.len();
+    let integrations: Vec<Integration> = integrations
+        .into_iter()
+        .filter(Integration::is_enabled)
+        .collect();
+    log::debug!(
+        "skipped {} disabled integration(s)",
+        count - integrations.len()
+    );
+    integrations
+}
+
+fn filter_empty_integrations(integrations: Vec<Integration>) -> Vec<Integration> {
+    integrations
+        .into_iter()
+        .filter(|integration| {
+            if integration.is_empty() {
+                log::debug!(
+                    "skipping integration {}. it has no method replacements",
+                    &integration.name
+                );
+            }
+            !integration.is_empty()
+        })
+        .collect()
+}
+
+fn filter_supported_integrations(
+    integrations: Vec<Integration>,
+    runtime_version: &str,
+) -> Vec<Integration> {
+    integrations
+        .into_iter()
+        .filter(|integration| {
+            let supported = integration.supports_runtime(runtime_version);
+            if !supported {
+                log::debug!(
+                    "skipping integration {}. runtime version {} is not within [{}, {}]",
+                    &integration.name,
+                    runtime_version,
+                    integration
+                        .min_target_framework_version
+                        .as_ref()
+                        .map_or_else(|| "*".to_string(), |v| v.to_string()),
+                    integration
+                        .max_target_framework_version
+                        .as_ref()
+                        .map_or_else(|| "*".to_string(), |v| v.to_string())
+                );
+            }
+            supported
+        })
+        .collect()
+}
+
+/// Gets the architecture of the process, one of `x64`, `x86` or `arm64`
+fn current_architecture() -> &'static str {
+    if cfg!(target_arch = "x86") {
+        "x86"
+    } else if cfg!(target_arch = "aarch64") {
+        "arm64"
+    } else {
+        "x64"
+    }
+}
+
+fn filter_architecture_integrations(
+    integrations: Vec<Integration>,
+    architecture: &str,
+) -> Vec<Integration> {
+    let count = integrations.len();
+    let integrations: Vec<Integration> = integrations
+        .into_iter()
+        .filter(|integration| {
+            let supported = integration.supports_architecture(architecture);
+            if !supported {
+                log::info!(
+                    "skipping integration {}. process architecture {} is not one of its supported architectures [{}]",
+                    &integration.name,
+                    architecture,
+                    integration
+                        .supported_architectures
+                        .as_deref()
+                        .unwrap_or_default()
+                        .join(", ")
+                );
+            }
+            supported
+        })
+        .collect();
+
+    if cfg!(target_arch = "x86") && integrations.len() < count {
+        log::warn!(
+            "running as a 32-bit profiler. skipped {} integration(s) that do not support x86",
+            count - integrations.len()
+        );
+    }
+
+    integrations
+}
+
+/// Invalidates the cached integrations, so that the next call to [load_integrations]
+/// loads them again
+pub fn invalidate_integrations_cache() {
+    *INTEGRATIONS_CACHE.write().unwrap() = None;
+}
+
+/// The cached integrations. [None] when integrations have not been loaded
+static INTEGRATIONS_CACHE: Lazy<Arc<RwLock<Option<Vec<Integration>>>>> =
+    Lazy::new(|| Arc::new(RwLock::new(None)));
+
+fn load_cached_integrations(
+    cache: &RwLock<Option<Vec<Integration>>>,
+    load: impl FnOnce() -> Result<Vec<Integration>, ProfilerError>,
+) -> Result<Vec<Integration>, ProfilerError> {
+    if let Some(integrations) = cache.read().unwrap().as_ref() {
+        return Ok(integrations.clone());
+    }
+
+    let mut cached = cache.write().unwrap();
+    // another thread may have loaded integrations while waiting for the write lock
+    if let Some(integrations) = cached.as_ref() {
+        return Ok(integrations.clone());
+    }
+
+    let integrations = load()?;
+    *cached = Some(integrations.clone());
+    Ok(integrations)
+}
+
+/// The format of an integrations file
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum IntegrationsFormat {
+    Yaml,
+    Json,
+    Toml,
+}
+
+impl IntegrationsFormat {
+    /// Determines the format from the file extension, defaulting to yml
+    fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("json") => IntegrationsFormat::Json,
+            Some(ext) if ext.eq_ignore_ascii_case("toml") => IntegrationsFormat::Toml,
+            _ => IntegrationsFormat::Yaml,
+        }
+    }
+}
+
+/// Parses integrations from yml, skipping malformed integrations
+fn parse_integrations_yaml(reader: impl Read) -> Result<Vec<Integration>, ProfilerError> {
+    let entries: Vec<serde_yaml::Value> = serde_yaml::from_reader(reader)?;
+    deserialize_integrations(entries)
+}
+
+/// Parses integrations from json, skipping malformed integrations
+fn parse_integrations_json(reader: impl Read) -> Result<Vec<Integration>, ProfilerError> {
+    let entries: Vec<serde_json::Value> = serde_json::from_reader(reader)?;
+    deserialize_integrations(entries)
+}
+
+/// Integrations in toml, which defines integrations as an `[[integrations]]` array of tables
+/// because the root of a toml document must be a table
+#[derive(Debug, Deserialize, Serialize)]
+struct IntegrationsToml<T = Integration> {
+    integrations: Vec<T>,
+}
+
+/// Parses integrations from toml, skipping malformed integrations
+fn parse_integrations_toml(mut reader: impl Read) -> Result<Vec<Integration>, ProfilerError> {
+    let mut toml = String::new();
+    reader.read_to_string(&mut toml)?;
+    let entries: IntegrationsToml<toml::Value> = toml::from_str(&toml)?;
+    deserialize_integrations(entries.integrations)
+}
+
+/// Deserializes each entry into an [Integration]. Malformed entries are logged with their
+/// index and skipped, so that one malformed integration does not prevent loading the others.
+/// Unknown fields are logged, and are an error when
+/// [ProfilerConfig::integrations_schema_validate] is `true`
+fn deserialize_integrations<V: Deserializer<'static>>(
+    entries: Vec<V>,
+) -> Result<Vec<Integration>, ProfilerError> {
+    deserialize_integrations_with(entries, PROFILER_CONFIG.integrations_schema_validate)
+}
+
+fn deserialize_integrations_with<V: Deserializer<'static>>(
+    entries: Vec<V>,
+    schema_validate: bool,
+) -> Result<Vec<Integration>, ProfilerError> {
+    let mut integrations = Vec::with_capacity(entries.len());
+    let mut malformed = Vec::new();
+    let mut unknown_fields = Vec::new();
+    for (index, entry) in entries.into_iter().enumerate() {
+        let raw = match serde_yaml::Value::deserialize(entry) {
+            Ok(raw) => raw,
+            Err(e) => {
+                malformed.push((index, e.to_string()));
+                continue;
+            }
+        };
+        let schema_version = integration_schema_version(&raw);
+        if schema_version > INTEGRATION_SCHEMA_VERSION {
+            log::warn!(
+                "integration at index {} has schema version {}, newer than the supported schema \
+                version {}",
+                index,
+                schema_version,
+                INTEGRATION_SCHEMA_VERSION
+            );
+        }
+        let raw = migrate_integration(raw, schema_version);
+
+        let mut unknown = Vec::new();
+        match serde_ignored::deserialize::<_, _, Integration>(raw, |path| {
+            unknown.push(path.to_string())
+        }) {
+            Ok(integration) => {
+                if !unknown.is_empty() {
+                    log::warn!(
+                        "integration {} at index {} has unknown field(s): {}",
+                        &integration.name,
+                        index,
+                        unknown.join(", ")
+                    );
+                    unknown_fields.extend(
+                        unknown
+                            .into_iter()
+                            .map(|field| format!("{}.{}", &integration.name, field)),
+                    );
+                }
+                integrations.push(integration);
+            }
+            Err(e) => malformed.push((index, e.to_string())),
+        }
+    }
+
+    for (index, e) in &malformed {
+        log::warn!("skipping malformed integration at index {}: {}", index, e);
+    }
+
+    if schema_validate && !unknown_fields.is_empty() {
+        return Err(ProfilerError::new(ProfilerErrorKind::IntegrationsSchema(
+            unknown_fields,
+        )));
+    }
+
+    Ok(integrations)
+}
+
+/// Transforms a raw integration to the schema version migrated to
+type IntegrationMigration = fn(serde_yaml::Value) -> serde_yaml::Value;
+
+/// The migrations of raw integrations, ordered by the schema version that each migrates to.
+/// The migration to version `n` transforms an integration in version `n - 1` to version `n`.
+/// Add a migration here when incrementing [INTEGRATION_SCHEMA_VERSION]
+const INTEGRATION_MIGRATIONS: &[(u8, IntegrationMigration)] = &[];
+
+/// Gets the schema version of a raw integration, defaulting to `1` when absent. A value
+/// that is not a valid version is returned as `1`, and fails deserialization later
+fn integration_schema_version(raw: &serde_yaml::Value) -> u8 {
+    raw.get("schema_version")
+        .and_then(serde_yaml::Value::as_u64)
+        .and_then(|v| u8::try_from(v).ok())
+        .unwrap_or(1)
+}
+
+/// Transforms a raw integration from `schema_version` to [INTEGRATION_SCHEMA_VERSION],
+/// before it is deserialized into an [Integration]
+pub fn migrate_integration(raw: serde_yaml::Value, schema_version: u8) -> serde_yaml::Value {
+    migrate_integration_with(raw, schema_version, INTEGRATION_MIGRATIONS)
+}
+
+fn migrate_integration_with(
+    mut raw: serde_yaml::Value,
+    schema_version: u8,
+    migrations: &[(u8, IntegrationMigration)],
+) -> serde_yaml::Value {
+    for (version, migrate) in migrations {
+        if *version <= schema_version {
+            continue;
+        }
+        raw = migrate(raw);
+        if let serde_yaml::Value::Mapping(mapping) = &mut raw {
+            mapping.insert(
+                "schema_version".into(),
+                serde_yaml::Value::Number((*version).into()),
+            );
+        }
+    }
+    raw
+}
+
+/// Reads integrations from the file at the given path, in the format
+/// determined by the file extension. When [ProfilerConfig::integrations_expected_sha256]
+/// is set, the hash of the file is verified before it is parsed. The file is read once, so
+/// that the bytes parsed are the bytes verified
+fn read_integrations_file(path: &str) -> Result<Vec<Integration>, ProfilerError> {
+    let error_kind = || ProfilerErrorKind::IntegrationsFile(path.into());
+    let bytes = std::fs::read(path).map_err(|e| ProfilerError::with_source(error_kind(), e))?;
+    if let Some(expected) = &PROFILER_CONFIG.integrations_expected_sha256 {
+        verify_hash(Path::new(path), &bytes, expected)?;
+    }
+    parse_integrations_bytes(Path::new(path), &bytes)
+        .map_err(|e| ProfilerError::with_source(error_kind(), e))
+}
+
+/// Verifies that the SHA-256 hash of the file at `path` is the hex encoded `expected` hash,
+/// compared case insensitively
+pub fn verify_file_hash(path: &Path, expected: &str) -> Result<(), ProfilerError> {
+    let bytes = std::fs::read(path).map_err(|e| {
+        ProfilerError::with_source(ProfilerErrorKind::IntegrationsFile(path.into()), e)
+    })?;
+    verify_hash(path, &bytes, expected)
+}
+
+/// Verifies that the SHA-256 hash of the `bytes` read from `path` is the hex encoded
+/// `expected` hash, compared case insensitively
+fn verify_hash(path: &Path, bytes: &[u8], expected: &str) -> Result<(), ProfilerError> {
+    let mut sha256 = Sha256::new();
+    sha256.input(bytes);
+    let actual = sha256.result_str();
+    if actual.eq_ignore_ascii_case(expected.trim()) {
+        Ok(())
+    } else {
+        Err(ProfilerError::new(ProfilerErrorKind::HashMismatch {
+            path: path.into(),
+            expected: expected.trim().to_lowercase(),
+            actual,
+        }))
+    }
+}
+
+/// Verifies that at most one integrations file is read when an expected hash is set, since
+/// the same hash can't be the hash of more than one file
+fn verify_single_hashed_file(
+    expected_sha256: Option<&String>,
+    paths: &[PathBuf],
+) -> Result<(), ProfilerError> {
+    if expected_sha256.is_some() && paths.len() > 1 {
+        return Err(ProfilerError::new(ProfilerErrorKind::HashMultipleFiles(
+            paths.to_vec(),
+        )));
+    }
+    Ok(())
+}
+
+/// Parses integrations from the bytes read from the file at `path`, in the format
+/// determined by the file extension
+fn parse_integrations_bytes(path: &Path, bytes: &[u8]) -> Result<Vec<Integration>, ProfilerError> {
+    let integrations = match IntegrationsFormat::from_path(path) {
+        IntegrationsFormat::Yaml => parse_integrations_yaml(bytes)?,
+        IntegrationsFormat::Json => parse_integrations_json(bytes)?,
+        IntegrationsFormat::Toml => parse_integrations_toml(bytes)?,
+    };
+    Ok(integrations)
+}
+
+/// Reads and merges the integrations files in a semicolon separated list of paths.
+/// When an integration with the same name is in multiple files, the last file wins.
+fn read_integrations_files(paths: &str) -> Result<Vec<Integration>, ProfilerError> {
+    merge_integrations_files(paths.split(';').map(str::trim).filter(|p| !p.is_empty()))
+}
+
+/// Reads and merges the integrations files matching the glob pattern, in lexicographic order
+/// of their paths. When an integration with the same name is in multiple files,
+/// the last file wins.
+pub fn load_integrations_from_glob(pattern: &str) -> Result<Vec<Integration>, ProfilerError> {
+    let paths: Vec<String> = glob_integrations_file_paths(pattern)?
+        .iter()
+        .map(|path| path.to_string_lossy().to_string())
+        .collect();
+    merge_integrations_files(paths.iter().map(String::as_str))
+}
+
+/// Gets the paths of the files matching the glob pattern, sorted lexicographically
+fn glob_integrations_file_paths(pattern: &str) -> Result<Vec<PathBuf>, ProfilerError> {
+    let error_kind = || ProfilerErrorKind::IntegrationsGlob(pattern.to_string());
+    let entries = glob::glob(pattern).map_err(|e| ProfilerError::with_source(error_kind(), e))?;
+
+    let mut paths = Vec::new();
+    for entry in entries {
+        match entry {
+            Ok(path) if path.is_file() => paths.push(path),
+            Ok(_) => {}
+            Err(e) => log::warn!("skipping integrations file matching {}: {}", pattern, e),
+        }
+    }
+
+    if paths.is_empty() {
+        return Err(ProfilerError::with_source(error_kind(), "no files match"));
+    }
+
+    paths.sort();
+    Ok(paths)
+}
+
+fn merge_integrations_files<'a>(
+    paths: impl IntoIterator<Item = &'a str>,
+) -> Result<Vec<Integration>, ProfilerError> {
+    let paths: Vec<&str> = paths.into_iter().collect();
+    verify_single_hashed_file(
+        PROFILER_CONFIG.integrations_expected_sha256.as_ref(),
+        &paths.iter().map(PathBuf::from).collect::<Vec<_>>(),
+    )?;
+
+    let mut integrations: Vec<Integration> = Vec::new();
+    for path in paths {
+        for integration in read_integrations_file(path)? {
+            match integrations.iter_mut().find(|i| i.name == integration.name) {
+                Some(existing) => {
+                    log::warn!(
+                        "integration {} in integrations file {} overrides a previously loaded integration of the same name",
+                        &integration.name,
+                        path
+                    );
+                    *existing = integration;
+                }
+                None => integrations.push(integration),
+            }
+        }
+    }
+
+    Ok(dedup_integrations(integrations))
+}
+
+/// Removes integrations that duplicate a later integration, by comparing the targets of
+/// their method replacements, preserving the order of the remaining integrations
+fn dedup_integrations(integrations: Vec<Integration>) -> Vec<Integration> {
+    let mut seen: BTreeSet<&Integration> = BTreeSet::new();
+    // iterate in reverse so that, as for integrations of the same name, the last one wins
+    let keep: Vec<bool> = integrations
+        .iter()
+        .rev()
+        .map(|integration| match seen.get(integration) {
+            Some(duplicate) => {
+                log::warn!(
+                    "skipping integration {} with wrappers [{}]. it instruments the same methods as integration {} with wrappers [{}]",
+                    &integration.name,
+                    integration.wrapper_names().join(", "),
+                    &duplicate.name,
+                    duplicate.wrapper_names().join(", ")
+                );
+                false
+            }
+            None => seen.insert(integration),
+        })
+        .collect();
+
+    integrations
+        .into_iter()
+        .zip(keep.into_iter().rev())
+        .filter_map(|(integration, keep)| if keep { Some(integration) } else { None })
+        .collect()
+}
+
+/// Loads the integrations from the files matching [ProfilerConfig::integrations_glob],
+/// falling back to [ProfilerConfig::integrations_path],
+/// [ProfilerConfig::integrations_inline] and then integrations.yml in [ProfilerConfig::home],
+/// filtering integrations by [ProfilerConfig::exclude_integrations], if present.
+/// Integrations read from files are cached in [ProfilerConfig::integrations_cache_path],
+/// if present
+fn load_integrations_with_config(
+    config: &ProfilerConfig,
+) -> Result<Vec<Integration>, ProfilerError> {
+    if config.disable_all {
+        log::debug!(
+            "{} is true. not loading integrations",
+            ELASTIC_APM_PROFILER_DISABLE_ALL_ENV_VAR
+        );
+        return Ok(Vec::new());
+    }
+
+    let (source, mut integrations) = match &config.integrations_cache_path {
+        Some(cache_path) => load_integrations_cached(config, cache_path)?,
+        None => read_configured_integrations(config)?,
+    };
+
+    log::trace!(
+        "loaded {} integration(s) from {}",
+        integrations.len(),
+        &source
+    );
+
+    // Now filter integrations
+    if let Some(exclude_integrations) = &config.exclude_integrations {
+        for exclude_integration in exclude_integrations {
+            log::trace!("exclude integrations that match {}", exclude_integration);
+            integrations.retain(|i| i.name.to_lowercase() != exclude_integration.to_lowercase());
+        }
+    };
+
+    apply_integration_env_overrides(&mut integrations);
+
+    if let Some(max_call_targets) = config.max_call_targets {
+        limit_call_targets(
+            &mut integrations,
+            max_call_targets,
+            config.calltarget_enabled,
+        );
+    }
 
     Ok(integrations)
 }
+
+/// Reads the integrations from the source configured in `config`, returning the
+/// name of the source with the integrations
+fn read_configured_integrations(
+    config: &ProfilerConfig,
+) -> Result<(String, Vec<Integration>), ProfilerError> {
+    let (source, integrations) = if let Some(url) = &config.integrations_download_url {
+        let timeout = Duration::from_secs(config.integrations_download_timeout_secs);
+        let integrations = download_integrations(
+            url,
+            config.integrations_expected_sha256.as_deref(),
+            timeout,
+            config.integrations_download_etag_cache,
+        )?;
+        (url.clone(), integrations)
+    } else if let Some(pattern) = &config.integrations_glob {
+        (pattern.clone(), load_integrations_from_glob(pattern)?)
+    } else if let Some(path) = &config.integrations_path {
+        (path.clone(), read_integrations_files(path)?)
+    } else if let Some(yaml) = &config.integrations_inline {
+        log::debug!(
+            "{} environment variable not set. reading integrations from {} environment variable value",
+            ELASTIC_APM_PROFILER_INTEGRATIONS_ENV_VAR,
+            ELASTIC_APM_PROFILER_INTEGRATIONS_INLINE_ENV_VAR
+        );
+
+        let integrations = parse_integrations_yaml(yaml.as_bytes())
+            .map_err(|e| ProfilerError::with_source(ProfilerErrorKind::IntegrationsInline, e))?;
+        (
+            ELASTIC_APM_PROFILER_INTEGRATIONS_INLINE_ENV_VAR.to_string(),
+            integrations,
+        )
+    } else {
+        log::debug!(
+            "{} and {} environment variables not set. trying integrations.yml in directory of {} environment variable value",
+            ELASTIC_APM_PROFILER_INTEGRATIONS_ENV_VAR,
+            ELASTIC_APM_PROFILER_INTEGRATIONS_INLINE_ENV_VAR,
+            ELASTIC_APM_PROFILER_HOME_ENV_VAR
+        );
+
+        match &config.home {
+            Some(val) => {
+                let path = val.join("integrations.yml").to_string_lossy().to_string();
+                let integrations = read_integrations_file(&path)?;
+                (path, integrations)
+            }
+            None => {
+                return Err(ProfilerError::new(
+                    ProfilerErrorKind::IntegrationsNotConfigured,
+                ));
+            }
+        }
+    };
+
+    Ok((source, integrations))
+}
+
+/// Downloads the integrations file from the https `url`, verifying that it has the
+/// `expected_sha256` hash, which must be configured. See [download_verified_integrations]
+fn download_integrations(
+    url: &str,
+    expected_sha256: Option<&str>,
+    timeout: Duration,
+    etag_cache: bool,
+) -> Result<Vec<Integration>, ProfilerError> {
+    let error_kind = || ProfilerErrorKind::IntegrationsDownload(url.into());
+    let https = matches!(
+        url.get(.."https://".len()),
+        Some(scheme) if scheme.eq_ignore_ascii_case("https://")
+    );
+    if !https {
+        return Err(ProfilerError::with_source(
+            error_kind(),
+            "the URL must use https",
+        ));
+    }
+    let expected_sha256 = expected_sha256.ok_or_else(|| {
+        ProfilerError::with_source(
+            error_kind(),
+            format!(
+                "{} must be set to verify downloaded integrations",
+                ELASTIC_APM_PROFILER_INTEGRATIONS_EXPECTED_SHA256_ENV_VAR
+            ),
+        )
+    })?;
+    download_verified_integrations(url, expected_sha256, timeout, etag_cache)
+}
+
+/// Downloads the integrations file from `url` to a file in the temp directory, and reads
+/// the integrations from it. A download without the `expected_sha256` hash is discarded.
+/// When the download fails, the integrations are read from the file written by a previous
+/// successful download, if there is one and it has the expected hash.
+/// With `etag_cache`, the file is only downloaded when it has changed since the previous
+/// download
+fn download_verified_integrations(
+    url: &str,
+    expected_sha256: &str,
+    timeout: Duration,
+    etag_cache: bool,
+) -> Result<Vec<Integration>, ProfilerError> {
+    let path = downloaded_integrations_path(url).map_err(|e| {
+        ProfilerError::with_source(ProfilerErrorKind::IntegrationsDownload(url.into()), e)
+    })?;
+    let etag_cache = if etag_cache {
+        Some(ETagCache::for_file(&path))
+    } else {
+        None
+    };
+    match download_to_file(url, &path, expected_sha256, timeout, etag_cache.as_ref()) {
+        Ok(Download::Modified) => {
+            log::debug!("downloaded integrations from {} to {}", url, path.display())
+        }
+        Ok(Download::NotModified) => log::debug!(
+            "integrations from {} not modified. using previously downloaded {}",
+            url,
+            path.display()
+        ),
+        Err(e) if path.is_file() => log::warn!(
+            "could not download integrations from {}: {}. using previously downloaded {}",
+            url,
+            e,
+            path.display()
+        ),
+        Err(e) => {
+            return Err(ProfilerError::with_source(
+                ProfilerErrorKind::IntegrationsDownload(url.into()),
+                e,
+            ))
+        }
+    }
+
+    // the previous download is verified too, since the expected hash may have changed
+    let error_kind = || ProfilerErrorKind::IntegrationsFile(path.clone());
+    let bytes = std::fs::read(&path).map_err(|e| ProfilerError::with_source(error_kind(), e))?;
+    verify_hash(&path, &bytes, expected_sha256)?;
+    parse_integrations_bytes(&path, &bytes).map_err(|e| ProfilerError::with_source(error_kind(), e))
+}
+
+/// The path of the file to which integrations downloaded from `url` are written, in
+/// [get_private_temp_dir]. The file is named for a SHA-256 hash of the URL, which is stable
+/// across profiler versions, and the file extension of the URL path is kept, so that the
+/// file is parsed in the right format
+fn downloaded_integrations_path(url: &str) -> std::io::Result<PathBuf> {
+    let mut sha256 = Sha256::new();
+    sha256.input_str(url);
+    let hash = sha256.result_str();
+    let url_path = url.split(['?', '#']).next().unwrap_or_default();
+    let extension = Path::new(url_path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .filter(|e| matches!(e.to_lowercase().as_str(), "yml" | "yaml" | "json" | "toml"))
+        .unwrap_or("yml");
+    Ok(get_private_temp_dir()?.join(format!(
+        "elastic_apm_profiler_integrations_{}.{}",
+        &hash[..16],
+        extension
+    )))
+}
+
+/// Gets a directory in [get_temp_dir] that only the current user can access, creating it
+/// if it does not exist. Other users can create files with predictable names in a shared
+/// temp directory beforehand, so files that the profiler later reads back, such as
+/// downloaded integrations, are written here instead
+fn get_private_temp_dir() -> std::io::Result<PathBuf> {
+    let dir = get_temp_dir().join(format!("elastic_apm_profiler_{}", current_user_id()));
+    create_private_dir(&dir)?;
+    Ok(dir)
+}
+
+#[cfg(unix)]
+fn current_user_id() -> String {
+    // SAFETY: geteuid has no preconditions
+    unsafe { libc::geteuid() }.to_string()
+}
+
+#[cfg(not(unix))]
+fn current_user_id() -> String {
+    std::env::var("USERNAME")
+        .ok()
+        .map(|name| {
+            name.chars()
+                .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+                .collect::<String>()
+        })
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "user".into())
+}
+
+/// Creates a directory that only the current user can access, or checks that an existing
+/// directory is owned by the current user and can't be accessed by other users
+#[cfg(unix)]
+fn create_private_dir(dir: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+
+    match std::fs::DirBuilder::new().mode(0o700).create(dir) {
+        Err(e) if e.kind() != std::io::ErrorKind::AlreadyExists => return Err(e),
+        _ => {}
+    }
+
+    // the directory may have been created by another user. Don't follow a symlink to it
+    let metadata = std::fs::symlink_metadata(dir)?;
+    // SAFETY: geteuid has no preconditions
+    let private = metadata.is_dir()
+        && metadata.uid() == unsafe { libc::geteuid() }
+        && metadata.permissions().mode() & 0o077 == 0;
+    if private {
+        Ok(())
+    } else {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            format!(
+                "{} is not a directory accessible only by the current user",
+                dir.display()
+            ),
+        ))
+    }
+}
+
+/// Creates a directory for the current user. The default temp directory on Windows is in
+/// the user's profile, which other users can't access
+#[cfg(not(unix))]
+fn create_private_dir(dir: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)
+}
+
+/// Creates a new file that only the current user can access, replacing a file left at the
+/// path by an interrupted previous write
+fn create_private_file(path: &Path) -> std::io::Result<File> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)
+}
+
+/// The outcome of a successful download
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Download {
+    /// The file was downloaded
+    Modified,
+    /// The file has not changed since the previous download, so was not downloaded
+    NotModified,
+}
+
+/// The ETag of a downloaded file, stored in a sidecar file next to it with an
+/// additional `.etag` extension
+#[derive(Debug)]
+struct ETagCache {
+    path: PathBuf,
+}
+
+impl ETagCache {
+    fn for_file(file: &Path) -> Self {
+        let mut path = file.as_os_str().to_owned();
+        path.push(".etag");
+        Self { path: path.into() }
+    }
+
+    fn read(&self) -> Option<String> {
+        std::fs::read_to_string(&self.path)
+            .ok()
+            .map(|etag| etag.trim().to_string())
+            .filter(|etag| !etag.is_empty())
+    }
+
+    fn write(&self, etag: Option<&str>) -> std::io::Result<()> {
+        match etag {
+            Some(etag) => std::fs::write(&self.path, etag),
+            None if self.path.exists() => std::fs::remove_file(&self.path),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Downloads `url` to `path`, replacing the file only when the download succeeds and has
+/// the `expected_sha256` hash. With an `etag_cache`, the file is only downloaded when its ETag differs from the one
+/// stored for a previous download
+fn download_to_file(
+    url: &str,
+    path: &Path,
+    expected_sha256: &str,
+    timeout: Duration,
+    etag_cache: Option<&ETagCache>,
+) -> Result<Download, Box<dyn std::error::Error + Send + Sync>> {
+    let mut request = ureq::AgentBuilder::new().timeout(timeout).build().get(url);
+    if let Some(etag) = etag_cache
+        .filter(|_| path.is_file())
+        .and_then(ETagCache::read)
+    {
+        request = request.set("If-None-Match", &etag);
+    }
+
+    let response = request.call()?;
+    match response.status() {
+        200 => {}
+        304 if etag_cache.is_some() => return Ok(Download::NotModified),
+        status => return Err(format!("unexpected HTTP status {}", status).into()),
+    }
+
+    let etag = response.header("ETag").map(String::from);
+    let download_path = path.with_extension("download");
+    let mut file = create_private_file(&download_path)?;
+    std::io::copy(&mut response.into_reader(), &mut file)?;
+    file.flush()?;
+    drop(file);
+    if let Err(e) = verify_file_hash(&download_path, expected_sha256) {
+        let _ = std::fs::remove_file(&download_path);
+        return Err(e.into());
+    }
+    std::fs::rename(&download_path, path)?;
+    if let Some(etag_cache) = etag_cache {
+        etag_cache.write(etag.as_deref())?;
+    }
+    Ok(Download::Modified)
+}
+
+/// The integrations cached in [ProfilerConfig::integrations_cache_path], with the
+/// integrations files from which they were read
+#[derive(Debug, Deserialize, Serialize)]
+struct IntegrationsCache {
+    sources: Vec<PathBuf>,
+    integrations: Vec<Integration>,
+}
+
+/// Reads the integrations configured in `config` from the cache at `cache_path` when the
+/// cache is newer than all of the integrations files it was read from, and the files match
+/// [ProfilerConfig::integrations_expected_sha256], if set. Otherwise, reads the
+/// integrations files and writes the integrations to the cache.
+/// Integrations supplied inline are not cached.
+fn load_integrations_cached(
+    config: &ProfilerConfig,
+    cache_path: &Path,
+) -> Result<(String, Vec<Integration>), ProfilerError> {
+    let sources = get_integrations_file_paths(config);
+    if sources.is_empty() {
+        return read_configured_integrations(config);
+    }
+
+    verify_single_hashed_file(config.integrations_expected_sha256.as_ref(), &sources)?;
+
+    if is_integrations_cache_fresh(cache_path, &sources) {
+        if let Some(expected) = &config.integrations_expected_sha256 {
+            for source in &sources {
+                verify_file_hash(source, expected)?;
+            }
+        }
+
+        match read_integrations_cache(cache_path) {
+            Ok(cache) if cache.sources == sources => {
+                return Ok((cache_path.display().to_string(), cache.integrations));
+            }
+            Ok(_) => log::debug!(
+                "integrations cache {} was written for other integrations files",
+                cache_path.display()
+            ),
+            Err(e) => log::warn!(
+                "could not read integrations cache {}: {}",
+                cache_path.display(),
+                e
+            ),
+        }
+    }
+
+    let (source, integrations) = read_configured_integrations(config)?;
+    let cache = IntegrationsCache {
+        sources,
+        integrations,
+    };
+    if let Err(e) = write_integrations_cache(cache_path, &cache) {
+        log::warn!(
+            "could not write integrations cache {}: {}",
+            cache_path.display(),
+            e
+        );
+    }
+
+    Ok((source, cache.integrations))
+}
+
+/// Whether the cache was modified after all of the integrations files
+fn is_integrations_cache_fresh(cache_path: &Path, sources: &[PathBuf]) -> bool {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    match modified(cache_path) {
+        Some(cache_modified) => sources
+            .iter()
+            .all(|source| modified(source).map_or(false, |m| m < cache_modified)),
+        None => false,
+    }
+}
+
+fn read_integrations_cache(cache_path: &Path) -> Result<IntegrationsCache, bincode::Error> {
+    bincode::deserialize_from(BufReader::new(File::open(cache_path)?))
+}
+
+/// Writes the cache to a temporary file that is renamed to `cache_path`, so that a
+/// process reading the cache never sees a partially written cache
+fn write_integrations_cache(
+    cache_path: &Path,
+    cache: &IntegrationsCache,
+) -> Result<(), bincode::Error> {
+    let temp_path = cache_path.with_extension(format!("{}.tmp", std::process::id()));
+    let result = File::create(&temp_path)
+        .map_err(bincode::Error::from)
+        .and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            bincode::serialize_into(&mut writer, cache)?;
+            writer.flush()?;
+            Ok(())
+        })
+        .and_then(|_| Ok(std::fs::rename(&temp_path, cache_path)?));
+
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// Disables enabled integrations, in the order in which they are defined, from the first
+/// whose instrumented methods would take the number of instrumented methods beyond
+/// `max_call_targets`
+fn limit_call_targets(
+    integrations: &mut [Integration],
+    max_call_targets: usize,
+    calltarget_enabled: bool,
+) {
+    let mut call_targets = 0;
+    let mut skipped = Vec::new();
+    for integration in integrations.iter_mut().filter(|i| i.is_enabled()) {
+        call_targets += integration.instrumented_methods_count(calltarget_enabled);
+        if call_targets > max_call_targets {
+            integration.enabled = false;
+            skipped.push(integration.name.as_str());
+        }
+    }
+
+    if !skipped.is_empty() {
+        log::warn!(
+            "{} limits instrumented methods to {}. skipping {} integration(s): {}",
+            ELASTIC_APM_PROFILER_MAX_CALL_TARGETS_ENV_VAR,
+            max_call_targets,
+            skipped.len(),
+            skipped.join(", ")
+        );
+    }
+}
+
+/// Disables integrations disabled by an `ELASTIC_APM_PROFILER_INTEGRATION_<NAME>_ENABLED`
+/// environment variable with a value of false, where `<NAME>` is the uppercased integration
+/// name with characters other than letters and digits replaced by `_`
+pub fn apply_integration_env_overrides(integrations: &mut [Integration]) {
+    for integration in integrations.iter_mut() {
+        let key = get_integration_enabled_env_var(&integration.name);
+        if std::env::var(&key).is_ok() && !read_bool_env_var(&key, true) {
+            log::info!(
+                "integration {} disabled by {} environment variable",
+                integration,
+                &key
+            );
+            integration.enabled = false;
+        }
+    }
+}
+
+fn get_integration_enabled_env_var(integration_name: &str) -> String {
+    let name: String = integration_name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("ELASTIC_APM_PROFILER_INTEGRATION_{}_ENABLED", name)
+}
+
+/// Reloads the integrations, replacing the current integrations only if loading succeeds.
+/// Logs the names of integrations added and removed.
+pub fn reload_integrations(current: &mut Vec<Integration>) -> Result<(), ProfilerError> {
+    invalidate_integrations_cache();
+    reload_integrations_with(current, load_integrations)
+}
+
+fn reload_integrations_with(
+    current: &mut Vec<Integration>,
+    load: impl FnOnce() -> Result<Vec<Integration>, ProfilerError>,
+) -> Result<(), ProfilerError> {
+    let integrations = load()?;
+    let current_names: HashSet<&str> = current.iter().map(|i| i.name.as_str()).collect();
+    let names: HashSet<&str> = integrations.iter().map(|i| i.name.as_str()).collect();
+
+    let mut added: Vec<&str> = names.difference(&current_names).copied().collect();
+    let mut removed: Vec<&str> = current_names.difference(&names).copied().collect();
+    added.sort_unstable();
+    removed.sort_unstable();
+
+    log::info!(
+        "reloaded {} integration(s). added: [{}], removed: [{}]",
+        integrations.len(),
+        added.join(", "),
+        removed.join(", ")
+    );
+
+    *current = integrations;
+    Ok(())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::profiler::{
+        env::{
+            apply_agent_env_var, apply_agent_span_stack_trace_env_var_with,
+            apply_integration_env_overrides, calltarget_is_allowed_with_config, clamp_to_min,
+            config_as_json, create_private_dir, dedup_integrations, deserialize_integrations_with,
+            detect_container, download_integrations, download_verified_integrations,
+            downloaded_integrations_path, exception_capture_enabled_with_config,
+            exception_type_is_capturable_with_config, filter_architecture_integrations,
+            filter_empty_integrations, filter_enabled_integrations, filter_supported_integrations,
+            format_configuration, format_configuration_with, get_agent_config_path_with,
+            get_configured_log_dirs_with, get_coreclr_profiler_clsid_with, get_env_vars,
+            get_home_log_dir_with, get_integration_enabled_env_var, get_integrations_file_paths,
+            get_managed_loader_path_with, get_private_temp_dir, get_process_name, get_profiler_dir,
+            get_temp_dir_with, get_windows_default_log_dir_with, integrations_load_timeout_ms,
+            is_profiler_already_attached_with, load_cached_integrations, load_integrations_cached,
+            load_integrations_from_glob, load_integrations_with_config,
+            load_integrations_with_timeout, log_dir_candidates, merge_integrations_files,
+            merge_startup_hooks, migrate_integration_with, os_info, parse_assembly_names,
+            parse_calltarget_allow_list, parse_duration, parse_integrations_json,
+            parse_integrations_toml, parse_integrations_yaml, parse_log_context_fields,
+            parse_log_level, parse_log_level_overrides, parse_os_release,
+            process_is_allowed_with_config, profiler_version, read_bool_env_var,
+            read_bool_env_var_with_source, read_duration_env_var, read_f64_env_var,
+            read_log_level_from_env_var, read_log_targets_with, read_optional_parsed_env_var,
+            read_path_env_var, read_u32_env_var, read_u64_env_var, read_usize_env_var,
+            reload_integrations_with, sanitize_log_prefix, validate_env_vars_with,
+            verify_file_hash, verify_single_hashed_file, write_env_vars, EnvSource, IlDumpFormat,
+            IntegrationMigration, IntegrationsToml, LogTarget, LogTargetSet, ProfilerConfig,
+            CURRENT_EXE, ELASTIC_APM_PROFILER_AGENT_ENVIRONMENT_ENV_VAR,
+            ELASTIC_APM_PROFILER_AGENT_SERVICE_NAME_ENV_VAR,
+            ELASTIC_APM_PROFILER_IL_DUMP_FORMAT_ENV_VAR, ELASTIC_APM_SERVICE_NAME_ENV_VAR,
+            ELASTIC_APM_SPAN_STACK_TRACE_MIN_DURATION_ENV_VAR, STARTUP_HOOKS_SEPARATOR,
+        },
+        error::{ProfilerError, ProfilerErrorKind},
+        logging::IL_LOG_TARGET,
+        test_helpers::{get_var_from, with_env_var, with_env_vars, without_env_var, TempPath},
+        types::Integration,
+    };
+    use crypto::{digest::Digest, sha2::Sha256};
+    use log::LevelFilter;
+    use std::{
+        cell::Cell,
+        collections::HashMap,
+        error::Error,
+        fs::File,
+        io::{BufReader, Read, Write},
+        net::TcpListener,
+        path::{Path, PathBuf},
+        sync::{mpsc, RwLock},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    fn read_u64_env_var_parses_value() {
+        with_env_var("ELASTIC_APM_PROFILER_TEST_U64_VALID", " 42 ", || {
+            assert_eq!(
+                read_u64_env_var("ELASTIC_APM_PROFILER_TEST_U64_VALID", 5),
+                42
+            );
+        });
+    }
+
+    #[test]
+    fn read_optional_parsed_env_var_is_none_for_invalid_empty_or_missing_value() {
+        let key = "ELASTIC_APM_PROFILER_TEST_OPTIONAL_U64";
+        with_env_var(key, " 42 ", || {
+            assert_eq!(read_optional_parsed_env_var::<u64>(key), Some(42));
+        });
+        for value in ["-1", "ten", " "] {
+            with_env_var(key, value, || {
+                assert_eq!(read_optional_parsed_env_var::<u64>(key), None);
+            });
+        }
+        without_env_var(key, || {
+            assert_eq!(read_optional_parsed_env_var::<u64>(key), None);
+        });
+    }
+
+    #[test]
+    fn read_u64_env_var_returns_default_for_invalid_or_missing_value() {
+        with_env_var("ELASTIC_APM_PROFILER_TEST_U64_INVALID", "-1", || {
+            assert_eq!(
+                read_u64_env_var("ELASTIC_APM_PROFILER_TEST_U64_INVALID", 5),
+                5
+            );
+        });
+        without_env_var("ELASTIC_APM_PROFILER_TEST_U64_MISSING", || {
+            assert_eq!(
+                read_u64_env_var("ELASTIC_APM_PROFILER_TEST_U64_MISSING", 5),
+                5
+            );
+        });
+    }
+
+    #[test]
+    fn read_u32_env_var_parses_value() {
+        const KEY: &str = "ELASTIC_APM_PROFILER_TEST_U32";
+        with_env_var(KEY, "42", || assert_eq!(read_u32_env_var(KEY, 5), 42));
+        with_env_var(KEY, "0", || assert_eq!(read_u32_env_var(KEY, 5), 0));
+        with_env_var(KEY, &u32::MAX.to_string(), || {
+            assert_eq!(read_u32_env_var(KEY, 5), u32::MAX)
+        });
+    }
+
+    #[test]
+    fn read_u32_env_var_returns_default_for_invalid_or_missing_value() {
+        const KEY: &str = "ELASTIC_APM_PROFILER_TEST_U32_DEFAULT";
+        with_env_var(KEY, "ten", || assert_eq!(read_u32_env_var(KEY, 5), 5));
+        with_env_var(KEY, &(u32::MAX as u64 + 1).to_string(), || {
+            assert_eq!(read_u32_env_var(KEY, 5), 5)
+        });
+        without_env_var(KEY, || assert_eq!(read_u32_env_var(KEY, 5), 5));
+    }
+
+    #[test]
+    fn read_usize_env_var_parses_value() {
+        const KEY: &str = "ELASTIC_APM_PROFILER_TEST_USIZE";
+        with_env_var(KEY, " 42", || assert_eq!(read_usize_env_var(KEY, 5), 42));
+        with_env_var(KEY, "0", || assert_eq!(read_usize_env_var(KEY, 5), 0));
+        with_env_var(KEY, &usize::MAX.to_string(), || {
+            assert_eq!(read_usize_env_var(KEY, 5), usize::MAX)
+        });
+    }
+
+    #[test]
+    fn read_usize_env_var_returns_default_for_invalid_or_missing_value() {
+        const KEY: &str = "ELASTIC_APM_PROFILER_TEST_USIZE_DEFAULT";
+        with_env_var(KEY, "-1", || assert_eq!(read_usize_env_var(KEY, 5), 5));
+        without_env_var(KEY, || assert_eq!(read_usize_env_var(KEY, 5), 5));
+    }
+
+    #[test]
+    fn read_f64_env_var_clamps_to_fraction() {
+        let key = "ELASTIC_APM_PROFILER_TEST_F64";
+        with_env_var(key, " 0.25", || {
+            assert_eq!(read_f64_env_var(key, 1.0), 0.25)
+        });
+        with_env_var(key, "1.5", || assert_eq!(read_f64_env_var(key, 0.5), 1.0));
+        with_env_var(key, "-1", || assert_eq!(read_f64_env_var(key, 0.5), 0.0));
+        with_env_var(key, "NaN", || assert_eq!(read_f64_env_var(key, 0.5), 0.5));
+        without_env_var(key, || assert_eq!(read_f64_env_var(key, 0.5), 0.5));
+    }
+
+    #[test]
+    fn clamp_to_min_returns_min_for_smaller_value() {
+        assert_eq!(clamp_to_min("KEY", 0, 1), 1);
+        assert_eq!(clamp_to_min("KEY", 1, 1), 1);
+        assert_eq!(clamp_to_min("KEY", 20, 1), 20);
+    }
+
+    #[test]
+    fn get_env_vars_includes_vars_of_interest() {
+        let vars = [
+            ("ELASTIC_APM_GET_ENV_VARS_TEST", "elastic"),
+            ("CORECLR_GET_ENV_VARS_TEST", "coreclr"),
+            ("COR_GET_ENV_VARS_TEST", "cor"),
+            ("DOTNET_GET_ENV_VARS_TEST", "dotnet"),
+            ("ASPNETCORE_GET_ENV_VARS_TEST", "aspnetcore"),
+        ];
+        let env_vars = with_env_vars(&vars, || {
+            with_env_var("OTHER_GET_ENV_VARS_TEST", "other", get_env_vars)
+        });
+
+        for (key, value) in vars.iter() {
+            assert!(env_vars.contains(&format!("  {}=\"{}\"", key, value)));
+        }
+        assert!(!env_vars.contains("OTHER_GET_ENV_VARS_TEST"));
+    }
+
+    #[test]
+    fn log_dir_candidates_are_in_order_without_duplicates() {
+        let configured = get_configured_log_dirs_with(|key| {
+            assert_eq!(key, "ELASTIC_APM_PROFILER_LOG_DIR");
+            Some("/first; /second;;/default".into())
+        });
+        assert_eq!(
+            configured,
+            vec![
+                PathBuf::from("/first"),
+                PathBuf::from("/second"),
+                PathBuf::from("/default")
+            ]
+        );
+
+        let candidates = log_dir_candidates(
+            Path::new("/first"),
+            configured,
+            PathBuf::from("/default"),
+            Some(PathBuf::from("/home/logs")),
+        );
+        assert_eq!(
+            candidates,
+            vec![
+                PathBuf::from("/first"),
+                PathBuf::from("/second"),
+                PathBuf::from("/default"),
+                PathBuf::from("/home/logs")
+            ]
+        );
+    }
+
+    #[test]
+    fn process_is_allowed_with_config_matches_process_filter() {
+        let config = ProfilerConfig::with_defaults();
+        assert!(process_is_allowed_with_config(&config, "dotnet"));
+
+        let config = ProfilerConfig {
+            process_filter: Some(vec!["w3wp".into(), "dotnet".into()]),
+            ..config
+        };
+        assert!(process_is_allowed_with_config(&config, "w3wp"));
+        assert!(process_is_allowed_with_config(&config, "DotNet"));
+        assert!(!process_is_allowed_with_config(&config, "worker"));
+    }
+
+    #[test]
+    fn process_is_allowed_with_config_checks_process_exclude_first() {
+        let config = ProfilerConfig {
+            process_exclude: Some(vec!["worker".into()]),
+            ..ProfilerConfig::with_defaults()
+        };
+        assert!(!process_is_allowed_with_config(&config, "Worker"));
+        assert!(process_is_allowed_with_config(&config, "dotnet"));
+
+        let config = ProfilerConfig {
+            process_filter: Some(vec!["worker".into(), "dotnet".into()]),
+            ..config
+        };
+        assert!(!process_is_allowed_with_config(&config, "worker"));
+        assert!(process_is_allowed_with_config(&config, "dotnet"));
+        assert!(!process_is_allowed_with_config(&config, "w3wp"));
+    }
+
+    #[test]
+    fn merge_startup_hooks_appends_without_duplicates() {
+        let join = |hooks: &[&str]| hooks.join(&STARTUP_HOOKS_SEPARATOR.to_string());
+
+        assert_eq!(merge_startup_hooks(None, &join(&["a.dll"])), "a.dll");
+        assert_eq!(
+            merge_startup_hooks(Some(&join(&["a.dll", "b.dll"])), &join(&["b.dll", "c.dll"])),
+            join(&["a.dll", "b.dll", "c.dll"])
+        );
+        assert_eq!(merge_startup_hooks(Some("a.dll"), ""), "a.dll");
+    }
+
+    #[test]
+    fn is_profiler_already_attached_detects_second_attach() {
+        let name = format!("elastic_apm_profiler_test_{}", std::process::id());
+        assert!(!is_profiler_already_attached_with(&name));
+        assert!(is_profiler_already_attached_with(&name));
+        assert!(is_profiler_already_attached_with(&name));
+
+        // a guard for another process is independent
+        let other = format!("{}_other", name);
+        assert!(!is_profiler_already_attached_with(&other));
+        assert!(is_profiler_already_attached_with(&other));
+    }
+
+    #[test]
+    fn apply_agent_env_var_does_not_override_agent_setting() {
+        let apply = |value| {
+            apply_agent_env_var(
+                ELASTIC_APM_SERVICE_NAME_ENV_VAR,
+                ELASTIC_APM_PROFILER_AGENT_SERVICE_NAME_ENV_VAR,
+                value,
+            )
+        };
+
+        with_env_var(ELASTIC_APM_SERVICE_NAME_ENV_VAR, "agent-service", || {
+            apply(Some("profiler-service"));
+            assert_eq!(
+                std::env::var(ELASTIC_APM_SERVICE_NAME_ENV_VAR).as_deref(),
+                Ok("agent-service")
+            );
+        });
+
+        without_env_var(ELASTIC_APM_SERVICE_NAME_ENV_VAR, || {
+            apply(None);
+            assert!(std::env::var(ELASTIC_APM_SERVICE_NAME_ENV_VAR).is_err());
+
+            apply(Some("profiler-service"));
+            assert_eq!(
+                std::env::var(ELASTIC_APM_SERVICE_NAME_ENV_VAR).as_deref(),
+                Ok("profiler-service")
+            );
+        });
+    }
+
+    #[test]
+    fn apply_agent_span_stack_trace_env_var_only_forwards_configured_value() {
+        let mut config = ProfilerConfig::with_defaults();
+        without_env_var(ELASTIC_APM_SPAN_STACK_TRACE_MIN_DURATION_ENV_VAR, || {
+            apply_agent_span_stack_trace_env_var_with(&config);
+            assert!(std::env::var(ELASTIC_APM_SPAN_STACK_TRACE_MIN_DURATION_ENV_VAR).is_err());
+        });
+
+        without_env_var(ELASTIC_APM_SPAN_STACK_TRACE_MIN_DURATION_ENV_VAR, || {
+            config.span_stack_trace_min_duration_ms = Some(250);
+            apply_agent_span_stack_trace_env_var_with(&config);
+            assert_eq!(
+                std::env::var(ELASTIC_APM_SPAN_STACK_TRACE_MIN_DURATION_ENV_VAR).as_deref(),
+                Ok("250ms")
+            );
+        });
+    }
+
+    #[test]
+    fn agent_environment_is_read_from_env_var() {
+        with_env_var(
+            ELASTIC_APM_PROFILER_AGENT_ENVIRONMENT_ENV_VAR,
+            " staging ",
+            || {
+                assert_eq!(
+                    ProfilerConfig::from_env().agent_environment.as_deref(),
+                    Some("staging")
+                );
+            },
+        );
+        with_env_var(ELASTIC_APM_PROFILER_AGENT_ENVIRONMENT_ENV_VAR, " ", || {
+            assert_eq!(ProfilerConfig::from_env().agent_environment, None);
+        });
+    }
+
+    #[test]
+    fn read_path_env_var_canonicalizes_existing_paths() -> Result<(), Box<dyn Error>> {
+        let key = "ELASTIC_APM_PROFILER_TEST_PATH";
+        let dir = std::env::temp_dir();
+        let expected = dir.canonicalize()?;
+        with_env_var(key, &dir.join(".").to_string_lossy(), || {
+            assert_eq!(read_path_env_var(key), Some(expected));
+        });
+
+        let missing = dir.join(format!("elastic_apm_missing_{}", std::process::id()));
+        with_env_var(key, &missing.to_string_lossy(), || {
+            assert_eq!(read_path_env_var(key), None);
+        });
+        with_env_var(key, " ", || assert_eq!(read_path_env_var(key), None));
+        without_env_var(key, || assert_eq!(read_path_env_var(key), None));
+        Ok(())
+    }
+
+    #[test]
+    fn get_agent_config_path_resolves_existing_files_only() -> Result<(), Box<dyn Error>> {
+        let dir = std::env::temp_dir();
+        let path = TempPath::new("agent_config.json");
+        std::fs::write(&path, "{}")?;
+
+        let resolved = get_agent_config_path_with(Some(&path));
+        let expected = path.canonicalize()?;
+        std::fs::remove_file(&path)?;
+
+        assert_eq!(resolved, Some(expected));
+        assert_eq!(get_agent_config_path_with(Some(&path)), None);
+        assert_eq!(get_agent_config_path_with(Some(&dir)), None);
+        assert_eq!(get_agent_config_path_with(None), None);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_os_release_prefers_pretty_name() {
+        let contents =
+            "NAME=\"Ubuntu\"\nVERSION_ID=\"22.04\"\nPRETTY_NAME=\"Ubuntu 22.04.3 LTS\"\n";
+        assert_eq!(
+            parse_os_release(contents),
+            Some("Ubuntu 22.04.3 LTS".to_string())
+        );
+        assert_eq!(
+            parse_os_release("NAME='Alpine Linux'\nVERSION_ID=3.19.1\n"),
+            Some("Alpine Linux 3.19.1".to_string())
+        );
+        assert_eq!(parse_os_release("ID=distroless\n"), None);
+    }
+
+    #[test]
+    fn os_info_reads_os_and_arch() {
+        let info = os_info();
+        assert_eq!(info.os_name, std::env::consts::OS);
+        assert_eq!(info.arch, std::env::consts::ARCH);
+        assert!(!info.os_version.is_empty());
+        assert_eq!(
+            info.to_string(),
+            format!("{} {} ({})", info.os_name, info.os_version, info.arch)
+        );
+    }
+
+    #[test]
+    fn profiler_version_is_semver() {
+        let version = profiler_version();
+        let core = version.split(['-', '+']).next().unwrap();
+        let parts: Vec<&str> = core.split('.').collect();
+        assert_eq!(parts.len(), 3, "{} is not semver", version);
+        assert!(
+            parts
+                .iter()
+                .all(|p| !p.is_empty() && p.chars().all(|c| c.is_ascii_digit())),
+            "{} is not semver",
+            version
+        );
+    }
+
+    #[test]
+    fn get_process_name_strips_exe_extension_on_windows() {
+        let process_name = |path: &str| {
+            CURRENT_EXE.with(|c| c.replace(Some(PathBuf::from(path))));
+            get_process_name()
+        };
+
+        assert_eq!(process_name("/usr/share/dotnet/dotnet"), "dotnet");
+        if cfg!(target_os = "windows") {
+            assert_eq!(
+                process_name(r"C:\Windows\System32\inetsrv\w3wp.EXE"),
+                "w3wp"
+            );
+        } else {
+            assert_eq!(process_name("/app/w3wp.exe"), "w3wp.exe");
+        }
+        assert_eq!(process_name("/"), "unknown");
+        CURRENT_EXE.with(|c| c.replace(None));
+    }
+
+    #[test]
+    fn log_target_from_str_and_display_round_trip() {
+        for target in [LogTarget::File, LogTarget::Stdout, LogTarget::Stderr] {
+            assert_eq!(target.to_string().parse::<LogTarget>(), Ok(target));
+        }
+        assert_eq!("STDOUT".parse::<LogTarget>(), Ok(LogTarget::Stdout));
+        assert!("syslog".parse::<LogTarget>().is_err());
+    }
+
+    #[test]
+    fn validate_env_vars_reports_all_invalid_values() {
+        let vars: HashMap<&str, &str> = [
+            ("ELASTIC_APM_PROFILER_CALLTARGET_ENABLED", "yes"),
+            ("ELASTIC_APM_PROFILER_LOG_IL", "true"),
+            ("ELASTIC_APM_PROFILER_LOG_MAX_FILES", "0"),
+            ("ELASTIC_APM_PROFILER_LOG_MAX_SIZE_MB", "ten"),
+            ("ELASTIC_APM_PROFILER_LOG", "verbose"),
+            (
+                "ELASTIC_APM_PROFILER_LOG_LEVEL_OVERRIDES",
+                "profiler::rejit=debug;il=loud",
+            ),
+            ("ELASTIC_APM_PROFILER_LOG_FORMAT", "json"),
+            ("ELASTIC_APM_PROFILER_LOG_TARGETS", "file;syslog"),
+        ]
+        .iter()
+        .copied()
+        .collect();
+
+        let warnings = validate_env_vars_with(|key| vars.get(key).map(|v| v.to_string()));
+        let keys: Vec<&str> = warnings.iter().map(|w| w.key.as_str()).collect();
+        assert_eq!(
+            keys,
+            vec![
+                "ELASTIC_APM_PROFILER_CALLTARGET_ENABLED",
+                "ELASTIC_APM_PROFILER_LOG_MAX_FILES",
+                "ELASTIC_APM_PROFILER_LOG_MAX_SIZE_MB",
+                "ELASTIC_APM_PROFILER_LOG",
+                "ELASTIC_APM_PROFILER_LOG_LEVEL_OVERRIDES",
+                "ELASTIC_APM_PROFILER_LOG_TARGETS",
+            ]
+        );
+        assert!(warnings[4].reason.contains("il=loud"));
+        assert_eq!(&warnings[5].raw_value, "file;syslog");
+        assert!(warnings[5].reason.contains("syslog"));
+    }
+
+    #[test]
+    #[cfg(not(feature = "strict-env"))]
+    fn read_env_vars_with_unknown_values_use_defaults() {
+        with_env_vars(
+            &[
+                ("ELASTIC_APM_PROFILER_TEST_BOOL", "yes"),
+                ("ELASTIC_APM_PROFILER_LOG", "verbose"),
+            ],
+            || {
+                assert!(read_bool_env_var("ELASTIC_APM_PROFILER_TEST_BOOL", true));
+                assert_eq!(
+                    read_log_level_from_env_var(LevelFilter::Warn),
+                    LevelFilter::Warn
+                );
+            },
+        );
+    }
+
+    #[test]
+    fn read_duration_env_var_parses_units() {
+        let key = "ELASTIC_APM_PROFILER_TEST_DURATION";
+        let expected = [
+            ("500ms", Duration::from_millis(500)),
+            ("2s", Duration::from_secs(2)),
+            ("1m", Duration::from_secs(60)),
+            ("1h", Duration::from_secs(60 * 60)),
+            (" 3S ", Duration::from_secs(3)),
+            ("250", Duration::from_millis(250)),
+        ];
+        for (value, duration) in expected {
+            with_env_var(key, value, || {
+                assert_eq!(
+                    read_duration_env_var(key, Duration::from_secs(1)),
+                    duration,
+                    "{}",
+                    value
+                );
+            });
+        }
+
+        without_env_var(key, || {
+            assert_eq!(
+                read_duration_env_var(key, Duration::from_secs(1)),
+                Duration::from_secs(1)
+            );
+        });
+    }
+
+    #[test]
+    fn parse_duration_rejects_invalid_values() {
+        for value in [
+            "",
+            "ms",
+            "2d",
+            "1.5s",
+            "-1s",
+            "1 s",
+            "s1",
+            "99999999999999999h",
+        ] {
+            assert_eq!(parse_duration(value), None, "{}", value);
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "strict-env"))]
+    fn read_duration_env_var_with_invalid_value_uses_default() {
+        with_env_var("ELASTIC_APM_PROFILER_TEST_DURATION", "soon", || {
+            assert_eq!(
+                read_duration_env_var("ELASTIC_APM_PROFILER_TEST_DURATION", Duration::from_secs(5)),
+                Duration::from_secs(5)
+            );
+        });
+    }
+
+    #[test]
+    #[cfg(feature = "strict-env")]
+    fn read_env_vars_with_unknown_values_fail_check_when_strict() {
+        with_env_vars(
+            &[
+                ("ELASTIC_APM_PROFILER_TEST_BOOL", "yes"),
+                ("ELASTIC_APM_PROFILER_LOG", "verbose"),
+            ],
+            || {
+                assert!(read_bool_env_var("ELASTIC_APM_PROFILER_TEST_BOOL", true));
+                assert_eq!(
+                    read_log_level_from_env_var(LevelFilter::Warn),
+                    LevelFilter::Warn
+                );
+            },
+        );
+
+        let error = crate::profiler::env::check_strict_env()
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("ELASTIC_APM_PROFILER_TEST_BOOL=yes"));
+        assert!(error.contains("ELASTIC_APM_PROFILER_LOG=verbose"));
+    }
+
+    #[test]
+    fn load_integrations_with_timeout_fails_when_loading_is_slow() {
+        let loaded = load_integrations_with_timeout(5000, || Ok(Vec::new()));
+        assert!(loaded.unwrap().is_empty());
+
+        let error = load_integrations_with_timeout(10, || {
+            std::thread::sleep(Duration::from_secs(1));
+            Ok(Vec::new())
+        })
+        .unwrap_err();
+        assert_eq!(
+            error.kind(),
+            &ProfilerErrorKind::Timeout("loading integrations".into())
+        );
+
+        let inline = std::thread::current().id();
+        let thread = load_integrations_with_timeout(0, move || {
+            assert_eq!(std::thread::current().id(), inline);
+            Ok(Vec::new())
+        });
+        assert!(thread.is_ok());
+
+        let mut config = ProfilerConfig::with_defaults();
+        assert_eq!(integrations_load_timeout_ms(&config), 5000);
+        config.integrations_download_url = Some("https://example.com/integrations.yml".into());
+        assert_eq!(integrations_load_timeout_ms(&config), 0);
+    }
+
+    #[test]
+    fn validate_env_vars_without_values_reports_nothing() {
+        assert!(validate_env_vars_with(|_| None).is_empty());
+    }
+
+    #[test]
+    fn load_integrations_with_config_excludes_integrations() -> Result<(), Box<dyn Error>> {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("../Elastic.Apm.Profiler.Managed/integrations.yml");
+        let config = ProfilerConfig {
+            integrations_path: Some(path.to_string_lossy().to_string()),
+            ..ProfilerConfig::with_defaults()
+        };
+        let all_integrations = load_integrations_with_config(&config).unwrap();
+        assert!(all_integrations.iter().any(|i| i.name == "AdoNet"));
+
+        let config = ProfilerConfig {
+            exclude_integrations: Some(vec!["adonet".into()]),
+            ..config
+        };
+        let integrations = load_integrations_with_config(&config).unwrap();
+        assert!(!integrations.iter().any(|i| i.name == "AdoNet"));
+        assert_eq!(all_integrations.len() - 1, integrations.len());
+        Ok(())
+    }
+
+    #[test]
+    fn load_integrations_with_config_from_inline_matches_file() -> Result<(), Box<dyn Error>> {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("../Elastic.Apm.Profiler.Managed/integrations.yml");
+        let file_config = ProfilerConfig {
+            integrations_path: Some(path.to_string_lossy().to_string()),
+            ..ProfilerConfig::with_defaults()
+        };
+        let inline_config = ProfilerConfig {
+            integrations_inline: Some(std::fs::read_to_string(&path)?),
+            ..ProfilerConfig::with_defaults()
+        };
+
+        let file_integrations = load_integrations_with_config(&file_config).unwrap();
+        let inline_integrations = load_integrations_with_config(&inline_config).unwrap();
+        assert!(!file_integrations.is_empty());
+        assert_eq!(
+            serde_json::to_value(&file_integrations)?,
+            serde_json::to_value(&inline_integrations)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn load_integrations_with_config_from_json_file() -> Result<(), Box<dyn Error>> {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("../Elastic.Apm.Profiler.Managed/integrations.yml");
+        let file = File::open(path)?;
+        let integrations: Vec<Integration> = serde_yaml::from_reader(BufReader::new(file))?;
+
+        let json_path = TempPath::new("integrations.json");
+        serde_json::to_writer(File::create(&json_path)?, &integrations)?;
+
+        let config = ProfilerConfig {
+            integrations_path: Some(json_path.to_string_lossy().to_string()),
+            ..ProfilerConfig::with_defaults()
+        };
+        let json_integrations = load_integrations_with_config(&config);
+
+        assert!(!integrations.is_empty());
+        assert_eq!(
+            serde_json::to_value(&integrations)?,
+            serde_json::to_value(&json_integrations?)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn load_integrations_with_config_from_toml_file_matches_yml() -> Result<(), Box<dyn Error>> {
+        // small fixtures of the same integrations, so that changes to the integrations.yml
+        // of the managed profiler don't need a matching change to the TOML fixture
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("tests/fixtures/integrations.yml");
+        let file = File::open(path)?;
+        let integrations: Vec<Integration> = serde_yaml::from_reader(BufReader::new(file))?;
+
+        let mut toml_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        toml_path.push("tests/fixtures/integrations.toml");
+        let config = ProfilerConfig {
+            integrations_path: Some(toml_path.to_string_lossy().to_string()),
+            ..ProfilerConfig::with_defaults()
+        };
+        let toml_integrations = load_integrations_with_config(&config)?;
+
+        assert_eq!(integrations.len(), 2);
+        assert_eq!(
+            serde_json::to_value(&integrations)?,
+            serde_json::to_value(&toml_integrations)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parse_integrations_toml_round_trips() -> Result<(), Box<dyn Error>> {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("tests/fixtures/integrations.toml");
+        let integrations = parse_integrations_toml(File::open(path)?)?;
+
+        // values must be written before tables, which toml::Value orders correctly
+        let toml = toml::to_string(&toml::Value::try_from(IntegrationsToml {
+            integrations: integrations.clone(),
+        })?)?;
+
+        assert_eq!(
+            serde_json::to_value(&integrations)?,
+            serde_json::to_value(&parse_integrations_toml(toml.as_bytes())?)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn deserialize_integrations_with_rejects_unknown_fields_when_validating(
+    ) -> Result<(), Box<dyn Error>> {
+        let yaml = "- name: First\n  method_replacements: []\n  enabeld: false\n\
+                    - name: Second\n  method_replacements: []\n";
+        let entries = || serde_yaml::from_str::<Vec<serde_yaml::Value>>(yaml);
+
+        let integrations = deserialize_integrations_with(entries()?, false)?;
+        assert_eq!(integrations.len(), 2);
+        assert!(integrations[0].enabled);
+
+        let error = deserialize_integrations_with(entries()?, true).unwrap_err();
+        assert_eq!(
+            error.kind(),
+            &ProfilerErrorKind::IntegrationsSchema(vec!["First.enabeld".to_string()])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn deserialize_integrations_with_defaults_schema_version_to_1() -> Result<(), Box<dyn Error>> {
+        let yaml = "- name: First\n  method_replacements: []\n";
+        let integrations = deserialize_integrations_with(
+            serde_yaml::from_str::<Vec<serde_yaml::Value>>(yaml)?,
+            true,
+        )?;
+        assert_eq!(integrations[0].schema_version, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn migrate_integration_with_applies_migrations_after_schema_version(
+    ) -> Result<(), Box<dyn Error>> {
+        fn rename_replacements(mut raw: serde_yaml::Value) -> serde_yaml::Value {
+            if let serde_yaml::Value::Mapping(mapping) = &mut raw {
+                if let Some(value) = mapping.remove(&"replacements".into()) {
+                    mapping.insert("method_replacements".into(), value);
+                }
+            }
+            raw
+        }
+        fn disable(mut raw: serde_yaml::Value) -> serde_yaml::Value {
+            if let serde_yaml::Value::Mapping(mapping) = &mut raw {
+                mapping.insert("enabled".into(), false.into());
+            }
+            raw
+        }
+        let migrations: &[(u8, IntegrationMigration)] = &[(2, rename_replacements), (3, disable)];
+
+        let raw: serde_yaml::Value = serde_yaml::from_str("name: Old\nreplacements: []\n")?;
+        let migrated = migrate_integration_with(raw, 1, migrations);
+        let integration: Integration = serde_yaml::from_value(migrated)?;
+        assert_eq!(integration.schema_version, 3);
+        assert!(!integration.enabled);
+
+        let raw: serde_yaml::Value =
+            serde_yaml::from_str("name: Version2\nmethod_replacements: []\nschema_version: 2\n")?;
+        let integration: Integration =
+            serde_yaml::from_value(migrate_integration_with(raw, 2, migrations))?;
+        assert_eq!(integration.schema_version, 3);
+        assert!(!integration.enabled);
+
+        let raw: serde_yaml::Value = serde_yaml::from_str("name: Current\nschema_version: 3\n")?;
+        let integration: Integration =
+            serde_yaml::from_value(migrate_integration_with(raw, 3, migrations))?;
+        assert!(integration.enabled);
+        Ok(())
+    }
+
+    #[test]
+    fn verify_file_hash_compares_sha256_of_file() -> Result<(), Box<dyn Error>> {
+        let path = TempPath::new("hash.yml");
+        std::fs::write(&path, "abc")?;
+        let expected = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+        let correct = verify_file_hash(&path, expected);
+        let uppercase = verify_file_hash(&path, &expected.to_uppercase());
+        let incorrect = verify_file_hash(&path, &"0".repeat(64));
+
+        assert!(correct.is_ok());
+        assert!(uppercase.is_ok());
+        assert_eq!(
+            incorrect.unwrap_err().kind(),
+            &ProfilerErrorKind::HashMismatch {
+                path: path.to_path_buf(),
+                expected: "0".repeat(64),
+                actual: expected.into(),
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn verify_single_hashed_file_rejects_multiple_files_with_expected_hash() {
+        let expected = Some("0".repeat(64));
+        let one = vec![PathBuf::from("a.yml")];
+        let two = vec![PathBuf::from("a.yml"), PathBuf::from("b.yml")];
+
+        assert!(verify_single_hashed_file(expected.as_ref(), &one).is_ok());
+        assert!(verify_single_hashed_file(None, &two).is_ok());
+        assert_eq!(
+            verify_single_hashed_file(expected.as_ref(), &two)
+                .unwrap_err()
+                .kind(),
+            &ProfilerErrorKind::HashMultipleFiles(two)
+        );
+    }
+
+    #[test]
+    fn parse_integrations_yaml_skips_malformed_integrations() -> Result<(), Box<dyn Error>> {
+        let yaml = "- name: First\n  method_replacements: []\n\
+                    - method_replacements: []\n\
+                    - name: Third\n  method_replacements: []\n";
+        let names: Vec<String> = parse_integrations_yaml(yaml.as_bytes())?
+            .into_iter()
+            .map(|i| i.name)
+            .collect();
+        assert_eq!(names, vec!["First", "Third"]);
+
+        let json = r#"[{"name":"First","method_replacements":[]},{"name":2},{"name":"Third","method_replacements":[]}]"#;
+        assert_eq!(parse_integrations_json(json.as_bytes())?.len(), 2);
+
+        let toml = "[[integrations]]\nname = \"First\"\nmethod_replacements = []\n\
+                    [[integrations]]\nmethod_replacements = []\n";
+        assert_eq!(parse_integrations_toml(toml.as_bytes())?.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn filter_architecture_integrations_skips_unsupported_architecture() {
+        let integrations = load_integrations_with_config(&ProfilerConfig {
+            integrations_inline: Some(
+                "- name: Any\n\
+                 - name: X64Only\n  supported_architectures: [x64]\n\
+                 - name: X86AndArm64\n  supported_architectures: [x86, arm64]\n"
+                    .into(),
+            ),
+            ..ProfilerConfig::with_defaults()
+        })
+        .unwrap();
+
+        let names: Vec<String> = filter_architecture_integrations(integrations, "arm64")
+            .into_iter()
+            .map(|i| i.name)
+            .collect();
+        assert_eq!(names, vec!["Any", "X86AndArm64"]);
+    }
+
+    #[test]
+    fn filter_supported_integrations_skips_unsupported_runtime() {
+        let integrations = load_integrations_with_config(&ProfilerConfig {
+            integrations_inline: Some(
+                "- name: Any\n  method_replacements: []\n\
+                 - name: Net6AndAbove\n  method_replacements: []\n  min_target_framework_version: 6.0.0\n\
+                 - name: NetFramework\n  method_replacements: []\n  max_target_framework_version: 4.*\n"
+                    .into(),
+            ),
+            ..ProfilerConfig::with_defaults()
+        })
+        .unwrap();
+
+        let names: Vec<String> = filter_supported_integrations(integrations, "6.0.25.0")
+            .into_iter()
+            .map(|i| i.name)
+            .collect();
+        assert_eq!(names, vec!["Any", "Net6AndAbove"]);
+    }
+
+    #[test]
+    fn load_integrations_from_glob_merges_files_in_lexicographic_order(
+    ) -> Result<(), Box<dyn Error>> {
+        let dir = TempPath::new("integrations_glob");
+        std::fs::create_dir_all(&dir)?;
+        // written out of order to check that files are merged in lexicographic order
+        std::fs::write(
+            dir.join("c_team.yml"),
+            "- name: Shared\n  method_replacements: []\n  enabled: false\n",
+        )?;
+        std::fs::write(
+            dir.join("a_team.yml"),
+            "- name: TeamA\n  method_replacements: []\n- name: Shared\n  method_replacements: []\n",
+        )?;
+        std::fs::write(
+            dir.join("b_team.yml"),
+            "- name: TeamB\n  method_replacements: []\n",
+        )?;
+        std::fs::write(dir.join("ignored.txt"), "not integrations")?;
+
+        let pattern = dir.join("*.yml").to_string_lossy().to_string();
+        let integrations = load_integrations_with_config(&ProfilerConfig {
+            integrations_glob: Some(pattern.clone()),
+            integrations_path: Some("does_not_exist.yml".into()),
+            ..ProfilerConfig::with_defaults()
+        });
+        let no_matches = load_integrations_from_glob(&dir.join("*.json").to_string_lossy());
+
+        let integrations = integrations?;
+        let names: Vec<&str> = integrations.iter().map(|i| i.name.as_str()).collect();
+        assert_eq!(names, vec!["TeamA", "Shared", "TeamB"]);
+        assert!(!integrations[1].is_enabled());
+        assert_eq!(
+            no_matches.unwrap_err().kind(),
+            &ProfilerErrorKind::IntegrationsGlob(dir.join("*.json").to_string_lossy().to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn merge_integrations_files_skips_duplicate_integrations() -> Result<(), Box<dyn Error>> {
+        let target = "  method_replacements:
+  - target:
+      assembly: System.Data
+      type: System.Data.Common.DbCommand
+      method: ExecuteNonQuery
+    wrapper:
+      assembly: Elastic.Apm.Profiler.Managed, Version=1.27.0.0, Culture=neutral, PublicKeyToken=ae7400d2c189cf22
+      type: Elastic.Apm.Profiler.Managed.Integrations.AdoNet.CommandExecuteNonQueryIntegration
+      action: CallTargetModification
+";
+        let first_path = TempPath::new("dedup_first.yml");
+        let second_path = TempPath::new("dedup_second.yml");
+        std::fs::write(
+            &first_path,
+            format!(
+                "- name: AdoNet\n{}- name: First\n  method_replacements: []\n",
+                target
+            ),
+        )?;
+        std::fs::write(
+            &second_path,
+            format!(
+                "- name: Second\n  method_replacements: []\n- name: DbCommand\n{}",
+                target
+            ),
+        )?;
+
+        let first_path = first_path.to_string_lossy().to_string();
+        let second_path = second_path.to_string_lossy().to_string();
+        let result = merge_integrations_files(vec![first_path.as_str(), second_path.as_str()]);
+
+        let integrations = result?;
+        let names: Vec<&str> = integrations.iter().map(|i| i.name.as_str()).collect();
+        assert_eq!(names, vec!["First", "Second", "DbCommand"]);
+
+        let mut sorted = integrations.clone();
+        sorted.sort();
+        sorted.dedup();
+        assert_eq!(sorted.len(), integrations.len());
+        Ok(())
+    }
+
+    #[test]
+    fn dedup_integrations_keeps_last_of_conflicting_wrappers() -> Result<(), Box<dyn Error>> {
+        let integrations: Vec<Integration> = serde_yaml::from_str(
+            r#"---
+- name: First
+  method_replacements:
+  - target:
+      assembly: System.Data
+      type: System.Data.Common.DbCommand
+      method: ExecuteNonQuery
+    wrapper:
+      assembly: Elastic.Apm.Profiler.Managed, Version=1.27.0.0, Culture=neutral, PublicKeyToken=ae7400d2c189cf22
+      type: FirstIntegration
+      action: CallTargetModification
+- name: Empty
+- name: Second
+  method_replacements:
+  - target:
+      assembly: System.Data
+      type: System.Data.Common.DbCommand
+      method: ExecuteNonQuery
+    wrapper:
+      assembly: Elastic.Apm.Profiler.Managed, Version=1.27.0.0, Culture=neutral, PublicKeyToken=ae7400d2c189cf22
+      type: SecondIntegration
+      action: CallTargetModification"#,
+        )?;
+
+        let integrations = dedup_integrations(integrations);
+        let names: Vec<&str> = integrations.iter().map(|i| i.name.as_str()).collect();
+        assert_eq!(names, vec!["Empty", "Second"]);
+        assert_eq!(integrations[1].wrapper_names(), vec!["SecondIntegration"]);
+        Ok(())
+    }
+
+    #[test]
+    fn load_integrations_cached_reads_cache_until_file_changes() -> Result<(), Box<dyn Error>> {
+        let path = TempPath::new("cached_integrations.yml");
+        let cache_path = TempPath::new("integrations_cache.bin");
+        let mut integrations_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        integrations_path.push("../Elastic.Apm.Profiler.Managed/integrations.yml");
+        std::fs::copy(&integrations_path, &path)?;
+        let config = ProfilerConfig {
+            integrations_path: Some(path.to_string_lossy().to_string()),
+            integrations_cache_path: Some(cache_path.to_path_buf()),
+            ..ProfilerConfig::with_defaults()
+        };
+
+        let (source, integrations) = load_integrations_cached(&config, &cache_path)?;
+        assert_eq!(source, path.to_string_lossy());
+        let (cached_source, cached_integrations) = load_integrations_cached(&config, &cache_path)?;
+        assert_eq!(cached_source, cache_path.display().to_string());
+
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        std::fs::write(&path, "- name: Changed\n  method_replacements: []\n")?;
+        let result = load_integrations_cached(&config, &cache_path);
+
+        assert!(!integrations.is_empty());
+        assert_eq!(
+            serde_json::to_value(&integrations)?,
+            serde_json::to_value(&cached_integrations)?
+        );
+        let (changed_source, changed_integrations) = result?;
+        assert_eq!(changed_source, path.to_string_lossy());
+        assert_eq!(changed_integrations.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn load_integrations_cached_verifies_hash_of_cached_files() -> Result<(), Box<dyn Error>> {
+        let path = TempPath::new("hashed_cached_integrations.yml");
+        let cache_path = TempPath::new("hashed_integrations_cache.bin");
+        std::fs::write(&path, "- name: Cached\n  method_replacements: []\n")?;
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        let config = ProfilerConfig {
+            integrations_path: Some(path.to_string_lossy().to_string()),
+            integrations_cache_path: Some(cache_path.to_path_buf()),
+            ..ProfilerConfig::with_defaults()
+        };
+        load_integrations_cached(&config, &cache_path)?;
+        let temp_cache_path = cache_path.with_extension(format!("{}.tmp", std::process::id()));
+        let temp_cache_exists = temp_cache_path.exists();
+
+        let config = ProfilerConfig {
+            integrations_expected_sha256: Some("0".repeat(64)),
+            ..config
+        };
+        let result = load_integrations_cached(&config, &cache_path);
+
+        assert!(!temp_cache_exists);
+        match result {
+            Err(e) => assert!(matches!(e.kind(), ProfilerErrorKind::HashMismatch { .. })),
+            Ok(_) => panic!("expected a hash mismatch"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn load_integrations_with_config_returns_no_integrations_when_disabled() {
+        let config = ProfilerConfig {
+            disable_all: true,
+            ..ProfilerConfig::with_defaults()
+        };
+        assert!(load_integrations_with_config(&config).unwrap().is_empty());
+    }
+
+    #[test]
+    fn load_integrations_with_config_returns_error_kind() {
+        let path = TempPath::new("missing_integrations.yml");
+        let config = ProfilerConfig {
+            integrations_path: Some(path.to_string_lossy().to_string()),
+            ..ProfilerConfig::with_defaults()
+        };
+        let error = load_integrations_with_config(&config).unwrap_err();
+        assert_eq!(
+            error.kind(),
+            &ProfilerErrorKind::IntegrationsFile(path.to_path_buf())
+        );
+        assert!(error.source().is_some());
+
+        let config = ProfilerConfig {
+            integrations_inline: Some("not integrations".into()),
+            ..ProfilerConfig::with_defaults()
+        };
+        let error = load_integrations_with_config(&config).unwrap_err();
+        assert_eq!(error.kind(), &ProfilerErrorKind::IntegrationsInline);
+
+        let config = ProfilerConfig::with_defaults();
+        let error = load_integrations_with_config(&config).unwrap_err();
+        assert_eq!(error.kind(), &ProfilerErrorKind::IntegrationsNotConfigured);
+    }
+
+    #[test]
+    fn reload_integrations_replaces_integrations_only_on_success() -> Result<(), Box<dyn Error>> {
+        let path = TempPath::new("reload_integrations.yml");
+        std::fs::write(&path, "- name: First\n  method_replacements: []\n")?;
+        let config = ProfilerConfig {
+            integrations_path: Some(path.to_string_lossy().to_string()),
+            ..ProfilerConfig::with_defaults()
+        };
+        let mut integrations = load_integrations_with_config(&config).unwrap();
+
+        std::fs::write(&path, "- name: Second\n  method_replacements: []\n")?;
+        reload_integrations_with(&mut integrations, || load_integrations_with_config(&config))
+            .unwrap();
+        assert_eq!(integrations.len(), 1);
+        assert_eq!(&integrations[0].name, "Second");
+
+        std::fs::write(&path, "not: [valid")?;
+        let result =
+            reload_integrations_with(&mut integrations, || load_integrations_with_config(&config));
+
+        assert!(result.is_err());
+        assert_eq!(integrations.len(), 1);
+        assert_eq!(&integrations[0].name, "Second");
+        Ok(())
+    }
+
+    #[test]
+    fn filter_empty_integrations_skips_integrations_without_method_replacements(
+    ) -> Result<(), Box<dyn Error>> {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("../Elastic.Apm.Profiler.Managed/integrations.yml");
+        let mut integrations = parse_integrations_yaml(File::open(path)?)?;
+        let count = integrations.len();
+        integrations.push(Integration {
+            name: "Empty".into(),
+            ..Integration::default()
+        });
+
+        let integrations = filter_empty_integrations(integrations);
+        assert_eq!(integrations.len(), count);
+        assert!(integrations
+            .iter()
+            .all(|i| i.method_replacements_count() > 0));
+        Ok(())
+    }
+
+    #[test]
+    fn load_integrations_with_config_merges_multiple_files() -> Result<(), Box<dyn Error>> {
+        let base_path = TempPath::new("merge_base.yml");
+        let override_path = TempPath::new("merge_override.yml");
+        std::fs::write(
+            &base_path,
+            "- name: Shared\n  method_replacements: []\n- name: Base\n  method_replacements: []\n",
+        )?;
+        std::fs::write(
+            &override_path,
+            r#"- name: Shared
+  method_replacements:
+  - target:
+      assembly: System.Data
+      type: System.Data.Common.DbCommand
+      method: ExecuteNonQuery
+      signature_types:
+      - System.Int32
+      minimum_version: 4.0.0
+      maximum_version: 4.*.*
+    wrapper:
+      assembly: Elastic.Apm.Profiler.Managed, Version=1.27.0.0, Culture=neutral, PublicKeyToken=ae7400d2c189cf22
+      type: Elastic.Apm.Profiler.Managed.Integrations.AdoNet.CommandExecuteNonQueryIntegration
+      action: CallTargetModification
+- name: Override
+  method_replacements: []
+"#,
+        )?;
+        let config = ProfilerConfig {
+            integrations_path: Some(format!(
+                "{};{}",
+                base_path.to_string_lossy(),
+                override_path.to_string_lossy()
+            )),
+            ..ProfilerConfig::with_defaults()
+        };
+        let result = load_integrations_with_config(&config);
+
+        let integrations = result.unwrap();
+        let names: Vec<&str> = integrations.iter().map(|i| i.name.as_str()).collect();
+        assert_eq!(names, vec!["Shared", "Base", "Override"]);
+        assert_eq!(integrations[0].method_replacements_count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn load_cached_integrations_loads_once() {
+        let cache = RwLock::new(None);
+        let load_count = Cell::new(0);
+        let load = || {
+            load_count.set(load_count.get() + 1);
+            load_integrations_with_config(&ProfilerConfig {
+                integrations_inline: Some("- name: Cached\n  method_replacements: []\n".into()),
+                ..ProfilerConfig::with_defaults()
+            })
+        };
+
+        let integrations = load_cached_integrations(&cache, load).unwrap();
+        let cached_integrations = load_cached_integrations(&cache, load).unwrap();
+        assert_eq!(
+            serde_json::to_value(&integrations).unwrap(),
+            serde_json::to_value(&cached_integrations).unwrap()
+        );
+        assert_eq!(load_count.get(), 1);
+
+        *cache.write().unwrap() = None;
+        load_cached_integrations(&cache, load).unwrap();
+        assert_eq!(load_count.get(), 2);
+    }
+
+    #[test]
+    fn load_cached_integrations_does_not_cache_failure() {
+        let cache = RwLock::new(None);
+        assert!(load_cached_integrations(&cache, || {
+            Err(ProfilerError::new(
+                ProfilerErrorKind::IntegrationsNotConfigured,
+            ))
+        })
+        .is_err());
+        assert!(cache.read().unwrap().is_none());
+    }
+
+    /// Compares looking up an integration by name without and with the cache.
+    /// Run with `cargo test --release -- --ignored load_integrations_benchmark`
+    #[test]
+    #[ignore]
+    fn load_integrations_benchmark() -> Result<(), Box<dyn Error>> {
+        const LOOKUPS: usize = 10_000;
+        let path = TempPath::new("benchmark.yml");
+        let yaml: String = (0..100)
+            .map(|i| format!("- name: Integration{}\n  method_replacements: []\n", i))
+            .collect();
+        std::fs::write(&path, yaml)?;
+        let config = ProfilerConfig {
+            integrations_path: Some(path.to_string_lossy().to_string()),
+            ..ProfilerConfig::with_defaults()
+        };
+
+        let names: Vec<String> = (0..LOOKUPS)
+            .map(|i| format!("Integration{}", i % 100))
+            .collect();
+        let lookup = |integrations: Vec<Integration>, name: &str| {
+            assert!(integrations.iter().any(|i| i.name == name));
+        };
+
+        let start = Instant::now();
+        for name in &names {
+            lookup(load_integrations_with_config(&config)?, name);
+        }
+        let cold = start.elapsed();
+
+        let cache = RwLock::new(None);
+        let start = Instant::now();
+        for name in &names {
+            lookup(
+                load_cached_integrations(&cache, || load_integrations_with_config(&config))?,
+                name,
+            );
+        }
+        let cached = start.elapsed();
+
+        assert!(
+            cached < cold,
+            "cached lookups ({:?}) not faster than cold lookups ({:?})",
+            cached,
+            cold
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn get_integration_enabled_env_var_replaces_special_chars() {
+        assert_eq!(
+            get_integration_enabled_env_var("AdoNet"),
+            "ELASTIC_APM_PROFILER_INTEGRATION_ADONET_ENABLED"
+        );
+        assert_eq!(
+            get_integration_enabled_env_var("Sql-Client.Core"),
+            "ELASTIC_APM_PROFILER_INTEGRATION_SQL_CLIENT_CORE_ENABLED"
+        );
+    }
+
+    #[test]
+    fn apply_integration_env_overrides_disables_integrations() {
+        let mut integrations = load_integrations_with_config(&ProfilerConfig {
+            integrations_inline: Some(
+                "- name: OverrideDisabled\n  method_replacements: []\n\
+                 - name: OverrideEnabled\n  method_replacements: []\n\
+                 - name: OverrideUnset\n  method_replacements: []\n"
+                    .into(),
+            ),
+            ..ProfilerConfig::with_defaults()
+        })
+        .unwrap();
+        with_env_vars(
+            &[
+                (
+                    "ELASTIC_APM_PROFILER_INTEGRATION_OVERRIDEDISABLED_ENABLED",
+                    "false",
+                ),
+                (
+                    "ELASTIC_APM_PROFILER_INTEGRATION_OVERRIDEENABLED_ENABLED",
+                    "true",
+                ),
+            ],
+            || apply_integration_env_overrides(&mut integrations),
+        );
+
+        let enabled: Vec<bool> = integrations.iter().map(|i| i.is_enabled()).collect();
+        assert_eq!(enabled, vec![false, true, true]);
+        let names: Vec<String> = filter_enabled_integrations(integrations)
+            .into_iter()
+            .map(|i| i.name)
+            .collect();
+        assert_eq!(names, vec!["OverrideEnabled", "OverrideUnset"]);
+    }
+
+    #[test]
+    fn format_configuration_writes_each_setting_on_a_line() {
+        let config = ProfilerConfig {
+            log_max_files: 3,
+            service_name: Some("my-service".into()),
+            ..ProfilerConfig::with_defaults()
+        };
+        let lines: Vec<String> = format_configuration(&config)
+            .lines()
+            .map(String::from)
+            .collect();
+
+        assert_eq!(lines.len(), 64);
+        assert!(lines.contains(&"  log_max_files=3 (default)".to_string()));
+        assert!(lines.contains(&"  service_name=Some(\"my-service\") (default)".to_string()));
+        assert!(lines.contains(&"  calltarget_enabled=true (default)".to_string()));
+    }
+
+    #[test]
+    fn format_configuration_annotates_settings_read_from_env_vars() {
+        let config = ProfilerConfig {
+            log_max_size: 20 * 1024 * 1024,
+            ..ProfilerConfig::with_defaults()
+        };
+        let output = format_configuration_with(
+            &config,
+            get_var_from(&[
+                ("ELASTIC_APM_PROFILER_LOG_MAX_SIZE_MB", "20"),
+                ("ELASTIC_APM_PROFILER_LOG_IL", " "),
+                ("ELASTIC_APM_PROFILER_LOG", "verbose"),
+                ("ELASTIC_APM_PROFILER_EXCEPTION_SAMPLING_RATE", "2"),
+            ]),
+        );
+        let lines: Vec<&str> = output.lines().collect();
+
+        assert!(lines.contains(&"  log_max_size=20971520 (env var)"));
+        assert!(lines.contains(&"  log_il=false (default)"));
+        assert!(lines.contains(&"  log_level=Warn (default)"));
+        assert!(lines.contains(&"  exception_sampling_rate=1.0 (env var)"));
+    }
+
+    #[test]
+    fn read_bool_env_var_with_source_returns_where_value_was_read_from() {
+        let key = "ELASTIC_APM_PROFILER_TEST_BOOL_WITH_SOURCE";
+        with_env_var(key, "1", || {
+            assert_eq!(
+                read_bool_env_var_with_source(key, false),
+                (true, EnvSource::EnvVar)
+            );
+        });
+        without_env_var(key, || {
+            assert_eq!(
+                read_bool_env_var_with_source(key, true),
+                (true, EnvSource::Default)
+            );
+        });
+    }
+
+    #[test]
+    fn config_as_json_serializes_all_settings() -> Result<(), Box<dyn Error>> {
+        let config = ProfilerConfig {
+            log_targets: vec![LogTarget::Stdout, LogTarget::File]
+                .into_iter()
+                .collect(),
+            ..ProfilerConfig::with_defaults()
+        };
+        let json: serde_json::Value = serde_json::from_str(&config_as_json(&config))?;
+
+        assert_eq!(json.as_object().unwrap().len(), 64);
+        assert_eq!(json["log_level"], "WARN");
+        assert_eq!(json["log_format"], "text");
+        assert_eq!(json["log_rotation"], "size");
+        assert_eq!(json["log_targets"], serde_json::json!(["file", "stdout"]));
+        assert_eq!(json["service_name"], serde_json::Value::Null);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_log_level_accepts_numeric_severities() {
+        let expected = [
+            ("0", LevelFilter::Off),
+            ("1", LevelFilter::Error),
+            ("2", LevelFilter::Warn),
+            ("3", LevelFilter::Info),
+            ("4", LevelFilter::Debug),
+            ("5", LevelFilter::Trace),
+        ];
+        for (value, level) in expected {
+            assert_eq!(parse_log_level(value), Some(level), "{}", value);
+        }
+
+        assert_eq!(parse_log_level("info"), Some(LevelFilter::Info));
+        assert_eq!(parse_log_level("99"), None);
+        assert_eq!(parse_log_level("-1"), None);
+    }
+
+    #[test]
+    fn parse_log_level_overrides_prefixes_crate_name() {
+        let overrides = parse_log_level_overrides(
+            "profiler::rejit=debug; il = TRACE;elastic_apm_profiler::ffi=off;invalid;=info;\
+             profiler::rejit=info",
+        );
+        assert_eq!(
+            overrides,
+            vec![
+                (
+                    "elastic_apm_profiler::profiler::rejit".to_string(),
+                    LevelFilter::Info
+                ),
+                (IL_LOG_TARGET.to_string(), LevelFilter::Trace),
+                ("elastic_apm_profiler::ffi".to_string(), LevelFilter::Off),
+            ]
+        );
+    }
+
+    #[test]
+    fn calltarget_is_allowed_by_allow_list() {
+        let config = ProfilerConfig::with_defaults();
+        assert!(calltarget_is_allowed_with_config(
+            &config,
+            "System.Data",
+            "System.Data.Common.DbCommand",
+            "ExecuteNonQuery"
+        ));
+
+        let config = ProfilerConfig {
+            calltarget_allow_list: parse_calltarget_allow_list(
+                " System.Data::System.Data.Common.DbCommand::ExecuteNonQuery;invalid;A::B;",
+            ),
+            ..ProfilerConfig::with_defaults()
+        };
+        assert_eq!(config.calltarget_allow_list.len(), 1);
+        assert!(calltarget_is_allowed_with_config(
+            &config,
+            "system.data",
+            "System.Data.Common.DbCommand",
+            "ExecuteNonQuery"
+        ));
+        assert!(!calltarget_is_allowed_with_config(
+            &config,
+            "System.Data",
+            "System.Data.Common.DbCommand",
+            "ExecuteReader"
+        ));
+        assert!(!calltarget_is_allowed_with_config(
+            &config,
+            "System.Data",
+            "system.data.common.dbcommand",
+            "ExecuteNonQuery"
+        ));
+    }
+
+    #[test]
+    fn exception_type_is_capturable_by_prefix() {
+        let config = ProfilerConfig::with_defaults();
+        assert!(!exception_capture_enabled_with_config(&config));
+        assert!(exception_capture_enabled_with_config(&ProfilerConfig {
+            exception_sampling_rate: 0.5,
+            ..ProfilerConfig::with_defaults()
+        }));
+        assert!(exception_type_is_capturable_with_config(
+            &config,
+            "System.OperationCanceledException"
+        ));
+
+        let config = ProfilerConfig {
+            exception_filter_types: Some(vec![
+                "System.Data.".to_string(),
+                "System.InvalidOperationException".to_string(),
+            ]),
+            ..ProfilerConfig::with_defaults()
+        };
+        assert!(exception_capture_enabled_with_config(&config));
+        assert!(exception_type_is_capturable_with_config(
+            &config,
+            "System.Data.SqlClient.SqlException"
+        ));
+        assert!(exception_type_is_capturable_with_config(
+            &config,
+            "System.InvalidOperationException"
+        ));
+        assert!(!exception_type_is_capturable_with_config(
+            &config,
+            "System.OperationCanceledException"
+        ));
+    }
+
+    #[test]
+    fn parse_assembly_names_uses_lowercased_simple_names() {
+        let names = parse_assembly_names(
+            "My.Middleware; Other.Assembly, Version=1.0.0.0, Culture=neutral;;",
+        );
+        let mut names: Vec<String> = names.into_iter().collect();
+        names.sort();
+        assert_eq!(names, vec!["my.middleware", "other.assembly"]);
+    }
+
+    #[test]
+    fn integration_enabled_defaults_to_true_and_is_read_from_yaml() {
+        let integrations = load_integrations_with_config(&ProfilerConfig {
+            integrations_inline: Some(
+                "- name: EnabledByDefault\n  method_replacements: []\n\
+                 - name: DisabledInYaml\n  method_replacements: []\n  enabled: false\n"
+                    .into(),
+            ),
+            ..ProfilerConfig::with_defaults()
+        })
+        .unwrap();
+
+        let enabled: Vec<bool> = integrations.iter().map(|i| i.is_enabled()).collect();
+        assert_eq!(enabled, vec![true, false]);
+    }
+
+    #[test]
+    fn integration_enable_inlining_is_optional() {
+        let integrations = load_integrations_with_config(&ProfilerConfig {
+            integrations_inline: Some(
+                "- name: InliningDefault\n  method_replacements: []\n\
+                 - name: InliningDisabled\n  method_replacements: []\n  enable_inlining: false\n"
+                    .into(),
+            ),
+            ..ProfilerConfig::with_defaults()
+        })
+        .unwrap();
+
+        let enable_inlining: Vec<Option<bool>> =
+            integrations.iter().map(|i| i.enable_inlining).collect();
+        assert_eq!(enable_inlining, vec![None, Some(false)]);
+    }
+
+    #[test]
+    fn il_dump_format_is_read_from_env_var() {
+        assert_eq!(IlDumpFormat::parse(" Disasm"), Some(IlDumpFormat::Disasm));
+        assert_eq!(IlDumpFormat::parse("binary"), None);
+
+        with_env_var(ELASTIC_APM_PROFILER_IL_DUMP_FORMAT_ENV_VAR, "raw", || {
+            assert_eq!(ProfilerConfig::from_env().il_dump_format, IlDumpFormat::Raw);
+        });
+        with_env_var(
+            ELASTIC_APM_PROFILER_IL_DUMP_FORMAT_ENV_VAR,
+            "binary",
+            || {
+                assert_eq!(ProfilerConfig::from_env().il_dump_format, IlDumpFormat::Hex);
+            },
+        );
+
+        let warnings = validate_env_vars_with(get_var_from(&[(
+            ELASTIC_APM_PROFILER_IL_DUMP_FORMAT_ENV_VAR,
+            "binary",
+        )]));
+        assert_eq!(warnings.len(), 1);
+    }
+
+    #[test]
+    fn integration_disable_optimizations_is_optional() {
+        let integrations = load_integrations_with_config(&ProfilerConfig {
+            integrations_inline: Some(
+                "- name: OptimizationsDefault\n  method_replacements: []\n\
+                 - name: OptimizationsDisabled\n  method_replacements: []\n  disable_optimizations: true\n"
+                    .into(),
+            ),
+            ..ProfilerConfig::with_defaults()
+        })
+        .unwrap();
+
+        let disable_optimizations: Vec<Option<bool>> = integrations
+            .iter()
+            .map(|i| i.disable_optimizations)
+            .collect();
+        assert_eq!(disable_optimizations, vec![None, Some(true)]);
+    }
+
+    #[test]
+    fn get_coreclr_profiler_clsid_validates_format() {
+        let clsid = "{FA65FE15-F085-4681-9B20-95E04F6C03CC}";
+        assert_eq!(
+            get_coreclr_profiler_clsid_with(get_var_from(&[("CORECLR_PROFILER", clsid)])),
+            Some(clsid.to_string())
+        );
+        assert_eq!(
+            get_coreclr_profiler_clsid_with(get_var_from(&[("COR_PROFILER", clsid)])),
+            Some(clsid.to_string())
+        );
+        assert_eq!(get_coreclr_profiler_clsid_with(get_var_from(&[])), None);
+
+        for invalid in [
+            "FA65FE15-F085-4681-9B20-95E04F6C03CC",
+            "{FA65FE15-F085-4681-9B20-95E04F6C03C}",
+            "{FA65FE15-F085-4681-9B20-95E04F6C03CG}",
+            "{FA65FE15F085-4681-9B20-95E04F6C03CCC}",
+            "",
+        ] {
+            assert_eq!(
+                get_coreclr_profiler_clsid_with(get_var_from(&[("CORECLR_PROFILER", invalid)])),
+                None,
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn detect_container_from_cgroup_dockerenv_or_kubernetes() {
+        let no_vars = get_var_from(&[]);
+        assert!(detect_container(
+            Some("12:pids:/docker/8f3a2b1c4d5e\n"),
+            false,
+            &no_vars
+        ));
+        assert!(detect_container(
+            Some("0::/kubepods/besteffort/pod1234\n"),
+            false,
+            &no_vars
+        ));
+        assert!(!detect_container(Some("0::/init.scope\n"), true, &no_vars));
+        assert!(detect_container(None, true, &no_vars));
+        assert!(!detect_container(None, false, &no_vars));
+        assert!(detect_container(
+            None,
+            false,
+            get_var_from(&[("KUBERNETES_SERVICE_HOST", "10.0.0.1")])
+        ));
+    }
+
+    #[test]
+    fn read_log_targets_defaults_to_stdout_in_container() {
+        let stdout: LogTargetSet = std::iter::once(LogTarget::Stdout).collect();
+        let stderr: LogTargetSet = std::iter::once(LogTarget::Stderr).collect();
+        assert_eq!(read_log_targets_with(get_var_from(&[]), true), stdout);
+        assert_eq!(
+            read_log_targets_with(get_var_from(&[]), false),
+            LogTargetSet::default()
+        );
+        assert_eq!(
+            read_log_targets_with(
+                get_var_from(&[("ELASTIC_APM_PROFILER_LOG_TARGETS", "invalid")]),
+                true
+            ),
+            stdout
+        );
+        assert_eq!(
+            read_log_targets_with(
+                get_var_from(&[("ELASTIC_APM_PROFILER_LOG_TARGETS", "stderr")]),
+                true
+            ),
+            stderr
+        );
+    }
+
+    #[test]
+    fn get_profiler_dir_prefers_coreclr_env_vars() {
+        let coreclr_env_var = if cfg!(target_pointer_width = "64") {
+            "CORECLR_PROFILER_PATH_64"
+        } else {
+            "CORECLR_PROFILER_PATH_32"
+        };
+        let vars = [
+            ("COR_PROFILER_PATH", "/cor"),
+            ("CORECLR_PROFILER_PATH", "/coreclr"),
+            (coreclr_env_var, "/coreclr_bitness"),
+        ];
+        assert_eq!(
+            get_profiler_dir(get_var_from(&vars)),
+            Some(PathBuf::from("/coreclr_bitness"))
+        );
+        assert_eq!(
+            get_profiler_dir(get_var_from(&vars[..2])),
+            Some(PathBuf::from("/coreclr"))
+        );
+        assert_eq!(
+            get_profiler_dir(get_var_from(&vars[..1])),
+            Some(PathBuf::from("/cor"))
+        );
+    }
+
+    #[test]
+    fn get_managed_loader_path_with_validates_configured_file() -> Result<(), Box<dyn Error>> {
+        let path = TempPath::new("managed_loader.dll");
+        let error =
+            get_managed_loader_path_with(Some(&path), get_var_from(&[]), "net462").unwrap_err();
+        assert_eq!(
+            error.kind(),
+            &ProfilerErrorKind::ManagedLoaderFile(path.to_path_buf())
+        );
+
+        std::fs::write(&path, b"loader")?;
+        let result = get_managed_loader_path_with(Some(&path), get_var_from(&[]), "net462");
+        assert_eq!(result?, path.to_path_buf());
+
+        let error = get_managed_loader_path_with(None, get_var_from(&[]), "net462").unwrap_err();
+        assert_eq!(error.kind(), &ProfilerErrorKind::ManagedLoaderNotConfigured);
+        Ok(())
+    }
+
+    #[test]
+    fn get_managed_loader_path_with_defaults_to_profiler_directory() -> Result<(), Box<dyn Error>> {
+        let profiler_dir = TempPath::new("managed_loader_dir");
+        let loader_path = profiler_dir
+            .join("netcoreapp2.0")
+            .join("Elastic.Apm.Profiler.Managed.Loader.dll");
+        std::fs::create_dir_all(loader_path.parent().unwrap())?;
+        std::fs::write(&loader_path, b"loader")?;
+        let profiler_path = profiler_dir.join("libelastic_apm_profiler.so");
+        let profiler_path = profiler_path.to_string_lossy();
+        let vars = [("CORECLR_PROFILER_PATH", profiler_path.as_ref())];
+
+        let result = get_managed_loader_path_with(None, get_var_from(&vars), "netcoreapp2.0");
+        let missing = get_managed_loader_path_with(None, get_var_from(&vars), "net462");
+
+        assert_eq!(result?, loader_path);
+        assert_eq!(
+            missing.unwrap_err().kind(),
+            &ProfilerErrorKind::ManagedLoaderFile(
+                profiler_dir
+                    .join("net462")
+                    .join("Elastic.Apm.Profiler.Managed.Loader.dll")
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn get_profiler_dir_returns_none_when_not_set_or_empty() {
+        assert_eq!(get_profiler_dir(get_var_from(&[])), None);
+        assert_eq!(
+            get_profiler_dir(get_var_from(&[("COR_PROFILER_PATH", " ")])),
+            None
+        );
+    }
+
+    #[test]
+    fn get_home_log_dir_with_falls_back_to_profiler_dir() {
+        let vars = [
+            ("ELASTIC_APM_PROFILER_HOME", "/home"),
+            ("COR_PROFILER_PATH", "/profiler"),
+        ];
+        assert_eq!(
+            get_home_log_dir_with(get_var_from(&vars)),
+            Some(PathBuf::from("/home/logs"))
+        );
+        assert_eq!(
+            get_home_log_dir_with(get_var_from(&vars[1..])),
+            Some(PathBuf::from("/profiler/logs"))
+        );
+        assert_eq!(get_home_log_dir_with(get_var_from(&[])), None);
+    }
+
+    #[test]
+    fn get_temp_dir_with_prefers_configured_dir() {
+        let configured = PathBuf::from("/configured/temp");
+        assert_eq!(
+            get_temp_dir_with(Some(&configured), &std::env::temp_dir(), || {
+                PathBuf::from("/logs")
+            }),
+            configured
+        );
+    }
+
+    #[test]
+    fn get_temp_dir_with_falls_back_to_system_temp_dir() {
+        let system_temp_dir = std::env::temp_dir();
+        assert_eq!(
+            get_temp_dir_with(None, &system_temp_dir, || PathBuf::from("/logs")),
+            system_temp_dir
+        );
+    }
+
+    #[test]
+    fn get_temp_dir_with_falls_back_to_log_dir_when_system_temp_dir_is_missing() {
+        let missing = TempPath::new("missing_temp");
+        assert_eq!(
+            get_temp_dir_with(None, &missing, || PathBuf::from("/logs")),
+            PathBuf::from("/logs")
+        );
+    }
+
+    #[test]
+    fn get_windows_default_log_dir_with_falls_back_to_home_then_temp_dir() {
+        let vars = [
+            ("PROGRAMDATA", "/programdata"),
+            ("ELASTIC_APM_PROFILER_HOME", "/home"),
+        ];
+        assert_eq!(
+            get_windows_default_log_dir_with(get_var_from(&vars)),
+            PathBuf::from("/programdata/elastic/apm-agent-dotnet/logs")
+        );
+        assert_eq!(
+            get_windows_default_log_dir_with(get_var_from(&vars[1..])),
+            PathBuf::from("/home/logs")
+        );
+        assert_eq!(
+            get_windows_default_log_dir_with(get_var_from(&[])),
+            std::env::temp_dir()
+                .join("elastic")
+                .join("apm-agent-dotnet")
+                .join("logs")
+        );
+    }
+
+    #[test]
+    fn load_integrations_with_config_limits_call_targets() -> Result<(), Box<dyn Error>> {
+        let path = TempPath::new("max_call_targets.yml");
+        let method = |i: usize, action: &str| {
+            format!(
+                "  - target:
+      assembly: System.Data
+      type: System.Data.Common.DbCommand
+      method: Execute{}
+    wrapper:
+      assembly: Elastic.Apm.Profiler.Managed, Version=1.27.0.0, Culture=neutral, PublicKeyToken=ae7400d2c189cf22
+      type: Integration{}
+      action: {}
+",
+                i, i, action
+            )
+        };
+        // each integration instruments two methods with CallTarget
+        let yaml: String = (1..=20)
+            .map(|i| {
+                format!(
+                    "- name: Integration{}\n  method_replacements:\n{}{}{}",
+                    i,
+                    method(i * 3, "CallTargetModification"),
+                    method(i * 3 + 1, "CallTargetModification"),
+                    method(i * 3 + 2, "ReplaceTargetMethod")
+                )
+            })
+            .collect();
+        std::fs::write(&path, yaml)?;
+
+        let integrations = load_integrations_with_config(&ProfilerConfig {
+            integrations_path: Some(path.to_string_lossy().to_string()),
+            max_call_targets: Some(5),
+            ..ProfilerConfig::with_defaults()
+        });
+
+        let integrations = filter_enabled_integrations(integrations.unwrap());
+        let names: Vec<&str> = integrations.iter().map(|i| i.name.as_str()).collect();
+        assert_eq!(names, vec!["Integration1", "Integration2"]);
+        Ok(())
+    }
+
+    #[test]
+    fn get_integrations_file_paths_follows_load_order() {
+        let config = ProfilerConfig {
+            home: Some("home".into()),
+            ..ProfilerConfig::with_defaults()
+        };
+        assert_eq!(
+            get_integrations_file_paths(&config),
+            vec![PathBuf::from("home").join("integrations.yml")]
+        );
+
+        let config = ProfilerConfig {
+            integrations_inline: Some("[]".into()),
+            ..config
+        };
+        assert!(get_integrations_file_paths(&config).is_empty());
+
+        let config = ProfilerConfig {
+            integrations_path: Some("a.yml; ;b.yml".into()),
+            ..config
+        };
+        assert_eq!(
+            get_integrations_file_paths(&config),
+            vec![PathBuf::from("a.yml"), PathBuf::from("b.yml")]
+        );
+    }
+
+    #[test]
+    fn write_env_vars_writes_environment_variables() -> Result<(), Box<dyn Error>> {
+        let output = with_env_var("ELASTIC_APM_PROFILER_TEST_PRINT_ENV", "printed", || {
+            let mut output = Vec::new();
+            write_env_vars(&mut output).map(|_| output)
+        })?;
+
+        let output = String::from_utf8(output)?;
+        assert!(output.starts_with("Environment variables\n"));
+        assert!(output.contains("  ELASTIC_APM_PROFILER_TEST_PRINT_ENV=\"printed\""));
+        Ok(())
+    }
+
+    #[test]
+    fn sanitize_log_prefix_removes_newlines_and_truncates() {
+        assert_eq!(
+            sanitize_log_prefix("[my\napp]\r\n"),
+            Some("[myapp]".to_string())
+        );
+        assert_eq!(sanitize_log_prefix(&"a".repeat(100)), Some("a".repeat(64)));
+        assert_eq!(sanitize_log_prefix("\n"), None);
+    }
+
+    #[test]
+    fn parse_log_context_fields_ignores_invalid_entries() {
+        assert_eq!(
+            parse_log_context_fields(
+                " datacenter = us-east-1;cluster=prod-k8s;invalid;=empty;level=info;cluster=dev"
+            ),
+            vec![
+                ("datacenter".to_string(), "us-east-1".to_string()),
+                ("cluster".to_string(), "dev".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn load_integrations_with_config_without_path_or_home_fails() {
+        assert!(load_integrations_with_config(&ProfilerConfig::with_defaults()).is_err());
+    }
+
+    /// Serves each `(status, headers, body)` HTTP response in turn on a local port,
+    /// returning the URL to request and a receiver of the requests received
+    fn serve(
+        responses: Vec<(&'static str, &'static str, &'static str)>,
+    ) -> Result<(String, mpsc::Receiver<String>), Box<dyn Error>> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("http://{}/integrations.yml", listener.local_addr()?);
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for (status, headers, body) in responses {
+                if let Ok((mut stream, _)) = listener.accept() {
+                    let mut request = [0; 1024];
+                    let len = stream.read(&mut request).unwrap_or_default();
+                    let _ = sender.send(String::from_utf8_lossy(&request[..len]).into_owned());
+                    let _ = write!(
+                        stream,
+                        "HTTP/1.1 {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        status,
+                        headers,
+                        body.len(),
+                        body
+                    );
+                }
+            }
+        });
+        Ok((url, receiver))
+    }
+
+    #[test]
+    fn downloaded_integrations_path_is_stable_and_private() -> Result<(), Box<dyn Error>> {
+        let path = downloaded_integrations_path("https://example.com/integrations.json?v=1")?;
+        assert_eq!(
+            path.file_name().unwrap(),
+            "elastic_apm_profiler_integrations_32e183be295d190a.json"
+        );
+        assert_eq!(path.parent(), Some(get_private_temp_dir()?.as_path()));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let shared = TempPath::new("shared");
+            std::fs::create_dir_all(&shared)?;
+            std::fs::set_permissions(&shared, std::fs::Permissions::from_mode(0o777))?;
+            let result = create_private_dir(&shared);
+            assert_eq!(
+                result.unwrap_err().kind(),
+                std::io::ErrorKind::PermissionDenied
+            );
+        }
+        Ok(())
+    }
+
+    fn sha256(body: &str) -> String {
+        let mut sha256 = Sha256::new();
+        sha256.input_str(body);
+        sha256.result_str()
+    }
+
+    #[test]
+    fn download_integrations_requires_https_and_expected_hash() {
+        let timeout = Duration::from_secs(5);
+        let hash = "0".repeat(64);
+        for (url, expected_sha256, message) in [
+            (
+                "http://example.com/integrations.yml",
+                Some(hash.as_str()),
+                "the URL must use https",
+            ),
+            (
+                "https://example.com/integrations.yml",
+                None,
+                "ELASTIC_APM_PROFILER_INTEGRATIONS_EXPECTED_SHA256 must be set",
+            ),
+        ] {
+            let err = download_integrations(url, expected_sha256, timeout, false).unwrap_err();
+            assert!(matches!(
+                err.kind(),
+                ProfilerErrorKind::IntegrationsDownload(u) if u == url
+            ));
+            assert!(err.to_string().contains(message), "{}", err);
+        }
+    }
+
+    #[test]
+    fn download_integrations_falls_back_to_previous_download() -> Result<(), Box<dyn Error>> {
+        let timeout = Duration::from_secs(5);
+        let body = "- name: Downloaded\n  method_replacements: []\n";
+        let hash = sha256(body);
+        let (url, _) = serve(vec![
+            ("200 OK", "", body),
+            (
+                "200 OK",
+                "",
+                "- name: Tampered\n  method_replacements: []\n",
+            ),
+        ])?;
+        let path = downloaded_integrations_path(&url)?;
+        assert_eq!(path.extension().unwrap(), "yml");
+
+        let integrations = download_verified_integrations(&url, &hash, timeout, false)?;
+        assert_eq!(integrations[0].name, "Downloaded");
+
+        // a download without the expected hash is discarded, so the previous download is used
+        let integrations = download_verified_integrations(&url, &hash, timeout, false)?;
+        assert_eq!(integrations[0].name, "Downloaded");
+
+        // the server has stopped, so the previous download is used
+        let integrations = download_verified_integrations(&url, &hash, timeout, false)?;
+        assert_eq!(integrations[0].name, "Downloaded");
+
+        // the previous download is verified against the expected hash
+        let err =
+            download_verified_integrations(&url, &"0".repeat(64), timeout, false).unwrap_err();
+        assert!(matches!(err.kind(), ProfilerErrorKind::HashMismatch { .. }));
+        std::fs::remove_file(&path)?;
+
+        let (url, _) = serve(vec![("404 Not Found", "", "")])?;
+        let err = download_verified_integrations(&url, &hash, timeout, false).unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            ProfilerErrorKind::IntegrationsDownload(u) if u == &url
+        ));
+        assert!(!downloaded_integrations_path(&url)?.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn download_integrations_with_etag_cache_reuses_unmodified_file() -> Result<(), Box<dyn Error>>
+    {
+        let timeout = Duration::from_secs(5);
+        let (url, requests) = serve(vec![
+            (
+                "200 OK",
+                "ETag: \"v1\"\r\n",
+                "- name: Downloaded\n  method_replacements: []\n",
+            ),
+            ("304 Not Modified", "", ""),
+            (
+                "200 OK",
+                "ETag: \"v2\"\r\n",
+                "- name: Changed\n  method_replacements: []\n",
+            ),
+        ])?;
+        let path = downloaded_integrations_path(&url)?;
+        let etag_path = PathBuf::from(format!("{}.etag", path.display()));
+
+        let hash = sha256("- name: Downloaded\n  method_replacements: []\n");
+        let integrations = download_verified_integrations(&url, &hash, timeout, true)?;
+        assert_eq!(integrations[0].name, "Downloaded");
+        assert!(!requests.recv()?.to_lowercase().contains("if-none-match"));
+        assert_eq!(std::fs::read_to_string(&etag_path)?, "\"v1\"");
+
+        let integrations = download_verified_integrations(&url, &hash, timeout, true)?;
+        assert_eq!(integrations[0].name, "Downloaded");
+        assert!(requests
+            .recv()?
+            .to_lowercase()
+            .contains("if-none-match: \"v1\""));
+
+        let hash = sha256("- name: Changed\n  method_replacements: []\n");
+        let integrations = download_verified_integrations(&url, &hash, timeout, true)?;
+        assert_eq!(integrations[0].name, "Changed");
+        assert_eq!(std::fs::read_to_string(&etag_path)?, "\"v2\"");
+
+        std::fs::remove_file(&path)?;
+        std::fs::remove_file(&etag_path)?;
+        Ok(())
+    }
+}
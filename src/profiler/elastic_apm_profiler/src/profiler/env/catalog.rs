@@ -0,0 +1,596 @@
+// Licensed to Elasticsearch B.V under
+// one or more agreements.
+// Elasticsearch B.V licenses this file to you under the Apache 2.0 License.
+// See the LICENSE file in the project root for more information
+
+//! A catalog of the environment variables recognised by the profiler.
+//!
+//! The catalog is printed as a table to stdout at startup when
+//! `ELASTIC_APM_PROFILER_PRINT_HELP` is `true`.
+
+use super::*;
+use std::io::{self, Write};
+
+/// An environment variable recognised by the profiler
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct EnvVarEntry {
+    /// The name of the environment variable
+    pub name: &'static str,
+    /// What the environment variable configures
+    pub description: &'static str,
+    /// The value used when the environment variable is not set
+    pub default: &'static str,
+    /// The type of value expected
+    pub value_type: &'static str,
+}
+
+const fn entry(
+    name: &'static str,
+    value_type: &'static str,
+    default: &'static str,
+    description: &'static str,
+) -> EnvVarEntry {
+    EnvVarEntry {
+        name,
+        description,
+        default,
+        value_type,
+    }
+}
+
+/// The environment variables recognised by the profiler, ordered by name
+pub static ENV_VAR_CATALOG: &[EnvVarEntry] = &[
+    entry(
+        APP_POOL_ID_ENV_VAR,
+        "string",
+        "",
+        "The IIS application pool of the process. Set by IIS",
+    ),
+    entry(
+        COMPLUS_LOADEROPTIMIZATION,
+        "string",
+        "",
+        "The .NET Framework loader optimization. Logged for diagnostics",
+    ),
+    entry(
+        DOTNET_CLI_TELEMETRY_PROFILE_ENV_VAR,
+        "string",
+        "",
+        "Identifies processes started by the dotnet CLI, which are not profiled",
+    ),
+    entry(
+        DOTNET_STARTUP_HOOKS_ENV_VAR,
+        "paths",
+        "",
+        "The .NET startup hooks, to which ELASTIC_APM_PROFILER_STARTUP_HOOKS are added",
+    ),
+    entry(
+        ELASTIC_APM_AGENT_CONFIG_FILE_ENV_VAR,
+        "path",
+        "",
+        "The Elastic APM agent config file. Set from ELASTIC_APM_PROFILER_AGENT_CONFIG_PATH",
+    ),
+    entry(
+        ELASTIC_APM_ENVIRONMENT_ENV_VAR,
+        "string",
+        "",
+        "The deployment environment of the agent. Set from ELASTIC_APM_PROFILER_AGENT_ENVIRONMENT",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_AGENT_CONFIG_PATH_ENV_VAR,
+        "path",
+        "",
+        "The Elastic APM agent config file to pass to the managed loader",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_AGENT_ENVIRONMENT_ENV_VAR,
+        "string",
+        "",
+        "The deployment environment to pass to the managed loader, unless \
+        ELASTIC_APM_ENVIRONMENT is set",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_AGENT_SERVICE_NAME_ENV_VAR,
+        "string",
+        "",
+        "The service name to pass to the managed loader, unless ELASTIC_APM_SERVICE_NAME is set",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_CALLTARGET_ALLOW_LIST_ENV_VAR,
+        "list",
+        "",
+        "Semicolon separated Assembly::Type::Method of the only methods instrumented with \
+        CallTarget",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_CALLTARGET_BATCH_SIZE_ENV_VAR,
+        "integer",
+        "100",
+        "The maximum number of methods requested for CallTarget instrumentation per JIT \
+        compilation",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_CALLTARGET_ENABLED_ENV_VAR,
+        "bool",
+        "true",
+        "Whether CallTarget instrumentation is enabled",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_CALLTARGET_EXCLUDE_ASSEMBLIES_ENV_VAR,
+        "list",
+        "",
+        "Semicolon separated names of assemblies not instrumented with CallTarget",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_CONFIG_DUMP_ENABLED_ENV_VAR,
+        "bool",
+        "false",
+        "Whether the configuration can be dumped to the log directory on demand, after startup",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_DIAGNOSTIC_REPORT_PATH_ENV_VAR,
+        "path",
+        "",
+        "The path of a diagnostic report to write at startup",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_DISABLE_ALL_ENV_VAR,
+        "bool",
+        "false",
+        "Disables the profiler for all processes, without uninstalling it",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_DISABLE_OPTIMIZATIONS_ENV_VAR,
+        "bool",
+        "false",
+        "Whether JIT optimizations are disabled",
+    ),
+    entry(
+        "ELASTIC_APM_PROFILER_DUMP_CONFIG",
+        "bool",
+        "false",
+        "On Windows, setting to true after startup dumps the configuration to the log \
+        directory, when ELASTIC_APM_PROFILER_CONFIG_DUMP_ENABLED is true",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_ENABLE_INLINING_ENV_VAR,
+        "bool",
+        "CallTarget enabled",
+        "Whether JIT inlining is enabled",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_EVENTLOG_SOURCE_ENV_VAR,
+        "string",
+        DEFAULT_EVENTLOG_SOURCE,
+        "The source name used when logging to the Windows Event Log",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_EXCEPTION_FILTER_TYPES_ENV_VAR,
+        "list",
+        "",
+        "Semicolon separated prefixes of the fully qualified names of the only exception types \
+        captured",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_EXCEPTION_SAMPLING_RATE_ENV_VAR,
+        "number",
+        "1.0",
+        "The fraction of exceptions captured, between 0.0 and 1.0",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_EXCLUDE_INTEGRATIONS_ENV_VAR,
+        "list",
+        "",
+        "Semicolon separated names of integrations to exclude",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_EXCLUDE_PROCESSES_ENV_VAR,
+        "list",
+        "",
+        "Semicolon separated names of processes not to profile",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_EXCLUDE_SERVICE_NAMES_ENV_VAR,
+        "list",
+        "",
+        "Semicolon separated service names of processes not to profile",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_GC_PRESSURE_SAMPLING_ENV_VAR,
+        "bool",
+        "false",
+        "Whether to log the sizes of the GC heap generations after each GC",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_HEAP_PROFILING_ENABLED_ENV_VAR,
+        "bool",
+        "false",
+        "Whether to sample object allocations and log them by type",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_HEAP_PROFILING_INTERVAL_SECS_ENV_VAR,
+        "integer",
+        "60",
+        "The interval in seconds at which to log the sampled object allocations",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_HEAP_PROFILING_RATE_ENV_VAR,
+        "integer",
+        "1000",
+        "1 in every N object allocations is sampled when heap profiling is enabled",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_HOME_ENV_VAR,
+        "path",
+        "",
+        "The directory containing the profiler and its integrations.yml file",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_IL_DUMP_FORMAT_ENV_VAR,
+        "string",
+        "hex",
+        "The format in which IL is dumped when ELASTIC_APM_PROFILER_LOG_IL is true: hex, \
+        disasm or raw",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_INTEGRATIONS_ENV_VAR,
+        "paths",
+        "",
+        "Semicolon separated paths of integrations files",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_INTEGRATIONS_CACHE_PATH_ENV_VAR,
+        "path",
+        "",
+        "The path of a file in which parsed integrations are cached",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_INTEGRATIONS_DOWNLOAD_ETAG_CACHE_ENV_VAR,
+        "bool",
+        "false",
+        "Whether to download the integrations file only when its ETag has changed",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_INTEGRATIONS_DOWNLOAD_TIMEOUT_SECS_ENV_VAR,
+        "integer",
+        "10",
+        "The timeout in seconds for downloading the integrations file",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_INTEGRATIONS_DOWNLOAD_URL_ENV_VAR,
+        "url",
+        "",
+        "The HTTPS URL from which to download the integrations file. Requires \
+        ELASTIC_APM_PROFILER_INTEGRATIONS_EXPECTED_SHA256",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_INTEGRATIONS_EXPECTED_SHA256_ENV_VAR,
+        "hex",
+        "",
+        "The SHA-256 hash that integrations files must have to be loaded",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_INTEGRATIONS_GLOB_ENV_VAR,
+        "glob",
+        "",
+        "A glob pattern matching the integrations files",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_INTEGRATIONS_INLINE_ENV_VAR,
+        "yaml",
+        "",
+        "Integrations as YAML",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_INTEGRATIONS_RELOAD_INTERVAL_SECS_ENV_VAR,
+        "integer",
+        "0",
+        "The interval in seconds at which to reload integrations. 0 disables reloading",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_INTEGRATIONS_SCHEMA_VALIDATE_ENV_VAR,
+        "bool",
+        "false",
+        "Whether integrations with unknown fields fail to load",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_INTEGRATIONS_WATCH_ENV_VAR,
+        "bool",
+        "false",
+        "Whether to reload integrations when the integrations files change",
+    ),
+    entry(
+        "ELASTIC_APM_PROFILER_INTEGRATION_<NAME>_ENABLED",
+        "bool",
+        "",
+        "Whether the integration named <NAME> is enabled, overriding the integrations file",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_INTEGRATION_TIMEOUT_MS_ENV_VAR,
+        "integer",
+        "5000",
+        "The timeout in milliseconds for loading integrations, other than downloaded \
+        integrations. 0 disables the timeout",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_LOG_ENV_VAR,
+        "level",
+        "warn",
+        "The log level: off, error, warn, info, debug or trace",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_LOG_ASYNC_BUFFER_SIZE_ENV_VAR,
+        "integer",
+        "1024",
+        "The number of log records buffered for writing to log files. 0 writes synchronously",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_LOG_CONTEXT_FIELDS_ENV_VAR,
+        "list",
+        "",
+        "Semicolon separated key=value pairs attached to every log record",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_LOG_DIR_ENV_VAR,
+        "paths",
+        "",
+        "Semicolon separated directories in which to write log files, tried in order",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_LOG_FLUSH_INTERVAL_MS_ENV_VAR,
+        "integer",
+        "500",
+        "The interval in milliseconds at which buffered log records are flushed",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_LOG_FORMAT_ENV_VAR,
+        "string",
+        "text",
+        "The format of log records: text or json",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_LOG_IL_ENV_VAR,
+        "bool",
+        "false",
+        "Whether to log the IL of instrumented methods",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_LOG_IL_DIR_ENV_VAR,
+        "path",
+        "",
+        "The directory in which to write IL log files. Defaults to the log directory",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_LOG_LEVEL_OVERRIDES_ENV_VAR,
+        "list",
+        "",
+        "Comma separated module=level log level overrides",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_LOG_MAX_FILES_ENV_VAR,
+        "integer",
+        "10",
+        "The maximum number of rolled log files to keep",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_LOG_MAX_SIZE_MB_ENV_VAR,
+        "integer",
+        "5",
+        "The size in megabytes at which a log file is rolled",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_LOG_PREFIX_ENV_VAR,
+        "string",
+        "",
+        "A string prepended to every text log record",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_LOG_REDACT_ENV_VAR,
+        "bool",
+        "false",
+        "Whether to redact the home directory and username from log records",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_LOG_ROTATION_ENV_VAR,
+        "string",
+        "size",
+        "When log files are rolled: size or daily",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_LOG_SHUTDOWN_TIMEOUT_MS_ENV_VAR,
+        "integer",
+        "2000",
+        "The milliseconds to wait for buffered log records to be written when the process exits",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_LOG_TARGETS_ENV_VAR,
+        "list",
+        "file",
+        "Semicolon separated log targets: file, stdout, stderr or eventlog",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_MANAGED_LOADER_PATH_ENV_VAR,
+        "path",
+        "",
+        "The path of the managed loader assembly, used instead of the embedded assembly",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_MAX_CALL_TARGETS_ENV_VAR,
+        "integer",
+        "",
+        "The maximum number of instrumented methods. Unlimited when not set",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_METRICS_ENABLED_ENV_VAR,
+        "bool",
+        "false",
+        "Whether to periodically log a summary of the profiler metrics",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_METRICS_INTERVAL_SECS_ENV_VAR,
+        "integer",
+        "60",
+        "The interval in seconds at which to log the profiler metrics",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_PRINT_ENV_ENV_VAR,
+        "bool",
+        "false",
+        "Whether to print the environment variables to stderr at startup",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_PRINT_HELP_ENV_VAR,
+        "bool",
+        "false",
+        "Whether to print this table of environment variables to stdout at startup",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_PROCESS_EXCLUDE_ENV_VAR,
+        "list",
+        "",
+        "Comma separated names of processes never profiled",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_PROCESS_FILTER_ENV_VAR,
+        "list",
+        "",
+        "Comma separated names of the only processes profiled",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_SPAN_STACK_TRACE_MIN_DURATION_MS_ENV_VAR,
+        "integer",
+        "",
+        "The minimum duration in milliseconds of spans for which the agent captures a stack \
+        trace, unless ELASTIC_APM_SPAN_STACK_TRACE_MIN_DURATION is set",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_STARTUP_HOOKS_ENV_VAR,
+        "paths",
+        "",
+        "Startup hook assemblies added to DOTNET_STARTUP_HOOKS",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_TEMP_DIR_ENV_VAR,
+        "path",
+        "",
+        "The directory in which temporary files are created. Defaults to the system temp \
+        directory",
+    ),
+    entry(
+        ELASTIC_APM_PROFILER_THREAD_CPU_SAMPLING_INTERVAL_MS_ENV_VAR,
+        "integer",
+        "0",
+        "The interval in milliseconds at which to sample the CPU time of each thread. 0 disables \
+        sampling",
+    ),
+    entry(
+        ELASTIC_APM_SERVICE_NAME_ENV_VAR,
+        "string",
+        "",
+        "The service name of the process",
+    ),
+    entry(
+        ELASTIC_APM_SPAN_STACK_TRACE_MIN_DURATION_ENV_VAR,
+        "duration",
+        "",
+        "The minimum duration of spans for which the agent captures a stack trace. Set from \
+        ELASTIC_APM_PROFILER_SPAN_STACK_TRACE_MIN_DURATION_MS",
+    ),
+    entry(
+        KUBERNETES_SERVICE_HOST_ENV_VAR,
+        "string",
+        "",
+        "Set in Kubernetes pods. Used to detect running in a container",
+    ),
+];
+
+/// Prints the catalog of environment variables as a table to stdout
+pub fn print_env_var_help() {
+    // nowhere to report a failure to write to stdout
+    let _ = write_env_var_help(&mut io::stdout());
+}
+
+fn write_env_var_help(w: &mut impl Write) -> io::Result<()> {
+    let headers = ("NAME", "TYPE", "DEFAULT", "DESCRIPTION");
+    let name_width = column_width(headers.0, |e| e.name);
+    let type_width = column_width(headers.1, |e| e.value_type);
+    let default_width = column_width(headers.2, |e| e.default);
+    let mut write_row = |name: &str, value_type: &str, default: &str, description: &str| {
+        writeln!(
+            w,
+            "{:name_width$}  {:type_width$}  {:default_width$}  {}",
+            name,
+            value_type,
+            default,
+            description,
+            name_width = name_width,
+            type_width = type_width,
+            default_width = default_width
+        )
+    };
+
+    write_row(headers.0, headers.1, headers.2, headers.3)?;
+    for entry in ENV_VAR_CATALOG {
+        write_row(
+            entry.name,
+            entry.value_type,
+            entry.default,
+            entry.description,
+        )?;
+    }
+    Ok(())
+}
+
+fn column_width(header: &str, value: impl Fn(&EnvVarEntry) -> &str) -> usize {
+    ENV_VAR_CATALOG
+        .iter()
+        .map(|e| value(e).len())
+        .chain(std::iter::once(header.len()))
+        .max()
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::profiler::env::catalog::{write_env_var_help, ENV_VAR_CATALOG};
+    use std::error::Error;
+
+    #[test]
+    fn catalog_has_entry_for_every_env_var_constant() {
+        let source = include_str!("../env.rs");
+        let mut missing = Vec::new();
+        for (i, _) in source.match_indices("_ENV_VAR: &str =") {
+            let value = source[i..].split('"').nth(1).unwrap();
+            if !ENV_VAR_CATALOG.iter().any(|e| e.name == value) {
+                missing.push(value);
+            }
+        }
+        assert!(missing.is_empty(), "not in catalog: {:?}", missing);
+    }
+
+    #[test]
+    fn catalog_is_ordered_by_name() {
+        let names: Vec<&str> = ENV_VAR_CATALOG.iter().map(|e| e.name).collect();
+        let mut sorted = names.clone();
+        sorted.sort_unstable();
+        assert_eq!(names, sorted);
+    }
+
+    #[test]
+    fn write_env_var_help_writes_aligned_table() -> Result<(), Box<dyn Error>> {
+        let mut help = Vec::new();
+        write_env_var_help(&mut help)?;
+        let help = String::from_utf8(help)?;
+        let lines: Vec<&str> = help.lines().collect();
+
+        assert_eq!(lines.len(), ENV_VAR_CATALOG.len() + 1);
+        assert!(lines[0].starts_with("NAME "));
+        let type_column = lines[0].find("TYPE").unwrap();
+        let line = lines
+            .iter()
+            .find(|l| l.starts_with("ELASTIC_APM_PROFILER_LOG "))
+            .unwrap();
+        assert_eq!(&line[type_column..type_column + 5], "level");
+        Ok(())
+    }
+}
@@ -0,0 +1,233 @@
+// Licensed to Elasticsearch B.V under
+// one or more agreements.
+// Elasticsearch B.V licenses this file to you under the Apache 2.0 License.
+// See the LICENSE file in the project root for more information
+
+//! Point-in-time snapshots of the environment variables that configure the profiler.
+//!
+//! A snapshot is captured at startup, and compared with a later snapshot to detect when
+//! the host process mutates the environment after the profiler has read its configuration.
+
+use crate::profiler::env::is_secret_env_var;
+use once_cell::sync::OnceCell;
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+};
+
+/// The prefixes of the environment variables captured in a snapshot
+const SNAPSHOT_ENV_VAR_PREFIXES: [&str; 3] = ["ELASTIC_", "CORECLR_", "COR_"];
+
+static STARTUP_SNAPSHOT: OnceCell<EnvSnapshot> = OnceCell::new();
+
+/// The environment variables relevant to the profiler at a point in time
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct EnvSnapshot {
+    vars: HashMap<String, String>,
+}
+
+impl EnvSnapshot {
+    /// Captures the current `ELASTIC_`, `CORECLR_` and `COR_` environment variables
+    pub fn capture() -> Self {
+        Self::from_vars(std::env::vars())
+    }
+
+    /// Creates a snapshot of the relevant variables in `vars`
+    pub fn from_vars(vars: impl IntoIterator<Item = (String, String)>) -> Self {
+        Self {
+            vars: vars
+                .into_iter()
+                .filter(|(key, _)| {
+                    let key = key.to_uppercase();
+                    SNAPSHOT_ENV_VAR_PREFIXES
+                        .iter()
+                        .any(|prefix| key.starts_with(prefix))
+                })
+                .collect(),
+        }
+    }
+
+    /// The captured environment variables
+    pub fn vars(&self) -> &HashMap<String, String> {
+        &self.vars
+    }
+
+    /// Gets the entries added, removed or changed in `other` compared to this snapshot,
+    /// ordered by key
+    pub fn diff(&self, other: &EnvSnapshot) -> Vec<EnvDiff> {
+        let mut diffs: Vec<EnvDiff> = self
+            .vars
+            .iter()
+            .filter_map(|(key, value)| match other.vars.get(key) {
+                None => Some(EnvDiff::Removed {
+                    key: key.clone(),
+                    value: value.clone(),
+                }),
+                Some(new_value) if new_value != value => Some(EnvDiff::Changed {
+                    key: key.clone(),
+                    old_value: value.clone(),
+                    new_value: new_value.clone(),
+                }),
+                Some(_) => None,
+            })
+            .chain(
+                other
+                    .vars
+                    .iter()
+                    .filter(|(key, _)| !self.vars.contains_key(*key))
+                    .map(|(key, value)| EnvDiff::Added {
+                        key: key.clone(),
+                        value: value.clone(),
+                    }),
+            )
+            .collect();
+        diffs.sort_by(|a, b| a.key().cmp(b.key()));
+        diffs
+    }
+}
+
+/// A difference in an environment variable between two snapshots
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum EnvDiff {
+    Added {
+        key: String,
+        value: String,
+    },
+    Removed {
+        key: String,
+        value: String,
+    },
+    Changed {
+        key: String,
+        old_value: String,
+        new_value: String,
+    },
+}
+
+impl EnvDiff {
+    /// The name of the environment variable
+    pub fn key(&self) -> &str {
+        match self {
+            EnvDiff::Added { key, .. } | EnvDiff::Removed { key, .. } => key,
+            EnvDiff::Changed { key, .. } => key,
+        }
+    }
+}
+
+impl Display for EnvDiff {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let display_value = |value: &str| -> String {
+            if is_secret_env_var(self.key()) {
+                "[REDACTED]".into()
+            } else {
+                format!("\"{}\"", value)
+            }
+        };
+        match self {
+            EnvDiff::Added { key, value } => write!(f, "added {}={}", key, display_value(value)),
+            EnvDiff::Removed { key, value } => {
+                write!(f, "removed {}={}", key, display_value(value))
+            }
+            EnvDiff::Changed {
+                key,
+                old_value,
+                new_value,
+            } => write!(
+                f,
+                "changed {}={} to {}",
+                key,
+                display_value(old_value),
+                display_value(new_value)
+            ),
+        }
+    }
+}
+
+/// Captures the snapshot of environment variables at startup, to which later snapshots
+/// are compared. Only the first call captures a snapshot
+pub fn capture_startup_snapshot() {
+    STARTUP_SNAPSHOT.get_or_init(EnvSnapshot::capture);
+}
+
+/// Logs at WARN level each environment variable that has changed since the startup
+/// snapshot was captured
+pub fn log_changes_since_startup() {
+    if let Some(startup_snapshot) = STARTUP_SNAPSHOT.get() {
+        let diffs = startup_snapshot.diff(&EnvSnapshot::capture());
+        if !diffs.is_empty() {
+            log::warn!(
+                "{} environment variable(s) changed since startup. Changes are not applied \
+                to the profiler configuration",
+                diffs.len()
+            );
+            for diff in diffs {
+                log::warn!("environment variable {}", diff);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::profiler::env::snapshot::{EnvDiff, EnvSnapshot};
+
+    fn snapshot(vars: &[(&str, &str)]) -> EnvSnapshot {
+        EnvSnapshot::from_vars(vars.iter().map(|(k, v)| (k.to_string(), v.to_string())))
+    }
+
+    #[test]
+    fn from_vars_captures_only_relevant_vars() {
+        let snapshot = snapshot(&[
+            ("ELASTIC_APM_SERVICE_NAME", "service"),
+            ("CORECLR_ENABLE_PROFILING", "1"),
+            ("COR_PROFILER", "{FA65FE15}"),
+            ("PATH", "/usr/bin"),
+        ]);
+
+        assert_eq!(snapshot.vars().len(), 3);
+        assert!(!snapshot.vars().contains_key("PATH"));
+    }
+
+    #[test]
+    fn diff_returns_added_removed_and_changed_vars() {
+        let before = snapshot(&[
+            ("ELASTIC_APM_SERVICE_NAME", "service"),
+            ("ELASTIC_APM_PROFILER_LOG", "warn"),
+            ("COR_PROFILER", "{FA65FE15}"),
+        ]);
+        let after = snapshot(&[
+            ("ELASTIC_APM_SERVICE_NAME", "service"),
+            ("ELASTIC_APM_PROFILER_LOG", "trace"),
+            ("ELASTIC_APM_SECRET_TOKEN", "token"),
+        ]);
+
+        let diffs = before.diff(&after);
+        assert_eq!(
+            diffs,
+            vec![
+                EnvDiff::Removed {
+                    key: "COR_PROFILER".into(),
+                    value: "{FA65FE15}".into()
+                },
+                EnvDiff::Changed {
+                    key: "ELASTIC_APM_PROFILER_LOG".into(),
+                    old_value: "warn".into(),
+                    new_value: "trace".into()
+                },
+                EnvDiff::Added {
+                    key: "ELASTIC_APM_SECRET_TOKEN".into(),
+                    value: "token".into()
+                },
+            ]
+        );
+        assert_eq!(
+            diffs[1].to_string(),
+            "changed ELASTIC_APM_PROFILER_LOG=\"warn\" to \"trace\""
+        );
+        assert_eq!(
+            diffs[2].to_string(),
+            "added ELASTIC_APM_SECRET_TOKEN=[REDACTED]"
+        );
+        assert!(after.diff(&after).is_empty());
+    }
+}
@@ -0,0 +1,274 @@
+// Licensed to Elasticsearch B.V under
+// one or more agreements.
+// Elasticsearch B.V licenses this file to you under the Apache 2.0 License.
+// See the LICENSE file in the project root for more information
+
+use crate::ffi::E_FAIL;
+use com::sys::HRESULT;
+use std::{
+    error::Error,
+    fmt::{self, Display, Formatter},
+    io,
+    path::PathBuf,
+};
+
+/// The kind of error raised by the profiler
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum ProfilerErrorKind {
+    /// The log directory, or any of its fallbacks, could not be created
+    LogDirectory(PathBuf),
+    /// The roller for rolled log files could not be created
+    LogRoller,
+    /// The log file could not be opened
+    LogFile,
+    /// The event log source could not be registered
+    #[cfg(target_os = "windows")]
+    EventLog,
+    /// The logging configuration is invalid
+    LogConfig,
+    /// The logger could not be set
+    SetLogger,
+    /// Integrations could not be read from the integrations file
+    IntegrationsFile(PathBuf),
+    /// Integrations could not be read from the files matching the glob pattern
+    IntegrationsGlob(String),
+    /// Integrations could not be read from the inline integrations environment variable
+    IntegrationsInline,
+    /// Integrations could not be downloaded from the URL, and no downloaded copy exists
+    IntegrationsDownload(String),
+    /// None of the environment variables from which integrations are loaded are set
+    IntegrationsNotConfigured,
+    /// Integrations have unknown fields, listed by integration name and field path
+    IntegrationsSchema(Vec<String>),
+    /// The SHA-256 hash of a file is not the expected hash
+    HashMismatch {
+        path: PathBuf,
+        expected: String,
+        actual: String,
+    },
+    /// An expected SHA-256 hash is set, but integrations are read from more than one file
+    HashMultipleFiles(Vec<PathBuf>),
+    /// Environment variables have unrecognised values, listed as `key=value`
+    #[cfg(feature = "strict-env")]
+    UnknownEnvVarValues(Vec<String>),
+    /// The managed loader assembly does not exist or could not be read
+    ManagedLoaderFile(PathBuf),
+    /// The path to the managed loader assembly is not configured, and could not be
+    /// determined from the path of the native profiler
+    ManagedLoaderNotConfigured,
+    /// The described operation did not complete within its timeout
+    Timeout(String),
+    /// The described operation panicked
+    Panicked(String),
+    /// A thread to run the described operation on could not be spawned
+    Thread(String),
+    /// An I/O operation failed
+    Io,
+    /// YAML could not be parsed
+    Yaml,
+    /// JSON could not be parsed
+    Json,
+    /// TOML could not be parsed
+    Toml,
+}
+
+impl Display for ProfilerErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ProfilerErrorKind::LogDirectory(path) => {
+                write!(f, "could not create log directory {}", path.display())
+            }
+            ProfilerErrorKind::LogRoller => f.write_str("could not create log file roller"),
+            ProfilerErrorKind::LogFile => f.write_str("could not open log file"),
+            #[cfg(target_os = "windows")]
+            ProfilerErrorKind::EventLog => f.write_str("could not register event log source"),
+            ProfilerErrorKind::LogConfig => f.write_str("invalid logging configuration"),
+            ProfilerErrorKind::SetLogger => f.write_str("could not set logger"),
+            ProfilerErrorKind::IntegrationsFile(path) => {
+                write!(f, "problem reading integrations file {}", path.display())
+            }
+            ProfilerErrorKind::IntegrationsGlob(pattern) => {
+                write!(f, "problem reading integrations files matching {}", pattern)
+            }
+            ProfilerErrorKind::IntegrationsInline => f.write_str(
+                "problem reading integrations from ELASTIC_APM_PROFILER_INTEGRATIONS_INLINE \
+                environment variable",
+            ),
+            ProfilerErrorKind::IntegrationsDownload(url) => {
+                write!(f, "problem downloading integrations from {}", url)
+            }
+            ProfilerErrorKind::IntegrationsNotConfigured => f.write_str(
+                "none of ELASTIC_APM_PROFILER_INTEGRATIONS_GLOB, ELASTIC_APM_PROFILER_INTEGRATIONS, \
+                ELASTIC_APM_PROFILER_INTEGRATIONS_INLINE or ELASTIC_APM_PROFILER_HOME environment \
+                variables set",
+            ),
+            ProfilerErrorKind::IntegrationsSchema(fields) => {
+                write!(f, "unknown integrations fields {}", fields.join(", "))
+            }
+            ProfilerErrorKind::HashMismatch {
+                path,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "SHA-256 hash of {} is {}, expected {}",
+                path.display(),
+                actual,
+                expected
+            ),
+            ProfilerErrorKind::HashMultipleFiles(paths) => write!(
+                f,
+                "ELASTIC_APM_PROFILER_INTEGRATIONS_EXPECTED_SHA256 is set, but integrations are \
+                read from multiple files {}",
+                paths
+                    .iter()
+                    .map(|p| p.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            #[cfg(feature = "strict-env")]
+            ProfilerErrorKind::UnknownEnvVarValues(values) => write!(
+                f,
+                "unrecognised environment variable values {}",
+                values.join(", ")
+            ),
+            ProfilerErrorKind::ManagedLoaderFile(path) => {
+                write!(f, "could not read managed loader assembly {}", path.display())
+            }
+            ProfilerErrorKind::ManagedLoaderNotConfigured => f.write_str(
+                "ELASTIC_APM_PROFILER_MANAGED_LOADER_PATH environment variable not set, \
+                and native profiler path unknown",
+            ),
+            ProfilerErrorKind::Timeout(operation) => write!(f, "{} timed out", operation),
+            ProfilerErrorKind::Panicked(operation) => write!(f, "{} panicked", operation),
+            ProfilerErrorKind::Thread(operation) => {
+                write!(f, "could not spawn a thread for {}", operation)
+            }
+            ProfilerErrorKind::Io => f.write_str("I/O error"),
+            ProfilerErrorKind::Yaml => f.write_str("invalid YAML"),
+            ProfilerErrorKind::Json => f.write_str("invalid JSON"),
+            ProfilerErrorKind::Toml => f.write_str("invalid TOML"),
+        }
+    }
+}
+
+/// An error raised by the profiler, with the error that caused it, if any
+#[derive(Debug)]
+pub struct ProfilerError {
+    kind: ProfilerErrorKind,
+    source: Option<Box<dyn Error + Send + Sync>>,
+}
+
+impl ProfilerError {
+    /// Creates a new error of the given kind
+    pub fn new(kind: ProfilerErrorKind) -> Self {
+        Self { kind, source: None }
+    }
+
+    /// Creates a new error of the given kind, caused by `source`
+    pub fn with_source(
+        kind: ProfilerErrorKind,
+        source: impl Into<Box<dyn Error + Send + Sync>>,
+    ) -> Self {
+        Self {
+            kind,
+            source: Some(source.into()),
+        }
+    }
+
+    pub fn kind(&self) -> &ProfilerErrorKind {
+        &self.kind
+    }
+
+    /// Whether the error was raised creating a log appender,
+    /// in which case logging can fall back to the console
+    pub fn is_appender_error(&self) -> bool {
+        match self.kind {
+            ProfilerErrorKind::LogDirectory(_)
+            | ProfilerErrorKind::LogRoller
+            | ProfilerErrorKind::LogFile => true,
+            #[cfg(target_os = "windows")]
+            ProfilerErrorKind::EventLog => true,
+            _ => false,
+        }
+    }
+}
+
+impl Display for ProfilerError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match &self.source {
+            Some(source) => write!(f, "{}: {}", self.kind, source),
+            None => write!(f, "{}", self.kind),
+        }
+    }
+}
+
+impl Error for ProfilerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source
+            .as_ref()
+            .map(|source| source.as_ref() as &(dyn Error + 'static))
+    }
+}
+
+impl From<io::Error> for ProfilerError {
+    fn from(e: io::Error) -> Self {
+        ProfilerError::with_source(ProfilerErrorKind::Io, e)
+    }
+}
+
+impl From<serde_yaml::Error> for ProfilerError {
+    fn from(e: serde_yaml::Error) -> Self {
+        ProfilerError::with_source(ProfilerErrorKind::Yaml, e)
+    }
+}
+
+impl From<serde_json::Error> for ProfilerError {
+    fn from(e: serde_json::Error) -> Self {
+        ProfilerError::with_source(ProfilerErrorKind::Json, e)
+    }
+}
+
+impl From<toml::de::Error> for ProfilerError {
+    fn from(e: toml::de::Error) -> Self {
+        ProfilerError::with_source(ProfilerErrorKind::Toml, e)
+    }
+}
+
+/// Errors are returned across the FFI boundary as [E_FAIL]
+impl From<ProfilerError> for HRESULT {
+    fn from(_: ProfilerError) -> Self {
+        E_FAIL
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::{
+        ffi::E_FAIL,
+        profiler::error::{ProfilerError, ProfilerErrorKind},
+    };
+    use com::sys::HRESULT;
+    use std::{error::Error, io, path::PathBuf};
+
+    #[test]
+    fn display_includes_kind_and_source() {
+        let error = ProfilerError::with_source(
+            ProfilerErrorKind::IntegrationsFile(PathBuf::from("integrations.yml")),
+            ProfilerError::from(io::Error::new(io::ErrorKind::NotFound, "not found")),
+        );
+        assert_eq!(
+            error.to_string(),
+            "problem reading integrations file integrations.yml: I/O error: not found"
+        );
+        assert!(error.source().is_some());
+        assert!(!error.is_appender_error());
+    }
+
+    #[test]
+    fn converts_to_e_fail() {
+        let hr: HRESULT = ProfilerError::new(ProfilerErrorKind::IntegrationsNotConfigured).into();
+        assert_eq!(hr, E_FAIL);
+    }
+}
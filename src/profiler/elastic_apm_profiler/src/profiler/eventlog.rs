@@ -0,0 +1,102 @@
+// Licensed to Elasticsearch B.V under
+// one or more agreements.
+// Elasticsearch B.V licenses this file to you under the Apache 2.0 License.
+// See the LICENSE file in the project root for more information
+
+use log::{Level, Record};
+use log4rs::{
+    append::Append,
+    encode::{writer::simple::SimpleWriter, Encode},
+};
+use std::{fmt, io, ptr};
+use widestring::U16CString;
+use winapi::{
+    shared::ntdef::HANDLE,
+    um::{
+        winbase::{DeregisterEventSource, RegisterEventSourceW, ReportEventW},
+        winnt::{EVENTLOG_ERROR_TYPE, EVENTLOG_INFORMATION_TYPE, EVENTLOG_WARNING_TYPE},
+    },
+};
+
+/// An appender that writes log records to the Windows Event Log
+pub struct EventLogAppender {
+    source: String,
+    handle: HANDLE,
+    encoder: Box<dyn Encode>,
+}
+
+// The event log handle returned by RegisterEventSourceW can be used from multiple threads
+unsafe impl Send for EventLogAppender {}
+unsafe impl Sync for EventLogAppender {}
+
+impl EventLogAppender {
+    /// Registers the event source with the given name
+    pub fn new(source: &str, encoder: Box<dyn Encode>) -> io::Result<Self> {
+        let wide_source = U16CString::from_str(source)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let handle = unsafe { RegisterEventSourceW(ptr::null(), wide_source.as_ptr()) };
+        if handle.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            source: source.into(),
+            handle,
+            encoder,
+        })
+    }
+}
+
+impl fmt::Debug for EventLogAppender {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventLogAppender")
+            .field("source", &self.source)
+            .field("encoder", &self.encoder)
+            .finish()
+    }
+}
+
+impl Append for EventLogAppender {
+    fn append(&self, record: &Record) -> anyhow::Result<()> {
+        let mut writer = SimpleWriter(Vec::new());
+        self.encoder.encode(&mut writer, record)?;
+        let message = String::from_utf8_lossy(&writer.0).replace('\0', "");
+        let wide_message = U16CString::from_str(message.trim_end())?;
+        let mut strings = [wide_message.as_ptr()];
+
+        let event_type = match record.level() {
+            Level::Error => EVENTLOG_ERROR_TYPE,
+            Level::Warn => EVENTLOG_WARNING_TYPE,
+            _ => EVENTLOG_INFORMATION_TYPE,
+        };
+
+        let result = unsafe {
+            ReportEventW(
+                self.handle,
+                event_type,
+                0,
+                0,
+                ptr::null_mut(),
+                strings.len() as u16,
+                0,
+                strings.as_mut_ptr(),
+                ptr::null_mut(),
+            )
+        };
+
+        if result == 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok(())
+    }
+
+    fn flush(&self) {}
+}
+
+impl Drop for EventLogAppender {
+    fn drop(&mut self) {
+        unsafe {
+            DeregisterEventSource(self.handle);
+        }
+    }
+}
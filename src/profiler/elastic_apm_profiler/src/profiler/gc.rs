@@ -0,0 +1,177 @@
+// Licensed to Elasticsearch B.V under
+// one or more agreements.
+// Elasticsearch B.V licenses this file to you under the Apache 2.0 License.
+// See the LICENSE file in the project root for more information
+
+//! Sampling of GC pressure.
+//!
+//! When `ELASTIC_APM_PROFILER_GC_PRESSURE_SAMPLING` is `true`, the profiler monitors GC events,
+//! and a [GcEventHandler] logs the sizes of the GC heap generations after each GC at DEBUG
+//! level, along with the number of collections of each generation so far.
+
+use crate::ffi::{COR_PRF_GC_GENERATION, COR_PRF_GC_GENERATION_RANGE, COR_PRF_GC_REASON};
+use std::fmt::{self, Display, Formatter};
+
+/// The sizes in bytes of the GC heap generations
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct GenerationSizes {
+    pub gen0: u64,
+    pub gen1: u64,
+    pub gen2: u64,
+    /// The large object heap
+    pub loh: u64,
+}
+
+impl GenerationSizes {
+    /// Sums the lengths of the ranges of each generation
+    pub fn from_ranges(ranges: &[COR_PRF_GC_GENERATION_RANGE]) -> Self {
+        let mut sizes = GenerationSizes::default();
+        for range in ranges {
+            let size = match range.generation {
+                COR_PRF_GC_GENERATION::COR_PRF_GC_GEN_0 => &mut sizes.gen0,
+                COR_PRF_GC_GENERATION::COR_PRF_GC_GEN_1 => &mut sizes.gen1,
+                COR_PRF_GC_GENERATION::COR_PRF_GC_GEN_2 => &mut sizes.gen2,
+                COR_PRF_GC_GENERATION::COR_PRF_GC_LARGE_OBJECT_HEAP => &mut sizes.loh,
+            };
+            *size += range.rangeLength as u64;
+        }
+        sizes
+    }
+}
+
+impl Display for GenerationSizes {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "gen0_bytes={} gen1_bytes={} gen2_bytes={} loh_bytes={}",
+            self.gen0, self.gen1, self.gen2, self.loh
+        )
+    }
+}
+
+/// The generations collected by the GC in progress
+#[derive(Debug)]
+struct Collection {
+    generations: Vec<usize>,
+    induced: bool,
+}
+
+/// Aggregates GC events, counting the collections of each generation
+#[derive(Debug, Default)]
+pub struct GcEventHandler {
+    /// The number of collections of gen 0, 1 and 2
+    generation_collections: [u64; 3],
+    induced_collections: u64,
+    collections: u64,
+    current: Option<Collection>,
+}
+
+impl GcEventHandler {
+    /// Records the start of a GC that collects the generations whose index in
+    /// `generations_collected` is `true`
+    pub fn garbage_collection_started(
+        &mut self,
+        generations_collected: &[bool],
+        reason: COR_PRF_GC_REASON,
+    ) {
+        let generations: Vec<usize> = generations_collected
+            .iter()
+            .enumerate()
+            .filter_map(|(generation, collected)| if *collected { Some(generation) } else { None })
+            .collect();
+        for generation in &generations {
+            if let Some(count) = self.generation_collections.get_mut(*generation) {
+                *count += 1;
+            }
+        }
+
+        let induced = reason == COR_PRF_GC_REASON::COR_PRF_GC_INDUCED;
+        if induced {
+            self.induced_collections += 1;
+        }
+        self.collections += 1;
+        self.current = Some(Collection {
+            generations,
+            induced,
+        });
+    }
+
+    /// Records the end of the GC in progress, returning a summary of the GC and the
+    /// generation sizes after it
+    pub fn garbage_collection_finished(&mut self, sizes: GenerationSizes) -> String {
+        let (generations, induced) = match self.current.take() {
+            Some(collection) => (
+                collection
+                    .generations
+                    .iter()
+                    .map(|g| g.to_string())
+                    .collect::<Vec<_>>()
+                    .join(","),
+                collection.induced,
+            ),
+            None => (String::new(), false),
+        };
+
+        format!(
+            "gc={} generations_collected=[{}] induced={} {} gen0_collections={} \
+            gen1_collections={} gen2_collections={} induced_collections={}",
+            self.collections,
+            generations,
+            induced,
+            sizes,
+            self.generation_collections[0],
+            self.generation_collections[1],
+            self.generation_collections[2],
+            self.induced_collections
+        )
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::{
+        ffi::{COR_PRF_GC_GENERATION, COR_PRF_GC_GENERATION_RANGE, COR_PRF_GC_REASON},
+        profiler::gc::{GcEventHandler, GenerationSizes},
+    };
+
+    #[test]
+    fn gc_event_handler_summarizes_collections() {
+        let range = |generation, length| COR_PRF_GC_GENERATION_RANGE {
+            generation,
+            rangeStart: 0,
+            rangeLength: length,
+            rangeLengthReserved: length,
+        };
+        let sizes = GenerationSizes::from_ranges(&[
+            range(COR_PRF_GC_GENERATION::COR_PRF_GC_GEN_0, 100),
+            range(COR_PRF_GC_GENERATION::COR_PRF_GC_GEN_2, 300),
+            range(COR_PRF_GC_GENERATION::COR_PRF_GC_GEN_2, 200),
+            range(COR_PRF_GC_GENERATION::COR_PRF_GC_LARGE_OBJECT_HEAP, 50),
+        ]);
+        assert_eq!(
+            sizes,
+            GenerationSizes {
+                gen0: 100,
+                gen1: 0,
+                gen2: 500,
+                loh: 50
+            }
+        );
+
+        let mut handler = GcEventHandler::default();
+        handler
+            .garbage_collection_started(&[true, false, false], COR_PRF_GC_REASON::COR_PRF_GC_OTHER);
+        handler.garbage_collection_finished(sizes);
+        handler.garbage_collection_started(
+            &[true, true, false],
+            COR_PRF_GC_REASON::COR_PRF_GC_INDUCED,
+        );
+
+        assert_eq!(
+            handler.garbage_collection_finished(sizes),
+            "gc=2 generations_collected=[0,1] induced=true gen0_bytes=100 gen1_bytes=0 \
+            gen2_bytes=500 loh_bytes=50 gen0_collections=2 gen1_collections=1 \
+            gen2_collections=0 induced_collections=1"
+        );
+    }
+}
@@ -0,0 +1,272 @@
+// Licensed to Elasticsearch B.V under
+// one or more agreements.
+// Elasticsearch B.V licenses this file to you under the Apache 2.0 License.
+// See the LICENSE file in the project root for more information
+
+//! Sampling of heap object allocations.
+//!
+//! When `ELASTIC_APM_PROFILER_HEAP_PROFILING_ENABLED` is `true`, the profiler monitors object
+//! allocations, and a [HeapProfiler] samples 1 in every `ELASTIC_APM_PROFILER_HEAP_PROFILING_RATE`
+//! of them. The number and size of the sampled allocations are accumulated by type name, and a
+//! [HeapProfilerSnapshot] is logged by a [HeapProfilerReporter] every
+//! `ELASTIC_APM_PROFILER_HEAP_PROFILING_INTERVAL_SECS`.
+//!
+//! Allocations are recorded by class id, which the runtime reuses after a class is unloaded,
+//! so the allocations of a class are moved to its type name when the class is unloaded.
+
+use crate::{ffi::ClassID, profiler::metrics::PeriodicTask};
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+/// The number of types with the most allocated bytes that are logged in a snapshot
+const SNAPSHOT_TOP_TYPES: usize = 10;
+
+/// The sampled allocations of a type
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TypeAllocations {
+    pub type_name: String,
+    /// The number of sampled allocations
+    pub count: u64,
+    /// The total size in bytes of the sampled allocations
+    pub bytes: u64,
+}
+
+impl TypeAllocations {
+    fn new(type_name: String) -> Self {
+        Self {
+            type_name,
+            count: 0,
+            bytes: 0,
+        }
+    }
+}
+
+/// The sampled allocations of loaded and unloaded classes
+#[derive(Debug, Default)]
+struct SampledTypes {
+    /// The allocations of loaded classes
+    loaded: HashMap<ClassID, TypeAllocations>,
+    /// The allocations of unloaded classes, by type name
+    unloaded: HashMap<String, TypeAllocations>,
+}
+
+/// Samples object allocations, accumulating the sampled allocations by type
+#[derive(Debug)]
+pub struct HeapProfiler {
+    rate: u64,
+    allocations: AtomicU64,
+    types: Mutex<SampledTypes>,
+}
+
+impl HeapProfiler {
+    /// Creates a heap profiler that samples 1 in every `rate` allocations
+    pub fn new(rate: u64) -> Self {
+        Self {
+            rate: rate.max(1),
+            allocations: AtomicU64::new(0),
+            types: Mutex::new(SampledTypes::default()),
+        }
+    }
+
+    /// Counts an allocation, returning whether it is sampled
+    pub fn should_sample(&self) -> bool {
+        self.allocations.fetch_add(1, Ordering::Relaxed) % self.rate == 0
+    }
+
+    /// Records a sampled allocation of an object of the class. `type_name` is called to get
+    /// the name of the class the first time the class is sampled
+    pub fn record_allocation(
+        &self,
+        class_id: ClassID,
+        size: u64,
+        type_name: impl FnOnce() -> String,
+    ) {
+        let mut types = self.types.lock().unwrap();
+        let allocations = types
+            .loaded
+            .entry(class_id)
+            .or_insert_with(|| TypeAllocations::new(type_name()));
+        allocations.count += 1;
+        allocations.bytes += size;
+    }
+
+    /// Moves the sampled allocations of an unloaded class to its type name, so that they
+    /// are not attributed to a class that is later loaded with the same class id
+    pub fn class_unloaded(&self, class_id: ClassID) {
+        let mut types = self.types.lock().unwrap();
+        if let Some(TypeAllocations {
+            type_name,
+            count,
+            bytes,
+        }) = types.loaded.remove(&class_id)
+        {
+            let unloaded = types
+                .unloaded
+                .entry(type_name.clone())
+                .or_insert_with(|| TypeAllocations::new(type_name));
+            unloaded.count += count;
+            unloaded.bytes += bytes;
+        }
+    }
+
+    /// Gets the allocations so far, by type name, with the most allocated bytes first
+    pub fn snapshot(&self) -> HeapProfilerSnapshot {
+        let mut by_name: HashMap<String, TypeAllocations> = HashMap::new();
+        let types = self.types.lock().unwrap();
+        for allocations in types.loaded.values().chain(types.unloaded.values()) {
+            // classes of different generic instantiations may have the same name
+            let entry = by_name
+                .entry(allocations.type_name.clone())
+                .or_insert_with(|| TypeAllocations::new(allocations.type_name.clone()));
+            entry.count += allocations.count;
+            entry.bytes += allocations.bytes;
+        }
+
+        let mut types: Vec<TypeAllocations> = by_name.into_values().collect();
+        types.sort_by(|a, b| {
+            b.bytes
+                .cmp(&a.bytes)
+                .then_with(|| a.type_name.cmp(&b.type_name))
+        });
+
+        HeapProfilerSnapshot {
+            rate: self.rate,
+            allocations: self.allocations.load(Ordering::Relaxed),
+            sampled_allocations: types.iter().map(|t| t.count).sum(),
+            types,
+        }
+    }
+}
+
+/// The allocations sampled by a [HeapProfiler]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct HeapProfilerSnapshot {
+    /// 1 in every `rate` allocations is sampled
+    pub rate: u64,
+    /// The number of allocations, sampled or not
+    pub allocations: u64,
+    pub sampled_allocations: u64,
+    /// The sampled allocations by type name, with the most allocated bytes first
+    pub types: Vec<TypeAllocations>,
+}
+
+impl Display for HeapProfilerSnapshot {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "allocations={} sampled_allocations={} rate={}",
+            self.allocations, self.sampled_allocations, self.rate
+        )?;
+        for allocations in self.types.iter().take(SNAPSHOT_TOP_TYPES) {
+            write!(
+                f,
+                "\n  {} count={} bytes={}",
+                allocations.type_name, allocations.count, allocations.bytes
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Periodically logs a [HeapProfilerSnapshot] at INFO level on a background thread
+pub struct HeapProfilerReporter {
+    heap_profiler: Arc<HeapProfiler>,
+    task: PeriodicTask,
+}
+
+impl HeapProfilerReporter {
+    pub fn new(heap_profiler: Arc<HeapProfiler>, interval: Duration) -> Self {
+        let reported = heap_profiler.clone();
+        Self {
+            heap_profiler,
+            task: PeriodicTask::spawn(interval, move || {
+                log::info!("HeapProfiler: {}", reported.snapshot())
+            }),
+        }
+    }
+
+    /// Stops reporting and logs a final snapshot
+    pub fn shutdown(self) {
+        match self.task.stop() {
+            Ok(()) => log::info!("HeapProfiler: {}", self.heap_profiler.snapshot()),
+            Err(_) => log::error!("Error in joining heap profiler reporter thread"),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::profiler::heap::{HeapProfiler, TypeAllocations};
+
+    #[test]
+    fn heap_profiler_samples_and_accumulates_by_type() {
+        let heap_profiler = HeapProfiler::new(3);
+        let sampled = (0..7).filter(|_| heap_profiler.should_sample()).count();
+        assert_eq!(sampled, 3);
+
+        heap_profiler.record_allocation(1, 24, || "System.String".into());
+        heap_profiler.record_allocation(1, 32, || unreachable!());
+        heap_profiler.record_allocation(2, 100, || "System.Byte[]".into());
+        // a different class with the same name, such as another generic instantiation
+        heap_profiler.record_allocation(3, 40, || "System.String".into());
+
+        let snapshot = heap_profiler.snapshot();
+        assert_eq!(snapshot.allocations, 7);
+        assert_eq!(snapshot.sampled_allocations, 4);
+        assert_eq!(
+            snapshot.types,
+            vec![
+                TypeAllocations {
+                    type_name: "System.Byte[]".into(),
+                    count: 1,
+                    bytes: 100
+                },
+                TypeAllocations {
+                    type_name: "System.String".into(),
+                    count: 3,
+                    bytes: 96
+                },
+            ]
+        );
+        assert_eq!(
+            snapshot.to_string(),
+            "allocations=7 sampled_allocations=4 rate=3\n  System.Byte[] count=1 bytes=100\n  \
+            System.String count=3 bytes=96"
+        );
+    }
+
+    #[test]
+    fn heap_profiler_keeps_allocations_of_unloaded_classes() {
+        let heap_profiler = HeapProfiler::new(1);
+        heap_profiler.record_allocation(1, 24, || "Collectible.Type".into());
+        heap_profiler.class_unloaded(1);
+        heap_profiler.class_unloaded(2);
+
+        // the class id is reused by another class
+        heap_profiler.record_allocation(1, 100, || "Other.Type".into());
+        heap_profiler.record_allocation(3, 8, || "Collectible.Type".into());
+
+        assert_eq!(
+            heap_profiler.snapshot().types,
+            vec![
+                TypeAllocations {
+                    type_name: "Other.Type".into(),
+                    count: 1,
+                    bytes: 100
+                },
+                TypeAllocations {
+                    type_name: "Collectible.Type".into(),
+                    count: 2,
+                    bytes: 32
+                },
+            ]
+        );
+    }
+}
@@ -5,7 +5,7 @@
 
 use crate::{
     cil::{
-        uncompress_data, uncompress_token, CorExceptionFlag, Method,
+        il_hex_dump, uncompress_data, uncompress_token, CorExceptionFlag, Method,
         Operand::{
             InlineBrTarget, InlineField, InlineI, InlineI8, InlineMethod, InlineString, InlineType,
             ShortInlineBrTarget, ShortInlineI, ShortInlineVar,
@@ -18,15 +18,23 @@ use crate::{
     },
     interfaces::{IMetaDataAssemblyEmit, IMetaDataEmit2, IMetaDataImport2},
     profiler::{
+        env::{self, IlDumpFormat},
         sig::parse_type,
         types::{
             AssemblyMetaData, FunctionInfo, Integration, IntegrationMethod, MethodSignature,
-            ModuleMetadata, WrapperMethodAction,
+            ModuleMetadata,
         },
     },
 };
 use com::sys::HRESULT;
 use num_traits::FromPrimitive;
+use std::{
+    fs,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// The number of raw IL files written, used to give each file a unique name
+static RAW_IL_FILES: AtomicU64 = AtomicU64::new(0);
 
 pub(crate) fn return_type_is_value_type_or_generic(
     module_metadata: &ModuleMetadata,
@@ -348,8 +356,13 @@ pub fn create_assembly_ref_to_corlib(
     )
 }
 
-pub fn get_il_codes(
+/// Formats the IL of the method body of the caller for logging, in the configured
+/// [IlDumpFormat]. With [IlDumpFormat::Raw], the method body is written to a file in
+/// [env::get_il_dump_dir] named after the function token, a sequence number and `stage`,
+/// and the path of the file is formatted instead
+pub fn format_il(
     title: &str,
+    stage: &str,
     method: &Method,
     caller: &FunctionInfo,
     module_metadata: &ModuleMetadata,
@@ -364,15 +377,42 @@ pub fn get_il_codes(
     let local_sig = method.header.local_var_sig_tok();
     if local_sig != mdTokenNil {
         if let Ok(signature) = module_metadata.import.get_sig_from_token(local_sig) {
-            buf.push('\n');
-            buf.push_str(". Local Var Signature ");
+            buf.push_str("\n. Local Var Signature ");
             buf.push_str(hex::encode(signature).as_str());
-            buf.push('\n');
         }
     }
 
     buf.push('\n');
 
+    match env::il_dump_format() {
+        IlDumpFormat::Hex => buf.push_str(&il_hex_dump(&method.into_bytes())),
+        IlDumpFormat::Disasm => write_il_codes(&mut buf, method, module_metadata),
+        IlDumpFormat::Raw => {
+            let dir = env::get_il_dump_dir();
+            let path = dir.join(format!(
+                "il_{}_{:08x}_{}_{}.bin",
+                std::process::id(),
+                caller.id,
+                RAW_IL_FILES.fetch_add(1, Ordering::Relaxed),
+                stage
+            ));
+            match fs::create_dir_all(&dir).and_then(|_| fs::write(&path, method.into_bytes())) {
+                Ok(()) => buf.push_str(&format!("raw IL written to {}\n", path.display())),
+                Err(e) => {
+                    log::warn!("could not write raw IL to {}: {}", path.display(), e);
+                    buf.push_str(&format!("raw IL could not be written: {}\n", e));
+                }
+            }
+        }
+    }
+
+    buf
+}
+
+/// Writes the instructions of the method body as mnemonics, one per line prefixed with
+/// its address, indented within the .try, .catch and .finally blocks of its exception
+/// handling clauses. Method, type and string tokens are resolved from module metadata
+fn write_il_codes(buf: &mut String, method: &Method, module_metadata: &ModuleMetadata) {
     let mut address = method.address;
     let mut sum_len = 0;
     let mut indent = 1;
@@ -601,8 +641,6 @@ pub fn get_il_codes(
 
         address += instruction.len();
     }
-
-    buf
 }
 
 pub fn find_type_def_by_name(
@@ -660,25 +698,16 @@ pub fn flatten_integrations(
         .into_iter()
         .flat_map(|i| {
             let name = i.name.clone();
+            let enable_inlining = i.enable_inlining;
+            let disable_optimizations = i.disable_optimizations;
             i.method_replacements
                 .into_iter()
-                .filter_map(move |method_replacement| {
-                    if let Some(wrapper_method) = method_replacement.wrapper() {
-                        let is_calltarget =
-                            wrapper_method.action == WrapperMethodAction::CallTargetModification;
-                        if (calltarget_enabled && is_calltarget)
-                            || (!calltarget_enabled && !is_calltarget)
-                        {
-                            Some(IntegrationMethod {
-                                name: name.clone(),
-                                method_replacement,
-                            })
-                        } else {
-                            None
-                        }
-                    } else {
-                        None
-                    }
+                .filter(move |m| m.is_instrumented(calltarget_enabled))
+                .map(move |method_replacement| IntegrationMethod {
+                    name: name.clone(),
+                    method_replacement,
+                    enable_inlining,
+                    disable_optimizations,
                 })
         })
         .collect()
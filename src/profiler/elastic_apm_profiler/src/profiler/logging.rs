@@ -0,0 +1,1238 @@
+// Licensed to Elasticsearch B.V under
+// one or more agreements.
+// Elasticsearch B.V licenses this file to you under the Apache 2.0 License.
+// See the LICENSE file in the project root for more information
+
+use crate::profiler::{
+    build_info,
+    env::{self, LogFormat, LogRotation, LogTarget, LogTargetSet, ProfilerConfig, PROFILER_CONFIG},
+    error::{ProfilerError, ProfilerErrorKind},
+    timeout::with_timeout,
+};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use log::{Level, LevelFilter, Record};
+use log4rs::{
+    append::{
+        console::{ConsoleAppender, Target},
+        rolling_file::{
+            policy::compound::{
+                roll::{fixed_window::FixedWindowRoller, Roll},
+                trigger::{size::SizeTrigger, Trigger},
+                CompoundPolicy,
+            },
+            LogFile, RollingFileAppender,
+        },
+        Append,
+    },
+    config::{Appender, Config, Logger, Root},
+    encode::{self, pattern::PatternEncoder, writer::simple::SimpleWriter, Encode},
+    Handle,
+};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::{
+    fmt,
+    fs::File,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Instant, SystemTime},
+};
+
+/// The date format appended to the names of log files rolled daily
+const DAILY_ROLL_DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// Triggers a roll of the log file at midnight UTC
+#[derive(Debug)]
+pub struct DailyTrigger {
+    next_roll_time: Mutex<DateTime<Utc>>,
+}
+
+impl DailyTrigger {
+    pub fn new() -> Self {
+        Self {
+            next_roll_time: Mutex::new(next_midnight(Utc::now())),
+        }
+    }
+}
+
+impl Default for DailyTrigger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Trigger for DailyTrigger {
+    fn trigger(&self, _file: &LogFile) -> anyhow::Result<bool> {
+        let now = Utc::now();
+        let mut next_roll_time = self.next_roll_time.lock().unwrap();
+        if now >= *next_roll_time {
+            *next_roll_time = next_midnight(now);
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn is_pre_process(&self) -> bool {
+        true
+    }
+}
+
+fn next_midnight(now: DateTime<Utc>) -> DateTime<Utc> {
+    (now.date_naive() + Duration::days(1))
+        .and_hms_opt(0, 0, 0)
+        .unwrap()
+        .and_utc()
+}
+
+/// Rolls the log file by appending the date of its last write to its name,
+/// keeping at most `max_files` rolled files
+#[derive(Debug)]
+pub struct DailyRoller {
+    max_files: u32,
+}
+
+impl DailyRoller {
+    pub fn new(max_files: u32) -> Self {
+        Self { max_files }
+    }
+}
+
+impl Roll for DailyRoller {
+    fn roll(&self, file: &Path) -> anyhow::Result<()> {
+        // the file holds records written up to its last modification
+        let date = std::fs::metadata(file)
+            .and_then(|m| m.modified())
+            .map(|modified| DateTime::<Utc>::from(modified).date_naive())
+            .unwrap_or_else(|_| (Utc::now() - Duration::days(1)).date_naive());
+
+        std::fs::rename(file, rolled_file_path(file, date))?;
+        remove_oldest_rolled_files(file, self.max_files as usize)?;
+        Ok(())
+    }
+}
+
+/// Gets the path of the rolled file for the given date. If a rolled file for the date
+/// already exists, an index is appended
+fn rolled_file_path(file: &Path, date: NaiveDate) -> PathBuf {
+    let (stem, extension) = stem_and_extension(file);
+    let date = date.format(DAILY_ROLL_DATE_FORMAT);
+    let mut path = file.with_file_name(format!("{}_{}{}", stem, date, extension));
+    let mut index = 1;
+    while path.exists() {
+        path = file.with_file_name(format!("{}_{}_{}{}", stem, date, index, extension));
+        index += 1;
+    }
+    path
+}
+
+fn remove_oldest_rolled_files(file: &Path, max_files: usize) -> std::io::Result<()> {
+    let dir = match file.parent() {
+        Some(dir) => dir,
+        None => return Ok(()),
+    };
+    let (stem, extension) = stem_and_extension(file);
+    let prefix = format!("{}_", stem);
+
+    let mut rolled_files: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            path.file_name()
+                .map(|name| {
+                    let name = name.to_string_lossy();
+                    name.starts_with(&prefix) && name.ends_with(&extension)
+                })
+                .unwrap_or(false)
+        })
+        .collect();
+
+    if rolled_files.len() > max_files {
+        // dates sort lexicographically, so the oldest files are first
+        rolled_files.sort();
+        for path in &rolled_files[..rolled_files.len() - max_files] {
+            std::fs::remove_file(path)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn stem_and_extension(file: &Path) -> (String, String) {
+    let stem = file
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let extension = file
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    (stem, extension)
+}
+
+/// The number of log records dropped because the buffer of an [AsyncAppender] was full
+static LOST_LOG_RECORDS: AtomicU64 = AtomicU64::new(0);
+
+/// Gets the number of log records dropped because the buffer of an [AsyncAppender] was full
+pub fn lost_log_records() -> u64 {
+    LOST_LOG_RECORDS.load(Ordering::Relaxed)
+}
+
+/// The handle to the logger stored at initialisation, used to shut down logging when the
+/// process exits
+static LOG_HANDLE: Lazy<Mutex<Option<Handle>>> = Lazy::new(|| Mutex::new(None));
+
+/// Whether [flush_and_shutdown] has been called
+static LOGGING_SHUT_DOWN: AtomicBool = AtomicBool::new(false);
+
+/// Stores the handle to the logger for [flush_and_shutdown], and arranges for it to be called
+/// when the process exits. On Windows, it's called when the runtime shuts down the profiler
+pub fn register_log_handle(handle: Handle) {
+    let previous = LOG_HANDLE
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .replace(handle);
+
+    #[cfg(not(target_os = "windows"))]
+    if previous.is_none() {
+        extern "C" fn flush_and_shutdown_at_exit() {
+            flush_and_shutdown();
+        }
+
+        if unsafe { libc::atexit(flush_and_shutdown_at_exit) } != 0 {
+            log::warn!("could not register handler to flush log records at exit");
+        }
+    }
+}
+
+/// Flushes all appenders, waiting up to [env::get_log_shutdown_timeout_ms] for buffered
+/// log records to be written, then shuts down logging. Records logged afterwards are
+/// discarded, as there is no longer the time to write them. Calls after the first do nothing
+pub fn flush_and_shutdown() {
+    if LOGGING_SHUT_DOWN.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    let handle = LOG_HANDLE.lock().unwrap_or_else(|e| e.into_inner()).take();
+    flush_and_shutdown_with(handle, env::get_log_shutdown_timeout_ms(), || {
+        log::logger().flush()
+    });
+}
+
+fn flush_and_shutdown_with(
+    handle: Option<Handle>,
+    timeout_ms: u64,
+    flush: impl FnOnce() + Send + 'static,
+) {
+    // the flush thread of an async appender may already have been stopped by the
+    // process exiting, in which case the flush never completes
+    let _ = with_timeout("flushing logs", timeout_ms, flush);
+
+    if let Some(handle) = handle {
+        if let Ok(config) = Config::builder().build(Root::builder().build(LevelFilter::Off)) {
+            handle.set_config(config);
+        }
+    }
+}
+
+/// An owned copy of a log record, sent to the thread of an [AsyncAppender]
+struct OwnedRecord {
+    level: Level,
+    target: String,
+    message: String,
+    module_path: Option<String>,
+    file: Option<String>,
+    line: Option<u32>,
+}
+
+impl OwnedRecord {
+    fn from_record(record: &Record) -> Self {
+        Self {
+            level: record.level(),
+            target: record.target().into(),
+            message: record.args().to_string(),
+            module_path: record.module_path().map(String::from),
+            file: record.file().map(String::from),
+            line: record.line(),
+        }
+    }
+
+    fn append_to(&self, appender: &dyn Append) -> anyhow::Result<()> {
+        appender.append(
+            &Record::builder()
+                .level(self.level)
+                .target(&self.target)
+                .args(format_args!("{}", self.message))
+                .module_path(self.module_path.as_deref())
+                .file(self.file.as_deref())
+                .line(self.line)
+                .build(),
+        )
+    }
+}
+
+enum AsyncMessage {
+    Record(OwnedRecord),
+    /// Flushes the inner appender, signalling the sender when done
+    Flush(SyncSender<()>),
+}
+
+/// An appender that writes log records to an inner appender on a background thread,
+/// so that callers are not blocked on I/O. Records are buffered in a bounded buffer;
+/// when the buffer is full, records are dropped and counted in [lost_log_records].
+/// The inner appender is flushed periodically, at the given flush interval
+pub struct AsyncAppender {
+    sender: SyncSender<AsyncMessage>,
+    buffer_size: usize,
+    /// The number of records dropped by this appender
+    lost_records: Arc<AtomicU64>,
+}
+
+impl AsyncAppender {
+    /// Creates a new async appender that buffers up to `buffer_size` records, and flushes
+    /// written records every `flush_interval`
+    pub fn new(
+        appender: Box<dyn Append>,
+        buffer_size: usize,
+        flush_interval: std::time::Duration,
+    ) -> Self {
+        let (sender, receiver) = mpsc::sync_channel(buffer_size);
+        let lost_records = Arc::new(AtomicU64::new(0));
+        let thread_lost_records = lost_records.clone();
+        thread::Builder::new()
+            .name("elastic_apm_profiler_log".into())
+            .spawn(move || write_records(appender, receiver, thread_lost_records, flush_interval))
+            .expect("could not spawn async log appender thread");
+        Self {
+            sender,
+            buffer_size,
+            lost_records,
+        }
+    }
+}
+
+/// Writes records received from an [AsyncAppender] to the inner appender, flushing it
+/// every `flush_interval` when records have been written or lost, until all senders
+/// are dropped
+fn write_records(
+    appender: Box<dyn Append>,
+    receiver: Receiver<AsyncMessage>,
+    lost_records: Arc<AtomicU64>,
+    flush_interval: std::time::Duration,
+) {
+    let mut reported_lost_records = 0;
+    let mut unflushed = false;
+    let mut last_flush = Instant::now();
+    loop {
+        let timeout = flush_interval.saturating_sub(last_flush.elapsed());
+        match receiver.recv_timeout(timeout) {
+            Ok(AsyncMessage::Record(record)) => {
+                // errors can't be surfaced from this thread, so are ignored,
+                // as log4rs does for errors raised by synchronous appenders
+                let _ = record.append_to(appender.as_ref());
+                unflushed = true;
+            }
+            Ok(AsyncMessage::Flush(done)) => {
+                flush_records(appender.as_ref(), &lost_records, &mut reported_lost_records);
+                unflushed = false;
+                last_flush = Instant::now();
+                let _ = done.send(());
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+
+        if last_flush.elapsed() >= flush_interval {
+            if unflushed || lost_records.load(Ordering::Relaxed) > reported_lost_records {
+                flush_records(appender.as_ref(), &lost_records, &mut reported_lost_records);
+                unflushed = false;
+            }
+            last_flush = Instant::now();
+        }
+    }
+
+    if unflushed {
+        flush_records(appender.as_ref(), &lost_records, &mut reported_lost_records);
+    }
+}
+
+/// Flushes the inner appender, first writing a summary of any records lost since the
+/// last summary
+fn flush_records(appender: &dyn Append, lost_records: &AtomicU64, reported_lost_records: &mut u64) {
+    let lost_records = lost_records.load(Ordering::Relaxed);
+    if lost_records > *reported_lost_records {
+        let summary = OwnedRecord {
+            level: Level::Warn,
+            target: module_path!().into(),
+            message: format!(
+                "{} log record(s) lost because the async log buffer was full",
+                lost_records - *reported_lost_records
+            ),
+            module_path: Some(module_path!().into()),
+            file: Some(file!().into()),
+            line: Some(line!()),
+        };
+        let _ = summary.append_to(appender);
+        *reported_lost_records = lost_records;
+    }
+    appender.flush();
+}
+
+impl fmt::Debug for AsyncAppender {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AsyncAppender")
+            .field("buffer_size", &self.buffer_size)
+            .finish()
+    }
+}
+
+impl Append for AsyncAppender {
+    fn append(&self, record: &Record) -> anyhow::Result<()> {
+        match self
+            .sender
+            .try_send(AsyncMessage::Record(OwnedRecord::from_record(record)))
+        {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => {
+                self.lost_records.fetch_add(1, Ordering::Relaxed);
+                LOST_LOG_RECORDS.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                Err(anyhow::anyhow!("async log appender thread has stopped"))
+            }
+        }
+    }
+
+    /// Blocks until all buffered records are written and the inner appender is flushed,
+    /// writing a summary of any lost records
+    fn flush(&self) {
+        let (done_sender, done_receiver) = mpsc::sync_channel(1);
+        if self.sender.send(AsyncMessage::Flush(done_sender)).is_ok() {
+            let _ = done_receiver.recv();
+        }
+    }
+}
+
+/// The text with which sensitive values are replaced in log records
+const REDACTED: &str = "<redacted>";
+
+/// An encoder that redacts sensitive values from the records written by an inner encoder.
+///
+/// Each of the given values is replaced wherever it appears, and each path segment
+/// containing the username is replaced, compared case-insensitively.
+#[derive(Debug)]
+pub struct RedactingEncoder {
+    inner: Box<dyn Encode>,
+    values: Vec<String>,
+    username: Option<String>,
+}
+
+impl RedactingEncoder {
+    pub fn new(inner: Box<dyn Encode>, mut values: Vec<String>, username: Option<String>) -> Self {
+        // replace longer values first, so that a value containing another is fully redacted
+        values.sort_by_key(|value| std::cmp::Reverse(value.len()));
+        Self {
+            inner,
+            values,
+            username: username.map(|u| u.to_lowercase()),
+        }
+    }
+
+    fn redact(&self, text: &str) -> String {
+        let mut redacted = self.values.iter().fold(text.to_string(), |text, value| {
+            text.replace(value.as_str(), REDACTED)
+        });
+        if let Some(username) = &self.username {
+            redacted = redact_path_segments(&redacted, username);
+        }
+        redacted
+    }
+}
+
+impl Encode for RedactingEncoder {
+    fn encode(&self, w: &mut dyn encode::Write, record: &Record) -> anyhow::Result<()> {
+        let mut buffer = SimpleWriter(Vec::new());
+        self.inner.encode(&mut buffer, record)?;
+        w.write_all(self.redact(&String::from_utf8_lossy(&buffer.0)).as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Replaces each segment of a path containing the lowercase `username`. A segment is a run
+/// of characters between whitespace or path separators, next to at least one separator.
+fn redact_path_segments(text: &str, username: &str) -> String {
+    fn is_separator(c: Option<char>) -> bool {
+        matches!(c, Some('/') | Some('\\'))
+    }
+
+    fn push_segment(
+        redacted: &mut String,
+        segment: &str,
+        username: &str,
+        before: Option<char>,
+        after: Option<char>,
+    ) {
+        if (is_separator(before) || is_separator(after))
+            && segment.to_lowercase().contains(username)
+        {
+            redacted.push_str(REDACTED);
+        } else {
+            redacted.push_str(segment);
+        }
+    }
+
+    let mut redacted = String::with_capacity(text.len());
+    let mut segment_start = 0;
+    let mut before = None;
+    for (i, c) in text.char_indices() {
+        if c.is_whitespace() || is_separator(Some(c)) {
+            push_segment(
+                &mut redacted,
+                &text[segment_start..i],
+                username,
+                before,
+                Some(c),
+            );
+            redacted.push(c);
+            segment_start = i + c.len_utf8();
+            before = Some(c);
+        }
+    }
+    push_segment(
+        &mut redacted,
+        &text[segment_start..],
+        username,
+        before,
+        None,
+    );
+    redacted
+}
+
+fn get_sys_time_in_seconds() -> u64 {
+    match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(n) => n.as_secs(),
+        Err(_) => 0,
+    }
+}
+
+const LOG_FILE_PREFIX: &str = "elastic_apm_profiler";
+const IL_LOG_FILE_PREFIX: &str = "elastic_apm_profiler_il";
+
+/// The log target to which IL is logged
+pub const IL_LOG_TARGET: &str = "elastic_apm_profiler::il";
+
+/// The log target of the profiler crate, of which all other log targets are children
+pub const LOG_TARGET_ROOT: &str = "elastic_apm_profiler";
+
+const LOG_TIMESTAMP_FORMAT: &str = "%Y-%m-%dT%H:%M:%S.%f%:z";
+
+/// Encodes a log record as a single line JSON object, with the context fields as
+/// top-level fields
+#[derive(Debug, Default)]
+struct JsonEncoder {
+    context_fields: Vec<(String, String)>,
+}
+
+#[derive(Serialize)]
+struct JsonLogRecord<'a> {
+    timestamp: String,
+    level: &'a str,
+    message: String,
+    process_id: u32,
+    thread_id: usize,
+    #[serde(flatten)]
+    context_fields: JsonContextFields<'a>,
+}
+
+/// Serializes context fields as a map, in the order in which they are configured
+struct JsonContextFields<'a>(&'a [(String, String)]);
+
+impl Serialize for JsonContextFields<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (key, value) in self.0 {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
+
+impl Encode for JsonEncoder {
+    fn encode(&self, w: &mut dyn encode::Write, record: &Record) -> anyhow::Result<()> {
+        let log_record = JsonLogRecord {
+            timestamp: chrono::Local::now()
+                .format(LOG_TIMESTAMP_FORMAT)
+                .to_string(),
+            level: record.level().as_str(),
+            message: record.args().to_string(),
+            process_id: std::process::id(),
+            thread_id: thread_id::get(),
+            context_fields: JsonContextFields(&self.context_fields),
+        };
+        serde_json::to_writer(&mut *w, &log_record)?;
+        w.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+fn create_encoder(config: &ProfilerConfig, format: LogFormat) -> Box<dyn Encode> {
+    match format {
+        LogFormat::Text => Box::new(PatternEncoder::new(&text_log_pattern(
+            config.log_prefix.as_deref(),
+            &config.log_context_fields,
+        ))),
+        LogFormat::Json => Box::new(JsonEncoder {
+            context_fields: config.log_context_fields.clone(),
+        }),
+    }
+}
+
+/// Wraps the encoder in a [RedactingEncoder] when redaction of log records is enabled
+fn redact_encoder(config: &ProfilerConfig, encoder: Box<dyn Encode>) -> Box<dyn Encode> {
+    if config.log_redact {
+        let (values, username) = sensitive_log_values_with(|key| std::env::var(key).ok());
+        Box::new(RedactingEncoder::new(encoder, values, username))
+    } else {
+        encoder
+    }
+}
+
+/// Gets the values redacted from log records: the home directory, and the username
+/// whose containing path segments are redacted
+fn sensitive_log_values_with(
+    get_var: impl Fn(&str) -> Option<String>,
+) -> (Vec<String>, Option<String>) {
+    let values = ["HOME", "USERPROFILE"]
+        .iter()
+        .filter_map(|key| get_var(key))
+        .map(|value| value.trim_end_matches(&['/', '\\'][..]).to_string())
+        .filter(|value| !value.is_empty())
+        .collect();
+    let username = get_var("USER")
+        .or_else(|| get_var("USERNAME"))
+        .filter(|value| !value.is_empty());
+    (values, username)
+}
+
+/// Gets the pattern for text log records, starting with the prefix, if any, and ending
+/// with the context fields as `key=value` tokens
+fn text_log_pattern(prefix: Option<&str>, context_fields: &[(String, String)]) -> String {
+    // braces are escaped so that the prefix and context fields are written literally
+    let escape = |s: &str| s.replace('{', "{{").replace('}', "}}");
+    let fields: String = context_fields
+        .iter()
+        .map(|(key, value)| format!(" {}={}", escape(key), escape(value)))
+        .collect();
+    let pattern = format!(
+        "[{{d({})}}] [{{l:<5}}] {{m}}{}{{n}}",
+        LOG_TIMESTAMP_FORMAT, fields
+    );
+    match prefix {
+        Some(prefix) => format!("{} {}", escape(prefix), pattern),
+        None => pattern,
+    }
+}
+
+/// The event log records its own timestamp and level, so text records written
+/// to it contain only the message and context fields
+#[cfg(target_os = "windows")]
+fn create_eventlog_encoder(config: &ProfilerConfig, format: LogFormat) -> Box<dyn Encode> {
+    match format {
+        LogFormat::Text => {
+            let fields: String = config
+                .log_context_fields
+                .iter()
+                .map(|(key, value)| format!(" {}={}", key, value))
+                .collect();
+            Box::new(PatternEncoder::new(&format!(
+                "{{m}}{}",
+                fields.replace('{', "{{").replace('}', "}}")
+            )))
+        }
+        LogFormat::Json => Box::new(JsonEncoder {
+            context_fields: config.log_context_fields.clone(),
+        }),
+    }
+}
+
+/// Creates the log directory ahead of time so that we can determine if it's a valid
+/// directory. if the directory can't be created or written to, tries the other configured
+/// log directories, the default log directory and the home log directory before bailing
+/// and returning [None].
+fn create_log_dir(log_dir: &Path) -> Option<PathBuf> {
+    resolve_log_dir(&env::get_log_dir_candidates(log_dir))
+}
+
+/// Returns the first of `candidates` that can be created and written to
+fn resolve_log_dir(candidates: &[PathBuf]) -> Option<PathBuf> {
+    candidates
+        .iter()
+        .find_map(|dir| match is_writable_dir(dir) {
+            Ok(()) => Some(dir.clone()),
+            Err(e) => {
+                log::debug!("log directory {} is not writable: {}", dir.display(), e);
+                None
+            }
+        })
+}
+
+/// Creates `dir` if it doesn't exist, and checks that a file can be written in it
+fn is_writable_dir(dir: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let probe = dir.join(format!(
+        ".elastic_apm_profiler_write_test_{}",
+        std::process::id()
+    ));
+    File::create(&probe)?;
+    std::fs::remove_file(&probe)
+}
+
+/// Creates a rolling file appender writing files with the given prefix
+fn create_file_appender(
+    config: &ProfilerConfig,
+    log_dir: &Path,
+    file_prefix: &str,
+    process_name: &str,
+    format: LogFormat,
+) -> Result<RollingFileAppender, ProfilerError> {
+    let pid = std::process::id();
+    let timestamp = get_sys_time_in_seconds();
+    let log_dir = create_log_dir(log_dir)
+        .ok_or_else(|| ProfilerError::new(ProfilerErrorKind::LogDirectory(log_dir.into())))?;
+
+    let log_file_name = log_dir
+        .join(format!(
+            "{}_{}_{}_{}.log",
+            file_prefix, process_name, pid, timestamp
+        ))
+        .to_string_lossy()
+        .to_string();
+    let rolling_log_file_name = log_dir
+        .join(format!(
+            "{}_{}_{}_{}_{{}}.log",
+            file_prefix, process_name, pid, timestamp
+        ))
+        .to_string_lossy()
+        .to_string();
+
+    let policy = match config.log_rotation {
+        LogRotation::Size => {
+            let trigger = SizeTrigger::new(config.log_max_size);
+            let roller = FixedWindowRoller::builder()
+                .build(&rolling_log_file_name, config.log_max_files)
+                .map_err(|e| ProfilerError::with_source(ProfilerErrorKind::LogRoller, e))?;
+            CompoundPolicy::new(Box::new(trigger), Box::new(roller))
+        }
+        LogRotation::Daily => CompoundPolicy::new(
+            Box::new(DailyTrigger::new()),
+            Box::new(DailyRoller::new(config.log_max_files)),
+        ),
+    };
+    RollingFileAppender::builder()
+        .append(true)
+        .encoder(redact_encoder(config, create_encoder(config, format)))
+        .build(&log_file_name, Box::new(policy))
+        .map_err(|e| ProfilerError::with_source(ProfilerErrorKind::LogFile, e))
+}
+
+/// Wraps the appender in an [AsyncAppender] with the configured buffer size and flush
+/// interval, unless the buffer size is 0
+fn create_async_appender(config: &ProfilerConfig, appender: Box<dyn Append>) -> Box<dyn Append> {
+    if config.log_async_buffer_size == 0 {
+        appender
+    } else {
+        Box::new(AsyncAppender::new(
+            appender,
+            config.log_async_buffer_size,
+            std::time::Duration::from_millis(config.log_flush_interval_ms),
+        ))
+    }
+}
+
+/// Initializes logging to the configured log targets
+pub fn initialize_logging() -> Result<Handle, ProfilerError> {
+    let config = &*PROFILER_CONFIG;
+    let process_name = env::get_process_name();
+    let logging_config = create_logging_config(config, &config.log_targets, &process_name, true)?;
+    let handle = log4rs::init_config(logging_config)
+        .map_err(|e| ProfilerError::with_source(ProfilerErrorKind::SetLogger, e))?;
+    log_startup_banner(&process_name, config.log_level);
+    log_async_flush_interval(config);
+    Ok(handle)
+}
+
+/// Initializes logging to only the configured console log targets, or to stdout
+/// if no console log targets are configured. Used as a fallback when logging can't be
+/// initialized with the configured log targets.
+pub fn initialize_console_logging() -> Result<Handle, ProfilerError> {
+    let config = &*PROFILER_CONFIG;
+    let mut targets: LogTargetSet = config
+        .log_targets
+        .iter()
+        .copied()
+        .filter(|t| matches!(t, LogTarget::Stdout | LogTarget::Stderr))
+        .collect();
+    if targets.is_empty() {
+        targets = std::iter::once(LogTarget::Stdout).collect();
+    }
+
+    let logging_config = create_logging_config(config, &targets, "", false)?;
+    let handle = log4rs::init_config(logging_config)
+        .map_err(|e| ProfilerError::with_source(ProfilerErrorKind::SetLogger, e))?;
+    log_startup_banner(&env::get_process_name(), config.log_level);
+    log_async_flush_interval(config);
+    Ok(handle)
+}
+
+fn log_startup_banner(process_name: &str, level: LevelFilter) {
+    log::info!(
+        "Elastic APM profiler {} (commit: {}, built: {}, target: {}) starting. pid: {}, process: {}, log level: {}",
+        env::profiler_version(),
+        build_info::GIT_COMMIT,
+        build_info::BUILD_TIMESTAMP,
+        build_info::TARGET_TRIPLE,
+        std::process::id(),
+        process_name,
+        level
+    );
+    log::info!("operating system: {}", env::os_info());
+}
+
+fn log_async_flush_interval(config: &ProfilerConfig) {
+    if config.log_async_buffer_size > 0 {
+        log::debug!(
+            "async log buffer of {} record(s) is flushed every {} ms",
+            config.log_async_buffer_size,
+            config.log_flush_interval_ms
+        );
+    }
+}
+
+fn create_logging_config(
+    config: &ProfilerConfig,
+    targets: &LogTargetSet,
+    process_name: &str,
+    log_il_to_file: bool,
+) -> Result<Config, ProfilerError> {
+    let level = config.log_level;
+    let format = config.log_format;
+    let mut root_builder = Root::builder();
+    let mut config_builder = Config::builder();
+
+    for target in targets.iter() {
+        let appender: Box<dyn Append> = match target {
+            LogTarget::Stdout => Box::new(
+                ConsoleAppender::builder()
+                    .encoder(redact_encoder(config, create_encoder(config, format)))
+                    .build(),
+            ),
+            LogTarget::Stderr => Box::new(
+                ConsoleAppender::builder()
+                    .target(Target::Stderr)
+                    .encoder(redact_encoder(config, create_encoder(config, format)))
+                    .build(),
+            ),
+            #[cfg(target_os = "windows")]
+            LogTarget::EventLog => Box::new(
+                crate::profiler::eventlog::EventLogAppender::new(
+                    &config.eventlog_source,
+                    redact_encoder(config, create_eventlog_encoder(config, format)),
+                )
+                .map_err(|e| ProfilerError::with_source(ProfilerErrorKind::EventLog, e))?,
+            ),
+            LogTarget::File => create_async_appender(
+                config,
+                Box::new(create_file_appender(
+                    config,
+                    &config.log_dir,
+                    LOG_FILE_PREFIX,
+                    process_name,
+                    format,
+                )?),
+            ),
+        };
+
+        let name = target.to_string();
+        config_builder = config_builder.appender(Appender::builder().build(&name, appender));
+        root_builder = root_builder.appender(name);
+    }
+
+    // write IL to a separate file when a directory for IL is specified
+    let mut il_logger = false;
+    if let Some(log_il_dir) = config
+        .log_il_dir
+        .as_ref()
+        .filter(|_| config.log_il && log_il_to_file)
+    {
+        let il_file = Box::new(create_file_appender(
+            config,
+            log_il_dir,
+            IL_LOG_FILE_PREFIX,
+            process_name,
+            format,
+        )?);
+        let il_level = config
+            .log_level_overrides
+            .iter()
+            .find(|(target, _)| target == IL_LOG_TARGET)
+            .map_or(level, |(_, level)| *level);
+        config_builder = config_builder
+            .appender(Appender::builder().build("il", create_async_appender(config, il_file)))
+            .logger(
+                Logger::builder()
+                    .appender("il")
+                    .additive(false)
+                    .build(IL_LOG_TARGET, il_level),
+            );
+        il_logger = true;
+    }
+
+    for (target, target_level) in &config.log_level_overrides {
+        // the level of the IL logger is already overridden
+        if il_logger && target == IL_LOG_TARGET {
+            continue;
+        }
+        config_builder = config_builder.logger(Logger::builder().build(target, *target_level));
+    }
+
+    let root = root_builder.build(level);
+    config_builder
+        .build(root)
+        .map_err(|e| ProfilerError::with_source(ProfilerErrorKind::LogConfig, e))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::profiler::{
+        logging::{
+            create_log_dir, flush_and_shutdown_with, lost_log_records, next_midnight,
+            resolve_log_dir, rolled_file_path, sensitive_log_values_with, text_log_pattern,
+            AsyncAppender, DailyRoller, JsonEncoder, RedactingEncoder,
+        },
+        test_helpers::{get_var_from, TempPath},
+    };
+    use chrono::{NaiveDate, TimeZone, Utc};
+    use log::{Level, Record};
+    use log4rs::{
+        append::{rolling_file::policy::compound::roll::Roll, Append},
+        encode::{pattern::PatternEncoder, writer::simple::SimpleWriter, Encode},
+    };
+    use std::{
+        error::Error,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            mpsc, Arc, Mutex,
+        },
+        thread,
+        time::{Duration, Instant},
+    };
+
+    /// An appender that collects messages, optionally waiting for a signal before each,
+    /// and counts flushes
+    #[derive(Debug, Default)]
+    struct CollectingAppender {
+        messages: Arc<Mutex<Vec<String>>>,
+        gate: Option<Mutex<mpsc::Receiver<()>>>,
+        flushes: Arc<AtomicU64>,
+    }
+
+    impl Append for CollectingAppender {
+        fn append(&self, record: &Record) -> anyhow::Result<()> {
+            if let Some(gate) = &self.gate {
+                let _ = gate.lock().unwrap().recv();
+            }
+            self.messages
+                .lock()
+                .unwrap()
+                .push(record.args().to_string());
+            Ok(())
+        }
+
+        fn flush(&self) {
+            self.flushes.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn append_message(appender: &AsyncAppender, message: &str) -> anyhow::Result<()> {
+        appender.append(
+            &Record::builder()
+                .level(Level::Info)
+                .args(format_args!("{}", message))
+                .build(),
+        )
+    }
+
+    #[test]
+    fn async_appender_writes_records_on_flush() -> Result<(), Box<dyn Error>> {
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let appender = AsyncAppender::new(
+            Box::new(CollectingAppender {
+                messages: messages.clone(),
+                ..Default::default()
+            }),
+            16,
+            Duration::from_secs(60),
+        );
+        append_message(&appender, "one")?;
+        append_message(&appender, "two")?;
+        appender.flush();
+
+        assert_eq!(*messages.lock().unwrap(), vec!["one", "two"]);
+        Ok(())
+    }
+
+    #[test]
+    fn async_appender_drops_records_when_buffer_is_full() -> Result<(), Box<dyn Error>> {
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let (release, gate) = mpsc::channel();
+        let appender = AsyncAppender::new(
+            Box::new(CollectingAppender {
+                messages: messages.clone(),
+                gate: Some(Mutex::new(gate)),
+                ..Default::default()
+            }),
+            1,
+            Duration::from_secs(60),
+        );
+
+        let lost_before = lost_log_records();
+        // at most one record is being written and one buffered, so at least one is dropped
+        for message in ["one", "two", "three"] {
+            append_message(&appender, message)?;
+        }
+        assert!(lost_log_records() > lost_before);
+
+        for _ in 0..4 {
+            release.send(())?;
+        }
+        appender.flush();
+
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages[0], "one");
+        assert!(messages
+            .last()
+            .unwrap()
+            .ends_with("log record(s) lost because the async log buffer was full"));
+        Ok(())
+    }
+
+    #[test]
+    fn async_appender_flushes_periodically() -> Result<(), Box<dyn Error>> {
+        let flushes = Arc::new(AtomicU64::new(0));
+        let appender = AsyncAppender::new(
+            Box::new(CollectingAppender {
+                flushes: flushes.clone(),
+                ..Default::default()
+            }),
+            16,
+            Duration::from_millis(10),
+        );
+        append_message(&appender, "one")?;
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while flushes.load(Ordering::Relaxed) == 0 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert!(flushes.load(Ordering::Relaxed) > 0);
+        Ok(())
+    }
+
+    #[test]
+    fn flush_and_shutdown_waits_for_flush_up_to_timeout() -> Result<(), Box<dyn Error>> {
+        let appender = CollectingAppender::default();
+        let messages = appender.messages.clone();
+        let flushes = appender.flushes.clone();
+        let async_appender = AsyncAppender::new(Box::new(appender), 10, Duration::from_secs(60));
+        append_message(&async_appender, "before exit")?;
+
+        flush_and_shutdown_with(None, 5000, move || async_appender.flush());
+        assert_eq!(*messages.lock().unwrap(), vec!["before exit".to_string()]);
+        assert_eq!(flushes.load(Ordering::Relaxed), 1);
+
+        let start = Instant::now();
+        flush_and_shutdown_with(None, 10, || thread::sleep(Duration::from_secs(5)));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        Ok(())
+    }
+
+    #[test]
+    fn next_midnight_is_start_of_next_day() {
+        let now = Utc.with_ymd_and_hms(2024, 2, 28, 13, 45, 10).unwrap();
+        assert_eq!(
+            next_midnight(now),
+            Utc.with_ymd_and_hms(2024, 2, 29, 0, 0, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn daily_roller_appends_date_and_keeps_max_files() -> Result<(), Box<dyn Error>> {
+        let dir = TempPath::new("daily_roller");
+        std::fs::create_dir_all(&dir)?;
+        let file = dir.join("elastic_apm_profiler.log");
+        let date = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        assert_eq!(
+            rolled_file_path(&file, date),
+            dir.join("elastic_apm_profiler_2024-01-01.log")
+        );
+
+        let roller = DailyRoller::new(2);
+        for day in 1..=3 {
+            std::fs::write(
+                dir.join(format!("elastic_apm_profiler_2000-01-0{}.log", day)),
+                "",
+            )?;
+        }
+        std::fs::write(&file, "log")?;
+        roller.roll(&file)?;
+
+        let mut names: Vec<String> = std::fs::read_dir(&dir)?
+            .map(|e| e.map(|e| e.file_name().to_string_lossy().to_string()))
+            .collect::<Result<_, _>>()?;
+        names.sort();
+
+        let today = Utc::now().format("%Y-%m-%d");
+        assert_eq!(
+            names,
+            vec![
+                "elastic_apm_profiler_2000-01-03.log".to_string(),
+                format!("elastic_apm_profiler_{}.log", today),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn redacting_encoder_redacts_home_directory_and_username() -> Result<(), Box<dyn Error>> {
+        let encoder = RedactingEncoder::new(
+            Box::new(PatternEncoder::new("{m}")),
+            vec!["/home/jdoe".into()],
+            Some("JDoe".into()),
+        );
+        let mut writer = SimpleWriter(Vec::new());
+        encoder.encode(
+            &mut writer,
+            &Record::builder()
+                .level(Level::Info)
+                .args(format_args!(
+                    "loaded /home/jdoe/integrations.yml and C:\\Users\\JDoe.Corp\\logs. \
+                    logged in as jdoe"
+                ))
+                .build(),
+        )?;
+
+        assert_eq!(
+            String::from_utf8(writer.0)?,
+            "loaded <redacted>/integrations.yml and C:\\Users\\<redacted>\\logs. \
+            logged in as jdoe"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn json_encoder_writes_json_line() -> Result<(), Box<dyn Error>> {
+        let mut writer = SimpleWriter(Vec::new());
+        JsonEncoder::default().encode(
+            &mut writer,
+            &log::Record::builder()
+                .level(Level::Info)
+                .args(format_args!("loaded \"{}\" integrations", 2))
+                .build(),
+        )?;
+
+        let line = String::from_utf8(writer.0)?;
+        assert!(line.ends_with('\n'));
+        let value: serde_json::Value = serde_json::from_str(line.trim_end())?;
+        assert_eq!(value["level"], "INFO");
+        assert_eq!(value["message"], "loaded \"2\" integrations");
+        assert_eq!(value["process_id"], std::process::id());
+        assert!(value["thread_id"].is_u64());
+        assert!(value["timestamp"].is_string());
+        Ok(())
+    }
+    #[test]
+    fn create_log_dir_creates_directory() -> Result<(), Box<dyn Error>> {
+        let log_dir = TempPath::new("create_log_dir");
+        let created_dir = create_log_dir(&log_dir);
+        let exists = log_dir.is_dir();
+
+        assert_eq!(created_dir, Some(log_dir.to_path_buf()));
+        assert!(exists);
+        Ok(())
+    }
+    #[test]
+    fn resolve_log_dir_returns_first_writable_candidate() -> Result<(), Box<dyn Error>> {
+        let file = TempPath::new("resolve_log_dir_file");
+        std::fs::write(&file, "not a directory")?;
+        let writable = TempPath::new("resolve_log_dir");
+
+        let resolved = resolve_log_dir(&[file.join("logs"), writable.to_path_buf()]);
+        let unresolved = resolve_log_dir(&[file.join("logs")]);
+        let empty = writable.read_dir().map(|entries| entries.count() == 0);
+
+        assert_eq!(resolved, Some(writable.to_path_buf()));
+        assert_eq!(unresolved, None);
+        assert!(empty?);
+        Ok(())
+    }
+    #[test]
+    fn sensitive_log_values_with_reads_home_directory_and_username() {
+        assert_eq!(
+            sensitive_log_values_with(get_var_from(&[
+                ("HOME", "/home/jdoe/"),
+                ("USERPROFILE", "/"),
+                ("USERNAME", "jdoe"),
+            ])),
+            (vec!["/home/jdoe".to_string()], Some("jdoe".to_string()))
+        );
+        assert_eq!(sensitive_log_values_with(get_var_from(&[])), (vec![], None));
+    }
+    #[test]
+    fn text_log_pattern_writes_prefix_literally() -> Result<(), Box<dyn Error>> {
+        let encoder = PatternEncoder::new(&text_log_pattern(Some("[my{app}]"), &[]));
+        let mut writer = SimpleWriter(Vec::new());
+        encoder.encode(
+            &mut writer,
+            &Record::builder()
+                .level(Level::Info)
+                .args(format_args!("message"))
+                .build(),
+        )?;
+
+        let line = String::from_utf8(writer.0)?;
+        assert!(line.starts_with("[my{app}] ["));
+        assert!(line.ends_with("] [INFO ] message\n"));
+        Ok(())
+    }
+    #[test]
+    fn encoders_write_log_context_fields() -> Result<(), Box<dyn Error>> {
+        let context_fields = vec![
+            ("datacenter".to_string(), "us-east-1".to_string()),
+            ("cluster".to_string(), "{prod}".to_string()),
+        ];
+        let record = Record::builder()
+            .level(Level::Info)
+            .args(format_args!("message"))
+            .build();
+
+        let encoder = PatternEncoder::new(&text_log_pattern(None, &context_fields));
+        let mut writer = SimpleWriter(Vec::new());
+        encoder.encode(&mut writer, &record)?;
+        let line = String::from_utf8(writer.0)?;
+        assert!(line.ends_with("] [INFO ] message datacenter=us-east-1 cluster={prod}\n"));
+
+        let encoder = JsonEncoder { context_fields };
+        let mut writer = SimpleWriter(Vec::new());
+        encoder.encode(&mut writer, &record)?;
+        let json: serde_json::Value = serde_json::from_slice(&writer.0)?;
+        assert_eq!(json["message"], "message");
+        assert_eq!(json["datacenter"], "us-east-1");
+        assert_eq!(json["cluster"], "{prod}");
+        Ok(())
+    }
+}
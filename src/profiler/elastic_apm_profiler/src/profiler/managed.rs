@@ -4,12 +4,13 @@
 // See the LICENSE file in the project root for more information
 
 use crate::profiler::{
+    env,
     types::{AssemblyReference, PublicKeyToken},
     IS_ATTACHED, IS_DESKTOP_CLR,
 };
-use once_cell::sync::Lazy;
+use once_cell::sync::{Lazy, OnceCell};
 use rust_embed::RustEmbed;
-use std::sync::atomic::Ordering;
+use std::{borrow::Cow, sync::atomic::Ordering};
 
 /// Embedded assets of the managed loader assembly
 #[derive(RustEmbed)]
@@ -56,7 +57,43 @@ pub extern "C" fn IsProfilerAttached() -> bool {
     IS_ATTACHED.load(Ordering::SeqCst)
 }
 
-/// Gets the embedded loader assembly and symbol bytes
+/// Loader assembly bytes and symbol bytes
+type AssemblyAndSymbols = (Cow<'static, [u8]>, Cow<'static, [u8]>);
+
+/// The loader assembly and symbol bytes, which must live for the lifetime of the process
+/// because they're returned across the FFI boundary
+static LOADER_ASSEMBLY_AND_SYMBOLS: OnceCell<AssemblyAndSymbols> = OnceCell::new();
+
+/// Gets the loader assembly and symbols from the managed loader path, if the assembly
+/// exists. Otherwise, gets the embedded loader assembly and symbols.
+fn loader_assembly_and_symbols(tfm: &str) -> AssemblyAndSymbols {
+    match env::get_managed_loader_path(tfm) {
+        Ok(path) => match std::fs::read(&path) {
+            Ok(assembly) => {
+                // symbols are optional, so are empty when missing
+                let symbols = std::fs::read(path.with_extension("pdb")).unwrap_or_default();
+                return (Cow::Owned(assembly), Cow::Owned(symbols));
+            }
+            Err(e) => log::warn!(
+                "could not read managed loader assembly {}: {}. using embedded assembly",
+                path.display(),
+                e
+            ),
+        },
+        Err(e) if env::PROFILER_CONFIG.managed_loader_path.is_some() => {
+            log::warn!("{}. using embedded managed loader assembly", e)
+        }
+        Err(e) => log::debug!("{}. using embedded managed loader assembly", e),
+    }
+
+    let assembly =
+        ManagedLoader::get(&format!("{}/{}.dll", tfm, MANAGED_PROFILER_ASSEMBLY_LOADER)).unwrap();
+    let symbols =
+        ManagedLoader::get(&format!("{}/{}.pdb", tfm, MANAGED_PROFILER_ASSEMBLY_LOADER)).unwrap();
+    (assembly, symbols)
+}
+
+/// Gets the loader assembly and symbol bytes
 #[no_mangle]
 pub extern "C" fn GetAssemblyAndSymbolsBytes(
     assembly: *mut *mut u8,
@@ -69,12 +106,9 @@ pub extern "C" fn GetAssemblyAndSymbolsBytes(
     } else {
         "netcoreapp2.0"
     };
-    let a =
-        ManagedLoader::get(&format!("{}/{}.dll", tfm, MANAGED_PROFILER_ASSEMBLY_LOADER)).unwrap();
+    let (a, s) = LOADER_ASSEMBLY_AND_SYMBOLS.get_or_init(|| loader_assembly_and_symbols(tfm));
     unsafe { *assembly = a.as_ptr() as *mut _ };
     *assembly_size = a.len() as i32;
-    let s =
-        ManagedLoader::get(&format!("{}/{}.pdb", tfm, MANAGED_PROFILER_ASSEMBLY_LOADER)).unwrap();
     unsafe { *symbols = s.as_ptr() as *mut _ };
     *symbols_size = s.len() as i32;
 }
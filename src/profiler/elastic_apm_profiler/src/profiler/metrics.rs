@@ -0,0 +1,390 @@
+// Licensed to Elasticsearch B.V under
+// one or more agreements.
+// Elasticsearch B.V licenses this file to you under the Apache 2.0 License.
+// See the LICENSE file in the project root for more information
+
+//! Self-telemetry for the overhead that the profiler adds.
+//!
+//! Counters are always updated, and can be snapshotted with [collect_metrics]. When
+//! `ELASTIC_APM_PROFILER_METRICS_ENABLED` is `true`, a [MetricsReporter] logs a summary
+//! of the metrics and the most captured exception types at INFO level every
+//! `ELASTIC_APM_PROFILER_METRICS_INTERVAL_SECS` seconds.
+//!
+//! Calls to methods instrumented with CallTarget are counted in the [MetricRegistry], by
+//! IL inserted into each instrumented method that increments the method's counter. When
+//! DEBUG logging is enabled, a [CallCountsReporter] logs the most called methods every minute.
+
+use crate::profiler::{env, sampling::CapturedExceptions};
+use once_cell::sync::Lazy;
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    fmt::{self, Display, Formatter},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{channel, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    thread,
+    thread::JoinHandle,
+    time::Duration,
+};
+
+static JIT_HOOK_INVOCATIONS: AtomicU64 = AtomicU64::new(0);
+static JIT_HOOK_NANOS: AtomicU64 = AtomicU64::new(0);
+static INTEGRATIONS_APPLIED: AtomicU64 = AtomicU64::new(0);
+static INTEGRATIONS_SKIPPED: AtomicU64 = AtomicU64::new(0);
+static HOOK_ERRORS: AtomicU64 = AtomicU64::new(0);
+static EXCEPTION_SAMPLING_INVOCATIONS: AtomicU64 = AtomicU64::new(0);
+static EXCEPTIONS_CAPTURED: AtomicU64 = AtomicU64::new(0);
+static EXCEPTIONS_FILTERED: AtomicU64 = AtomicU64::new(0);
+
+/// The number of most captured exception types logged by a [MetricsReporter]
+const CAPTURED_EXCEPTIONS_REPORT_TOP_N: usize = 10;
+
+/// A snapshot of the profiler metrics
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ProfilerMetrics {
+    /// The number of times the JIT compilation hook has been invoked
+    pub jit_hook_invocations: u64,
+    /// The average time spent in the JIT compilation hook, in microseconds
+    pub jit_hook_average_latency_micros: u64,
+    /// The number of integration methods applied to target methods
+    pub integrations_applied: u64,
+    /// The number of integration methods whose target type was found, but for which
+    /// no target method matched
+    pub integrations_skipped: u64,
+    /// The number of errors returned by profiler hooks
+    pub hook_errors: u64,
+    /// The configured fraction of exceptions captured
+    pub exception_sampling_rate: f64,
+    /// The number of exceptions for which sampling has decided whether to capture them
+    pub exception_sampling_invocations: u64,
+    /// The number of exceptions captured by sampling
+    pub exceptions_captured: u64,
+    /// The number of exceptions not captured because their type is filtered
+    pub exceptions_filtered: u64,
+}
+
+impl Display for ProfilerMetrics {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "jit_hook_invocations={} jit_hook_average_latency_micros={} \
+            integrations_applied={} integrations_skipped={} hook_errors={} \
+            exception_sampling_rate={} exception_sampling_invocations={} exceptions_captured={} \
+            exceptions_filtered={}",
+            self.jit_hook_invocations,
+            self.jit_hook_average_latency_micros,
+            self.integrations_applied,
+            self.integrations_skipped,
+            self.hook_errors,
+            self.exception_sampling_rate,
+            self.exception_sampling_invocations,
+            self.exceptions_captured,
+            self.exceptions_filtered
+        )
+    }
+}
+
+/// Snapshots the current value of the profiler metrics
+pub fn collect_metrics() -> ProfilerMetrics {
+    let jit_hook_invocations = JIT_HOOK_INVOCATIONS.load(Ordering::Relaxed);
+    let jit_hook_nanos = JIT_HOOK_NANOS.load(Ordering::Relaxed);
+    ProfilerMetrics {
+        jit_hook_invocations,
+        jit_hook_average_latency_micros: jit_hook_nanos
+            .checked_div(jit_hook_invocations)
+            .unwrap_or(0)
+            / 1000,
+        integrations_applied: INTEGRATIONS_APPLIED.load(Ordering::Relaxed),
+        integrations_skipped: INTEGRATIONS_SKIPPED.load(Ordering::Relaxed),
+        hook_errors: HOOK_ERRORS.load(Ordering::Relaxed),
+        exception_sampling_rate: env::get_exception_sampling_rate(),
+        exception_sampling_invocations: EXCEPTION_SAMPLING_INVOCATIONS.load(Ordering::Relaxed),
+        exceptions_captured: EXCEPTIONS_CAPTURED.load(Ordering::Relaxed),
+        exceptions_filtered: EXCEPTIONS_FILTERED.load(Ordering::Relaxed),
+    }
+}
+
+/// Records an invocation of the JIT compilation hook that took `elapsed`
+pub fn record_jit_hook(elapsed: Duration) {
+    JIT_HOOK_INVOCATIONS.fetch_add(1, Ordering::Relaxed);
+    JIT_HOOK_NANOS.fetch_add(
+        u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX),
+        Ordering::Relaxed,
+    );
+}
+
+/// Records `count` integration methods applied to target methods
+pub fn record_integrations_applied(count: usize) {
+    INTEGRATIONS_APPLIED.fetch_add(count as u64, Ordering::Relaxed);
+}
+
+/// Records an integration method for which no target method matched
+pub fn record_integration_skipped() {
+    INTEGRATIONS_SKIPPED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Records an error returned by a profiler hook
+pub fn record_hook_error() {
+    HOOK_ERRORS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Records a sampling decision for an exception, and whether it was captured
+pub fn record_exception_sampled(captured: bool) {
+    EXCEPTION_SAMPLING_INVOCATIONS.fetch_add(1, Ordering::Relaxed);
+    if captured {
+        EXCEPTIONS_CAPTURED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Records an exception not captured because its type is filtered
+pub fn record_exception_filtered() {
+    EXCEPTIONS_FILTERED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// The interval at which a [CallCountsReporter] logs the most called methods
+pub const CALL_COUNTS_REPORT_INTERVAL: Duration = Duration::from_secs(60);
+/// The number of most called methods logged by a [CallCountsReporter]
+const CALL_COUNTS_REPORT_TOP_N: usize = 10;
+
+static METRIC_REGISTRY: Lazy<MetricRegistry> = Lazy::new(MetricRegistry::default);
+
+/// Call counters for methods instrumented with CallTarget, keyed by
+/// `(assembly_name, type_name, method_name)`.
+///
+/// Counters are never removed, so the address of a counter is stable for the lifetime of
+/// the process and can be embedded in the IL of an instrumented method, which increments
+/// it with `Interlocked.Increment`.
+#[derive(Debug, Default)]
+pub struct MetricRegistry {
+    counters: Mutex<HashMap<(String, String, String), Arc<AtomicU64>>>,
+}
+
+impl MetricRegistry {
+    /// Gets the registry used by the profiler
+    pub fn global() -> &'static MetricRegistry {
+        &METRIC_REGISTRY
+    }
+
+    /// Gets the call counter for a method, adding one if it doesn't exist
+    pub fn counter(
+        &self,
+        assembly_name: &str,
+        type_name: &str,
+        method_name: &str,
+    ) -> Arc<AtomicU64> {
+        self.counters
+            .lock()
+            .unwrap()
+            .entry((
+                assembly_name.to_string(),
+                type_name.to_string(),
+                method_name.to_string(),
+            ))
+            .or_default()
+            .clone()
+    }
+
+    /// Snapshots the call counts, keyed by `[assembly_name]type_name::method_name`
+    pub fn snapshot(&self) -> HashMap<String, u64> {
+        self.counters
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|((assembly_name, type_name, method_name), counter)| {
+                (
+                    format!("[{}]{}::{}", assembly_name, type_name, method_name),
+                    counter.load(Ordering::Relaxed),
+                )
+            })
+            .collect()
+    }
+
+    /// Gets the `n` most called methods, most called first
+    pub fn top(&self, n: usize) -> Vec<(String, u64)> {
+        let mut counts: Vec<(String, u64)> = self.snapshot().into_iter().collect();
+        counts.sort_by(|(a_key, a_count), (b_key, b_count)| {
+            b_count.cmp(a_count).then_with(|| a_key.cmp(b_key))
+        });
+        counts.truncate(n);
+        counts
+    }
+}
+
+/// Runs a task at an interval on a background thread, until stopped
+pub(crate) struct PeriodicTask {
+    sender: Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+impl PeriodicTask {
+    pub(crate) fn spawn(interval: Duration, task: impl Fn() + Send + 'static) -> Self {
+        let (sender, receiver) = channel::<()>();
+        let handle = thread::spawn(move || {
+            // dropping the sender disconnects the channel and stops the task
+            while let Err(RecvTimeoutError::Timeout) = receiver.recv_timeout(interval) {
+                task();
+            }
+        });
+
+        Self { sender, handle }
+    }
+
+    pub(crate) fn stop(self) -> thread::Result<()> {
+        drop(self.sender);
+        self.handle.join()
+    }
+}
+
+/// Periodically logs a summary of the profiler metrics on a background thread
+pub struct MetricsReporter {
+    task: PeriodicTask,
+}
+
+impl MetricsReporter {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            task: PeriodicTask::spawn(interval, log_metrics),
+        }
+    }
+
+    /// Stops reporting and logs a final summary of the metrics
+    pub fn shutdown(self) {
+        match self.task.stop() {
+            Ok(()) => log_metrics(),
+            Err(_) => log::error!("Error in joining metrics reporter thread"),
+        }
+    }
+}
+
+fn log_metrics() {
+    log::info!("MetricsReporter: {}", collect_metrics());
+    let top = CapturedExceptions::global().top(CAPTURED_EXCEPTIONS_REPORT_TOP_N);
+    if !top.is_empty() {
+        log::info!(
+            "MetricsReporter: most captured exceptions {}",
+            top.iter()
+                .map(|(type_name, count)| format!("{}={}", type_name, count))
+                .collect::<Vec<_>>()
+                .join(" ")
+        );
+    }
+}
+
+/// Whether a [CallCountsReporter] is running
+static CALL_COUNTS_REPORTED: AtomicBool = AtomicBool::new(false);
+
+/// Whether the calls to instrumented methods are counted, which is only when a
+/// [CallCountsReporter] is running to report them
+pub fn call_counts_reported() -> bool {
+    CALL_COUNTS_REPORTED.load(Ordering::Relaxed)
+}
+
+/// Periodically logs the most called instrumented methods at DEBUG level on a
+/// background thread
+pub struct CallCountsReporter {
+    task: PeriodicTask,
+}
+
+impl CallCountsReporter {
+    pub fn new(interval: Duration) -> Self {
+        CALL_COUNTS_REPORTED.store(true, Ordering::Relaxed);
+        Self {
+            task: PeriodicTask::spawn(interval, log_call_counts),
+        }
+    }
+
+    /// Stops reporting and logs the final call counts
+    pub fn shutdown(self) {
+        CALL_COUNTS_REPORTED.store(false, Ordering::Relaxed);
+        match self.task.stop() {
+            Ok(()) => log_call_counts(),
+            Err(_) => log::error!("Error in joining call counts reporter thread"),
+        }
+    }
+}
+
+fn log_call_counts() {
+    let top = MetricRegistry::global().top(CALL_COUNTS_REPORT_TOP_N);
+    if !top.is_empty() {
+        log::debug!(
+            "CallCountsReporter: most called methods {}",
+            top.iter()
+                .map(|(key, count)| format!("{}={}", key, count))
+                .collect::<Vec<_>>()
+                .join(" ")
+        );
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::profiler::metrics::{
+        collect_metrics, record_exception_filtered, record_exception_sampled, record_hook_error,
+        record_integration_skipped, record_integrations_applied, record_jit_hook, MetricRegistry,
+    };
+    use std::{sync::atomic::Ordering, time::Duration};
+
+    #[test]
+    fn collect_metrics_snapshots_counters() {
+        let before = collect_metrics();
+
+        record_jit_hook(Duration::from_micros(10));
+        record_jit_hook(Duration::from_micros(30));
+        record_integrations_applied(3);
+        record_integration_skipped();
+        record_hook_error();
+        record_exception_sampled(true);
+        record_exception_sampled(false);
+        record_exception_filtered();
+
+        let after = collect_metrics();
+        assert_eq!(after.jit_hook_invocations - before.jit_hook_invocations, 2);
+        assert_eq!(after.integrations_applied - before.integrations_applied, 3);
+        assert_eq!(after.integrations_skipped - before.integrations_skipped, 1);
+        assert_eq!(after.hook_errors - before.hook_errors, 1);
+        assert_eq!(
+            after.exception_sampling_invocations - before.exception_sampling_invocations,
+            2
+        );
+        assert_eq!(after.exceptions_captured - before.exceptions_captured, 1);
+        assert_eq!(after.exceptions_filtered - before.exceptions_filtered, 1);
+        assert!(after.to_string().starts_with(&format!(
+            "jit_hook_invocations={}",
+            after.jit_hook_invocations
+        )));
+    }
+
+    #[test]
+    fn metric_registry_counts_calls_per_method() {
+        let registry = MetricRegistry::default();
+        let execute_reader = registry.counter(
+            "System.Data",
+            "System.Data.Common.DbCommand",
+            "ExecuteReader",
+        );
+        let send = registry.counter("System.Net.Http", "System.Net.Http.HttpClient", "Send");
+        execute_reader.fetch_add(3, Ordering::Relaxed);
+        send.fetch_add(1, Ordering::Relaxed);
+        // the same counter is returned for the same method
+        registry
+            .counter("System.Net.Http", "System.Net.Http.HttpClient", "Send")
+            .fetch_add(1, Ordering::Relaxed);
+
+        let snapshot = registry.snapshot();
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(
+            snapshot["[System.Net.Http]System.Net.Http.HttpClient::Send"],
+            2
+        );
+        assert_eq!(
+            registry.top(1),
+            vec![(
+                "[System.Data]System.Data.Common.DbCommand::ExecuteReader".to_string(),
+                3
+            )]
+        );
+    }
+}
@@ -13,13 +13,21 @@ use crate::{
         IMetaDataAssemblyImport, IMetaDataEmit2, IMetaDataImport2,
     },
     profiler::{
+        calltarget::{
+            CallTarget, CallTargetBatchDrainer, CallTargetBatcher, CALLTARGET_BATCH_DRAIN_INTERVAL,
+        },
         calltarget_tokens::CallTargetTokens,
+        cpu::ThreadCpuSampler,
+        gc::{GcEventHandler, GenerationSizes},
+        heap::{HeapProfiler, HeapProfilerReporter},
         helpers::flatten_integrations,
         managed::{
             IGNORE, MANAGED_PROFILER_ASSEMBLY, MANAGED_PROFILER_ASSEMBLY_LOADER,
             MANAGED_PROFILER_FULL_ASSEMBLY_VERSION,
         },
+        metrics::{CallCountsReporter, MetricsReporter, CALL_COUNTS_REPORT_INTERVAL},
         rejit::RejitHandler,
+        reload::{watch_integrations_file, IntegrationsReloader, IntegrationsWatcher},
         sig::get_sig_type_token_name,
         types::{
             IntegrationMethod, MethodReplacement, ModuleMetadata, ModuleWrapperTokens,
@@ -33,7 +41,7 @@ use com::{
 };
 use log::Level;
 use log4rs::Handle;
-use once_cell::sync::Lazy;
+use once_cell::sync::{Lazy, OnceCell};
 use std::{
     cell::RefCell,
     collections::{HashMap, HashSet},
@@ -42,20 +50,38 @@ use std::{
     path::PathBuf,
     sync::{
         atomic::{AtomicBool, AtomicUsize, Ordering},
-        Mutex, RwLock,
+        Arc, Mutex, RwLock,
     },
+    time::Instant,
 };
-use types::{AssemblyMetaData, FunctionInfo, Version};
+use types::{AssemblyMetaData, AssemblyName, FunctionInfo, Version};
 use widestring::{U16CStr, U16CString};
 
+pub mod build_info;
+mod calltarget;
 mod calltarget_tokens;
+mod config_dump;
+mod cpu;
+mod diagnostics;
 pub mod env;
+pub mod error;
+#[cfg(target_os = "windows")]
+mod eventlog;
+mod gc;
+mod heap;
 mod helpers;
+pub mod logging;
 pub mod managed;
+pub mod metrics;
 mod process;
 mod rejit;
+mod reload;
+mod sampling;
 pub mod sig;
 mod startup_hook;
+#[cfg(test)]
+mod test_helpers;
+mod timeout;
 pub mod types;
 
 const SKIP_ASSEMBLY_PREFIXES: [&str; 22] = [
@@ -131,6 +157,16 @@ class! {
         logger: RefCell<Option<Handle>>,
         profiler_info: RefCell<Option<ICorProfilerInfo4>>,
         rejit_handler: RefCell<Option<RejitHandler>>,
+        calltarget_batcher: Arc<Mutex<CallTargetBatcher>>,
+        calltarget_batch_drainer: RefCell<Option<CallTargetBatchDrainer>>,
+        integrations_reloader: RefCell<Option<IntegrationsReloader>>,
+        integrations_watchers: RefCell<Vec<IntegrationsWatcher>>,
+        metrics_reporter: RefCell<Option<MetricsReporter>>,
+        call_counts_reporter: RefCell<Option<CallCountsReporter>>,
+        gc_event_handler: Mutex<Option<GcEventHandler>>,
+        heap_profiler: OnceCell<Arc<HeapProfiler>>,
+        heap_profiler_reporter: RefCell<Option<HeapProfilerReporter>>,
+        thread_cpu_sampler: RefCell<Option<ThreadCpuSampler>>,
         runtime_info: RefCell<Option<RuntimeInfo>>,
         modules: Mutex<HashMap<ModuleID, ModuleMetadata>>,
         module_wrapper_tokens: Mutex<HashMap<ModuleID, ModuleWrapperTokens>>,
@@ -139,8 +175,9 @@ class! {
         cor_lib_module_loaded: AtomicBool,
         cor_app_domain_id: AtomicUsize,
         is_desktop_iis: AtomicBool,
-        integration_methods: RwLock<Vec<IntegrationMethod>>,
+        integration_methods: Arc<RwLock<Vec<IntegrationMethod>>>,
         first_jit_compilation_app_domains: RwLock<HashSet<AppDomainID>>,
+        inlining_overrides: RwLock<HashMap<(ModuleID, mdMethodDef), bool>>,
     }
 
     impl ICorProfilerCallback for Profiler {
@@ -156,7 +193,10 @@ class! {
         pub fn Shutdown(&self) -> HRESULT {
             match self.shutdown() {
                 Ok(_) => S_OK,
-                Err(_) => S_OK
+                Err(hr) => {
+                    log::warn!("Shutdown: failed with 0x{:X}", hr);
+                    S_OK
+                }
             }
         }
         pub fn AppDomainCreationStarted(&self, appDomainId: AppDomainID) -> HRESULT { S_OK }
@@ -182,7 +222,10 @@ class! {
         ) -> HRESULT {
             match self.assembly_load_finished(assemblyId, hrStatus) {
                 Ok(_) => S_OK,
-                Err(_) => S_OK,
+                Err(hr) => {
+                    log::debug!("AssemblyLoadFinished: failed with 0x{:X}", hr);
+                    S_OK
+                }
             }
         }
         pub fn AssemblyUnloadStarted(&self, assemblyId: AssemblyID) -> HRESULT { S_OK }
@@ -195,13 +238,19 @@ class! {
         pub fn ModuleLoadFinished(&self, moduleId: ModuleID, hrStatus: HRESULT) -> HRESULT {
             match self.module_load_finished(moduleId, hrStatus) {
                 Ok(_) => S_OK,
-                Err(_) => S_OK,
+                Err(hr) => {
+                    log::debug!("ModuleLoadFinished: failed with 0x{:X}", hr);
+                    S_OK
+                }
             }
         }
         pub fn ModuleUnloadStarted(&self, moduleId: ModuleID) -> HRESULT {
             match self.module_unload_started(moduleId) {
                 Ok(_) => S_OK,
-                Err(_) => S_OK,
+                Err(hr) => {
+                    log::debug!("ModuleUnloadStarted: failed with 0x{:X}", hr);
+                    S_OK
+                }
             }
         }
         pub fn ModuleUnloadFinished(&self, moduleId: ModuleID, hrStatus: HRESULT) -> HRESULT { S_OK }
@@ -212,7 +261,10 @@ class! {
         ) -> HRESULT { S_OK }
         pub fn ClassLoadStarted(&self, classId: ClassID) -> HRESULT { S_OK }
         pub fn ClassLoadFinished(&self, classId: ClassID, hrStatus: HRESULT) -> HRESULT { S_OK }
-        pub fn ClassUnloadStarted(&self, classId: ClassID) -> HRESULT { S_OK }
+        pub fn ClassUnloadStarted(&self, classId: ClassID) -> HRESULT {
+            self.class_unload_started(classId);
+            S_OK
+        }
         pub fn ClassUnloadFinished(&self, classId: ClassID, hrStatus: HRESULT) -> HRESULT { S_OK }
         pub fn FunctionUnloadStarted(&self, functionId: FunctionID) -> HRESULT { S_OK }
         pub fn JITCompilationStarted(
@@ -220,9 +272,16 @@ class! {
             functionId: FunctionID,
             fIsSafeToBlock: BOOL,
         ) -> HRESULT {
-            match self.jit_compilation_started(functionId, fIsSafeToBlock) {
+            let start = Instant::now();
+            let result = self.jit_compilation_started(functionId, fIsSafeToBlock);
+            metrics::record_jit_hook(start.elapsed());
+            match result {
                 Ok(_) => S_OK,
-                Err(_) => S_OK,
+                Err(hr) => {
+                    log::debug!("JITCompilationStarted: failed with 0x{:X}", hr);
+                    metrics::record_hook_error();
+                    S_OK
+                }
             }
         }
         pub fn JITCompilationFinished(
@@ -247,7 +306,15 @@ class! {
             callerId: FunctionID,
             calleeId: FunctionID,
             pfShouldInline: *mut BOOL,
-        ) -> HRESULT { S_OK }
+        ) -> HRESULT {
+            match self.jit_inlining(calleeId, pfShouldInline) {
+                Ok(_) => S_OK,
+                Err(hr) => {
+                    log::debug!("JITInlining: failed with 0x{:X}", hr);
+                    S_OK
+                }
+            }
+        }
         pub fn ThreadCreated(&self, threadId: ThreadID) -> HRESULT { S_OK }
         pub fn ThreadDestroyed(&self, threadId: ThreadID) -> HRESULT { S_OK }
         pub fn ThreadAssignedToOSThread(
@@ -287,7 +354,10 @@ class! {
             newObjectIDRangeStart: *const ObjectID,
             cObjectIDRangeLength: *const ULONG,
         ) -> HRESULT { S_OK }
-        pub fn ObjectAllocated(&self, objectId: ObjectID, classId: ClassID) -> HRESULT { S_OK }
+        pub fn ObjectAllocated(&self, objectId: ObjectID, classId: ClassID) -> HRESULT {
+            self.object_allocated(objectId, classId);
+            S_OK
+        }
         pub fn ObjectsAllocatedByClass(
              &self,
             cClassCount: ULONG,
@@ -306,7 +376,10 @@ class! {
             cRootRefs: ULONG,
             rootRefIds: *const ObjectID,
         ) -> HRESULT { S_OK }
-        pub fn ExceptionThrown(&self, thrownObjectId: ObjectID) -> HRESULT { S_OK }
+        pub fn ExceptionThrown(&self, thrownObjectId: ObjectID) -> HRESULT {
+            self.exception_thrown(thrownObjectId);
+            S_OK
+        }
         pub fn ExceptionSearchFunctionEnter(&self, functionId: FunctionID) -> HRESULT { S_OK }
         pub fn ExceptionSearchFunctionLeave(&self) -> HRESULT { S_OK }
         pub fn ExceptionSearchFilterEnter(&self, functionId: FunctionID) -> HRESULT { S_OK }
@@ -351,13 +424,19 @@ class! {
             cGenerations: int,
             generationCollected: *const BOOL,
             reason: COR_PRF_GC_REASON,
-        ) -> HRESULT { S_OK }
+        ) -> HRESULT {
+            self.garbage_collection_started(cGenerations, generationCollected, reason);
+            S_OK
+        }
         pub fn SurvivingReferences(&self,
             cSurvivingObjectIDRanges: ULONG,
             objectIDRangeStart: *const ObjectID,
             cObjectIDRangeLength: *const ULONG,
         ) -> HRESULT { S_OK }
-        pub fn GarbageCollectionFinished(&self) -> HRESULT { S_OK }
+        pub fn GarbageCollectionFinished(&self) -> HRESULT {
+            self.garbage_collection_finished();
+            S_OK
+        }
         pub fn FinalizeableObjectQueued(&self,
             finalizerFlags: DWORD,
             objectID: ObjectID,
@@ -394,7 +473,10 @@ class! {
         ) -> HRESULT {
             match self.rejit_compilation_started(functionId, rejitId, fIsSafeToBlock) {
                 Ok(_) => S_OK,
-                Err(_) => S_OK,
+                Err(hr) => {
+                    log::debug!("ReJITCompilationStarted: failed with 0x{:X}", hr);
+                    S_OK
+                }
             }
         }
         pub fn GetReJITParameters(&self,
@@ -454,7 +536,10 @@ class! {
         ) -> HRESULT {
             match self.get_assembly_references(wszAssemblyPath, pAsmRefProvider) {
                 Ok(_) => S_OK,
-                Err(_) => S_OK,
+                Err(hr) => {
+                    log::debug!("GetAssemblyReferences: failed with 0x{:X}", hr);
+                    S_OK
+                }
             }
         }
     }
@@ -489,6 +574,10 @@ impl Profiler {
         }
         
         println!("hello world init");
+        env::print_env_vars();
+        if env::PROFILER_CONFIG.print_help {
+            env::print_env_var_help();
+        }
 
         let process_path = std::env::current_exe().map_err(|e| {
             // logging hasn't yet been initialized so unable to log
@@ -501,12 +590,16 @@ impl Profiler {
             .to_string_lossy()
             .to_string();
 
-        let process_name = process_path
-            .file_stem()
-            .unwrap()
-            .to_string_lossy()
-            .to_string();
-        let logger = env::initialize_logging(&process_name);
+        let process_name = env::get_process_name();
+        let logger = match logging::initialize_logging() {
+            Ok(handle) => Some(handle),
+            Err(e) if e.is_appender_error() => {
+                let handle = logging::initialize_console_logging().ok();
+                log::warn!("Initialize: falling back to console logging. {}", e);
+                handle
+            }
+            Err(_) => None,
+        };
 
         log::trace!(
             "Initialize: started. profiler package version {} (commit: {}) (anchor version: {})",
@@ -515,10 +608,53 @@ impl Profiler {
             *PROFILER_VERSION
         );
 
+        // checked before the profiler changes the process environment, so that a second
+        // instance leaves the environment set up by the first one untouched
+        if env::is_profiler_already_attached() {
+            log::error!(
+                "Initialize: another profiler instance is already attached to process {}. \
+                Check that profiling is enabled only once for the process. Profiler disabled",
+                std::process::id()
+            );
+            return Err(E_FAIL);
+        }
+
         if log::log_enabled!(Level::Debug) {
             log::debug!("Environment variables\n{}", env::get_env_vars());
         }
 
+        log::info!(
+            "Initialize: running in container: {}",
+            env::is_running_in_container()
+        );
+        env::dump_configuration_to_log(&env::PROFILER_CONFIG);
+        env::log_env_var_warnings();
+        if let Err(e) = env::check_strict_env() {
+            log::error!("Initialize: {}. Profiler disabled", e);
+            return Err(E_FAIL);
+        }
+
+        // the kill switch leaves the profiler attached, with no integrations and exiting
+        // JITCompilationStarted immediately, and without configuring the managed agent
+        let disable_all = *env::ELASTIC_APM_PROFILER_DISABLE_ALL;
+        if disable_all {
+            log::warn!(
+                "Initialize: ********************************************************************"
+            );
+            log::warn!(
+                "Initialize: ELASTIC_APM_PROFILER_DISABLE_ALL is true. Profiler disabled by kill switch. No methods will be instrumented"
+            );
+            log::warn!(
+                "Initialize: ********************************************************************"
+            );
+        } else {
+            env::apply_startup_hooks_env_var();
+            env::apply_agent_config_env_var();
+            env::apply_agent_service_name_env_var();
+            env::apply_agent_environment_env_var();
+            env::apply_agent_span_stack_trace_env_var();
+        }
+
         if let Some(exclude_process_names) = env::get_exclude_processes() {
             for exclude_process_name in exclude_process_names {
                 if process_file_name == exclude_process_name {
@@ -532,6 +668,14 @@ impl Profiler {
             }
         }
 
+        if !env::process_is_allowed(&process_name) {
+            log::info!(
+                "Initialize: process name {} is excluded or does not match process filter. Profiler disabled",
+                &process_name
+            );
+            return Err(E_FAIL);
+        }
+
         if let Some(exclude_service_names) = env::get_exclude_service_names() {
             if let Some(service_name) = env::get_service_name() {
                 for exclude_service_name in exclude_service_names {
@@ -556,17 +700,63 @@ impl Profiler {
                 E_FAIL
             })?;
 
+        // get the details for the runtime, against which integrations are checked
+        let runtime_info = profiler_info.get_runtime_information()?;
+        env::set_runtime_version(
+            Version::new(
+                runtime_info.major_version,
+                runtime_info.minor_version,
+                runtime_info.build_number,
+                runtime_info.qfe_version,
+            )
+            .to_string(),
+        );
+
         // get the integrations from file
-        let integrations = env::load_integrations()?;
+        let integrations = env::load_integrations();
+        if let Some(path) = env::get_diagnostic_report_path() {
+            match diagnostics::write_diagnostic_report(&path, &integrations) {
+                Ok(()) => log::info!("Initialize: wrote diagnostic report to {}", path.display()),
+                Err(e) => log::warn!(
+                    "Initialize: could not write diagnostic report to {}: {}",
+                    path.display(),
+                    e
+                ),
+            }
+        }
+        let integrations = integrations.map_err(|e| {
+            log::warn!("Initialize: {}. profiler is disabled.", e);
+            e
+        })?;
         let calltarget_enabled = *env::ELASTIC_APM_PROFILER_CALLTARGET_ENABLED;
         if calltarget_enabled {
             let rejit_handler = RejitHandler::new(profiler_info.clone());
+            *self.calltarget_batcher.lock().unwrap() =
+                CallTargetBatcher::new(env::get_calltarget_batch_size());
+            let rejit_queue = rejit_handler.queue();
+            self.calltarget_batch_drainer
+                .replace(Some(CallTargetBatchDrainer::new(
+                    self.calltarget_batcher.clone(),
+                    CALLTARGET_BATCH_DRAIN_INTERVAL,
+                    move |module_ids, method_ids| {
+                        rejit_queue.enqueue_for_rejit(module_ids, method_ids)
+                    },
+                )));
             self.rejit_handler.replace(Some(rejit_handler));
         }
 
+        let enable_inlining = env::enable_inlining();
+        let inlining_overridden = integrations
+            .iter()
+            .any(|i| i.enable_inlining == Some(!enable_inlining));
+
+        let reload_integrations = env::get_integrations_reload_interval()
+            .map(|interval| (integrations.clone(), interval));
+        let watched_integrations = env::get_watched_integrations_files()
+            .map(|paths| (Arc::new(RwLock::new(integrations.clone())), paths));
         let mut integration_methods = flatten_integrations(integrations, calltarget_enabled);
 
-        if integration_methods.is_empty() {
+        if integration_methods.is_empty() && !disable_all {
             log::warn!("Initialize: no integrations. Profiler disabled.");
             return Err(E_FAIL);
         } else {
@@ -576,11 +766,67 @@ impl Profiler {
             );
         }
 
+        // captured after the profiler sets the agent environment variables, so
+        // that only changes made by the application are logged at shutdown
+        env::snapshot::capture_startup_snapshot();
+
         self.integration_methods
             .write()
             .unwrap()
             .append(&mut integration_methods);
 
+        if let Some((integrations, interval)) = reload_integrations {
+            log::info!(
+                "Initialize: reloading integrations every {} second(s)",
+                interval.as_secs()
+            );
+            let integrations_reloader = IntegrationsReloader::new(
+                integrations,
+                self.integration_methods.clone(),
+                calltarget_enabled,
+                interval,
+            );
+            self.integrations_reloader
+                .replace(Some(integrations_reloader));
+        }
+
+        if let Some((integrations, paths)) = watched_integrations {
+            let mut integrations_watchers = self.integrations_watchers.borrow_mut();
+            for path in paths {
+                log::info!(
+                    "Initialize: reloading integrations when {} changes",
+                    path.display()
+                );
+                let integration_methods = self.integration_methods.clone();
+                integrations_watchers.push(watch_integrations_file(
+                    path,
+                    integrations.clone(),
+                    move |integrations| {
+                        *integration_methods.write().unwrap() =
+                            flatten_integrations(integrations.to_vec(), calltarget_enabled);
+                    },
+                ));
+            }
+        }
+
+        if let Some(dir) = env::get_config_dump_dir() {
+            config_dump::start_config_dump_trigger(dir);
+        }
+
+        if let Some(interval) = env::get_metrics_interval() {
+            log::info!(
+                "Initialize: logging profiler metrics every {} second(s)",
+                interval.as_secs()
+            );
+            self.metrics_reporter
+                .replace(Some(MetricsReporter::new(interval)));
+        }
+
+        if calltarget_enabled && log::log_enabled!(Level::Debug) {
+            self.call_counts_reporter
+                .replace(Some(CallCountsReporter::new(CALL_COUNTS_REPORT_INTERVAL)));
+        }
+
         // Set the event mask for CLR events we're interested in
         let mut event_mask = COR_PRF_MONITOR::COR_PRF_MONITOR_JIT_COMPILATION
             | COR_PRF_MONITOR::COR_PRF_DISABLE_TRANSPARENCY_CHECKS_UNDER_FULL_TRUST
@@ -596,7 +842,13 @@ impl Profiler {
             log::info!("Initialize: CallTarget instrumentation is disabled");
         }
 
-        if !env::enable_inlining(calltarget_enabled) {
+        if inlining_overridden {
+            // inlining is decided per method in JITInlining
+            log::info!(
+                "Initialize: JIT Inlining is {}, and overridden by integrations",
+                if enable_inlining { "enabled" } else { "disabled" }
+            );
+        } else if !enable_inlining {
             log::info!("Initialize: JIT Inlining is disabled");
             event_mask |= COR_PRF_MONITOR::COR_PRF_DISABLE_INLINING;
         } else {
@@ -608,22 +860,80 @@ impl Profiler {
             event_mask |= COR_PRF_MONITOR::COR_PRF_DISABLE_OPTIMIZATIONS;
         }
 
+        let gc_pressure_sampling = env::gc_pressure_sampling();
+        if gc_pressure_sampling {
+            log::info!("Initialize: GC pressure sampling is enabled");
+            self.gc_event_handler
+                .lock()
+                .unwrap()
+                .replace(GcEventHandler::default());
+        }
+
+        if let Some((rate, interval)) = env::get_heap_profiling() {
+            log::info!(
+                "Initialize: heap profiling is enabled. sampling 1 in every {} allocation(s)",
+                rate
+            );
+            let heap_profiler = Arc::new(HeapProfiler::new(rate));
+            self.heap_profiler_reporter
+                .replace(Some(HeapProfilerReporter::new(
+                    heap_profiler.clone(),
+                    interval,
+                )));
+            let _ = self.heap_profiler.set(heap_profiler);
+            // class loads are monitored to know when class ids are reused
+            event_mask |= COR_PRF_MONITOR::COR_PRF_ENABLE_OBJECT_ALLOCATED
+                | COR_PRF_MONITOR::COR_PRF_MONITOR_OBJECT_ALLOCATED
+                | COR_PRF_MONITOR::COR_PRF_MONITOR_CLASS_LOADS;
+        }
+
+        if let Some(interval) = env::get_thread_cpu_sampling_interval() {
+            log::info!(
+                "Initialize: thread CPU sampling is enabled. sampling every {} ms",
+                interval.as_millis()
+            );
+            self.thread_cpu_sampler
+                .replace(Some(ThreadCpuSampler::new(interval)));
+        }
+
+        if env::exception_capture_enabled() {
+            log::info!("Initialize: exceptions are monitored for capture");
+            event_mask |= COR_PRF_MONITOR::COR_PRF_MONITOR_EXCEPTIONS;
+        }
+
         // if the runtime also supports ICorProfilerInfo5, set eventmask2
         if let Some(profiler_info5) = unknown.query_interface::<ICorProfilerInfo5>() {
-            let event_mask2 = COR_PRF_HIGH_MONITOR::COR_PRF_HIGH_ADD_ASSEMBLY_REFERENCES;
+            let mut event_mask2 = COR_PRF_HIGH_MONITOR::COR_PRF_HIGH_ADD_ASSEMBLY_REFERENCES;
+            if gc_pressure_sampling {
+                // basic GC events don't disable concurrent GC, unlike COR_PRF_MONITOR_GC
+                event_mask2 |= COR_PRF_HIGH_MONITOR::COR_PRF_HIGH_BASIC_GC;
+            }
             log::trace!(
                 "Initialize: set event mask2 to {:?}, {:?}",
                 &event_mask,
                 &event_mask2
             );
-            profiler_info5.set_event_mask2(event_mask, event_mask2)?;
+            if let Err(hr) = profiler_info5.set_event_mask2(event_mask, event_mask2) {
+                if !event_mask2.contains(COR_PRF_HIGH_MONITOR::COR_PRF_HIGH_BASIC_GC) {
+                    return Err(hr);
+                }
+                // older runtimes don't support basic GC events
+                log::warn!(
+                    "Initialize: could not monitor basic GC events, 0x{:X}. GC pressure sampling disabled",
+                    hr
+                );
+                event_mask2.remove(COR_PRF_HIGH_MONITOR::COR_PRF_HIGH_BASIC_GC);
+                self.gc_event_handler.lock().unwrap().take();
+                profiler_info5.set_event_mask2(event_mask, event_mask2)?;
+            }
         } else {
+            if gc_pressure_sampling {
+                event_mask |= COR_PRF_MONITOR::COR_PRF_MONITOR_GC;
+            }
             log::trace!("Initialize: set event mask to {:?}", &event_mask);
             profiler_info.set_event_mask(event_mask)?;
         }
 
-        // get the details for the runtime
-        let runtime_info = profiler_info.get_runtime_information()?;
         let is_desktop_clr = runtime_info.is_desktop_clr();
         let process_name = process_path.file_name().unwrap();
         if process_name == "w3wp.exe" || process_name == "iisexpress.exe" {
@@ -633,6 +943,9 @@ impl Profiler {
         // Store the profiler and runtime info for later use
         self.profiler_info.replace(Some(profiler_info));
         self.runtime_info.replace(Some(runtime_info));
+        if let Some(handle) = &logger {
+            logging::register_log_handle(handle.clone());
+        }
         self.logger.replace(logger);
 
         IS_ATTACHED.store(true, Ordering::SeqCst);
@@ -645,17 +958,59 @@ impl Profiler {
         log::trace!("Shutdown: started");
         let _lock = self.modules.lock();
 
+        env::snapshot::log_changes_since_startup();
+
+        // shutdown the CallTarget batch drainer before the rejit handler, whose thread
+        // finishes once the drainer drops its queue
+        if let Some(calltarget_batch_drainer) = self.calltarget_batch_drainer.replace(None) {
+            calltarget_batch_drainer.shutdown();
+        }
+
         // shutdown the rejit handler, if it's running
         if let Some(rejit_handler) = self.rejit_handler.replace(None) {
             rejit_handler.shutdown();
         }
 
+        // shutdown the integrations reloader, if it's running
+        if let Some(integrations_reloader) = self.integrations_reloader.replace(None) {
+            integrations_reloader.shutdown();
+        }
+
+        // shutdown the integrations watchers, if any are running
+        for integrations_watcher in self.integrations_watchers.take() {
+            integrations_watcher.shutdown();
+        }
+
+        // shutdown the metrics reporter, if it's running
+        if let Some(metrics_reporter) = self.metrics_reporter.replace(None) {
+            metrics_reporter.shutdown();
+        }
+
+        // shutdown the call counts reporter, if it's running
+        if let Some(call_counts_reporter) = self.call_counts_reporter.replace(None) {
+            call_counts_reporter.shutdown();
+        }
+
+        // shutdown the heap profiler reporter, if it's running
+        if let Some(heap_profiler_reporter) = self.heap_profiler_reporter.replace(None) {
+            heap_profiler_reporter.shutdown();
+        }
+
+        // shutdown the thread CPU sampler, if it's running
+        if let Some(thread_cpu_sampler) = self.thread_cpu_sampler.replace(None) {
+            thread_cpu_sampler.shutdown();
+        }
+
         // Cannot safely call methods on profiler_info after shutdown is called,
         // so replace it on the profiler
         self.profiler_info.replace(None);
 
         IS_ATTACHED.store(false, Ordering::SeqCst);
 
+        // write any buffered log records. This is the last callback from the runtime, and
+        // unlike process detach, the threads of async appenders are still running
+        logging::flush_and_shutdown();
+
         Ok(())
     }
 
@@ -895,7 +1250,9 @@ impl Profiler {
                     .iter()
                     .filter(|m| {
                         if let Some(caller) = m.method_replacement.caller() {
-                            caller.assembly.is_empty() || &caller.assembly == assembly_name
+                            caller.assembly.is_empty()
+                                || AssemblyName::from(caller.assembly.as_str())
+                                    .matches(&AssemblyName::from(assembly_name.as_str()))
                         } else {
                             true
                         }
@@ -1061,7 +1418,15 @@ impl Profiler {
 
             log::trace!("ModuleLoadFinished: tracking {} module(s)", modules.len());
 
-            if call_target_enabled {
+            if call_target_enabled
+                && env::get_calltarget_exclude_assemblies().contains(&assembly_name.to_lowercase())
+            {
+                log::debug!(
+                    "ModuleLoadFinished: skipping CallTarget instrumentation of module {} {} because it is excluded",
+                    module_id,
+                    assembly_name
+                );
+            } else if call_target_enabled {
                 let rejit_count =
                     self.calltarget_request_rejit_for_module(module_id, module_metadata)?;
                 if rejit_count > 0 {
@@ -1076,6 +1441,123 @@ impl Profiler {
         Ok(())
     }
 
+    fn garbage_collection_started(
+        &self,
+        generations: int,
+        generation_collected: *const BOOL,
+        reason: COR_PRF_GC_REASON,
+    ) {
+        let mut gc_event_handler = self.gc_event_handler.lock().unwrap();
+        if let Some(gc_event_handler) = gc_event_handler.as_mut() {
+            let generations_collected: Vec<bool> = if generation_collected.is_null() {
+                Vec::new()
+            } else {
+                unsafe { std::slice::from_raw_parts(generation_collected, generations as usize) }
+                    .iter()
+                    .map(|collected| *collected != 0)
+                    .collect()
+            };
+            gc_event_handler.garbage_collection_started(&generations_collected, reason);
+        }
+    }
+
+    fn garbage_collection_finished(&self) {
+        let mut gc_event_handler = self.gc_event_handler.lock().unwrap();
+        if let Some(gc_event_handler) = gc_event_handler.as_mut() {
+            let sizes = {
+                let _lock = self.modules.lock();
+                let profiler_info_borrow = self.profiler_info.borrow();
+                match profiler_info_borrow.as_ref() {
+                    Some(profiler_info) => match profiler_info.get_generation_bounds() {
+                        Ok(ranges) => GenerationSizes::from_ranges(&ranges),
+                        Err(hr) => {
+                            log::debug!(
+                                "GarbageCollectionFinished: could not get generation bounds: {:X}",
+                                hr
+                            );
+                            GenerationSizes::default()
+                        }
+                    },
+                    None => GenerationSizes::default(),
+                }
+            };
+            log::debug!(
+                "GarbageCollectionFinished: {}",
+                gc_event_handler.garbage_collection_finished(sizes)
+            );
+        }
+    }
+
+    fn object_allocated(&self, object_id: ObjectID, class_id: ClassID) {
+        let heap_profiler = match self.heap_profiler.get() {
+            Some(heap_profiler) if heap_profiler.should_sample() => heap_profiler,
+            _ => return,
+        };
+
+        let _lock = self.modules.lock();
+        let profiler_info_borrow = self.profiler_info.borrow();
+        let profiler_info = match profiler_info_borrow.as_ref() {
+            Some(profiler_info) => profiler_info,
+            None => return,
+        };
+
+        let size = profiler_info
+            .get_object_size_2(object_id)
+            .unwrap_or_default() as u64;
+        heap_profiler.record_allocation(class_id, size, || {
+            Self::get_class_name(profiler_info, class_id)
+                .unwrap_or_else(|| format!("<class {}>", class_id))
+        });
+    }
+
+    fn class_unload_started(&self, class_id: ClassID) {
+        if let Some(heap_profiler) = self.heap_profiler.get() {
+            heap_profiler.class_unloaded(class_id);
+        }
+    }
+
+    fn exception_thrown(&self, object_id: ObjectID) {
+        let _lock = self.modules.lock();
+        let profiler_info_borrow = self.profiler_info.borrow();
+        let profiler_info = match profiler_info_borrow.as_ref() {
+            Some(profiler_info) => profiler_info,
+            None => return,
+        };
+
+        let type_name = match profiler_info
+            .get_class_from_object(object_id)
+            .ok()
+            .and_then(|class_id| Self::get_class_name(profiler_info, class_id))
+        {
+            Some(type_name) => type_name,
+            None => {
+                log::debug!("ExceptionThrown: could not get type of exception {}", object_id);
+                return;
+            }
+        };
+
+        if sampling::should_capture_exception(&type_name) {
+            log::debug!("ExceptionThrown: captured exception {}", type_name);
+            sampling::CapturedExceptions::global().record(&type_name);
+        } else {
+            log::trace!("ExceptionThrown: exception {} is filtered or not sampled", type_name);
+        }
+    }
+
+    /// Gets the name of the type definition of the class, which includes its namespace.
+    /// Returns [None] for classes without a type definition, such as arrays
+    fn get_class_name(profiler_info: &ICorProfilerInfo4, class_id: ClassID) -> Option<String> {
+        let class_info = profiler_info
+            .get_class_id_info(class_id)
+            .ok()
+            .filter(|class_info| class_info.token != mdTypeDefNil)?;
+        profiler_info
+            .get_module_metadata::<IMetaDataImport2>(class_info.module_id, CorOpenFlags::ofRead)
+            .and_then(|import| import.get_type_def_props(class_info.token))
+            .ok()
+            .map(|props| props.name)
+    }
+
     fn module_unload_started(&self, module_id: ModuleID) -> Result<(), HRESULT> {
         if !IS_ATTACHED.load(Ordering::SeqCst) {
             return Ok(());
@@ -1099,6 +1581,16 @@ impl Profiler {
             return Ok(());
         }
 
+        self.inlining_overrides
+            .write()
+            .unwrap()
+            .retain(|(id, _), _| *id != module_id);
+
+        self.calltarget_batcher
+            .lock()
+            .unwrap()
+            .remove_module(module_id);
+
         if let Some(module_metadata) = modules.remove(&module_id) {
             MANAGED_PROFILER_LOADED_APP_DOMAINS
                 .lock()
@@ -1109,15 +1601,59 @@ impl Profiler {
         Ok(())
     }
 
+    /// Prevents inlining of a target method when the global JIT inlining setting, or the
+    /// integration targeting the method, disables it
+    fn jit_inlining(&self, callee_id: FunctionID, should_inline: *mut BOOL) -> Result<(), HRESULT> {
+        if !IS_ATTACHED.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        let enable_inlining = env::enable_inlining();
+        // overrides are only added for target methods that override the global setting, so
+        // the function info of the callee is only needed when there are any
+        let enable_inlining = if self.inlining_overrides.read().unwrap().is_empty() {
+            enable_inlining
+        } else {
+            let _lock = self.modules.lock();
+
+            if !IS_ATTACHED.load(Ordering::SeqCst) {
+                return Ok(());
+            }
+
+            let profiler_borrow = self.profiler_info.borrow();
+            let profiler_info = profiler_borrow.as_ref().ok_or(E_FAIL)?;
+            let function_info = profiler_info.get_function_info(callee_id)?;
+            self.inlining_overrides
+                .read()
+                .unwrap()
+                .get(&(function_info.module_id, function_info.token))
+                .copied()
+                .unwrap_or(enable_inlining)
+        };
+
+        if !enable_inlining {
+            unsafe {
+                *should_inline = 0;
+            }
+        }
+
+        Ok(())
+    }
+
     fn jit_compilation_started(
         &self,
         function_id: FunctionID,
         is_safe_to_block: BOOL,
     ) -> Result<(), HRESULT> {
-        if !IS_ATTACHED.load(Ordering::SeqCst) || is_safe_to_block == 0 {
+        if *env::ELASTIC_APM_PROFILER_DISABLE_ALL
+            || !IS_ATTACHED.load(Ordering::SeqCst)
+            || is_safe_to_block == 0
+        {
             return Ok(());
         }
 
+        self.enqueue_calltarget_batch();
+
         let modules = self.modules.lock().unwrap();
 
         if !IS_ATTACHED.load(Ordering::SeqCst) {
@@ -1253,6 +1789,8 @@ impl Profiler {
                 &caller,
                 &method_replacements,
             )?;
+
+            metrics::record_integrations_applied(method_replacements.len());
         }
 
         Ok(())
@@ -1539,6 +2077,20 @@ impl Profiler {
                 _ => continue,
             };
 
+            if !env::calltarget_is_allowed(
+                &module_metadata.assembly_name,
+                target.type_name(),
+                target.method_name(),
+            ) {
+                log::debug!(
+                    "skipping CallTarget instrumentation of {}.{} in {} because it is not in the allow list",
+                    target.type_name(),
+                    target.method_name(),
+                    &module_metadata.assembly_name
+                );
+                continue;
+            }
+
             let type_def = match helpers::find_type_def_by_name(
                 target.type_name(),
                 &module_metadata.assembly_name,
@@ -1637,6 +2189,18 @@ impl Profiler {
                 let rejit_method = rejit_module.get_or_add_method(method_def);
                 rejit_method.set_function_info(caller);
                 rejit_method.set_method_replacement(integration.method_replacement.clone());
+                rejit_method
+                    .set_disable_optimizations(integration.disable_optimizations == Some(true));
+
+                if let Some(enable_inlining) = integration
+                    .enable_inlining
+                    .filter(|enable_inlining| *enable_inlining != env::enable_inlining())
+                {
+                    self.inlining_overrides
+                        .write()
+                        .unwrap()
+                        .insert((module_id, method_def), enable_inlining);
+                }
 
                 method_ids.push(method_def);
 
@@ -1662,6 +2226,7 @@ impl Profiler {
                 }
             }
             if !rejit_target_found {
+                metrics::record_integration_skipped();
                 log::error!(
                     "No rejit method found for target: {}.{}",
                     target.type_name(),
@@ -1671,14 +2236,31 @@ impl Profiler {
         }
 
         let len = method_ids.len();
+        metrics::record_integrations_applied(len);
         if !method_ids.is_empty() {
-            let borrow = self.rejit_handler.borrow();
-            let rejit_handler = borrow.as_ref().unwrap();
-            rejit_handler.enqueue_for_rejit(vec![module_id; method_ids.len()], method_ids);
+            {
+                let mut batcher = self.calltarget_batcher.lock().unwrap();
+                for method_id in method_ids {
+                    batcher.push(CallTarget::new(module_id, method_id));
+                }
+            }
+            self.enqueue_calltarget_batch();
         }
 
         Ok(len)
     }
+
+    /// Enqueues the next batch of pending CallTarget methods for ReJIT, leaving the
+    /// remainder to be enqueued on subsequent JIT compilations or by the
+    /// [CallTargetBatchDrainer]
+    fn enqueue_calltarget_batch(&self) {
+        let borrow = self.rejit_handler.borrow();
+        if let Some(rejit_handler) = borrow.as_ref() {
+            calltarget::enqueue_next_batch(&self.calltarget_batcher, |module_ids, method_ids| {
+                rejit_handler.enqueue_for_rejit(module_ids, method_ids)
+            });
+        }
+    }
 }
 
 pub fn profiler_assembly_loaded_in_app_domain(app_domain_id: AppDomainID) -> bool {
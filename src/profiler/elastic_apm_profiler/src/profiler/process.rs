@@ -5,7 +5,8 @@
 
 use crate::{
     cil::{
-        uncompress_token, Instruction, Method, Operand::InlineMethod, CALL, CALLVIRT, CONSTRAINED,
+        uncompress_token, verify_nop_padding, Instruction, Method, Operand::InlineMethod, CALL,
+        CALLVIRT, CONSTRAINED,
     },
     ffi::{
         mdMemberRefNil, mdToken, mdTypeRefNil, CorElementType, FunctionID, ModuleID, E_FAIL, ULONG,
@@ -15,6 +16,7 @@ use crate::{
     profiler::{
         env, helpers,
         helpers::return_type_is_value_type_or_generic,
+        logging,
         managed::IGNORE,
         sig::{parse_signature_types, parse_type},
         types::{
@@ -59,6 +61,7 @@ pub fn process_replacement_calls(
         E_FAIL
     })?;
 
+    let original_instructions = method.instructions_to_bytes();
     let mut original_il = None;
     let mut modified = false;
     for method_replacement in method_replacements {
@@ -269,8 +272,9 @@ pub fn process_replacement_calls(
             }
 
             if *env::ELASTIC_APM_PROFILER_LOG_IL {
-                original_il = Some(helpers::get_il_codes(
+                original_il = Some(helpers::format_il(
                     "IL original code for caller: ",
+                    "original",
                     &method,
                     caller,
                     module_metadata,
@@ -499,13 +503,26 @@ pub fn process_replacement_calls(
 
     if modified {
         if *env::ELASTIC_APM_PROFILER_LOG_IL {
-            let modified_il = helpers::get_il_codes(
+            let modified_il = helpers::format_il(
                 "IL modification for caller: ",
+                "modified",
                 &method,
                 caller,
                 module_metadata,
             );
-            log::debug!("{}\n{}", original_il.unwrap_or_default(), modified_il);
+            log::debug!(
+                target: logging::IL_LOG_TARGET,
+                "{}\n{}",
+                original_il.unwrap_or_default(),
+                modified_il
+            );
+        }
+
+        if !verify_nop_padding(&original_instructions, &method.instructions_to_bytes()) {
+            log::warn!(
+                "process_replacement_calls: inconsistent nop padding in il for caller_name={}()",
+                caller.full_name()
+            );
         }
 
         let method_bytes = method.into_bytes();
@@ -8,12 +8,15 @@ use crate::{
         CorExceptionFlag, FatSectionClause, Instruction, Method, Operand::ShortInlineBrTarget, RET,
     },
     ffi::{
-        mdMethodDef, mdTokenNil, mdTypeSpecNil, CorCallingConvention, FunctionID, ModuleID, ReJITID,
+        mdMemberRef, mdMethodDef, mdTokenNil, mdTypeSpecNil, CorCallingConvention, FunctionID,
+        ModuleID, ReJITID, COR_PRF_CODEGEN_FLAGS,
     },
     interfaces::{ICorProfilerFunctionControl, ICorProfilerInfo4},
     profiler::{
         calltarget_tokens::CallTargetTokens,
-        env, helpers, process,
+        env, helpers, logging,
+        metrics::{self, MetricRegistry},
+        process,
         types::{
             FunctionInfo, MethodArgumentTypeFlag, MethodReplacement, ModuleMetadata,
             ModuleWrapperTokens, TypeInfo,
@@ -25,8 +28,9 @@ use log::Level;
 use std::{
     collections::HashMap,
     sync::{
+        atomic::AtomicU64,
         mpsc::{channel, Sender},
-        Mutex,
+        Arc, Mutex,
     },
     thread,
     thread::JoinHandle,
@@ -59,6 +63,7 @@ pub struct RejitHandlerModuleMethod {
     method_def: mdMethodDef,
     function_info: Option<FunctionInfo>,
     method_replacement: Option<MethodReplacement>,
+    disable_optimizations: bool,
 }
 
 impl RejitHandlerModuleMethod {
@@ -67,6 +72,7 @@ impl RejitHandlerModuleMethod {
             method_def,
             function_info: None,
             method_replacement: None,
+            disable_optimizations: false,
         }
     }
 
@@ -78,6 +84,10 @@ impl RejitHandlerModuleMethod {
         self.method_replacement = Some(method_replacement);
     }
 
+    pub fn set_disable_optimizations(&mut self, disable_optimizations: bool) {
+        self.disable_optimizations = disable_optimizations;
+    }
+
     pub fn function_info(&self) -> Option<&FunctionInfo> {
         self.function_info.as_ref()
     }
@@ -89,8 +99,29 @@ struct RejitItem {
     method_ids: Vec<mdMethodDef>,
 }
 
-pub struct RejitHandler {
+/// Enqueues methods for ReJIT by a [RejitHandler] from another thread
+#[derive(Clone)]
+pub struct RejitQueue {
     sender: Sender<RejitItem>,
+}
+
+impl RejitQueue {
+    pub fn enqueue_for_rejit(&self, module_ids: Vec<ModuleID>, method_ids: Vec<mdMethodDef>) {
+        if let Err(err) = self.sender.send(RejitItem {
+            module_ids,
+            method_ids,
+        }) {
+            log::warn!(
+                "Unable to send module_ids={:?} method_ids={:?} for rejit",
+                &err.0.method_ids,
+                &err.0.module_ids
+            );
+        }
+    }
+}
+
+pub struct RejitHandler {
+    queue: RejitQueue,
     handle: JoinHandle<()>,
     modules: HashMap<ModuleID, RejitHandlerModule>,
     modules_mutex: Mutex<()>,
@@ -128,7 +159,7 @@ impl RejitHandler {
         });
 
         Self {
-            sender,
+            queue: RejitQueue { sender },
             handle,
             modules: HashMap::new(),
             modules_mutex: Mutex::new(()),
@@ -137,24 +168,21 @@ impl RejitHandler {
 
     pub fn shutdown(self) {
         // dropping channel sender causes the channel receiver to Err and break out thread loop.
-        drop(self.sender);
+        drop(self.queue);
         match self.handle.join() {
             Ok(()) => log::trace!("rejit thread finished"),
             Err(err) => log::error!("Error in joining rejit thread"),
         }
     }
 
+    /// Gets a queue with which methods can be enqueued for ReJIT from another thread. The
+    /// rejit thread finishes on shutdown only once all queues are dropped
+    pub fn queue(&self) -> RejitQueue {
+        self.queue.clone()
+    }
+
     pub fn enqueue_for_rejit(&self, module_ids: Vec<ModuleID>, method_ids: Vec<mdMethodDef>) {
-        if let Err(err) = self.sender.send(RejitItem {
-            module_ids,
-            method_ids,
-        }) {
-            log::warn!(
-                "Unable to send module_ids={:?} method_ids={:?} for rejit",
-                &err.0.method_ids,
-                &err.0.module_ids
-            );
-        }
+        self.queue.enqueue_for_rejit(module_ids, method_ids);
     }
 
     pub fn notify_rejit_compilation_started(
@@ -298,8 +326,9 @@ pub fn calltarget_rewriter_callback(
     })?;
 
     let original_il = if *env::ELASTIC_APM_PROFILER_LOG_IL {
-        Some(helpers::get_il_codes(
+        Some(helpers::format_il(
             "IL original code for caller: ",
+            "original",
             &method,
             caller,
             module_metadata,
@@ -324,6 +353,19 @@ pub fn calltarget_rewriter_callback(
 
     let type_info = caller.type_info.as_ref().unwrap();
 
+    if metrics::call_counts_reported() {
+        let call_counter = MetricRegistry::global().counter(
+            &module_metadata.assembly_name,
+            &type_info.name,
+            &caller.name,
+        );
+        let increment = call_target_tokens.get_interlocked_increment_member_ref(module_metadata)?;
+        for instruction in increment_call_counter(&call_counter, increment) {
+            method.insert(idx, instruction).map_err(|_| S_FALSE)?;
+            idx += 1;
+        }
+    }
+
     if is_static {
         if type_info.is_value_type {
             log::warn!("calltarget_rewriter_callback: static methods on value types cannot be instrumented");
@@ -811,13 +853,19 @@ pub fn calltarget_rewriter_callback(
         })?;
 
     if *env::ELASTIC_APM_PROFILER_LOG_IL {
-        let modified_il = helpers::get_il_codes(
+        let modified_il = helpers::format_il(
             "IL modification for caller: ",
+            "modified",
             &method,
             caller,
             module_metadata,
         );
-        log::debug!("{}\n{}", original_il.unwrap_or_default(), modified_il);
+        log::debug!(
+            target: logging::IL_LOG_TARGET,
+            "{}\n{}",
+            original_il.unwrap_or_default(),
+            modified_il
+        );
     }
 
     let method_bytes = method.into_bytes();
@@ -835,6 +883,26 @@ pub fn calltarget_rewriter_callback(
             e
         })?;
 
+    // optimizations disabled globally already apply to the rejitted method
+    // a failure here leaves the new IL in place, so the rewrite carries on with optimizations
+    if rejit_handler_module_method.disable_optimizations && !env::disable_optimizations() {
+        match function_control
+            .set_codegen_flags(COR_PRF_CODEGEN_FLAGS::COR_PRF_CODEGEN_DISABLE_ALL_OPTIMIZATIONS)
+        {
+            Ok(_) => log::debug!(
+                "calltarget_rewriter_callback: disabled optimizations for {}()",
+                caller.full_name()
+            ),
+            Err(e) => log::warn!(
+                "calltarget_rewriter_callback: failed to disable optimizations for \
+            module_id={} function_token={}. 0x{:X}",
+                module_id,
+                function_token,
+                e
+            ),
+        }
+    }
+
     log::info!("calltarget_rewriter_callback: finished {}() [is_void={}, is_static={}, integration_type={}, arguments={}]",
         caller.full_name(),
         is_void,
@@ -846,6 +914,18 @@ pub fn calltarget_rewriter_callback(
     Ok(())
 }
 
+/// Instructions that atomically increment the call counter, by passing the address of the
+/// counter to `Interlocked.Increment(ref long)`. The counter is kept alive by the
+/// [MetricRegistry] for the lifetime of the process
+fn increment_call_counter(counter: &Arc<AtomicU64>, increment: mdMemberRef) -> Vec<Instruction> {
+    vec![
+        Instruction::ldc_i8(Arc::as_ptr(counter) as i64),
+        Instruction::conv_i(),
+        Instruction::call(increment),
+        Instruction::pop(),
+    ]
+}
+
 fn log_caller_type_info(caller: &FunctionInfo, type_info: &TypeInfo) {
     let mut s = vec![
         format!("caller type.id: {}", caller.id),
@@ -0,0 +1,133 @@
+// Licensed to Elasticsearch B.V under
+// one or more agreements.
+// Elasticsearch B.V licenses this file to you under the Apache 2.0 License.
+// See the LICENSE file in the project root for more information
+
+use crate::profiler::{
+    env,
+    helpers::flatten_integrations,
+    types::{Integration, IntegrationMethod},
+};
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{channel, RecvTimeoutError, Sender},
+        Arc, RwLock,
+    },
+    thread,
+    thread::JoinHandle,
+    time::{Duration, SystemTime},
+};
+
+/// The interval at which watched integrations files are checked for changes
+const WATCH_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Periodically reloads integrations on a background thread, replacing the
+/// integration methods used for modules loaded after the reload.
+pub struct IntegrationsReloader {
+    sender: Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+impl IntegrationsReloader {
+    pub fn new(
+        mut integrations: Vec<Integration>,
+        integration_methods: Arc<RwLock<Vec<IntegrationMethod>>>,
+        calltarget_enabled: bool,
+        interval: Duration,
+    ) -> Self {
+        let (sender, receiver) = channel::<()>();
+        let handle = thread::spawn(move || {
+            // dropping the sender disconnects the channel and stops reloading
+            while let Err(RecvTimeoutError::Timeout) = receiver.recv_timeout(interval) {
+                match env::reload_integrations(&mut integrations) {
+                    Ok(()) => {
+                        let methods =
+                            flatten_integrations(integrations.clone(), calltarget_enabled);
+                        log::debug!(
+                            "IntegrationsReloader: replacing integration methods with {} method(s)",
+                            methods.len()
+                        );
+                        *integration_methods.write().unwrap() = methods;
+                    }
+                    Err(e) => log::warn!(
+                        "IntegrationsReloader: could not reload integrations: {}. \
+                        keeping current integrations",
+                        e
+                    ),
+                }
+            }
+        });
+
+        Self { sender, handle }
+    }
+
+    pub fn shutdown(self) {
+        drop(self.sender);
+        match self.handle.join() {
+            Ok(()) => log::trace!("integrations reloader thread finished"),
+            Err(_) => log::error!("Error in joining integrations reloader thread"),
+        }
+    }
+}
+
+/// Watches an integrations file on a background thread, reloading integrations
+/// when the file's modification time changes.
+pub struct IntegrationsWatcher {
+    sender: Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+impl IntegrationsWatcher {
+    pub fn shutdown(self) {
+        drop(self.sender);
+        match self.handle.join() {
+            Ok(()) => log::trace!("integrations watcher thread finished"),
+            Err(_) => log::error!("Error in joining integrations watcher thread"),
+        }
+    }
+}
+
+/// Starts watching the integrations file at the given path. When the file changes,
+/// integrations are reloaded into `integrations`, and `on_reload` is called with them.
+pub fn watch_integrations_file(
+    path: PathBuf,
+    integrations: Arc<RwLock<Vec<Integration>>>,
+    on_reload: impl Fn(&[Integration]) + Send + 'static,
+) -> IntegrationsWatcher {
+    let (sender, receiver) = channel::<()>();
+    let handle = thread::spawn(move || {
+        let mut last_modified = modified_time(&path);
+        // dropping the sender disconnects the channel and stops watching
+        while let Err(RecvTimeoutError::Timeout) = receiver.recv_timeout(WATCH_POLL_INTERVAL) {
+            let modified = modified_time(&path);
+            if modified == last_modified {
+                continue;
+            }
+            last_modified = modified;
+
+            let mut integrations = integrations.write().unwrap();
+            match env::reload_integrations(&mut integrations) {
+                Ok(()) => {
+                    log::info!(
+                        "IntegrationsWatcher: reloaded integrations after {} changed",
+                        path.display()
+                    );
+                    on_reload(&integrations);
+                }
+                Err(e) => log::warn!(
+                    "IntegrationsWatcher: could not reload integrations after {} changed: {}. \
+                    keeping current integrations",
+                    path.display(),
+                    e
+                ),
+            }
+        }
+    });
+
+    IntegrationsWatcher { sender, handle }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
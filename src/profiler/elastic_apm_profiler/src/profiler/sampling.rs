@@ -0,0 +1,146 @@
+// Licensed to Elasticsearch B.V under
+// one or more agreements.
+// Elasticsearch B.V licenses this file to you under the Apache 2.0 License.
+// See the LICENSE file in the project root for more information
+
+//! Sampling of captured exceptions.
+//!
+//! Exceptions of types not matched by `ELASTIC_APM_PROFILER_EXCEPTION_FILTER_TYPES` are not
+//! captured, and `ELASTIC_APM_PROFILER_EXCEPTION_SAMPLING_RATE` is the fraction of the rest
+//! that are captured. Each decision draws from a per-thread xorshift generator, so that sampling
+//! takes no lock on high-throughput error paths.
+//!
+//! Captured exceptions are counted by type in [CapturedExceptions], and the most captured types
+//! are logged with the profiler metrics.
+
+use crate::profiler::{env, metrics};
+use once_cell::sync::Lazy;
+use std::{
+    cell::Cell,
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hasher},
+    sync::Mutex,
+};
+
+static CAPTURED_EXCEPTIONS: Lazy<CapturedExceptions> = Lazy::new(CapturedExceptions::default);
+
+/// The number of captured exceptions, keyed by the fully qualified name of the exception type
+#[derive(Debug, Default)]
+pub struct CapturedExceptions {
+    counts: Mutex<HashMap<String, u64>>,
+}
+
+impl CapturedExceptions {
+    /// Gets the captured exceptions of the profiler
+    pub fn global() -> &'static CapturedExceptions {
+        &CAPTURED_EXCEPTIONS
+    }
+
+    /// Records a captured exception of the type
+    pub fn record(&self, type_name: &str) {
+        let mut counts = self.counts.lock().unwrap();
+        match counts.get_mut(type_name) {
+            Some(count) => *count += 1,
+            None => {
+                counts.insert(type_name.to_string(), 1);
+            }
+        }
+    }
+
+    /// Gets the `n` most captured exception types, most captured first
+    pub fn top(&self, n: usize) -> Vec<(String, u64)> {
+        let mut counts: Vec<(String, u64)> = self
+            .counts
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(type_name, count)| (type_name.clone(), *count))
+            .collect();
+        counts.sort_by(|(a_type, a_count), (b_type, b_count)| {
+            b_count.cmp(a_count).then_with(|| a_type.cmp(b_type))
+        });
+        counts.truncate(n);
+        counts
+    }
+}
+
+thread_local! {
+    static RNG_STATE: Cell<u64> = Cell::new(rng_seed());
+}
+
+/// Seeds the generator of the current thread. The state of a xorshift generator must not
+/// be zero
+fn rng_seed() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_usize(thread_id::get());
+    hasher.finish() | 1
+}
+
+/// Gets a random number in `[0.0, 1.0)` from the generator of the current thread
+fn next_random() -> f64 {
+    RNG_STATE.with(|state| {
+        let mut x = state.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        state.set(x);
+        // the 53 most significant bits fill the mantissa of an f64
+        (x >> 11) as f64 / (1u64 << 53) as f64
+    })
+}
+
+/// Whether to capture an exception of the type. Exceptions of types that are capturable are
+/// sampled at the configured exception sampling rate. Filtered exceptions and each sampling
+/// decision are counted in the profiler metrics
+pub fn should_capture_exception(type_name: &str) -> bool {
+    if !env::exception_type_is_capturable(type_name) {
+        metrics::record_exception_filtered();
+        return false;
+    }
+
+    let captured = should_capture(env::get_exception_sampling_rate(), next_random);
+    metrics::record_exception_sampled(captured);
+    captured
+}
+
+fn should_capture(rate: f64, random: impl FnOnce() -> f64) -> bool {
+    if rate >= 1.0 {
+        true
+    } else if rate <= 0.0 {
+        false
+    } else {
+        random() < rate
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::profiler::sampling::{next_random, should_capture, CapturedExceptions};
+
+    #[test]
+    fn captured_exceptions_counts_by_type() {
+        let captured = CapturedExceptions::default();
+        captured.record("System.InvalidOperationException");
+        captured.record("System.ArgumentException");
+        captured.record("System.InvalidOperationException");
+
+        assert_eq!(
+            captured.top(1),
+            vec![("System.InvalidOperationException".to_string(), 2)]
+        );
+        assert_eq!(captured.top(5).len(), 2);
+    }
+
+    #[test]
+    fn should_capture_samples_at_rate() {
+        assert!(should_capture(1.0, || unreachable!()));
+        assert!(!should_capture(0.0, || unreachable!()));
+        assert!(should_capture(0.5, || 0.25));
+        assert!(!should_capture(0.5, || 0.5));
+
+        let captured = (0..10_000)
+            .filter(|_| should_capture(0.1, next_random))
+            .count();
+        assert!((500..1500).contains(&captured), "captured {}", captured);
+    }
+}
@@ -17,15 +17,12 @@ fn parse_type_def_or_ref_encoded(signature: &[u8]) -> Option<(ULONG, usize)> {
 }
 
 fn parse_custom_mod(signature: &[u8]) -> Option<usize> {
-    if let Some(cor_element_type) = CorElementType::from_u8(signature[0]) {
+    if let Some(cor_element_type) = CorElementType::from_u8(*signature.first()?) {
         match cor_element_type {
             CorElementType::ELEMENT_TYPE_CMOD_OPT | CorElementType::ELEMENT_TYPE_CMOD_REQD => {
                 let idx = 1_usize;
-                if let Some((_, token_idx)) = parse_type_def_or_ref_encoded(&signature[idx..]) {
-                    Some(idx + token_idx)
-                } else {
-                    Some(idx)
-                }
+                parse_type_def_or_ref_encoded(&signature[idx..])
+                    .map(|(_, token_idx)| idx + token_idx)
             }
             _ => None,
         }
@@ -38,10 +35,10 @@ fn parse_optional_custom_mods(signature: &[u8]) -> Option<usize> {
     let mut idx = 0;
 
     loop {
-        if let Some(cor_element_type) = CorElementType::from_u8(signature[idx]) {
+        if let Some(cor_element_type) = CorElementType::from_u8(*signature.get(idx)?) {
             match cor_element_type {
                 CorElementType::ELEMENT_TYPE_CMOD_OPT | CorElementType::ELEMENT_TYPE_CMOD_REQD => {
-                    if let Some(mod_idx) = parse_custom_mod(signature) {
+                    if let Some(mod_idx) = parse_custom_mod(&signature[idx..]) {
                         idx += mod_idx;
                     } else {
                         return None;
@@ -102,9 +99,9 @@ pub fn parse_number(signature: &[u8]) -> Option<(ULONG, usize)> {
     Some((out, 4_usize))
 }
 
-fn parse_return_type(signature: &[u8]) -> Option<usize> {
+pub fn parse_return_type(signature: &[u8]) -> Option<usize> {
     if let Some(mut idx) = parse_optional_custom_mods(signature) {
-        if let Some(cor_element_type) = CorElementType::from_u8(signature[idx]) {
+        if let Some(cor_element_type) = CorElementType::from_u8(*signature.get(idx)?) {
             match cor_element_type {
                 CorElementType::ELEMENT_TYPE_TYPEDBYREF | CorElementType::ELEMENT_TYPE_VOID => {
                     idx += 1;
@@ -126,7 +123,7 @@ fn parse_return_type(signature: &[u8]) -> Option<usize> {
 fn parse_method(signature: &[u8]) -> Option<usize> {
     let mut idx = 0;
     if let Some(CorCallingConvention::IMAGE_CEE_CS_CALLCONV_GENERIC) =
-        CorCallingConvention::from_bits(signature[idx])
+        CorCallingConvention::from_bits(*signature.get(idx)?)
     {
         idx += 1;
         if let Some((_, gen_idx)) = parse_number(&signature[idx..]) {
@@ -152,7 +149,8 @@ fn parse_method(signature: &[u8]) -> Option<usize> {
 
     let mut sentinel_found = false;
     for _ in 0..params {
-        if let Some(CorElementType::ELEMENT_TYPE_SENTINEL) = CorElementType::from_u8(signature[idx])
+        if let Some(CorElementType::ELEMENT_TYPE_SENTINEL) =
+            CorElementType::from_u8(*signature.get(idx)?)
         {
             if sentinel_found {
                 return None;
@@ -174,7 +172,7 @@ fn parse_method(signature: &[u8]) -> Option<usize> {
 
 fn parse_param(signature: &[u8]) -> Option<usize> {
     if let Some(mut idx) = parse_optional_custom_mods(signature) {
-        if let Some(cor_element_type) = CorElementType::from_u8(signature[idx]) {
+        if let Some(cor_element_type) = CorElementType::from_u8(*signature.get(idx)?) {
             match cor_element_type {
                 CorElementType::ELEMENT_TYPE_TYPEDBYREF => {
                     idx += 1;
@@ -237,7 +235,7 @@ fn parse_array_shape(signature: &[u8]) -> Option<usize> {
 
 pub fn parse_type(signature: &[u8]) -> Option<usize> {
     let mut idx = 0;
-    if let Some(cor_element_type) = CorElementType::from_u8(signature[idx]) {
+    if let Some(cor_element_type) = CorElementType::from_u8(*signature.get(idx)?) {
         idx += 1;
 
         match cor_element_type {
@@ -260,7 +258,7 @@ pub fn parse_type(signature: &[u8]) -> Option<usize> {
                 if let Some(mods_idx) = parse_optional_custom_mods(&signature[idx..]) {
                     idx += mods_idx;
                     if let Some(CorElementType::ELEMENT_TYPE_VOID) =
-                        CorElementType::from_u8(signature[idx])
+                        CorElementType::from_u8(*signature.get(idx)?)
                     {
                         idx += 1;
                         Some(idx)
@@ -293,7 +291,7 @@ pub fn parse_type(signature: &[u8]) -> Option<usize> {
                 }
             }
             CorElementType::ELEMENT_TYPE_GENERICINST => {
-                if let Some(elem_type) = CorElementType::from_u8(signature[idx]) {
+                if let Some(elem_type) = CorElementType::from_u8(*signature.get(idx)?) {
                     match elem_type {
                         CorElementType::ELEMENT_TYPE_VALUETYPE
                         | CorElementType::ELEMENT_TYPE_CLASS => {
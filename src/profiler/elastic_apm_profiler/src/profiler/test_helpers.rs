@@ -0,0 +1,195 @@
+// Licensed to Elasticsearch B.V under
+// one or more agreements.
+// Elasticsearch B.V licenses this file to you under the Apache 2.0 License.
+// See the LICENSE file in the project root for more information
+
+//! Helpers for unit tests that read environment variables or write temporary files.
+//!
+//! Environment variables are process wide, so tests running in parallel that set them can
+//! observe each other's values. [with_env_var] and [without_env_var] serialize changes to
+//! environment variables across tests, and restore the original value when done.
+//! [TempPath] names a file or directory in the system temp directory that is removed when
+//! the test ends, including when it fails.
+
+use std::{
+    cell::Cell,
+    ffi::OsString,
+    ops::Deref,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+};
+
+/// Held by the test currently changing environment variables
+static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+thread_local! {
+    /// Whether the current thread holds [ENV_LOCK], so that helpers can be nested
+    static HOLDS_ENV_LOCK: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Calls `f` with the environment variable `key` set to `value`
+pub fn with_env_var<T>(key: &str, value: &str, f: impl FnOnce() -> T) -> T {
+    with_env_var_value(key, Some(value), f)
+}
+
+/// Calls `f` with each of the environment variables in `vars` set to its value
+pub fn with_env_vars<T>(vars: &[(&str, &str)], f: impl FnOnce() -> T) -> T {
+    match vars.split_first() {
+        Some(((key, value), rest)) => with_env_var(key, value, || with_env_vars(rest, f)),
+        None => f(),
+    }
+}
+
+/// Gets the value of a variable from `vars`, in place of reading the environment
+pub fn get_var_from<'a>(vars: &'a [(&'a str, &'a str)]) -> impl Fn(&str) -> Option<String> + 'a {
+    move |key| {
+        vars.iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| v.to_string())
+    }
+}
+
+/// Calls `f` with the environment variable `key` not set
+pub fn without_env_var<T>(key: &str, f: impl FnOnce() -> T) -> T {
+    with_env_var_value(key, None, f)
+}
+
+fn with_env_var_value<T>(key: &str, value: Option<&str>, f: impl FnOnce() -> T) -> T {
+    let _guard = lock_env();
+    let original = std::env::var_os(key);
+    set_env_var(key, value.map(OsString::from));
+
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+
+    set_env_var(key, original);
+    match result {
+        Ok(result) => result,
+        Err(panic) => panic::resume_unwind(panic),
+    }
+}
+
+fn set_env_var(key: &str, value: Option<OsString>) {
+    match value {
+        Some(value) => std::env::set_var(key, value),
+        None => std::env::remove_var(key),
+    }
+}
+
+/// Releases [ENV_LOCK] when dropped, if it was acquired
+struct EnvLockGuard(Option<MutexGuard<'static, ()>>);
+
+impl Drop for EnvLockGuard {
+    fn drop(&mut self) {
+        if self.0.take().is_some() {
+            HOLDS_ENV_LOCK.with(|holds| holds.set(false));
+        }
+    }
+}
+
+fn lock_env() -> EnvLockGuard {
+    if HOLDS_ENV_LOCK.with(Cell::get) {
+        return EnvLockGuard(None);
+    }
+
+    // a panic while holding the lock is resumed after restoring the environment,
+    // so a poisoned lock does not leave the environment in a bad state
+    let guard = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    HOLDS_ENV_LOCK.with(|holds| holds.set(true));
+    EnvLockGuard(Some(guard))
+}
+
+/// A path in the system temp directory, unique to the test process, whose file or
+/// directory is removed when dropped. The path is not created
+#[derive(Debug)]
+pub struct TempPath(PathBuf);
+
+impl TempPath {
+    /// A path named `elastic_apm_profiler_{name}_{pid}` in the system temp directory, or
+    /// `elastic_apm_profiler_{name}_{pid}.{extension}` when `name` has an extension
+    pub fn new(name: &str) -> Self {
+        let name = Path::new(name);
+        let mut file_name = format!(
+            "elastic_apm_profiler_{}_{}",
+            name.file_stem().unwrap_or_default().to_string_lossy(),
+            std::process::id()
+        );
+        if let Some(extension) = name.extension() {
+            file_name = format!("{}.{}", file_name, extension.to_string_lossy());
+        }
+        Self(std::env::temp_dir().join(file_name))
+    }
+}
+
+impl Deref for TempPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempPath {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        let _ = if self.0.is_dir() {
+            std::fs::remove_dir_all(&self.0)
+        } else {
+            std::fs::remove_file(&self.0)
+        };
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::profiler::test_helpers::{with_env_var, without_env_var, TempPath};
+    use std::panic;
+
+    #[test]
+    fn temp_path_is_removed_when_dropped() {
+        let file = TempPath::new("test_helpers_file.txt");
+        let dir = TempPath::new("test_helpers_dir");
+        assert!(file
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .ends_with(&format!("_{}.txt", std::process::id())));
+
+        std::fs::write(&file, "file").unwrap();
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+        let (file_path, dir_path) = (file.to_path_buf(), dir.to_path_buf());
+        drop(file);
+        drop(dir);
+
+        assert!(!file_path.exists());
+        assert!(!dir_path.exists());
+    }
+
+    #[test]
+    fn with_env_var_restores_original_value() {
+        const KEY: &str = "ELASTIC_APM_PROFILER_TEST_HELPERS_RESTORE";
+        with_env_var(KEY, "original", || {
+            with_env_var(KEY, "changed", || {
+                assert_eq!(std::env::var(KEY).unwrap(), "changed");
+            });
+            assert_eq!(std::env::var(KEY).unwrap(), "original");
+
+            without_env_var(KEY, || assert!(std::env::var(KEY).is_err()));
+            assert_eq!(std::env::var(KEY).unwrap(), "original");
+        });
+        assert!(std::env::var(KEY).is_err());
+    }
+
+    #[test]
+    fn with_env_var_restores_original_value_on_panic() {
+        const KEY: &str = "ELASTIC_APM_PROFILER_TEST_HELPERS_PANIC";
+        let result = panic::catch_unwind(|| with_env_var(KEY, "panicked", || panic!("test")));
+        assert!(result.is_err());
+        assert!(std::env::var(KEY).is_err());
+    }
+}
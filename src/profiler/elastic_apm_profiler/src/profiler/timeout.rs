@@ -0,0 +1,109 @@
+// Licensed to Elasticsearch B.V under
+// one or more agreements.
+// Elasticsearch B.V licenses this file to you under the Apache 2.0 License.
+// See the LICENSE file in the project root for more information
+
+//! Runs operations that can block, such as file I/O on network file systems, with a timeout.
+
+use crate::profiler::error::{ProfilerError, ProfilerErrorKind};
+use std::{
+    any::Any,
+    error::Error,
+    fmt::{self, Display, Formatter},
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::Duration,
+};
+
+/// The error returned when an operation does not complete within its timeout
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct TimeoutError {
+    /// The timeout in milliseconds
+    pub ms: u64,
+}
+
+impl Display for TimeoutError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "did not complete within {} ms", self.ms)
+    }
+}
+
+impl Error for TimeoutError {}
+
+/// Runs `f` on a new thread, waiting at most `ms` milliseconds for it to return.
+/// `operation` describes `f` in the returned errors.
+///
+/// When the timeout elapses, [ProfilerErrorKind::Timeout] is returned and the thread is left
+/// to run to completion in the background, with its result discarded. A panic in `f` is
+/// returned as [ProfilerErrorKind::Panicked], so that it does not unwind into the caller,
+/// and a thread that can't be spawned as [ProfilerErrorKind::Thread].
+pub fn with_timeout<T: Send + 'static>(
+    operation: &str,
+    ms: u64,
+    f: impl FnOnce() -> T + Send + 'static,
+) -> Result<T, ProfilerError> {
+    let (sender, receiver) = mpsc::channel();
+    let handle = thread::Builder::new()
+        .name("elastic_apm_profiler_timeout".into())
+        .spawn(move || {
+            // the receiver is dropped when the timeout elapses
+            let _ = sender.send(f());
+        })
+        .map_err(|e| ProfilerError::with_source(ProfilerErrorKind::Thread(operation.into()), e))?;
+
+    match receiver.recv_timeout(Duration::from_millis(ms)) {
+        Ok(value) => Ok(value),
+        Err(RecvTimeoutError::Timeout) => Err(ProfilerError::with_source(
+            ProfilerErrorKind::Timeout(operation.into()),
+            TimeoutError { ms },
+        )),
+        Err(RecvTimeoutError::Disconnected) => match handle.join() {
+            Err(panic) => Err(ProfilerError::with_source(
+                ProfilerErrorKind::Panicked(operation.into()),
+                panic_message(panic.as_ref()),
+            )),
+            Ok(()) => unreachable!("thread completed without sending a result"),
+        },
+    }
+}
+
+/// Gets the message of a panic, which is a `&str` or a `String` for panics raised with
+/// `panic!`
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    match panic.downcast_ref::<&str>() {
+        Some(message) => message.to_string(),
+        None => match panic.downcast_ref::<String>() {
+            Some(message) => message.clone(),
+            None => "unknown panic".into(),
+        },
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::profiler::{error::ProfilerErrorKind, timeout::with_timeout};
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn with_timeout_returns_result_of_completed_operation() {
+        assert_eq!(with_timeout("answering", 5000, || 42).unwrap(), 42);
+    }
+
+    #[test]
+    fn with_timeout_returns_error_when_operation_is_slow() {
+        let error =
+            with_timeout("sleeping", 10, || thread::sleep(Duration::from_secs(1))).unwrap_err();
+        assert_eq!(error.kind(), &ProfilerErrorKind::Timeout("sleeping".into()));
+        assert_eq!(
+            error.to_string(),
+            "sleeping timed out: did not complete within 10 ms"
+        );
+    }
+
+    #[test]
+    fn with_timeout_returns_panic_of_operation_as_error() {
+        let error = with_timeout("failing", 5000, || -> () { panic!("failed {}", 1) }).unwrap_err();
+        assert_eq!(error.kind(), &ProfilerErrorKind::Panicked("failing".into()));
+        assert_eq!(error.to_string(), "failing panicked: failed 1");
+    }
+}
@@ -15,7 +15,7 @@ use crate::{
     interfaces::{
         IMetaDataAssemblyEmit, IMetaDataAssemblyImport, IMetaDataEmit2, IMetaDataImport2,
     },
-    profiler::sig::parse_number,
+    profiler::sig::{parse_number, parse_return_type},
 };
 use com::sys::{GUID, HRESULT};
 use core::fmt;
@@ -24,7 +24,7 @@ use num_traits::FromPrimitive;
 use serde::{
     de,
     de::{DeserializeOwned, Visitor},
-    Deserialize, Deserializer,
+    Deserialize, Deserializer, Serialize, Serializer,
 };
 use std::{
     cmp::Ordering,
@@ -36,6 +36,7 @@ use std::{
 };
 use widestring::U16CString;
 
+#[derive(Debug, Eq, PartialEq, Clone)]
 pub(crate) struct ModuleInfo {
     pub id: ModuleID,
     pub path: String,
@@ -49,6 +50,7 @@ impl ModuleInfo {
     }
 }
 
+#[derive(Debug, Eq, PartialEq, Clone)]
 pub(crate) struct AssemblyInfo {
     pub id: AssemblyID,
     pub name: String,
@@ -57,7 +59,7 @@ pub(crate) struct AssemblyInfo {
     pub app_domain_name: String,
 }
 
-#[derive(Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, Eq, PartialEq, Hash, Clone)]
 pub struct MethodSignature {
     data: Vec<u8>,
 }
@@ -132,6 +134,61 @@ impl MethodSignature {
         self.calling_convention()
             .contains(CorCallingConvention::IMAGE_CEE_CS_CALLCONV_HASTHIS)
     }
+
+    /// Decodes the calling convention, the number of type arguments and parameters, and the
+    /// return type of the signature
+    pub fn parse(&self) -> Result<ParsedSignature, SignatureError> {
+        let (&first, rest) = self.data.split_first().ok_or(SignatureError::Empty)?;
+        let calling_convention = CorCallingConvention::from_bits(first)
+            .ok_or(SignatureError::InvalidCallingConvention(first))?;
+
+        let mut idx = 0;
+        let type_arguments_len = if calling_convention.is_generic() {
+            let (number, len) = parse_number(rest).ok_or(SignatureError::InvalidTypeArguments)?;
+            idx += len;
+            number
+        } else {
+            0
+        };
+
+        let (parameters_len, len) =
+            parse_number(&rest[idx..]).ok_or(SignatureError::InvalidParameters)?;
+        idx += len;
+
+        let return_type = &rest[idx..];
+        if return_type.is_empty() {
+            return Err(SignatureError::InvalidReturnType);
+        }
+        let return_type_len =
+            parse_return_type(return_type).ok_or(SignatureError::InvalidReturnType)?;
+
+        Ok(ParsedSignature {
+            calling_convention,
+            type_arguments_len,
+            parameters_len,
+            return_type: return_type[..return_type_len].to_vec(),
+        })
+    }
+}
+
+/// The decoded header of a [MethodSignature]
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct ParsedSignature {
+    pub calling_convention: CorCallingConvention,
+    pub type_arguments_len: ULONG,
+    pub parameters_len: ULONG,
+    /// The signature bytes of the return type
+    pub return_type: Vec<u8>,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum SignatureError {
+    Empty,
+    InvalidCallingConvention(u8),
+    InvalidTypeArguments,
+    InvalidParameters,
+    InvalidReturnType,
 }
 
 struct MethodSignatureVisitor;
@@ -166,6 +223,21 @@ impl<'de> Deserialize<'de> for MethodSignature {
     }
 }
 
+impl Serialize for MethodSignature {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let hex = self
+            .data
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect::<Vec<_>>()
+            .join(" ");
+        serializer.serialize_str(&hex)
+    }
+}
+
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub struct AssemblyReference {
     pub name: String,
@@ -240,6 +312,15 @@ impl<'de> Deserialize<'de> for AssemblyReference {
     }
 }
 
+impl Serialize for AssemblyReference {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
 /// deserializes any type that implements FromStr from a str
 pub(crate) fn deserialize_from_str<'de, T, D>(deserializer: D) -> Result<T, D::Error>
 where
@@ -274,6 +355,67 @@ where
     deserializer.deserialize_str(String(PhantomData))
 }
 
+/// The name of an assembly, used to match assemblies against integration targets
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct AssemblyName {
+    pub name: String,
+    /// The version of the assembly. A `*` component matches any value of the component
+    pub version: Option<Version>,
+    pub public_key_token: Option<PublicKeyToken>,
+}
+
+impl AssemblyName {
+    pub fn new<S: Into<String>>(
+        name: S,
+        version: Option<Version>,
+        public_key_token: Option<PublicKeyToken>,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            version,
+            public_key_token,
+        }
+    }
+
+    /// Whether the candidate assembly matches this name. An absent version or public key
+    /// token matches any version or public key token of the candidate
+    pub fn matches(&self, candidate: &AssemblyName) -> bool {
+        if self.name != candidate.name {
+            return false;
+        }
+
+        if let Some(version) = &self.version {
+            let candidate_version = match &candidate.version {
+                Some(v) => v,
+                None => return false,
+            };
+            let component_matches =
+                |component: u16, candidate: u16| component == u16::MAX || component == candidate;
+            if !component_matches(version.major, candidate_version.major)
+                || !component_matches(version.minor, candidate_version.minor)
+                || !component_matches(version.build, candidate_version.build)
+                || !component_matches(version.revision, candidate_version.revision)
+            {
+                return false;
+            }
+        }
+
+        match (&self.public_key_token, &candidate.public_key_token) {
+            (None, _) => true,
+            (Some(token), Some(candidate_token)) => {
+                token.0.eq_ignore_ascii_case(&candidate_token.0)
+            }
+            (Some(_), None) => false,
+        }
+    }
+}
+
+impl From<&str> for AssemblyName {
+    fn from(name: &str) -> Self {
+        AssemblyName::new(name, None, None)
+    }
+}
+
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub struct PublicKeyToken(String);
 
@@ -287,7 +429,7 @@ impl PublicKeyToken {
     }
 }
 
-#[derive(Debug, Eq, PartialEq, Deserialize, Clone)]
+#[derive(Debug, Eq, PartialEq, Deserialize, Serialize, Clone)]
 pub struct CallerMethodReference {
     pub(crate) assembly: String,
     #[serde(rename = "type")]
@@ -296,7 +438,7 @@ pub struct CallerMethodReference {
     pub(crate) method_name: String,
 }
 
-#[derive(Debug, Eq, PartialEq, Deserialize, Clone)]
+#[derive(Debug, Eq, PartialEq, Deserialize, Serialize, Clone)]
 pub struct WrapperMethodReference {
     pub(crate) assembly: AssemblyReference,
     #[serde(rename = "type")]
@@ -308,7 +450,7 @@ pub struct WrapperMethodReference {
     pub(crate) method_signature: Option<MethodSignature>,
 }
 
-#[derive(Debug, Eq, PartialEq, Deserialize, Clone)]
+#[derive(Debug, Eq, PartialEq, Deserialize, Serialize, Clone)]
 pub enum WrapperMethodAction {
     CallTargetModification,
     ReplaceTargetMethod,
@@ -332,7 +474,7 @@ impl WrapperMethodReference {
     }
 }
 
-#[derive(Debug, Eq, PartialEq, Deserialize, Clone)]
+#[derive(Debug, Eq, PartialEq, Deserialize, Serialize, Clone)]
 pub struct TargetMethodReference {
     assembly: String,
     #[serde(rename = "type")]
@@ -369,6 +511,16 @@ where
     deserializer.deserialize_str(VersionVisitor)
 }
 
+/// deserializes an optional [Version], defaulting any missing values to [u16::MAX]
+fn deserialize_optional_max_version<'de, D>(deserializer: D) -> Result<Option<Version>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|v| Version::parse(&v, u16::MAX).map_err(|e| de::Error::custom(format!("{:?}", e))))
+        .transpose()
+}
+
 fn version_max() -> Version {
     Version::MAX
 }
@@ -376,6 +528,23 @@ fn version_min() -> Version {
     Version::MIN
 }
 
+impl Display for TargetMethodReference {
+    /// Formats the target as `Type::Method (target: Assembly vMin+)`, or with
+    /// `vMin-Max` when the target has a maximum version
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}::{} (target: {} v{}",
+            &self.type_name, &self.method_name, &self.assembly, &self.minimum_version
+        )?;
+        if self.maximum_version == Version::MAX {
+            f.write_str("+)")
+        } else {
+            write!(f, "-{})", &self.maximum_version)
+        }
+    }
+}
+
 impl TargetMethodReference {
     pub fn assembly(&self) -> &str {
         &self.assembly
@@ -394,7 +563,8 @@ impl TargetMethodReference {
     }
 
     pub fn is_valid_for_assembly(&self, assembly_name: &str, version: &Version) -> bool {
-        if self.assembly != assembly_name {
+        let candidate = AssemblyName::new(assembly_name, Some(version.clone()), None);
+        if !AssemblyName::from(self.assembly.as_str()).matches(&candidate) {
             return false;
         }
 
@@ -411,7 +581,7 @@ impl TargetMethodReference {
 }
 
 /// The method replacement
-#[derive(Debug, Eq, PartialEq, Deserialize, Clone)]
+#[derive(Debug, Eq, PartialEq, Deserialize, Serialize, Clone)]
 pub struct MethodReplacement {
     /// The caller
     #[serde(default)]
@@ -435,9 +605,19 @@ impl MethodReplacement {
     pub fn wrapper(&self) -> Option<&WrapperMethodReference> {
         self.wrapper.as_ref()
     }
+
+    /// Whether the method is instrumented when CallTarget instrumentation is
+    /// `calltarget_enabled`. CallTarget wrappers are instrumented only when it is enabled,
+    /// and other wrappers only when it is disabled
+    pub fn is_instrumented(&self, calltarget_enabled: bool) -> bool {
+        matches!(&self.wrapper, Some(wrapper)
+            if (wrapper.action == WrapperMethodAction::CallTargetModification) == calltarget_enabled)
+    }
 }
 
-/// Deserializes a T to Option::Some(T) and an empty struct to Option::None
+/// Deserializes a T to Option::Some(T) and an empty struct or null to Option::None.
+/// Formats that are not human readable, such as the integrations cache, can't be
+/// deserialized untagged, and are deserialized as an Option<T>
 fn empty_struct_is_none<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
 where
     T: DeserializeOwned,
@@ -448,27 +628,250 @@ where
     enum EmptyOption<T> {
         Data(T),
         Empty {},
+        Null,
+    }
+
+    if !deserializer.is_human_readable() {
+        return Option::deserialize(deserializer);
     }
 
     match EmptyOption::deserialize(deserializer)? {
         EmptyOption::Data(data) => Ok(Some(data)),
-        EmptyOption::Empty {} => Ok(None),
+        EmptyOption::Empty {} | EmptyOption::Null => Ok(None),
     }
 }
 
-#[derive(Debug, Eq, PartialEq, Deserialize, Clone)]
+#[derive(Debug, Eq, PartialEq, Deserialize, Serialize, Clone)]
 pub struct IntegrationMethod {
     pub(crate) name: String,
     pub(crate) method_replacement: MethodReplacement,
+    /// Overrides the global JIT inlining setting for the target method
+    #[serde(default)]
+    pub(crate) enable_inlining: Option<bool>,
+    /// Disables JIT optimizations of the target method when optimizations are not
+    /// disabled globally
+    #[serde(default)]
+    pub(crate) disable_optimizations: Option<bool>,
 }
 
-#[derive(Debug, Eq, PartialEq, Deserialize)]
+/// The current version of the integrations schema. Integrations in an earlier version are
+/// migrated to this version before they are deserialized
+pub const INTEGRATION_SCHEMA_VERSION: u8 = 1;
+
+/// An integration read from an integrations file.
+///
+/// Only `name` is required. Optional fields that are missing take the value of the
+/// field in [Integration::default]. The assembly, type and method of the target and
+/// wrapper of each method replacement remain required.
+///
+/// Integrations are compared and ordered by the targets of their method replacements, so
+/// that integrations instrumenting the same methods are duplicates of one another.
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Integration {
+    /// The name of the integration. Required
     pub(crate) name: String,
+    /// The methods replaced by the integration. Defaults to no methods
+    #[serde(default)]
     pub(crate) method_replacements: Vec<MethodReplacement>,
+    /// Whether the integration is enabled. Defaults to `true`
+    #[serde(default = "enabled_default")]
+    pub(crate) enabled: bool,
+    /// Overrides the global JIT inlining setting for the target methods of the integration.
+    /// Defaults to [None], using the global setting
+    #[serde(default)]
+    pub(crate) enable_inlining: Option<bool>,
+    /// Disables JIT optimizations of the target methods of the integration when
+    /// optimizations are not disabled globally. Only applies to CallTarget instrumentation.
+    /// Defaults to [None], using the global setting
+    #[serde(default)]
+    pub(crate) disable_optimizations: Option<bool>,
+    /// The minimum runtime version supported by the integration, inclusive.
+    /// Defaults to [None], with no minimum
+    #[serde(default)]
+    pub(crate) min_target_framework_version: Option<Version>,
+    /// The maximum runtime version supported by the integration, inclusive.
+    /// Missing or `*` components match any value. Defaults to [None], with no maximum
+    #[serde(default, deserialize_with = "deserialize_optional_max_version")]
+    pub(crate) max_target_framework_version: Option<Version>,
+    /// The process architectures supported by the integration, one or more of `x64`, `x86`
+    /// and `arm64`. Defaults to [None], supporting all architectures
+    #[serde(default)]
+    pub(crate) supported_architectures: Option<Vec<String>>,
+    /// The version of the integrations schema that the integration is defined in.
+    /// Defaults to `1`
+    #[serde(default = "schema_version_default")]
+    pub(crate) schema_version: u8,
 }
 
-#[derive(Debug, Clone)]
+fn enabled_default() -> bool {
+    true
+}
+
+fn schema_version_default() -> u8 {
+    1
+}
+
+impl PartialEq for Integration {
+    fn eq(&self, other: &Self) -> bool {
+        self.canonical_key() == other.canonical_key()
+    }
+}
+
+impl Eq for Integration {}
+
+impl PartialOrd for Integration {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Integration {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.canonical_key().cmp(&other.canonical_key())
+    }
+}
+
+impl Default for Integration {
+    fn default() -> Self {
+        Integration {
+            name: String::new(),
+            method_replacements: Vec::new(),
+            enabled: enabled_default(),
+            enable_inlining: None,
+            disable_optimizations: None,
+            min_target_framework_version: None,
+            max_target_framework_version: None,
+            supported_architectures: None,
+            schema_version: INTEGRATION_SCHEMA_VERSION,
+        }
+    }
+}
+
+impl Display for Integration {
+    /// Formats the integration as its name followed by the targets of its method
+    /// replacements, such as `Name/Type::Method (target: Assembly v1.0.0.0+)`
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)?;
+        for (i, target) in self
+            .method_replacements
+            .iter()
+            .filter_map(MethodReplacement::target)
+            .enumerate()
+        {
+            f.write_str(if i == 0 { "/" } else { ", " })?;
+            write!(f, "{}", target)?;
+        }
+        Ok(())
+    }
+}
+
+/// The targets of the method replacements of an integration, each as an assembly, type,
+/// method, signature types and minimum and maximum version
+type CanonicalTargets<'a> = Vec<(
+    &'a str,
+    &'a str,
+    &'a str,
+    Option<&'a [String]>,
+    &'a Version,
+    &'a Version,
+)>;
+
+impl Integration {
+    /// The key by which the integration is compared: the sorted targets of its method
+    /// replacements, including their version ranges. An integration without targets is
+    /// identified by its name instead
+    fn canonical_key(&self) -> (CanonicalTargets<'_>, &str) {
+        let mut targets: CanonicalTargets<'_> = self
+            .method_replacements
+            .iter()
+            .filter_map(MethodReplacement::target)
+            .map(|target| {
+                (
+                    target.assembly.as_str(),
+                    target.type_name.as_str(),
+                    target.method_name.as_str(),
+                    target.signature_types.as_deref(),
+                    &target.minimum_version,
+                    &target.maximum_version,
+                )
+            })
+            .collect();
+        targets.sort_unstable();
+        if targets.is_empty() {
+            (targets, self.name.as_str())
+        } else {
+            (targets, "")
+        }
+    }
+
+    /// The names of the wrappers of the method replacements of the integration, as
+    /// `Type` or `Type.Method`
+    pub fn wrapper_names(&self) -> Vec<String> {
+        self.method_replacements
+            .iter()
+            .filter_map(MethodReplacement::wrapper)
+            .map(|wrapper| match &wrapper.method_name {
+                Some(method_name) => format!("{}.{}", &wrapper.type_name, method_name),
+                None => wrapper.type_name.clone(),
+            })
+            .collect()
+    }
+
+    /// The number of methods replaced by the integration
+    pub fn method_replacements_count(&self) -> usize {
+        self.method_replacements.len()
+    }
+
+    /// The number of methods instrumented by the integration when CallTarget
+    /// instrumentation is `calltarget_enabled`
+    pub fn instrumented_methods_count(&self, calltarget_enabled: bool) -> usize {
+        self.method_replacements
+            .iter()
+            .filter(|m| m.is_instrumented(calltarget_enabled))
+            .count()
+    }
+
+    /// Whether the integration replaces no methods
+    pub fn is_empty(&self) -> bool {
+        self.method_replacements.is_empty()
+    }
+
+    /// Whether the integration is enabled, either by the integrations file, or
+    /// by an `ELASTIC_APM_PROFILER_INTEGRATION_<NAME>_ENABLED` environment variable
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Whether the process architecture, one of `x64`, `x86` or `arm64`, is
+    /// in the supported architectures of the integration, compared case-insensitively
+    pub fn supports_architecture(&self, architecture: &str) -> bool {
+        match &self.supported_architectures {
+            Some(architectures) => architectures
+                .iter()
+                .any(|a| a.trim().eq_ignore_ascii_case(architecture)),
+            None => true,
+        }
+    }
+
+    /// Whether the runtime version falls within the minimum and maximum runtime versions
+    /// of the integration. A version that cannot be parsed does not exclude the integration
+    pub fn supports_runtime(&self, version: &str) -> bool {
+        let version = match Version::from_str(version) {
+            Ok(v) => v,
+            Err(_) => return true,
+        };
+
+        self.min_target_framework_version
+            .as_ref()
+            .map_or(true, |min| &version >= min)
+            && self
+                .max_target_framework_version
+                .as_ref()
+                .map_or(true, |max| &version <= max)
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
 pub struct ModuleWrapperTokens {
     failed_wrapper_keys: HashSet<String>,
     wrapper_refs: HashMap<String, mdMemberRef>,
@@ -580,6 +983,7 @@ impl ModuleMetadata {
     }
 }
 
+#[derive(Debug)]
 pub struct MetadataBuilder<'a> {
     module_metadata: &'a ModuleMetadata,
     module_wrapper_tokens: &'a mut ModuleWrapperTokens,
@@ -762,7 +1166,7 @@ impl<'a> MetadataBuilder<'a> {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 pub struct FunctionInfo {
     pub id: mdToken,
     pub name: String,
@@ -806,7 +1210,7 @@ impl FunctionInfo {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 pub struct FunctionMethodSignature {
     pub data: Vec<COR_SIGNATURE>,
 }
@@ -1038,6 +1442,7 @@ impl FunctionMethodSignature {
     }
 }
 
+#[derive(Debug, Eq, PartialEq, Clone)]
 pub struct FunctionMethodArgument<'a> {
     data: &'a [u8],
 }
@@ -1175,7 +1580,7 @@ impl<'a> FunctionMethodArgument<'a> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 pub struct ParsedFunctionMethodSignature {
     pub type_arg_len: u8,
     pub arg_len: u8,
@@ -1197,7 +1602,7 @@ impl ParsedFunctionMethodSignature {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 pub struct TypeInfo {
     pub id: mdToken,
     pub name: String,
@@ -1356,6 +1761,15 @@ impl<'de> Deserialize<'de> for Version {
     }
 }
 
+impl Serialize for Version {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
 /// Assembly public key
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub struct PublicKey {
@@ -1406,14 +1820,14 @@ impl PublicKey {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 pub struct WrapperMethodRef {
     pub type_ref: mdTypeRef,
     pub method_ref: mdMemberRef,
 }
 
 #[repr(C)]
-#[derive(Debug, Clone)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 pub struct AssemblyMetaData {
     pub name: String,
     pub locale: Option<String>,
@@ -1443,9 +1857,12 @@ bitflags! {
 
 #[cfg(test)]
 pub mod tests {
-    use crate::profiler::types::{
-        AssemblyReference, Integration, MethodSignature, PublicKeyToken, Version,
-        WrapperMethodAction,
+    use crate::{
+        ffi::CorCallingConvention,
+        profiler::types::{
+            AssemblyName, AssemblyReference, Integration, MethodSignature, PublicKeyToken,
+            SignatureError, Version, WrapperMethodAction,
+        },
     };
     use std::{error::Error, fs::File, io::BufReader, path::PathBuf};
 
@@ -1493,6 +1910,92 @@ pub mod tests {
         Ok(())
     }
 
+    #[test]
+    fn assembly_name_matches_wildcard_versions() {
+        let candidate = AssemblyName::new(
+            "System.Data",
+            Some(Version::new(4, 2, 1, 0)),
+            Some(PublicKeyToken::new("b77a5c561934e089")),
+        );
+
+        assert!(AssemblyName::from("System.Data").matches(&candidate));
+        assert!(!AssemblyName::from("System.Data.SqlClient").matches(&candidate));
+        assert!(
+            AssemblyName::new("System.Data", Some(Version::new(4, 2, 1, 0)), None)
+                .matches(&candidate)
+        );
+        assert!(AssemblyName::new(
+            "System.Data",
+            Some(Version::parse("4.*.*.*", 0).unwrap()),
+            Some(PublicKeyToken::new("B77A5C561934E089"))
+        )
+        .matches(&candidate));
+        assert!(
+            !AssemblyName::new("System.Data", Some(Version::new(4, 3, 0, 0)), None)
+                .matches(&candidate)
+        );
+        assert!(!AssemblyName::new(
+            "System.Data",
+            None,
+            Some(PublicKeyToken::new("cc7b13ffcd2ddd51"))
+        )
+        .matches(&candidate));
+        assert!(
+            !AssemblyName::new("System.Data", Some(Version::new(4, 2, 1, 0)), None)
+                .matches(&AssemblyName::from("System.Data"))
+        );
+    }
+
+    #[test]
+    fn parse_method_signature() -> Result<(), Box<dyn Error>> {
+        let method_signature: MethodSignature =
+            serde_yaml::from_str("\"00 08 1C 1C 1C 1C 1C 1C 08 08 0A\"")?;
+        let parsed = method_signature.parse().unwrap();
+        assert_eq!(
+            parsed.calling_convention,
+            CorCallingConvention::IMAGE_CEE_CS_CALLCONV_DEFAULT
+        );
+        assert_eq!(parsed.type_arguments_len, 0);
+        assert_eq!(parsed.parameters_len, 8);
+        assert_eq!(parsed.return_type, vec![0x1C]);
+
+        // generic instance method with 1 type argument and 2 parameters, returning void
+        let parsed = MethodSignature::new(vec![0x30, 0x01, 0x02, 0x01, 0x1E, 0x00, 0x08])
+            .parse()
+            .unwrap();
+        assert!(parsed.calling_convention.is_generic());
+        assert_eq!(parsed.type_arguments_len, 1);
+        assert_eq!(parsed.parameters_len, 2);
+        assert_eq!(parsed.return_type, vec![0x01]);
+
+        assert_eq!(
+            MethodSignature::new(vec![]).parse(),
+            Err(SignatureError::Empty)
+        );
+        assert_eq!(
+            MethodSignature::new(vec![0x00]).parse(),
+            Err(SignatureError::InvalidParameters)
+        );
+        assert_eq!(
+            MethodSignature::new(vec![0x00, 0x00]).parse(),
+            Err(SignatureError::InvalidReturnType)
+        );
+        // byref and custom modifier return types truncated before the type
+        assert_eq!(
+            MethodSignature::new(vec![0x00, 0x00, 0x10]).parse(),
+            Err(SignatureError::InvalidReturnType)
+        );
+        assert_eq!(
+            MethodSignature::new(vec![0x00, 0x00, 0x1F]).parse(),
+            Err(SignatureError::InvalidReturnType)
+        );
+        assert_eq!(
+            MethodSignature::new(vec![0x00, 0x00, 0x1F, 0x09]).parse(),
+            Err(SignatureError::InvalidReturnType)
+        );
+        Ok(())
+    }
+
     #[test]
     fn deserialize_assembly_reference() -> Result<(), Box<dyn Error>> {
         let json =
@@ -1585,10 +2088,164 @@ method_replacements:
         Ok(())
     }
 
+    #[test]
+    fn integration_supports_runtime_within_target_framework_versions() -> Result<(), Box<dyn Error>> {
+        let yml = r#"---
+name: Net6AndAbove
+method_replacements: []
+min_target_framework_version: 6.0.0
+max_target_framework_version: 8.*"#;
+
+        let integration: Integration = serde_yaml::from_str(yml)?;
+
+        assert_eq!(
+            integration.min_target_framework_version,
+            Some(Version::new(6, 0, 0, 0))
+        );
+        assert_eq!(
+            integration.max_target_framework_version,
+            Some(Version::new(8, u16::MAX, u16::MAX, u16::MAX))
+        );
+        assert!(!integration.supports_runtime("5.0.17"));
+        assert!(integration.supports_runtime("6.0.0"));
+        assert!(integration.supports_runtime("8.0.1.0"));
+        assert!(!integration.supports_runtime("9.0.0"));
+        assert!(integration.supports_runtime("not a version"));
+
+        let integration: Integration = serde_yaml::from_str("name: Any\nmethod_replacements: []")?;
+        assert!(integration.supports_runtime("4.0.30319.0"));
+
+        assert!(serde_yaml::from_str::<Integration>(
+            "name: Invalid\nmethod_replacements: []\nmax_target_framework_version: 8.x"
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn integration_supports_architecture_in_supported_architectures() -> Result<(), Box<dyn Error>>
+    {
+        let integration: Integration = serde_yaml::from_str(
+            "name: X64Only\nmethod_replacements: []\nsupported_architectures: [X64, arm64]",
+        )?;
+        assert!(integration.supports_architecture("x64"));
+        assert!(integration.supports_architecture("arm64"));
+        assert!(!integration.supports_architecture("x86"));
+
+        let integration = Integration::default();
+        assert!(integration.supports_architecture("x86"));
+        Ok(())
+    }
+
+    #[test]
+    fn integrations_with_same_targets_and_different_wrappers_conflict() -> Result<(), Box<dyn Error>>
+    {
+        let integration =
+            |name: &str, wrapper_type: &str, signature_types: &str, maximum_version: &str| {
+                serde_yaml::from_str::<Integration>(&format!(
+                    r#"---
+name: {}
+method_replacements:
+- target:
+    assembly: System.Data
+    type: System.Data.Common.DbCommand
+    method: ExecuteNonQuery
+    signature_types: [{}]
+    minimum_version: 4.0.0
+    maximum_version: {}
+  wrapper:
+    assembly: Elastic.Apm.Profiler.Managed, Version=1.9.0.0, Culture=neutral, PublicKeyToken=ae7400d2c189cf22
+    type: {}
+    action: CallTargetModification"#,
+                    name, signature_types, maximum_version, wrapper_type
+                ))
+            };
+
+        let first = integration("First", "FirstIntegration", "System.Int32", "4.*.*")?;
+        let second = integration("Second", "SecondIntegration", "System.Int32", "4.*.*")?;
+        assert_eq!(first, second);
+        assert_eq!(first.wrapper_names(), vec!["FirstIntegration"]);
+        assert_eq!(second.wrapper_names(), vec!["SecondIntegration"]);
+
+        let other_signature = integration("Third", "FirstIntegration", "System.Int64", "4.*.*")?;
+        assert_ne!(first, other_signature);
+
+        let other_version = integration("Fourth", "FirstIntegration", "System.Int32", "5.*.*")?;
+        assert_ne!(first, other_version);
+        assert!(first < other_version);
+        Ok(())
+    }
+
+    #[test]
+    fn deserialize_partial_integration_uses_defaults() -> Result<(), Box<dyn Error>> {
+        let integration: Integration = serde_yaml::from_str("name: Partial")?;
+        assert_eq!(
+            integration,
+            Integration {
+                name: "Partial".into(),
+                ..Integration::default()
+            }
+        );
+
+        assert!(serde_yaml::from_str::<Integration>("enabled: false").is_err());
+
+        let missing_wrapper_type = r#"---
+name: MissingWrapperType
+method_replacements:
+- target:
+    assembly: System.Data
+    type: System.Data.Common.DbCommand
+    method: ExecuteNonQueryAsync
+  wrapper:
+    assembly: Elastic.Apm.Profiler.Managed, Version=1.9.0.0, Culture=neutral, PublicKeyToken=ae7400d2c189cf22
+    action: CallTargetModification"#;
+        assert!(serde_yaml::from_str::<Integration>(missing_wrapper_type).is_err());
+        Ok(())
+    }
+
     #[test]
     fn public_key_token_into_bytes() {
         let public_key_token = PublicKeyToken::new("ae7400d2c189cf22");
         let bytes = public_key_token.into_bytes();
         assert_eq!(vec![174, 116, 0, 210, 193, 137, 207, 34], bytes);
     }
+
+    #[test]
+    fn display_integration_summarizes_targets() -> Result<(), Box<dyn Error>> {
+        let integrations: Vec<Integration> = serde_yaml::from_str(
+            r#"
+- name: AdoNet
+  method_replacements:
+  - target:
+      assembly: System.Data
+      type: System.Data.Common.DbCommand
+      method: ExecuteReader
+      minimum_version: 4.0.0
+    wrapper:
+      assembly: Elastic.Apm.Profiler.Managed, Version=1.9.0.0, Culture=neutral, PublicKeyToken=ae7400d2c189cf22
+      type: Elastic.Apm.Profiler.Managed.Integrations.AdoNet.CommandExecuteReaderIntegration
+      action: CallTargetModification
+  - target:
+      assembly: System.Data
+      type: System.Data.Common.DbCommand
+      method: ExecuteScalar
+      minimum_version: 4.0.0
+      maximum_version: 4.*.*
+    wrapper:
+      assembly: Elastic.Apm.Profiler.Managed, Version=1.9.0.0, Culture=neutral, PublicKeyToken=ae7400d2c189cf22
+      type: Elastic.Apm.Profiler.Managed.Integrations.AdoNet.CommandExecuteScalarIntegration
+      action: CallTargetModification
+- name: Empty
+"#,
+        )?;
+
+        assert_eq!(
+            integrations[0].to_string(),
+            "AdoNet/System.Data.Common.DbCommand::ExecuteReader (target: System.Data v4.0.0.0+), \
+            System.Data.Common.DbCommand::ExecuteScalar (target: System.Data \
+            v4.0.0.0-4.65535.65535.65535)"
+        );
+        assert_eq!(integrations[1].to_string(), "Empty");
+        Ok(())
+    }
 }